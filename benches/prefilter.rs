@@ -0,0 +1,60 @@
+//! Scans of a simulated process table, with and without rejecting processes from their comm
+//! before reading them in full.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::time::Duration;
+use stuck_writeback_workaround::prefilter::CommPrefilter;
+use stuck_writeback_workaround::system::{self, ProcInfo};
+use stuck_writeback_workaround::{is_monitored, Config};
+
+/// A process table as a busy host may have, mostly userspace processes and idle kworkers.
+fn processes() -> Vec<ProcInfo> {
+    let comms = ["bash", "sshd", "postgres", "kworker/3:1", "jbd2/sda1-8"];
+    (0..5000)
+        .map(|i| {
+            let stuck = i % 1000 == 0;
+            ProcInfo {
+                pid: i + 1,
+                uid: 0,
+                starttime: chrono::Utc::now(),
+                cpu_time: Duration::ZERO,
+                comm: if stuck {
+                    "kworker/u8:2+inode_switch_wbs".to_string()
+                } else {
+                    comms[i as usize % comms.len()].to_string()
+                },
+                cmdline: None,
+                kernel_thread: true,
+                state: if stuck { 'D' } else { 'S' },
+                wchan: None,
+            }
+        })
+        .collect()
+}
+
+fn bench_scan(c: &mut Criterion) {
+    let config = Config::default();
+    let processes = processes();
+    let scan = |prefilter: &CommPrefilter| {
+        // Reading a process in full is what the prefilter saves, so it is what costs here.
+        let entries = processes.iter().map(|p| {
+            let read = move || -> anyhow::Result<ProcInfo> { Ok(black_box(p.clone())) };
+            (p.pid, Some(p.comm.clone()), read)
+        });
+        system::scan(
+            entries,
+            prefilter,
+            None,
+            |p: &ProcInfo| is_monitored(&config, p),
+            |_| false,
+        )
+    };
+
+    let prefilter = CommPrefilter::new(config.globs());
+    assert_eq!(scan(&prefilter).kworkers.len(), 5);
+    c.bench_function("scan_prefiltered", |b| b.iter(|| scan(&prefilter)));
+    let accept_all = CommPrefilter::new(["*"]);
+    c.bench_function("scan_unfiltered", |b| b.iter(|| scan(&accept_all)));
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
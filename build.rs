@@ -0,0 +1,49 @@
+//! Embeds the git revision and build time for `--version`, as `STUCK_WBS_GIT_HASH` and
+//! `STUCK_WBS_BUILD_EPOCH`.
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    // Builds from a tarball, or in a sandbox such as Nix's, have no repository to ask: they can
+    // set the revision themselves.
+    println!("cargo:rerun-if-env-changed=STUCK_WBS_GIT_HASH");
+    let hash = std::env::var("STUCK_WBS_GIT_HASH")
+        .ok()
+        .filter(|hash| !hash.is_empty())
+        .or_else(git_hash)
+        .unwrap_or_else(|| String::from("unknown"));
+    println!("cargo:rustc-env=STUCK_WBS_GIT_HASH={hash}");
+
+    // Honored for reproducible builds, see https://reproducible-builds.org/specs/source-date-epoch/.
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+    println!("cargo:rustc-env=STUCK_WBS_BUILD_EPOCH={epoch}");
+}
+
+/// Returns the abbreviated hash of `HEAD`, suffixed by "-dirty" if the tree has changes, or
+/// `None` outside of a git checkout.
+fn git_hash() -> Option<String> {
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    let hash = git(&["rev-parse", "--short=12", "HEAD"])?;
+    // Rebuilds on commits and checkouts, rather than on every build.
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={git_dir}/HEAD");
+        println!("cargo:rerun-if-changed={git_dir}/index");
+    }
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+    Some(if dirty { format!("{hash}-dirty") } else { hash })
+}
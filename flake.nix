@@ -35,6 +35,8 @@
           // {
             inherit cargoArtifacts;
             doCheck = false;
+            # For `--version`, as the sandbox has no repository to ask.
+            STUCK_WBS_GIT_HASH = self.shortRev or self.dirtyShortRev or "unknown";
             meta = {
               mainProgram = "stuck_writeback_workaround";
             };
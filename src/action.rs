@@ -0,0 +1,198 @@
+//! Remediation actions taken when a monitored process is stuck, and their per-pattern mapping.
+use crate::system::ProcInfo;
+use anyhow::{bail, Result};
+use rustix::process::Signal;
+use std::fmt;
+
+/// Signals that may be named in a `signal:<SIGNAL>` action.
+const SIGNALS: &[(&str, Signal)] = &[
+    ("SIGHUP", Signal::HUP),
+    ("SIGINT", Signal::INT),
+    ("SIGQUIT", Signal::QUIT),
+    ("SIGKILL", Signal::KILL),
+    ("SIGUSR1", Signal::USR1),
+    ("SIGUSR2", Signal::USR2),
+    ("SIGTERM", Signal::TERM),
+    ("SIGSTOP", Signal::STOP),
+    ("SIGCONT", Signal::CONT),
+];
+
+/// What to do about a process that has been stuck for longer than the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Issue a system-wide `sync`.
+    Sync,
+    /// Send a signal to the stuck process.
+    Signal(Signal),
+    /// Run the `--action-command`, e.g. to write to `/proc/sysrq-trigger`.
+    Command,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Sync => f.write_str("sync"),
+            Action::Command => f.write_str("command"),
+            Action::Signal(signal) => match SIGNALS.iter().find(|(_, s)| s == signal) {
+                Some((name, _)) => write!(f, "signal:{name}"),
+                None => write!(f, "signal:{signal:?}"),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sync" => return Ok(Action::Sync),
+            "command" => return Ok(Action::Command),
+            _ => {}
+        }
+        let Some(name) = s.strip_prefix("signal:") else {
+            return Err(format!(
+                "invalid action '{s}', expected 'sync', 'command' or 'signal:<SIGNAL>'"
+            ));
+        };
+        let name = name.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        SIGNALS
+            .iter()
+            .find(|(n, _)| n[3..] == *name)
+            .map(|&(_, signal)| Action::Signal(signal))
+            .ok_or_else(|| format!("unsupported signal in action '{s}'"))
+    }
+}
+
+/// Serialized as on the command line, e.g. "signal:SIGKILL".
+impl serde::Serialize for Action {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// Associates processes matching `glob` with the action to take when they are stuck.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternAction {
+    pub glob: String,
+    pub action: Action,
+}
+
+impl fmt::Display for PatternAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.glob, self.action)
+    }
+}
+
+/// Serialized as on the command line, e.g. "stuckd=signal:SIGKILL".
+impl serde::Serialize for PatternAction {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl std::str::FromStr for PatternAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((glob, action)) = s.rsplit_once('=') else {
+            return Err(format!(
+                "invalid pattern action '{s}', expected '<glob>=<action>'"
+            ));
+        };
+        if glob.is_empty() {
+            return Err(format!("empty glob in pattern action '{s}'"));
+        }
+        Ok(PatternAction {
+            glob: glob.to_string(),
+            action: action.parse()?,
+        })
+    }
+}
+
+/// Checks that `target` is something we are willing to signal.
+///
+/// Signaling init or kthreadd would be catastrophic, and kernel threads ignore signals anyway, so
+/// signaling them would only give the illusion of having acted.
+pub fn check_signal_target(target: &ProcInfo) -> Result<()> {
+    if target.pid <= 2 {
+        bail!("refusing to signal pid {} ('{}')", target.pid, target.comm);
+    }
+    if target.kernel_thread {
+        bail!(
+            "refusing to signal kernel thread '{}' (pid {}), as it would ignore the signal",
+            target.comm,
+            target.pid
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proc_info(pid: i32, kernel_thread: bool) -> ProcInfo {
+        ProcInfo {
+            pid,
+            uid: 0,
+            comm: "stuckd".to_string(),
+            cmdline: None,
+            kernel_thread,
+            state: 'S',
+            wchan: None,
+            starttime: chrono::Utc::now(),
+            cpu_time: std::time::Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_parse_action() {
+        assert_eq!("sync".parse(), Ok(Action::Sync));
+        assert_eq!("signal:SIGKILL".parse(), Ok(Action::Signal(Signal::KILL)));
+        assert_eq!("signal:term".parse(), Ok(Action::Signal(Signal::TERM)));
+        assert_eq!("command".parse(), Ok(Action::Command));
+        assert!("signal:SIGNOPE".parse::<Action>().is_err());
+        assert!("reboot".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn test_action_display_round_trips() {
+        for s in ["sync", "signal:SIGKILL", "signal:SIGUSR1", "command"] {
+            assert_eq!(s.parse::<Action>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_pattern_action() {
+        assert_eq!(
+            "kworker/*inode_switch_wbs*=sync".parse(),
+            Ok(PatternAction {
+                glob: "kworker/*inode_switch_wbs*".to_string(),
+                action: Action::Sync,
+            })
+        );
+        assert_eq!(
+            "stuckd=signal:kill"
+                .parse::<PatternAction>()
+                .unwrap()
+                .to_string(),
+            "stuckd=signal:SIGKILL"
+        );
+        assert!("no-action".parse::<PatternAction>().is_err());
+        assert!("=sync".parse::<PatternAction>().is_err());
+    }
+
+    #[test]
+    fn test_check_signal_target_refuses_low_pids() {
+        assert!(check_signal_target(&proc_info(1, false)).is_err());
+        assert!(check_signal_target(&proc_info(2, false)).is_err());
+        assert!(check_signal_target(&proc_info(3, false)).is_ok());
+    }
+
+    #[test]
+    fn test_check_signal_target_refuses_kernel_threads() {
+        assert!(check_signal_target(&proc_info(1234, true)).is_err());
+    }
+}
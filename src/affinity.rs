@@ -0,0 +1,122 @@
+//! Pinning the daemon to a set of CPUs, so it doesn't compete with the ones stuck kworkers hog.
+use anyhow::{bail, Context, Result};
+use log::info;
+use rustix::thread::{sched_setaffinity, CpuSet};
+use std::fmt;
+
+/// Lists the CPUs that are currently online, in the kernel's CPU list format.
+const ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+
+/// A set of CPUs, parsed from the kernel's list format (e.g. "0-3,8").
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(Vec<usize>);
+
+impl CpuList {
+    /// Parses `s`, rejecting CPUs from `limit` on, if any, before allocating for them.
+    fn parse(s: &str, limit: Option<usize>) -> Result<Self, String> {
+        let mut cpus = Vec::new();
+        for range in s.trim().split(',') {
+            let parse = |n: &str| {
+                n.parse::<usize>()
+                    .map_err(|e| format!("invalid CPU '{n}' in '{s}': {e}"))
+            };
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (parse(first)?, parse(last)?),
+                None => {
+                    let cpu = parse(range)?;
+                    (cpu, cpu)
+                }
+            };
+            if first > last {
+                return Err(format!("invalid CPU range '{range}' in '{s}'"));
+            }
+            if let Some(limit) = limit.filter(|&limit| last >= limit) {
+                return Err(format!(
+                    "CPU {last} in '{s}' exceeds the maximum of {}",
+                    limit - 1
+                ));
+            }
+            cpus.extend(first..=last);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+impl std::str::FromStr for CpuList {
+    type Err = String;
+
+    /// Parses CPUs to pin to, which must fit in a `CpuSet`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CpuList::parse(s, Some(CpuSet::MAX_CPU))
+    }
+}
+
+impl fmt::Display for CpuList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cpus: Vec<String> = self.0.iter().map(usize::to_string).collect();
+        f.write_str(&cpus.join(","))
+    }
+}
+
+/// Checks that every CPU in `wanted` is in `online`.
+fn check_online(wanted: &CpuList, online: &CpuList) -> Result<()> {
+    let offline: Vec<usize> = wanted
+        .0
+        .iter()
+        .filter(|cpu| !online.0.contains(cpu))
+        .copied()
+        .collect();
+    if !offline.is_empty() {
+        bail!("CPUs {offline:?} are not online (online: {online})");
+    }
+    Ok(())
+}
+
+/// Restricts the calling thread, and the threads it spawns afterwards, to `cpus`.
+pub fn pin_to(cpus: &CpuList) -> Result<()> {
+    let online = std::fs::read_to_string(ONLINE_CPUS_PATH)
+        .with_context(|| format!("failed to read {ONLINE_CPUS_PATH}"))?;
+    // Hosts may have more CPUs online than a `CpuSet` holds, though those can't be pinned to.
+    let online = CpuList::parse(&online, None)
+        .map_err(|e| anyhow::anyhow!("failed to parse {ONLINE_CPUS_PATH}: {e}"))?;
+    check_online(cpus, &online)?;
+
+    let mut set = CpuSet::new();
+    for &cpu in &cpus.0 {
+        set.set(cpu);
+    }
+    sched_setaffinity(None, &set).context("failed to set CPU affinity")?;
+    info!("Pinned to CPUs {cpus}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!("0".parse(), Ok(CpuList(vec![0])));
+        assert_eq!("0-3,5".parse(), Ok(CpuList(vec![0, 1, 2, 3, 5])));
+        assert_eq!("5,1-2,2\n".parse(), Ok(CpuList(vec![1, 2, 5])));
+        assert!("".parse::<CpuList>().is_err());
+        assert!("3-1".parse::<CpuList>().is_err());
+        assert!("0,,1".parse::<CpuList>().is_err());
+        assert!("one".parse::<CpuList>().is_err());
+        // Rejected before allocating an entry for each.
+        assert!("0-4000000000".parse::<CpuList>().is_err());
+        let max = CpuSet::MAX_CPU - 1;
+        assert_eq!(max.to_string().parse(), Ok(CpuList(vec![max])));
+        assert!(CpuSet::MAX_CPU.to_string().parse::<CpuList>().is_err());
+        assert!(CpuList::parse(&format!("0-{}", CpuSet::MAX_CPU), None).is_ok());
+    }
+
+    #[test]
+    fn test_check_online() {
+        let online: CpuList = "0-3".parse().unwrap();
+        assert!(check_online(&"1,3".parse().unwrap(), &online).is_ok());
+        assert!(check_online(&"3-4".parse().unwrap(), &online).is_err());
+    }
+}
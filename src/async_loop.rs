@@ -0,0 +1,135 @@
+//! `--async`, driving the monitor loop from a tokio runtime, for embedding it alongside other
+//! async tasks. Iterations stay synchronous but run on blocking tasks, while the sleeps between
+//! them, like the iterations themselves, end as soon as a termination signal arrives.
+#[cfg(feature = "async")]
+use anyhow::Context;
+use anyhow::Result;
+
+/// Whether this build can drive the loop asynchronously, which requires the `async` feature.
+pub const SUPPORTED: bool = cfg!(feature = "async");
+
+/// A single-threaded tokio runtime, listening for termination signals.
+#[cfg(feature = "async")]
+pub struct Runtime {
+    runtime: tokio::runtime::Runtime,
+    termination: Termination,
+}
+
+/// Built without the `async` feature, there is no runtime to start.
+#[cfg(not(feature = "async"))]
+pub enum Runtime {}
+
+#[cfg(feature = "async")]
+impl Runtime {
+    /// Starts the runtime and listens for termination signals on it. Their handlers only see
+    /// them if they are left unblocked, so `shutdown::handle_termination_signals` must not be
+    /// called as well.
+    pub fn new() -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .context("failed to start the async runtime")?;
+        let termination = {
+            let _entered = runtime.enter();
+            Termination::listen()?
+        };
+        Ok(Runtime {
+            runtime,
+            termination,
+        })
+    }
+
+    /// Runs the future `run` returns to completion, passing it the termination signals to await.
+    pub fn block_on<F: std::future::Future>(self, run: impl FnOnce(Termination) -> F) -> F::Output {
+        let Runtime {
+            runtime,
+            termination,
+        } = self;
+        runtime.block_on(run(termination))
+    }
+}
+
+#[cfg(not(feature = "async"))]
+impl Runtime {
+    pub fn new() -> Result<Self> {
+        anyhow::bail!("--async requires building with the `async` feature")
+    }
+}
+
+/// The signals that terminate the daemon gracefully, as `shutdown` handles them otherwise.
+#[cfg(feature = "async")]
+pub struct Termination {
+    terminate: tokio::signal::unix::Signal,
+    interrupt: tokio::signal::unix::Signal,
+}
+
+#[cfg(feature = "async")]
+impl Termination {
+    fn listen() -> Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let listen = |kind| signal(kind).context("failed to listen for termination signals");
+        Ok(Termination {
+            terminate: listen(SignalKind::terminate())?,
+            interrupt: listen(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Waits for a termination signal, returning its name.
+    pub async fn recv(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.terminate.recv() => "SIGTERM",
+            _ = self.interrupt.recv() => "SIGINT",
+        }
+    }
+}
+
+/// Runs `f` on a blocking task, as the thread driving the runtime must be free to see signals.
+/// Panics as `f` does.
+#[cfg(feature = "async")]
+pub async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_termination_signals_end_waits() {
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        let name = Runtime::new()
+            .unwrap()
+            .block_on(|mut termination| async move {
+                let iteration = blocking(move || {
+                    // SAFETY: raising a signal is sound, and the runtime handles this one.
+                    unsafe { libc::raise(libc::SIGINT) };
+                    // Until the test is done, so that the runtime can shut down.
+                    let _ = receiver.recv_timeout(Duration::from_secs(60));
+                });
+                let name = tokio::select! {
+                    () = iteration => panic!("the iteration outlasted the signal"),
+                    name = termination.recv() => name,
+                };
+                drop(sender);
+                name
+            });
+        assert_eq!(name, "SIGINT");
+    }
+
+    #[test]
+    fn test_blocking_returns_or_panics_as_its_task() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(runtime.block_on(blocking(|| 42)), 42);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            runtime.block_on(blocking(|| panic!("iteration")))
+        }));
+        assert!(panicked.is_err());
+    }
+}
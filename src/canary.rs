@@ -0,0 +1,49 @@
+//! `--canary-percent`, which lets a fleet roll out remediation gradually by only acting on a
+//! stable subset of hosts, the others running detect-only.
+
+/// Returns the bucket, from 0 to 99, that `hostname` falls into.
+///
+/// This uses 64-bit FNV-1a rather than the standard library's hasher, whose output may change
+/// between releases, so a host stays in the same bucket across upgrades.
+fn bucket(hostname: &str) -> u8 {
+    let hash = hostname
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    (hash % 100) as u8
+}
+
+/// Returns whether `hostname` is among the `percent`% of hosts that act.
+///
+/// Raising the percentage only ever adds hosts to the canary set.
+pub fn includes(hostname: &str, percent: u8) -> bool {
+    bucket(hostname) < percent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucketing_is_stable() {
+        // Pinned, so a change in the hash, which would reshuffle fleets, is caught.
+        assert_eq!(bucket(""), (0xcbf2_9ce4_8422_2325_u64 % 100) as u8);
+        assert_eq!(bucket("a"), (0xaf63_dc4c_8601_ec8c_u64 % 100) as u8);
+    }
+
+    #[test]
+    fn test_canary_set_grows_with_percentage() {
+        let hosts: Vec<String> = (0..1000).map(|i| format!("node-{i}.example.com")).collect();
+        for percent in [0, 10, 50, 100] {
+            let canaries = hosts.iter().filter(|h| includes(h, percent)).count();
+            for host in &hosts {
+                if includes(host, percent) {
+                    assert!(includes(host, percent.saturating_add(10).min(100)));
+                }
+            }
+            // Roughly the requested share of the fleet.
+            assert!(canaries.abs_diff(percent as usize * 10) <= 50, "{canaries}");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Startup checks that the daemon holds the capabilities its enabled features need, so
+//! least-privilege deployments learn about a missing one upfront rather than when it is needed.
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+/// Where the kernel reports the capabilities of the current process.
+const PROC_SELF_STATUS_PATH: &str = "/proc/self/status";
+
+/// A Linux capability needed by some feature.
+///
+/// `sync`, I/O priorities below the default, CPU affinity and reading `/proc` need none. Process
+/// events need `CAP_NET_ADMIN`, but are checked by listening to them instead, which also catches
+/// kernels without the connector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Writing to `/proc/sysrq-trigger`, which only its owner, root, may do otherwise.
+    DacOverride,
+    /// Sending signals to processes of other users, as the monitored ones belong to root.
+    Kill,
+    /// Subscribing to process events from the kernel connector.
+    NetAdmin,
+    /// Reading where processes of other users are blocked, and their kernel stacks.
+    SysPtrace,
+    /// Reading the kernel stacks of processes.
+    SysAdmin,
+}
+
+impl Capability {
+    /// The capability's number, as in `linux/capability.h`.
+    pub(crate) fn number(self) -> u32 {
+        match self {
+            Capability::DacOverride => 1,
+            Capability::Kill => 5,
+            Capability::NetAdmin => 12,
+            Capability::SysPtrace => 19,
+            Capability::SysAdmin => 21,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Capability::DacOverride => "CAP_DAC_OVERRIDE",
+            Capability::Kill => "CAP_KILL",
+            Capability::NetAdmin => "CAP_NET_ADMIN",
+            Capability::SysPtrace => "CAP_SYS_PTRACE",
+            Capability::SysAdmin => "CAP_SYS_ADMIN",
+        }
+    }
+}
+
+/// A capability required by an enabled feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requirement {
+    pub capability: Capability,
+    /// What needs it, for error messages.
+    pub feature: &'static str,
+    /// Whether the feature cannot work at all without it, rather than in a degraded way.
+    pub fatal: bool,
+}
+
+/// Extracts the effective capability set from the contents of `/proc/<pid>/status`.
+fn parse_effective(status: &str) -> Result<u64> {
+    let Some(caps) = status.lines().find_map(|line| line.strip_prefix("CapEff:")) else {
+        bail!("no CapEff line");
+    };
+    u64::from_str_radix(caps.trim(), 16).with_context(|| format!("invalid CapEff '{caps}'"))
+}
+
+/// Returns the requirements that `effective` does not satisfy.
+fn missing(requirements: &[Requirement], effective: u64) -> Vec<Requirement> {
+    requirements
+        .iter()
+        .filter(|r| effective & (1 << r.capability.number()) == 0)
+        .copied()
+        .collect()
+}
+
+/// Checks that the current process holds every capability in `requirements`.
+///
+/// Missing capabilities are warned about, and an error is returned if any of them is fatal. If
+/// the capabilities cannot be read, the check is skipped with a warning.
+pub fn check(requirements: &[Requirement]) -> Result<()> {
+    let effective = std::fs::read_to_string(PROC_SELF_STATUS_PATH)
+        .with_context(|| format!("failed to read {PROC_SELF_STATUS_PATH}"))
+        .and_then(|status| {
+            parse_effective(&status)
+                .with_context(|| format!("failed to parse {PROC_SELF_STATUS_PATH}"))
+        });
+    let effective = match effective {
+        Ok(effective) => effective,
+        Err(e) => {
+            warn!("Not checking capabilities: {e:?}");
+            return Ok(());
+        }
+    };
+    let missing = missing(requirements, effective);
+    for r in &missing {
+        warn!("Missing {}, required by {}", r.capability.name(), r.feature);
+    }
+    if let Some(r) = missing.iter().find(|r| r.fatal) {
+        bail!("{} cannot work without {}", r.feature, r.capability.name());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYS_ADMIN: Requirement = Requirement {
+        capability: Capability::SysAdmin,
+        feature: "kernel stacks",
+        fatal: false,
+    };
+    const KILL: Requirement = Requirement {
+        capability: Capability::Kill,
+        feature: "signal actions",
+        fatal: true,
+    };
+
+    #[test]
+    fn test_parse_effective() {
+        let status = "Name:\tstuck_writeback\nCapInh:\t0000000000000000\n\
+                      CapEff:\t000001ffffffffff\nCapBnd:\t000001ffffffffff\n";
+        assert_eq!(parse_effective(status).unwrap(), 0x1ff_ffff_ffff);
+        assert!(parse_effective("Name:\tx\n").is_err());
+        assert!(parse_effective("CapEff:\tnope\n").is_err());
+    }
+
+    #[test]
+    fn test_missing() {
+        let requirements = [SYS_ADMIN, KILL];
+        assert!(missing(&requirements, u64::MAX).is_empty());
+        assert_eq!(missing(&requirements, 1 << 21), vec![KILL]);
+        assert_eq!(missing(&requirements, 0), vec![SYS_ADMIN, KILL]);
+    }
+}
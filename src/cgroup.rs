@@ -0,0 +1,141 @@
+//! Detection of processes in frozen cgroups, which legitimately look stuck without being the bug
+//! this daemon works around.
+use anyhow::{Context, Result};
+use log::debug;
+use procfs::process::Process;
+use procfs::ProcessCGroup;
+use std::path::Path;
+
+/// Where cgroup filesystems are mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Returns whether `cgroup`, from a cgroup filesystem mounted under `root`, is frozen.
+fn is_frozen(root: &Path, cgroup: &ProcessCGroup) -> Result<bool> {
+    let relative = cgroup.pathname.trim_start_matches('/');
+    if cgroup.hierarchy == 0 {
+        // cgroup.freeze only tells what was requested, cgroup.events whether it took effect. It
+        // also reflects freezing inherited from ancestors.
+        let path = root.join(relative).join("cgroup.events");
+        let events = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Ok(events.lines().any(|line| line.trim() == "frozen 1"))
+    } else if cgroup.controllers.iter().any(|c| c == "freezer") {
+        let path = root.join("freezer").join(relative).join("freezer.state");
+        let state = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        // Also excludes FREEZING, as the process is on its way to being frozen.
+        Ok(state.trim() != "THAWED")
+    } else {
+        Ok(false)
+    }
+}
+
+/// Returns whether any of `cgroups`, from cgroup filesystems mounted under `root`, is frozen.
+///
+/// Cgroups whose state cannot be read are assumed not to be frozen, so the process is still
+/// considered.
+fn any_frozen(root: &Path, cgroups: &[ProcessCGroup]) -> bool {
+    cgroups.iter().any(|cgroup| {
+        is_frozen(root, cgroup).unwrap_or_else(|e| {
+            debug!("Assuming cgroup '{}' is not frozen: {e:#}", cgroup.pathname);
+            false
+        })
+    })
+}
+
+/// Returns whether the process `pid`, read from the procfs at `procfs_root`, is in a frozen
+/// cgroup.
+pub fn in_frozen_cgroup(procfs_root: &Path, pid: i32) -> Result<bool> {
+    let process = Process::new_with_root(procfs_root.join(pid.to_string()))
+        .context("failed to open process")?;
+    let cgroups = process
+        .cgroups()
+        .context("failed to read process cgroups")?;
+    Ok(any_frozen(Path::new(CGROUP_ROOT), &cgroups.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A fake cgroup filesystem root in a fresh temporary directory, removed on drop.
+    struct FakeRoot(PathBuf);
+
+    impl FakeRoot {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("stuck_wbs_{}_{name}.cgroup", std::process::id()));
+            Self(path)
+        }
+
+        fn write(&self, file: &str, contents: &str) {
+            let path = self.0.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn v2(pathname: &str) -> ProcessCGroup {
+        ProcessCGroup {
+            hierarchy: 0,
+            controllers: Vec::new(),
+            pathname: pathname.to_string(),
+        }
+    }
+
+    fn v1(controllers: &[&str], pathname: &str) -> ProcessCGroup {
+        ProcessCGroup {
+            hierarchy: 7,
+            controllers: controllers.iter().map(|c| c.to_string()).collect(),
+            pathname: pathname.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_frozen_v2_cgroup_is_excluded() {
+        let root = FakeRoot::new("v2");
+        root.write(
+            "app.slice/frozen.scope/cgroup.events",
+            "populated 1\nfrozen 1\n",
+        );
+        root.write(
+            "app.slice/running.scope/cgroup.events",
+            "populated 1\nfrozen 0\n",
+        );
+
+        assert!(any_frozen(&root.0, &[v2("/app.slice/frozen.scope")]));
+        assert!(!any_frozen(&root.0, &[v2("/app.slice/running.scope")]));
+    }
+
+    #[test]
+    fn test_frozen_v1_freezer_is_excluded() {
+        let root = FakeRoot::new("v1");
+        root.write("freezer/frozen/freezer.state", "FROZEN\n");
+        root.write("freezer/freezing/freezer.state", "FREEZING\n");
+        root.write("freezer/running/freezer.state", "THAWED\n");
+
+        let cpu = v1(&["cpu", "cpuacct"], "/frozen");
+        assert!(any_frozen(
+            &root.0,
+            &[cpu.clone(), v1(&["freezer"], "/frozen")]
+        ));
+        assert!(any_frozen(&root.0, &[v1(&["freezer"], "/freezing")]));
+        assert!(!any_frozen(&root.0, &[cpu, v1(&["freezer"], "/running")]));
+    }
+
+    #[test]
+    fn test_unreadable_state_is_considered_running() {
+        let root = FakeRoot::new("missing");
+        assert!(!any_frozen(
+            &root.0,
+            &[v2("/gone.scope"), v1(&["freezer"], "/gone")]
+        ));
+    }
+}
@@ -0,0 +1,84 @@
+//! The clock runtimes are measured on, which steps of the wall clock, e.g. by NTP, don't move.
+//!
+//! Process start times are kept by the kernel as clock ticks since boot, and `procfs` converts
+//! them to wall clock times with the boot time it read first. A step of the wall clock would
+//! then make every process look older or younger by as much, triggering spuriously or not at
+//! all. Instead, both the current time and start times are read on the boot clock
+//! (`CLOCK_BOOTTIME`, which the kernel counts start ticks on), then placed after the boot time
+//! as the wall clock had it at startup.
+use rustix::time::{clock_gettime, ClockId};
+
+type Time = chrono::DateTime<chrono::Utc>;
+
+/// The boot clock, anchored on the wall clock as of startup.
+#[derive(Debug, Clone, Copy)]
+pub struct BootClock {
+    boot_time: Time,
+}
+
+/// Returns `time` in the local timezone, for display. Times are otherwise kept in UTC, so that
+/// neither DST transitions nor a change of the system's timezone shows in their arithmetic.
+pub fn local(time: Time) -> chrono::DateTime<chrono::Local> {
+    time.with_timezone(&chrono::Local)
+}
+
+/// Returns how long ago the system booted, including time spent suspended.
+fn since_boot() -> chrono::Duration {
+    let now = clock_gettime(ClockId::Boottime);
+    chrono::Duration::seconds(now.tv_sec) + chrono::Duration::nanoseconds(now.tv_nsec)
+}
+
+impl BootClock {
+    /// Anchors the boot clock on the wall clock as it is now.
+    pub fn anchored() -> Self {
+        let since_boot = since_boot();
+        BootClock {
+            boot_time: chrono::Utc::now() - since_boot,
+        }
+    }
+
+    /// Returns the current time, which only drifts from the wall clock as much as the latter is
+    /// stepped after startup.
+    pub fn now(&self) -> Time {
+        self.boot_time + since_boot()
+    }
+
+    /// Returns when a process started `ticks` clock ticks after boot, at `ticks_per_second`.
+    pub fn start_time(&self, ticks: u64, ticks_per_second: u64) -> Time {
+        let secs = ticks / ticks_per_second;
+        let nanos = (ticks % ticks_per_second) * 1_000_000_000 / ticks_per_second;
+        // Ticks since boot are far from overflowing either.
+        self.boot_time
+            + chrono::Duration::seconds(secs as i64)
+            + chrono::Duration::nanoseconds(nanos as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_time_counts_ticks_from_boot() {
+        let boot_time = chrono::Utc::now() - chrono::Duration::days(3);
+        let clock = BootClock { boot_time };
+        assert_eq!(
+            clock.start_time(12_345, 100),
+            boot_time + chrono::Duration::milliseconds(123_450)
+        );
+        assert_eq!(clock.start_time(0, 100), boot_time);
+    }
+
+    #[test]
+    fn test_processes_started_before_now() {
+        let clock = BootClock::anchored();
+        let own = procfs::process::Process::myself()
+            .and_then(|p| p.stat())
+            .unwrap();
+        let started = clock.start_time(own.starttime, procfs::ticks_per_second());
+        let age = clock.now().signed_duration_since(started);
+        // Start ticks are rounded down, so the age may be overestimated by a tick.
+        assert!(age >= chrono::Duration::zero(), "{age}");
+        assert!(age < chrono::Duration::minutes(10), "{age}");
+    }
+}
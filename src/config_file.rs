@@ -0,0 +1,375 @@
+//! `--config`, a TOML file of settings for deployments that would rather ship a file than manage
+//! long command lines. Keys are named after the flags, as in `--dump-config`'s output, which can
+//! be saved as a configuration file.
+use crate::action::{Action, PatternAction};
+use crate::affinity::CpuList;
+use crate::duration::{parse_duration, parse_std_duration, parse_threshold};
+use crate::ioprio::IoPrioClass;
+use crate::journald::LogTarget;
+use crate::labels::Label;
+use crate::log_color::ColorChoice;
+use crate::log_format::LogFormat;
+use crate::oom::OomScoreAdj;
+use crate::signature::{ProcessGlob, Signature};
+use crate::sync_mode::SyncMode;
+use crate::StartupBehavior;
+use anyhow::{Context, Result};
+use serde::de::{Deserialize, Deserializer, Error};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings from the configuration file, all optional.
+///
+/// `--supervise`, `--async` and one-shot flags such as `--dump-config` and `--once` can only be
+/// given on the command line.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ConfigFile {
+    /// One glob, or a list of them.
+    #[serde(default, deserialize_with = "parsed_one_or_list")]
+    pub process_glob: Vec<ProcessGlob>,
+    /// `Some(None)` if "off".
+    #[serde(default, deserialize_with = "threshold")]
+    pub runtime_threshold: Option<Option<chrono::Duration>>,
+    #[serde(default, deserialize_with = "duration")]
+    pub warn_threshold: Option<chrono::Duration>,
+    #[serde(default, deserialize_with = "duration")]
+    pub sum_age_threshold: Option<chrono::Duration>,
+    #[serde(default, deserialize_with = "duration")]
+    pub cpu_threshold: Option<chrono::Duration>,
+    pub min_stuck_count: Option<usize>,
+    #[serde(default, deserialize_with = "duration")]
+    pub first_action_after_boot: Option<chrono::Duration>,
+    #[serde(default)]
+    pub require_no_progress: bool,
+    pub require_wchan: Option<String>,
+    #[serde(default)]
+    pub any_state: bool,
+    /// One uid, or a list of them.
+    #[serde(default, deserialize_with = "one_or_list")]
+    pub uid: Vec<u32>,
+    #[serde(default)]
+    pub any_uid: bool,
+    #[serde(default, deserialize_with = "percent")]
+    pub canary_percent: Option<u8>,
+    #[serde(default, deserialize_with = "duration")]
+    pub episode_gap: Option<chrono::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub scan_budget: Option<std::time::Duration>,
+    pub max_examined: Option<usize>,
+    #[serde(default)]
+    pub no_netlink: bool,
+    pub procfs_root: Option<PathBuf>,
+    #[serde(default, deserialize_with = "duration")]
+    pub starttime_tolerance: Option<chrono::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub busy_poll: Option<std::time::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub error_backoff: Option<std::time::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub rescan_interval: Option<std::time::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub recovery_time: Option<std::time::Duration>,
+    #[serde(default, deserialize_with = "fraction")]
+    pub jitter: Option<f64>,
+    pub verify_command: Option<String>,
+    pub max_ineffective_syncs: Option<usize>,
+    pub escalation_command: Option<String>,
+    #[serde(default)]
+    pub escalate_sysrq: bool,
+    pub max_syncs: Option<u64>,
+    #[serde(default)]
+    pub from_cmdline: bool,
+    #[serde(default)]
+    pub verbose: bool,
+    #[serde(default)]
+    pub debug: bool,
+    #[serde(default)]
+    pub quiet: bool,
+    #[serde(default)]
+    pub no_timestamps: bool,
+    #[serde(default, deserialize_with = "parsed")]
+    pub log_format: Option<LogFormat>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub color: Option<ColorChoice>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub log_target: Option<LogTarget>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub log_dedup_window: Option<std::time::Duration>,
+    #[serde(default)]
+    pub match_cmdline: bool,
+    #[serde(default, deserialize_with = "parsed")]
+    pub sync_ioprio: Option<IoPrioClass>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub sync_mode: Option<SyncMode>,
+    #[serde(default, deserialize_with = "duration")]
+    pub sync_cooldown: Option<chrono::Duration>,
+    #[serde(default, deserialize_with = "std_duration")]
+    pub sync_timeout: Option<std::time::Duration>,
+    #[serde(default, deserialize_with = "percent")]
+    pub min_free_percent: Option<u8>,
+    pub sync_path: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub action: Option<Action>,
+    pub action_command: Option<String>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub pattern_action: Vec<PatternAction>,
+    #[serde(default, deserialize_with = "parsed_list")]
+    pub signature: Vec<Signature>,
+    pub rules: Option<PathBuf>,
+    pub pattern_file: Option<PathBuf>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub cpu_affinity: Option<CpuList>,
+    #[serde(default, deserialize_with = "oom_score_adj")]
+    pub oom_score_adj: Option<OomScoreAdj>,
+    #[serde(default, deserialize_with = "parsed")]
+    pub startup_behavior: Option<StartupBehavior>,
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub systemd: bool,
+    pub pidfile: Option<PathBuf>,
+    pub state_file: Option<PathBuf>,
+    pub event_log: Option<PathBuf>,
+    pub drop_to: Option<String>,
+    pub metrics_textfile: Option<PathBuf>,
+    pub metrics_listen: Option<SocketAddr>,
+    pub status_socket: Option<PathBuf>,
+    #[serde(default)]
+    pub print_stats_on_exit: bool,
+    pub webhook: Option<String>,
+    pub incident_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub capture_stack: bool,
+    pub stack_dir: Option<PathBuf>,
+    /// A table of labels, as `--dump-config` writes them.
+    #[serde(default, deserialize_with = "labels")]
+    pub label: Vec<Label>,
+    /// Every other key, which is ignored.
+    #[serde(flatten)]
+    unknown: toml::Table,
+}
+
+impl ConfigFile {
+    /// Reads and parses the file at `path`.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// Returns the keys that are not settings, and so were ignored.
+    pub fn unknown_keys(&self) -> impl Iterator<Item = &str> {
+        self.unknown.keys().map(String::as_str)
+    }
+}
+
+/// Deserializes a value in the form its flag takes, with the flag's parser.
+fn with_parser<'de, D: Deserializer<'de>, T>(
+    d: D,
+    parse: impl FnOnce(&str) -> Result<T, String>,
+) -> Result<Option<T>, D::Error> {
+    let s = String::deserialize(d)?;
+    parse(&s).map(Some).map_err(D::Error::custom)
+}
+
+fn duration<'de, D: Deserializer<'de>>(d: D) -> Result<Option<chrono::Duration>, D::Error> {
+    with_parser(d, parse_duration)
+}
+
+fn threshold<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<Option<chrono::Duration>>, D::Error> {
+    with_parser(d, parse_threshold)
+}
+
+fn std_duration<'de, D: Deserializer<'de>>(d: D) -> Result<Option<std::time::Duration>, D::Error> {
+    with_parser(d, parse_std_duration)
+}
+
+/// Accepts an integer, rather than a string as the other value types.
+fn percent<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u8>, D::Error> {
+    let percent = u64::deserialize(d)?;
+    crate::percent::parse_percent(&percent.to_string())
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// Accepts a number, whether written as an integer or a float.
+fn fraction<'de, D: Deserializer<'de>>(d: D) -> Result<Option<f64>, D::Error> {
+    let fraction = f64::deserialize(d)?;
+    crate::jitter::parse_fraction(&fraction.to_string())
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+/// Accepts an integer, as `percent` does.
+fn oom_score_adj<'de, D: Deserializer<'de>>(d: D) -> Result<Option<OomScoreAdj>, D::Error> {
+    let adj = i64::deserialize(d)?;
+    adj.to_string().parse().map(Some).map_err(D::Error::custom)
+}
+
+fn parsed<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(
+    d: D,
+) -> Result<Option<T>, D::Error> {
+    with_parser(d, T::from_str)
+}
+
+fn parsed_list<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(
+    d: D,
+) -> Result<Vec<T>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .iter()
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+/// Accepts a single value as well as a list of them.
+fn parsed_one_or_list<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(
+    d: D,
+) -> Result<Vec<T>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrList {
+        One(String),
+        List(Vec<String>),
+    }
+    let values = match OneOrList::deserialize(d)? {
+        OneOrList::One(value) => vec![value],
+        OneOrList::List(values) => values,
+    };
+    values
+        .iter()
+        .map(|s| s.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+/// Accepts a single value as well as a list of them, for values that aren't strings.
+fn one_or_list<'de, D: Deserializer<'de>, T: Deserialize<'de>>(d: D) -> Result<Vec<T>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrList<T> {
+        One(T),
+        List(Vec<T>),
+    }
+    Ok(match OneOrList::deserialize(d)? {
+        OneOrList::One(value) => vec![value],
+        OneOrList::List(values) => values,
+    })
+}
+
+fn labels<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Label>, D::Error> {
+    toml::Table::deserialize(d)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = value
+                .as_str()
+                .ok_or_else(|| D::Error::custom(format!("label '{key}' is not a string")))?;
+            format!("{key}={value}").parse().map_err(D::Error::custom)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_values_as_flags_do() {
+        let file = ConfigFile::parse(
+            r#"
+            process-glob = "kworker/*"
+            runtime-threshold = "1m 30s"
+            scan-budget = "200ms"
+            canary-percent = 25
+            min-free-percent = 5
+            sync-path = "/data"
+            jitter = 0.1
+            oom-score-adj = -900
+            verbose = true
+            sync-mode = "fs"
+            pattern-action = ["stuckd=signal:SIGKILL"]
+
+            [label]
+            cluster = "prod"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.process_glob, vec![ProcessGlob::new("kworker/*")]);
+        assert_eq!(
+            file.runtime_threshold,
+            Some(Some(chrono::Duration::seconds(90)))
+        );
+        assert_eq!(
+            file.scan_budget,
+            Some(std::time::Duration::from_millis(200))
+        );
+        assert_eq!(file.canary_percent, Some(25));
+        assert_eq!(file.min_free_percent, Some(5));
+        assert_eq!(file.sync_path, Some(PathBuf::from("/data")));
+        assert_eq!(file.jitter, Some(0.1));
+        assert_eq!(file.oom_score_adj, Some("-900".parse().unwrap()));
+        assert!(file.verbose);
+        assert!(!file.debug);
+        assert_eq!(file.sync_mode, Some(SyncMode::Filesystem));
+        assert_eq!(
+            file.pattern_action,
+            vec!["stuckd=signal:SIGKILL".parse().unwrap()]
+        );
+        assert_eq!(file.label, vec!["cluster=prod".parse().unwrap()]);
+        assert_eq!(file.unknown_keys().count(), 0);
+    }
+
+    #[test]
+    fn test_process_glob_takes_one_or_a_list() {
+        let file = ConfigFile::parse("process-glob = [\"kworker/*\", \"jbd2/*=2m\"]").unwrap();
+        assert_eq!(
+            file.process_glob,
+            vec![ProcessGlob::new("kworker/*"), "jbd2/*=2m".parse().unwrap()]
+        );
+        assert!(ConfigFile::parse("process-glob = 3").is_err());
+    }
+
+    #[test]
+    fn test_uid_takes_one_or_a_list() {
+        assert_eq!(ConfigFile::parse("uid = 1000").unwrap().uid, vec![1000]);
+        assert_eq!(
+            ConfigFile::parse("uid = [0, 100000]").unwrap().uid,
+            vec![0, 100_000]
+        );
+        assert!(ConfigFile::parse("uid = \"root\"").is_err());
+    }
+
+    #[test]
+    fn test_unknown_keys_are_kept_aside() {
+        let file = ConfigFile::parse("verbose = true\nprocess-gob = \"kworker/*\"\n").unwrap();
+        assert!(file.verbose);
+        assert!(file.process_glob.is_empty());
+        assert_eq!(file.unknown_keys().collect::<Vec<_>>(), vec!["process-gob"]);
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        for invalid in [
+            "runtime-threshold = \"soon\"",
+            "runtime-threshold = 30",
+            "canary-percent = 101",
+            "pattern-action = [\"stuckd\"]",
+            "label = { \"1st\" = \"a\" }",
+            "verbose = \"yes\"",
+        ] {
+            assert!(ConfigFile::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_missing_file_is_an_error() {
+        let error = ConfigFile::load(Path::new("/nonexistent/stuck_wbs.toml")).unwrap_err();
+        assert!(format!("{error:#}").contains("/nonexistent/stuck_wbs.toml"));
+    }
+}
@@ -0,0 +1,135 @@
+//! What the daemon remembers across iterations to decide what to do next, as opposed to the
+//! `Metrics` it reports.
+use crate::event_log::{Event, EventLog};
+use crate::incident::Incident;
+use crate::state_file::{StateFile, SyncRecord};
+use crate::tracker::{Key, Tracker};
+use anyhow::Result;
+use std::sync::{Mutex, MutexGuard};
+
+/// The state the decisions of `evaluate` depend on, shared with the teardown, which writes the
+/// ongoing incident.
+#[derive(Debug, Default)]
+pub struct DaemonState {
+    /// The matching processes of the last scan, to tell how long each has been matching.
+    tracker: Mutex<Tracker>,
+    /// When the last sync was issued, for `--sync-cooldown`.
+    last_sync: Mutex<Option<chrono::DateTime<chrono::Utc>>>,
+    /// The process the last sync was issued for, how many syncs in a row before it were issued
+    /// for it too, and whether that run of syncs was escalated.
+    last_synced: Mutex<Option<(Key, usize, bool)>>,
+    /// Where syncs are recorded to outlive the daemon, with `--state-file`.
+    state_file: Mutex<Option<StateFile>>,
+    /// The timeline of the current episode, until it is reported to `--incident-dir`.
+    incident: Mutex<Option<Incident>>,
+    /// Where every decision is appended, with `--event-log`.
+    event_log: Mutex<Option<EventLog>>,
+}
+
+/// Returns whether `a` and `b` are the same process. Start times are placed on the wall clock as
+/// of startup, so they may differ slightly between a key restored from the `--state-file` and one
+/// of the current scan, while a pid is never reused within a second.
+fn same_process(a: Key, b: Key) -> bool {
+    a.0 == b.0 && (a.1 - b.1).abs() < chrono::Duration::seconds(1)
+}
+
+impl DaemonState {
+    /// Returns the matching processes of the last scan.
+    pub fn tracker(&self) -> MutexGuard<'_, Tracker> {
+        self.tracker.lock().unwrap()
+    }
+
+    /// Returns the incident of the current episode, if one is being recorded.
+    pub fn incident(&self) -> MutexGuard<'_, Option<Incident>> {
+        self.incident.lock().unwrap()
+    }
+
+    /// Returns when the last sync was issued, if any was.
+    pub fn last_sync(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        *self.last_sync.lock().unwrap()
+    }
+
+    /// Records that a sync was issued at `at` for `kworker`, to the `--state-file` too if any.
+    pub fn record_sync(&self, at: chrono::DateTime<chrono::Utc>, kworker: Key) -> Result<()> {
+        *self.last_sync.lock().unwrap() = Some(at);
+        match self.state_file.lock().unwrap().as_mut() {
+            Some(state) => state.append(SyncRecord { at, kworker }),
+            None => Ok(()),
+        }
+    }
+
+    /// Records that a sync was issued for `kworker`, returning how many syncs in a row before it
+    /// were issued for it too, and so left it stuck.
+    pub fn record_synced_kworker(&self, kworker: Key) -> usize {
+        let mut last_synced = self.last_synced.lock().unwrap();
+        let (ineffective, escalated) = match *last_synced {
+            Some((key, ineffective, escalated)) if same_process(key, kworker) => {
+                (ineffective + 1, escalated)
+            }
+            _ => (0, false),
+        };
+        *last_synced = Some((kworker, ineffective, escalated));
+        ineffective
+    }
+
+    /// Marks the run of syncs issued for the same process as escalated, returning whether it
+    /// wasn't already.
+    pub fn first_escalation(&self) -> bool {
+        match self.last_synced.lock().unwrap().as_mut() {
+            Some((_, _, escalated)) => !std::mem::replace(escalated, true),
+            None => false,
+        }
+    }
+
+    /// Restores the last sync, and how many in a row were issued for the same process, from
+    /// `state`, to which syncs are recorded from then on.
+    pub fn restore_syncs(&self, state: StateFile) {
+        if let Some(last) = state.records().last() {
+            let in_a_row = state
+                .records()
+                .iter()
+                .rev()
+                .take_while(|r| same_process(r.kworker, last.kworker))
+                .count();
+            *self.last_sync.lock().unwrap() = Some(last.at);
+            *self.last_synced.lock().unwrap() = Some((last.kworker, in_a_row - 1, false));
+        }
+        *self.state_file.lock().unwrap() = Some(state);
+    }
+
+    /// Appends to `log` the events recorded from then on.
+    pub fn set_event_log(&self, log: EventLog) {
+        *self.event_log.lock().unwrap() = Some(log);
+    }
+
+    /// Appends `event`, which happened `at`, to the `--event-log`, if any.
+    pub fn record_event(&self, at: chrono::DateTime<chrono::Utc>, event: &Event) -> Result<()> {
+        match self.event_log.lock().unwrap().as_mut() {
+            Some(log) => log.append(at, event),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syncs_in_a_row_for_the_same_process() {
+        let at = chrono::Utc::now();
+        let state = DaemonState::default();
+        assert!(!state.first_escalation());
+        assert_eq!(state.record_synced_kworker((42, at)), 0);
+        // A start time read again after a restart may be off by a little.
+        let restarted = (42, at + chrono::Duration::milliseconds(300));
+        assert_eq!(state.record_synced_kworker(restarted), 1);
+        assert!(state.first_escalation());
+        assert!(!state.first_escalation());
+        assert_eq!(state.record_synced_kworker((42, at)), 2);
+
+        // Another process starts a new run, which may be escalated again.
+        assert_eq!(state.record_synced_kworker((43, at)), 0);
+        assert!(state.first_escalation());
+    }
+}
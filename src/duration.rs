@@ -0,0 +1,162 @@
+//! Parsing and formatting of user-facing durations, and conversions between `std` and `chrono`
+//! durations.
+use anyhow::{Context, Result};
+
+/// Converts a `std` duration into a `chrono` one.
+///
+/// This is the single place where durations cross into `chrono`; it errors instead of
+/// wrapping or panicking when the value is out of `chrono`'s range.
+pub fn to_chrono(d: std::time::Duration) -> Result<chrono::Duration> {
+    chrono::Duration::from_std(d).with_context(|| format!("duration {d:?} is out of range"))
+}
+
+/// Parses a human-readable duration such as "30s" or "1m", as used by the command line.
+pub fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
+    let d = parse_std_duration(s)?;
+    to_chrono(d).map_err(|e| format!("duration conversion error: {e:#}"))
+}
+
+/// Parses a threshold that may be disabled, as `None`, with "off", "never", "0" or any other zero
+/// duration.
+pub fn parse_threshold(s: &str) -> Result<Option<chrono::Duration>, String> {
+    match s {
+        "off" | "never" | "0" => Ok(None),
+        s => parse_duration(s).map(|d| Some(d).filter(|d| !d.is_zero())),
+    }
+}
+
+/// Formats a duration for users, e.g. "1m 30s", in the form accepted by `parse_duration`.
+///
+/// Durations are truncated to the millisecond, which is as precise as any displayed value needs.
+pub fn format_duration(d: std::time::Duration) -> String {
+    let d = std::time::Duration::from_millis(d.as_millis().try_into().unwrap_or(u64::MAX));
+    humantime::format_duration(d).to_string()
+}
+
+/// Like `format_duration`, for `chrono` durations, which may be negative when clocks misbehave.
+pub fn format_signed_duration(d: chrono::Duration) -> String {
+    match d.to_std() {
+        Ok(d) => format_duration(d),
+        Err(_) => format!("-{}", format_duration((-d).to_std().unwrap_or_default())),
+    }
+}
+
+/// Serializes a duration in the human-readable form accepted by `parse_duration`.
+pub fn serialize<S: serde::Serializer>(d: &chrono::Duration, s: S) -> Result<S::Ok, S::Error> {
+    if *d < chrono::Duration::zero() {
+        return Err(serde::ser::Error::custom("negative duration"));
+    }
+    s.collect_str(&format_signed_duration(*d))
+}
+
+/// Like `serialize`, for optional durations.
+pub fn serialize_opt<S: serde::Serializer>(
+    d: &Option<chrono::Duration>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => serialize(d, s),
+        None => s.serialize_none(),
+    }
+}
+
+/// Like `serialize`, for thresholds as parsed by `parse_threshold`, "off" if disabled.
+pub fn serialize_threshold<S: serde::Serializer>(
+    d: &Option<chrono::Duration>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match d {
+        Some(d) => serialize(d, s),
+        None => s.serialize_str("off"),
+    }
+}
+
+/// Like `serialize`, for `std` durations.
+pub fn serialize_std<S: serde::Serializer>(
+    d: &std::time::Duration,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    s.collect_str(&format_duration(*d))
+}
+
+/// Like `parse_duration`, for settings that are only ever compared to `std` instants.
+pub fn parse_std_duration(s: &str) -> Result<std::time::Duration, String> {
+    humantime::parse_duration(s).map_err(|e| format!("invalid duration: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_to_chrono_zero() {
+        assert_eq!(to_chrono(Duration::ZERO).unwrap(), chrono::Duration::zero());
+    }
+
+    #[test]
+    fn test_to_chrono_very_large() {
+        let max_ms = u64::try_from(chrono::Duration::MAX.num_milliseconds()).unwrap();
+        assert!(to_chrono(Duration::from_millis(max_ms)).is_ok());
+        let err = to_chrono(Duration::MAX).unwrap_err();
+        assert!(format!("{err:#}").contains("out of range"));
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(250)), "250ms");
+        assert_eq!(
+            format_duration(Duration::from_micros(1_500_999)),
+            "1s 500ms"
+        );
+        assert_eq!(format_duration(Duration::from_secs(30)), "30s");
+        assert_eq!(format_duration(Duration::from_secs(90)), "1m 30s");
+        assert_eq!(format_duration(Duration::from_secs(2 * 3600)), "2h");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 5)), "3h 5s");
+        assert_eq!(
+            format_duration(Duration::MAX),
+            format_duration(Duration::MAX)
+        );
+    }
+
+    #[test]
+    fn test_format_signed_duration() {
+        assert_eq!(
+            format_signed_duration(chrono::Duration::seconds(90)),
+            "1m 30s"
+        );
+        assert_eq!(
+            format_signed_duration(chrono::Duration::milliseconds(-1500)),
+            "-1s 500ms"
+        );
+    }
+
+    #[test]
+    fn test_formatted_durations_parse_back() {
+        for s in ["250ms", "1s 500ms", "1m 30s", "2h", "1day 1h"] {
+            let d = parse_duration(s).unwrap();
+            assert_eq!(format_signed_duration(d), s);
+        }
+    }
+
+    #[test]
+    fn test_parse_duration_rejects_negative() {
+        assert!(parse_duration("-5s").is_err());
+        assert_eq!(parse_duration("0s"), Ok(chrono::Duration::zero()));
+        assert_eq!(parse_duration("1m"), Ok(chrono::Duration::seconds(60)));
+    }
+
+    #[test]
+    fn test_parse_threshold_sentinels() {
+        for disabled in ["off", "never", "0", "0s"] {
+            assert_eq!(parse_threshold(disabled), Ok(None), "{disabled}");
+        }
+        assert_eq!(
+            parse_threshold("1m"),
+            Ok(Some(chrono::Duration::seconds(60)))
+        );
+        assert!(parse_threshold("Off").is_err());
+        assert!(parse_threshold("-5s").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Grouping of threshold crossings into stall episodes, so a worker cycling around the threshold
+//! is reported once rather than on every crossing.
+
+/// A threshold crossing, and the episode it belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crossing {
+    /// The episode's number, counting from 1 since the daemon started.
+    pub episode: u64,
+    /// The crossing's number within its episode, counting from 1.
+    pub crossing: u64,
+}
+
+impl Crossing {
+    /// Whether this crossing started a new episode.
+    pub fn is_new_episode(&self) -> bool {
+        self.crossing == 1
+    }
+}
+
+/// Tracks the current episode.
+#[derive(Debug, Default)]
+pub struct Episodes {
+    /// When the last crossing happened, and what it was.
+    last: Option<(chrono::DateTime<chrono::Utc>, Crossing)>,
+}
+
+impl Episodes {
+    /// Records a crossing at `now`, which continues the current episode if it is within `gap` of
+    /// the previous crossing. Without a `gap`, every crossing is its own episode.
+    pub fn record(
+        &mut self,
+        now: chrono::DateTime<chrono::Utc>,
+        gap: Option<chrono::Duration>,
+    ) -> Crossing {
+        let crossing = match (self.last, gap) {
+            (Some((last, previous)), Some(gap)) if now.signed_duration_since(last) <= gap => {
+                Crossing {
+                    episode: previous.episode,
+                    crossing: previous.crossing + 1,
+                }
+            }
+            (last, _) => Crossing {
+                episode: last.map_or(0, |(_, previous)| previous.episode) + 1,
+                crossing: 1,
+            },
+        };
+        self.last = Some((now, crossing));
+        crossing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the crossing's episode and number within it.
+    fn numbers(c: Crossing) -> (u64, u64) {
+        (c.episode, c.crossing)
+    }
+
+    #[test]
+    fn test_crossings_within_gap_are_one_episode() {
+        let now = chrono::Utc::now();
+        let gap = Some(chrono::Duration::minutes(2));
+        let mut episodes = Episodes::default();
+
+        let first = episodes.record(now, gap);
+        assert_eq!(numbers(first), (1, 1));
+        assert!(first.is_new_episode());
+        // The gap is measured from the previous crossing, not the episode's start.
+        let second = episodes.record(now + chrono::Duration::seconds(90), gap);
+        let third = episodes.record(now + chrono::Duration::seconds(180), gap);
+        assert_eq!(numbers(second), (1, 2));
+        assert_eq!(numbers(third), (1, 3));
+        assert!(!third.is_new_episode());
+    }
+
+    #[test]
+    fn test_crossings_beyond_gap_are_separate_episodes() {
+        let now = chrono::Utc::now();
+        let gap = Some(chrono::Duration::minutes(2));
+        let mut episodes = Episodes::default();
+
+        episodes.record(now, gap);
+        let later = episodes.record(now + chrono::Duration::minutes(5), gap);
+        assert_eq!(numbers(later), (2, 1));
+
+        // Without a gap, even back-to-back crossings are distinct.
+        let immediate = episodes.record(now + chrono::Duration::minutes(5), None);
+        assert_eq!(numbers(immediate), (3, 1));
+    }
+}
@@ -0,0 +1,124 @@
+//! `--event-log`, appending every decision the daemon makes to a file, one JSON object per line,
+//! for going through what happened after an incident, where metrics only keep aggregates.
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+type Time = chrono::DateTime<chrono::Utc>;
+
+/// What happened, as the `event` field of a line along with the fields of each.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A stuck process triggered a remediation, or would have on a dry run.
+    Trigger {
+        pid: i32,
+        comm: String,
+        /// The runtime compared to the threshold, as logged.
+        runtime_s: f64,
+        threshold_s: f64,
+        /// The remediation, e.g. "sync" or "signal:SIGKILL".
+        action: String,
+        /// Whether this is a synthetic event from `--emit-test-event`, for which nothing is done.
+        test: bool,
+    },
+    /// Process events announced a matching process while waiting for one.
+    KworkerAppeared {
+        pid: i32,
+        comm: String,
+        /// How long it has been running.
+        runtime_s: f64,
+    },
+    /// A wait for a matching process ended without one appearing, so every process is scanned.
+    WaitTimeout,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    /// In RFC 3339 format.
+    ts: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+/// An event log open for appending.
+#[derive(Debug)]
+pub struct EventLog {
+    path: PathBuf,
+    /// Opened for appending, so that the daemon can still write to it once privileges are
+    /// dropped, and that lines from restarts follow each other.
+    file: File,
+}
+
+impl EventLog {
+    /// Opens the file at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o644)
+            .open(path)
+            .with_context(|| format!("failed to open the event log {}", path.display()))?;
+        Ok(EventLog {
+            path: path.to_path_buf(),
+            file,
+        })
+    }
+
+    /// Appends `event`, which happened `at`.
+    pub fn append(&mut self, at: Time, event: &Event) -> Result<()> {
+        let line = Line {
+            ts: at.to_rfc3339(),
+            event,
+        };
+        // Events only hold strings, numbers and booleans, which always serialize.
+        let mut line = serde_json::to_string(&line).expect("failed to serialize event");
+        line.push('\n');
+        // In a single unbuffered write, so that each line is in the file once appended, even if
+        // the daemon crashes right after, and is never interleaved with another.
+        self.file
+            .write_all(line.as_bytes())
+            .with_context(|| format!("failed to write the event log {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_appended_as_json_lines() {
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_appended.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let at = chrono::DateTime::parse_from_rfc3339("2026-10-14T12:00:00Z")
+            .unwrap()
+            .into();
+
+        let mut log = EventLog::open(&path).unwrap();
+        log.append(at, &Event::WaitTimeout).unwrap();
+        drop(log);
+        // Reopened, as after a restart, without losing the earlier lines.
+        let mut log = EventLog::open(&path).unwrap();
+        log.append(
+            at,
+            &Event::KworkerAppeared {
+                pid: 1000,
+                comm: "kworker/u8:2+inode_switch_wbs".to_string(),
+                runtime_s: 1.5,
+            },
+        )
+        .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            contents,
+            "{\"ts\":\"2026-10-14T12:00:00+00:00\",\"event\":\"wait_timeout\"}\n\
+             {\"ts\":\"2026-10-14T12:00:00+00:00\",\"event\":\"kworker_appeared\",\"pid\":1000,\
+             \"comm\":\"kworker/u8:2+inode_switch_wbs\",\"runtime_s\":1.5}\n"
+        );
+    }
+}
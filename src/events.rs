@@ -0,0 +1,520 @@
+//! Waiting for matching processes on process events, with the event source behind a trait so the
+//! logic can be driven by scripted events in tests.
+use crate::duration::format_duration;
+use crate::keepalive::KeepAlive;
+use crate::system::{IsKworkerFn, ProcInfo};
+use anyhow::{anyhow, Context, Result};
+use cnproc::{PidEvent, PidMonitor};
+use log::{debug, warn};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How many times a wait reconnects to the event source after it failed, before giving up.
+const MAX_RECONNECTS: usize = 3;
+
+/// How many of the kernel connector's events are buffered up to. Past it, the kernel drops them,
+/// which forces a rescan.
+const EVENT_BACKLOG: usize = 4096;
+
+/// A source of process events, such as the kernel connector.
+pub trait EventSource {
+    /// Blocks until the next process event, for at most `timeout`, returning `None` past it.
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<PidEvent>>;
+}
+
+/// Process events from the kernel connector.
+///
+/// `PidMonitor` can only block until the next event, for as long as it takes, so events are
+/// received on a thread of their own and waited for with a timeout. That thread lives as long as
+/// the connection, across waits.
+pub struct ConnectorEvents(Receiver<Result<PidEvent>>);
+
+impl ConnectorEvents {
+    /// Connects to the kernel connector, failing as `PidMonitor::new` does.
+    pub fn connect() -> std::io::Result<Self> {
+        let mut monitor = PidMonitor::new()?;
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BACKLOG);
+        std::thread::Builder::new()
+            .name("process-events".to_string())
+            .spawn(move || loop {
+                let event = PidMonitor::recv(&mut monitor)
+                    .context("failed to receive process event from kernel");
+                // Past other failures, the connection is replaced by a new one.
+                let failed = event.as_ref().is_err_and(|e| !dropped_events(e));
+                if sender.send(event).is_err() || failed {
+                    break;
+                }
+            })?;
+        Ok(ConnectorEvents(receiver))
+    }
+
+    /// Discards the events received since the last wait, returning how many there were.
+    ///
+    /// They came before the scan that precedes each wait, which found what they announced, so
+    /// replaying them would only look up processes that are likely gone.
+    pub fn discard_pending(&mut self) -> usize {
+        self.0.try_iter().count()
+    }
+}
+
+impl EventSource for ConnectorEvents {
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<PidEvent>> {
+        match self.0.recv_timeout(timeout) {
+            Ok(event) => event.map(Some),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(anyhow!("process events stopped")),
+        }
+    }
+}
+
+/// Returns whether `e` reports the kernel dropped events, as the socket buffer was full.
+fn dropped_events(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<std::io::Error>()
+        .and_then(std::io::Error::raw_os_error)
+        == Some(libc::ENOBUFS)
+}
+
+/// Consumes `events` until one announces a process that `is_kworker` matches, returning it, or
+/// `timeout` elapsed. Processes announced exiting meanwhile are passed to `exited`, and
+/// `keepalive` pings while no event comes.
+///
+/// `lookup` reads the process an event is about, returning `None` if it is already gone. If
+/// `events` fails, it is replaced by a `reconnect`ed one, up to `MAX_RECONNECTS` times. If events
+/// were dropped, the wait ends right away for a scan to find what they announced.
+pub fn wait_for_kworker<E: EventSource, F: IsKworkerFn>(
+    events: &mut E,
+    mut reconnect: impl FnMut() -> Result<E>,
+    lookup: impl Fn(i32) -> Option<ProcInfo>,
+    is_kworker: F,
+    mut exited: impl FnMut(i32),
+    timeout: Duration,
+    keepalive: &KeepAlive,
+) -> Result<Option<ProcInfo>> {
+    let start = std::time::Instant::now();
+    let mut reconnects = 0;
+    loop {
+        // On a busy system, the kernel may drop netlink events. To safeguard against this,
+        // we'll periodically re-scan the full process list.
+        let left = timeout.saturating_sub(start.elapsed());
+        let received = if left.is_zero() {
+            None
+        } else {
+            keepalive.wait(left, |step| events.recv_timeout(step).transpose())
+        };
+        let Some(received) = received else {
+            debug!(
+                "wait_for_kworker timed out after {}, forcing a full process scan",
+                format_duration(timeout)
+            );
+            return Ok(None);
+        };
+
+        let event = match received {
+            Ok(event) => event,
+            Err(e) if dropped_events(&e) => {
+                warn!("Process events were dropped, scanning right away: {e:#}");
+                return Ok(None);
+            }
+            Err(e) if reconnects < MAX_RECONNECTS => {
+                reconnects += 1;
+                warn!("Reconnecting to process events ({reconnects}/{MAX_RECONNECTS}): {e:#}");
+                *events = reconnect().context("failed to reconnect to process events")?;
+                continue;
+            }
+            Err(e) => {
+                return Err(e.context(format!(
+                    "process events kept failing after {MAX_RECONNECTS} reconnects"
+                )))
+            }
+        };
+        let pid = match event {
+            PidEvent::Exec { process_pid, .. } => process_pid,
+            PidEvent::Fork { child_pid, .. } => child_pid,
+            // Threads other than the leader exiting leave the process, as /proc lists it, running.
+            PidEvent::Exit {
+                process_pid,
+                process_tgid,
+                ..
+            } => {
+                if process_pid == process_tgid {
+                    exited(process_pid);
+                }
+                continue;
+            }
+            // Always followed by the exit of the process that dumped core.
+            PidEvent::Coredump { .. } => continue,
+        };
+
+        if let Some(info) = lookup(pid) {
+            if is_kworker(&info) {
+                debug!(
+                    "Detected matching kworker (pid {}, comm: '{}'), returning",
+                    pid, info.comm
+                );
+                return Ok(Some(info));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::Tracker;
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    /// Replays a scripted sequence of events, then fails as a broken connector would.
+    struct ScriptedEvents(VecDeque<PidEvent>);
+
+    impl EventSource for ScriptedEvents {
+        fn recv_timeout(&mut self, _timeout: Duration) -> Result<Option<PidEvent>> {
+            self.0
+                .pop_front()
+                .map(Some)
+                .ok_or_else(|| anyhow::anyhow!("no more events"))
+        }
+    }
+
+    /// Pretends pid 1000 is a kworker, 1001 a shell, and any other pid already exited.
+    fn lookup(pid: i32) -> Option<ProcInfo> {
+        let comm = match pid {
+            1000 => "kworker/0:1",
+            1001 => "bash",
+            _ => return None,
+        };
+        Some(ProcInfo {
+            pid,
+            uid: 0,
+            starttime: chrono::Utc::now(),
+            cpu_time: Duration::ZERO,
+            comm: comm.to_string(),
+            cmdline: None,
+            kernel_thread: pid == 1000,
+            state: 'S',
+            wchan: None,
+        })
+    }
+
+    fn no_reconnect() -> Result<ScriptedEvents> {
+        anyhow::bail!("cannot reconnect")
+    }
+
+    fn is_kworker(p: &ProcInfo) -> bool {
+        p.comm.starts_with("kworker/")
+    }
+
+    fn exec(pid: i32) -> PidEvent {
+        PidEvent::Exec {
+            process_pid: pid,
+            process_tgid: pid,
+        }
+    }
+
+    #[test]
+    fn test_returns_on_matching_fork() {
+        let mut events = ScriptedEvents(VecDeque::from([
+            PidEvent::Exit {
+                process_pid: 1000,
+                process_tgid: 1000,
+                exit_code: 0,
+            },
+            exec(1001),
+            exec(4242),
+            PidEvent::Fork {
+                parent_pid: 2,
+                parent_tgid: 2,
+                child_pid: 1000,
+                child_tgid: 1000,
+            },
+            exec(1001),
+        ]));
+
+        let found = wait_for_kworker(
+            &mut events,
+            no_reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert_eq!(found.map(|p| p.pid), Some(1000));
+        // Stopped right after the fork.
+        assert_eq!(events.0.len(), 1);
+    }
+
+    #[test]
+    fn test_returns_on_matching_exec() {
+        let mut events = ScriptedEvents(VecDeque::from([exec(1000)]));
+        let found = wait_for_kworker(
+            &mut events,
+            no_reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert_eq!(found.map(|p| p.comm), Some("kworker/0:1".to_string()));
+    }
+
+    #[test]
+    fn test_exits_evict_tracked_processes() {
+        let now = chrono::Utc::now();
+        let interval = Duration::from_secs(60);
+        let tracked = |pid| ProcInfo {
+            pid,
+            ..lookup(1000).unwrap()
+        };
+        let mut tracker = Tracker::default();
+        tracker.observe(&[tracked(2000), tracked(3000), tracked(4000)], now);
+        let mut events = ScriptedEvents(VecDeque::from([
+            PidEvent::Exit {
+                process_pid: 2000,
+                process_tgid: 2000,
+                exit_code: 0,
+            },
+            // A thread of 3000 exiting, the process itself still running.
+            PidEvent::Exit {
+                process_pid: 3001,
+                process_tgid: 3000,
+                exit_code: 0,
+            },
+            PidEvent::Fork {
+                parent_pid: 1,
+                parent_tgid: 1,
+                child_pid: 1001,
+                child_tgid: 1001,
+            },
+            PidEvent::Coredump {
+                process_pid: 4000,
+                process_tgid: 4000,
+            },
+            PidEvent::Exit {
+                process_pid: 4000,
+                process_tgid: 4000,
+                exit_code: 139,
+            },
+            exec(1000),
+        ]));
+
+        let found = wait_for_kworker(
+            &mut events,
+            no_reconnect,
+            lookup,
+            is_kworker,
+            |pid| tracker.forget(pid),
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert_eq!(found.map(|p| p.pid), Some(1000));
+        assert_eq!(tracker.candidates(now, interval), Some(vec![3000]));
+    }
+
+    #[test]
+    fn test_ignores_exits_and_unrelated_processes() {
+        let mut events = ScriptedEvents(VecDeque::from([
+            PidEvent::Exit {
+                process_pid: 1000,
+                process_tgid: 1000,
+                exit_code: 0,
+            },
+            exec(1001),
+            exec(4242),
+        ]));
+        // Nothing matched, so the wait went on until the source failed.
+        assert!(wait_for_kworker(
+            &mut events,
+            no_reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_reconnects_after_failures() {
+        let mut events = ScriptedEvents(VecDeque::new());
+        let mut sources = VecDeque::from([
+            ScriptedEvents(VecDeque::new()),
+            ScriptedEvents(VecDeque::from([exec(1001), exec(1000)])),
+        ]);
+        let reconnect = || Ok(sources.pop_front().unwrap());
+        wait_for_kworker(
+            &mut events,
+            reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(sources.is_empty());
+
+        // Reconnecting only ever yields sources that fail right away.
+        let mut reconnects = 0;
+        let reconnect = || {
+            reconnects += 1;
+            Ok(ScriptedEvents(VecDeque::new()))
+        };
+        let error = wait_for_kworker(
+            &mut events,
+            reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::MAX,
+            &KeepAlive::default(),
+        )
+        .unwrap_err();
+        assert_eq!(reconnects, MAX_RECONNECTS);
+        assert!(format!("{error:#}").contains("no more events"));
+    }
+
+    #[test]
+    fn test_dropped_events_force_rescan() {
+        struct Overrun;
+        impl EventSource for Overrun {
+            fn recv_timeout(&mut self, _timeout: Duration) -> Result<Option<PidEvent>> {
+                Err(std::io::Error::from_raw_os_error(libc::ENOBUFS))
+                    .context("failed to receive process event from kernel")
+            }
+        }
+        let reconnect = || -> Result<Overrun> { panic!("reconnected after dropped events") };
+        let found = wait_for_kworker(
+            &mut Overrun,
+            reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::MAX,
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(found.is_none());
+    }
+
+    #[test]
+    fn test_timeout_forces_rescan() {
+        let mut events = ScriptedEvents(VecDeque::from([exec(1001)]));
+        let found = wait_for_kworker(
+            &mut events,
+            no_reconnect,
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::ZERO,
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(found.is_none());
+        // The timeout is checked before waiting for any event.
+        assert_eq!(events.0.len(), 1);
+    }
+
+    #[test]
+    fn test_events_queued_before_a_wait_are_discarded() {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_BACKLOG);
+        let mut events = ConnectorEvents(receiver);
+        sender.send(Ok(exec(1000))).unwrap();
+        sender
+            .send(Err(std::io::Error::from_raw_os_error(libc::ENOBUFS).into()))
+            .unwrap();
+        sender.send(Ok(exec(1001))).unwrap();
+        assert_eq!(events.discard_pending(), 3);
+
+        // Neither the kworker nor the dropped events they announced end the wait early.
+        let started = std::time::Instant::now();
+        let found = wait_for_kworker(
+            &mut events,
+            || -> Result<ConnectorEvents> { panic!("reconnected without failures") },
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_millis(20),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(found.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+
+        // Those received during the wait are not.
+        sender.send(Ok(exec(1000))).unwrap();
+        let found = wait_for_kworker(
+            &mut events,
+            || -> Result<ConnectorEvents> { panic!("reconnected without failures") },
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_secs(60),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert_eq!(found.map(|p| p.pid), Some(1000));
+        assert_eq!(events.discard_pending(), 0);
+    }
+
+    #[test]
+    fn test_quiet_sources_time_out() {
+        /// Never announces anything, waiting as long as it is asked to.
+        struct Quiet(Vec<Duration>);
+        impl EventSource for Quiet {
+            fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<PidEvent>> {
+                self.0.push(timeout);
+                std::thread::sleep(timeout);
+                Ok(None)
+            }
+        }
+        let mut events = Quiet(Vec::new());
+        let started = std::time::Instant::now();
+        let found = wait_for_kworker(
+            &mut events,
+            || -> Result<Quiet> { panic!("reconnected without failures") },
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_millis(20),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(found.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+        // Without a watchdog to ping, about the whole timeout is waited for at once.
+        assert!(events.0[0] > Duration::from_millis(19), "{:?}", events.0);
+    }
+
+    #[test]
+    fn test_timeout_elapsing_between_events_forces_rescan() {
+        /// Announces an unrelated process every 10ms, forever.
+        struct Busy(usize);
+        impl EventSource for Busy {
+            fn recv_timeout(&mut self, _timeout: Duration) -> Result<Option<PidEvent>> {
+                std::thread::sleep(Duration::from_millis(10));
+                self.0 += 1;
+                Ok(Some(exec(1001)))
+            }
+        }
+        let mut events = Busy(0);
+        let found = wait_for_kworker(
+            &mut events,
+            || -> Result<Busy> { panic!("reconnected without failures") },
+            lookup,
+            is_kworker,
+            |_| {},
+            Duration::from_millis(50),
+            &KeepAlive::default(),
+        )
+        .unwrap();
+        assert!(found.is_none());
+        // Ended by the first event received past the timeout, as the source never fails.
+        assert!((1..=5).contains(&events.0), "{}", events.0);
+    }
+}
@@ -0,0 +1,78 @@
+//! `--min-free-percent`, degrading to detect-only when the filesystem a `sync` would flush is
+//! nearly full or was remounted read-only after errors, as syncing it then can't complete the
+//! writeback and only adds I/O to a disk already in trouble.
+
+/// The state of a mounted filesystem, as `statvfs()` reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStatus {
+    /// The bytes available to unprivileged users.
+    pub available: u64,
+    /// The size of the filesystem, in bytes.
+    pub total: u64,
+    /// Whether it is mounted read-only, which filesystems such as ext4 fall back to on errors.
+    pub read_only: bool,
+}
+
+impl FsStatus {
+    /// Reads the status of the filesystem `path` is on.
+    pub fn of(path: &std::path::Path) -> rustix::io::Result<Self> {
+        let stat = rustix::fs::statvfs(path)?;
+        Ok(FsStatus {
+            available: stat.f_bavail.saturating_mul(stat.f_frsize),
+            total: stat.f_blocks.saturating_mul(stat.f_frsize),
+            read_only: stat.f_flag.contains(rustix::fs::StatVfsMountFlags::RDONLY),
+        })
+    }
+
+    /// Returns why syncing this filesystem is pointless, if it is read-only or has less than
+    /// `min_free_percent` of its space available.
+    pub fn unsyncable(&self, min_free_percent: u8) -> Option<String> {
+        if self.read_only {
+            return Some("it is mounted read-only".to_string());
+        }
+        // Pseudo filesystems report no blocks at all, and are never full.
+        if self.total == 0 {
+            return None;
+        }
+        let free_percent = self.available as f64 * 100.0 / self.total as f64;
+        (free_percent < f64::from(min_free_percent))
+            .then(|| format!("only {free_percent:.1}% of it is free"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_or_read_only_filesystems_are_unsyncable() {
+        let status = |available, read_only| FsStatus {
+            available,
+            total: 1000,
+            read_only,
+        };
+        assert_eq!(status(500, false).unsyncable(5), None);
+        assert_eq!(status(50, false).unsyncable(5), None);
+        assert_eq!(
+            status(49, false).unsyncable(5).as_deref(),
+            Some("only 4.9% of it is free")
+        );
+        assert_eq!(
+            status(500, true).unsyncable(5).as_deref(),
+            Some("it is mounted read-only")
+        );
+        let pseudo = FsStatus {
+            available: 0,
+            total: 0,
+            read_only: false,
+        };
+        assert_eq!(pseudo.unsyncable(5), None);
+    }
+
+    #[test]
+    fn test_status_of_the_temporary_directory() {
+        let status = FsStatus::of(&std::env::temp_dir()).unwrap();
+        assert!(status.available <= status.total);
+        assert!(!status.read_only);
+    }
+}
@@ -0,0 +1,140 @@
+//! The distribution of the runtimes of the oldest matching kworker, as observed by every scan
+//! finding one, to tell whether stalls cluster just under the threshold or are extreme outliers.
+use std::fmt::Write as _;
+
+/// Upper bounds of the buckets, in seconds, around the default 30s threshold and up to an hour.
+pub const BUCKETS: [f64; 12] = [
+    1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// The percentiles logged by `--print-stats-on-exit` and served by `--status-socket`, in
+/// seconds.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Percentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl std::fmt::Display for Percentiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "p50 {:.1}s, p90 {:.1}s, p99 {:.1}s",
+            self.p50, self.p90, self.p99
+        )
+    }
+}
+
+/// Counts of observations per bucket, as a Prometheus histogram.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations at most each of `BUCKETS`, but not the previous one, then above them all.
+    counts: [u64; BUCKETS.len() + 1],
+    /// The sum of all observations, in seconds.
+    sum: f64,
+}
+
+impl Histogram {
+    /// Records an observation of `seconds`.
+    pub fn observe(&mut self, seconds: f64) {
+        let bucket = BUCKETS.partition_point(|bound| *bound < seconds);
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    /// Returns how many observations were recorded.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Estimates the `percentile`th percentile, in seconds, with the same linear interpolation
+    /// within buckets as Prometheus' `histogram_quantile`. Observations above the last bucket are
+    /// estimated at its bound, which is then a lower bound.
+    pub fn percentile(&self, percentile: u8) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = count as f64 * f64::from(percentile) / 100.0;
+        let mut below = 0;
+        for (i, &in_bucket) in self.counts.iter().enumerate() {
+            if in_bucket > 0 && (below + in_bucket) as f64 >= rank {
+                let Some(&upper) = BUCKETS.get(i) else {
+                    break;
+                };
+                let lower = if i == 0 { 0.0 } else { BUCKETS[i - 1] };
+                let within = (rank - below as f64) / in_bucket as f64;
+                return Some(lower + (upper - lower) * within);
+            }
+            below += in_bucket;
+        }
+        Some(BUCKETS[BUCKETS.len() - 1])
+    }
+
+    /// Returns the usual percentiles, if anything was observed.
+    pub fn percentiles(&self) -> Option<Percentiles> {
+        Some(Percentiles {
+            p50: self.percentile(50)?,
+            p90: self.percentile(90)?,
+            p99: self.percentile(99)?,
+        })
+    }
+
+    /// Renders the histogram as `name`, in the Prometheus text exposition format.
+    pub fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+        let count = self.count();
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {:.3}\n{name}_count {count}", self.sum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_of_observed_runtimes() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50), None);
+        assert_eq!(histogram.percentiles(), None);
+
+        // Mostly short stalls, some just past the 30s threshold, and one outlier.
+        for seconds in [0.5, 0.8, 3.0, 4.0, 6.0, 8.0, 25.0, 31.0, 35.0, 4000.0] {
+            histogram.observe(seconds);
+        }
+        assert_eq!(histogram.count(), 10);
+        // The 5th observation is the first of the 2 in (5, 10].
+        assert_eq!(histogram.percentile(50), Some(7.5));
+        // The 9th is the second of the 2 in (30, 60].
+        assert_eq!(histogram.percentile(90), Some(60.0));
+        // Above every bucket, so only known to be past the last one.
+        assert_eq!(histogram.percentile(99), Some(3600.0));
+        assert_eq!(
+            histogram.percentiles().unwrap().to_string(),
+            "p50 7.5s, p90 60.0s, p99 3600.0s"
+        );
+
+        let mut rendered = String::new();
+        histogram.render(&mut rendered, "runtime_seconds", "Runtimes.");
+        assert!(rendered.starts_with(
+            "# HELP runtime_seconds Runtimes.\n\
+             # TYPE runtime_seconds histogram\n\
+             runtime_seconds_bucket{le=\"1\"} 2\n\
+             runtime_seconds_bucket{le=\"2\"} 2\n\
+             runtime_seconds_bucket{le=\"5\"} 4\n"
+        ));
+        assert!(rendered.ends_with(
+            "runtime_seconds_bucket{le=\"3600\"} 9\n\
+             runtime_seconds_bucket{le=\"+Inf\"} 10\n\
+             runtime_seconds_sum 4113.300\n\
+             runtime_seconds_count 10\n"
+        ));
+    }
+}
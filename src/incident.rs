@@ -0,0 +1,207 @@
+//! Human-readable reports of stall episodes for `--incident-dir`, ready to share without digging
+//! through logs.
+use crate::clock::local;
+use crate::duration::format_signed_duration;
+use crate::system::ProcInfo;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+/// How an incident ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// A scan found no process past its threshold anymore.
+    Resolved,
+    /// Another episode started before it was resolved, carrying on in its own report.
+    Superseded,
+    /// The daemon exited before it was resolved.
+    Unresolved,
+}
+
+impl Resolution {
+    fn as_str(self) -> &'static str {
+        match self {
+            Resolution::Resolved => "resolved",
+            Resolution::Superseded => "superseded by the next episode, unresolved",
+            Resolution::Unresolved => "unresolved when the daemon exited",
+        }
+    }
+}
+
+/// The timeline of one stall episode, from the trigger that started it.
+#[derive(Debug, Clone)]
+pub struct Incident {
+    episode: u64,
+    host: String,
+    comm: String,
+    pid: i32,
+    detected: chrono::DateTime<chrono::Utc>,
+    /// The kernel stack of the stuck process when detected, if it could be read.
+    stack: Option<String>,
+    /// Number of remediations taken.
+    actions: u64,
+    timeline: Vec<(chrono::DateTime<chrono::Utc>, String)>,
+}
+
+impl Incident {
+    /// Starts the incident of `episode`, detected at `detected` on `host` with `kworker` stuck.
+    pub fn new(
+        episode: u64,
+        host: String,
+        kworker: &ProcInfo,
+        detected: chrono::DateTime<chrono::Utc>,
+        stack: Option<String>,
+    ) -> Self {
+        Incident {
+            episode,
+            host,
+            comm: kworker.comm.clone(),
+            pid: kworker.pid,
+            detected,
+            stack,
+            actions: 0,
+            timeline: Vec::new(),
+        }
+    }
+
+    /// Adds `event` to the timeline.
+    pub fn record(&mut self, at: chrono::DateTime<chrono::Utc>, event: String) {
+        self.timeline.push((at, event));
+    }
+
+    /// Adds a remediation, described by `event`, to the timeline.
+    pub fn record_action(&mut self, at: chrono::DateTime<chrono::Utc>, event: String) {
+        self.actions += 1;
+        self.record(at, event);
+    }
+
+    /// Renders the report of the incident, which ended at `ended` with `resolution`, as Markdown.
+    pub fn report(&self, ended: chrono::DateTime<chrono::Utc>, resolution: Resolution) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Stall episode #{} on {}\n", self.episode, self.host);
+        let _ = writeln!(out, "- Process: `{}` (pid {})", self.comm, self.pid);
+        let _ = writeln!(out, "- Detected: {}", local(self.detected).to_rfc3339());
+        let _ = writeln!(
+            out,
+            "- Ended: {}, after {}",
+            local(ended).to_rfc3339(),
+            format_signed_duration(ended.signed_duration_since(self.detected))
+        );
+        let _ = writeln!(out, "- Resolution: {}", resolution.as_str());
+        let _ = writeln!(out, "- Remediations: {}", self.actions);
+        let _ = writeln!(out, "\n## Timeline\n");
+        for (at, event) in &self.timeline {
+            let _ = writeln!(out, "- {} {event}", local(*at).format("%H:%M:%S"));
+        }
+        if let Some(stack) = &self.stack {
+            let _ = writeln!(out, "\n## Kernel stack\n\n```\n{}\n```", stack.trim_end());
+        }
+        out
+    }
+
+    /// Writes the report to a new file in `dir`, returning its path.
+    pub fn write(
+        &self,
+        dir: &Path,
+        ended: chrono::DateTime<chrono::Utc>,
+        resolution: Resolution,
+    ) -> Result<PathBuf> {
+        let name = format!(
+            "incident-{}-{}.md",
+            local(self.detected).format("%Y%m%dT%H%M%S"),
+            self.episode
+        );
+        let path = dir.join(name);
+        std::fs::write(&path, self.report(ended, resolution))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_report_given_timeline() {
+        // Reports show local times.
+        let detected = chrono::Local
+            .with_ymd_and_hms(2026, 10, 14, 10, 0, 0)
+            .unwrap()
+            .to_utc();
+        let at = |s| detected + chrono::Duration::seconds(s);
+        let kworker = ProcInfo {
+            pid: 1234,
+            uid: 0,
+            comm: "kworker/u8:2+inode_switch_wbs".to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'R',
+            wchan: None,
+            starttime: at(-40),
+            cpu_time: std::time::Duration::ZERO,
+        };
+        let stack = "[<0>] inode_switch_wbs_work_fn+0x2a/0x4a0\n[<0>] worker_thread+0xc2/0x3a0\n";
+        let mut incident = Incident::new(
+            3,
+            "node-1".to_string(),
+            &kworker,
+            detected,
+            Some(stack.to_string()),
+        );
+        incident.record(
+            at(0),
+            "Sync triggered: 40s old (threshold: 30s)".to_string(),
+        );
+        incident.record_action(at(0), "Ran sync".to_string());
+        incident.record(at(30), "Sync triggered again: 1m 10s old".to_string());
+        incident.record_action(at(30), "Ran sync".to_string());
+        incident.record(at(65), "No process past its threshold".to_string());
+
+        assert_eq!(
+            incident.report(at(65), Resolution::Resolved),
+            format!(
+                "# Stall episode #3 on node-1\n\n\
+                 - Process: `kworker/u8:2+inode_switch_wbs` (pid 1234)\n\
+                 - Detected: {}\n\
+                 - Ended: {}, after 1m 5s\n\
+                 - Resolution: resolved\n\
+                 - Remediations: 2\n\n\
+                 ## Timeline\n\n\
+                 - 10:00:00 Sync triggered: 40s old (threshold: 30s)\n\
+                 - 10:00:00 Ran sync\n\
+                 - 10:00:30 Sync triggered again: 1m 10s old\n\
+                 - 10:00:30 Ran sync\n\
+                 - 10:01:05 No process past its threshold\n\n\
+                 ## Kernel stack\n\n\
+                 ```\n\
+                 [<0>] inode_switch_wbs_work_fn+0x2a/0x4a0\n\
+                 [<0>] worker_thread+0xc2/0x3a0\n\
+                 ```\n",
+                local(detected).to_rfc3339(),
+                local(at(65)).to_rfc3339()
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_without_stack() {
+        let now = chrono::Utc::now();
+        let kworker = ProcInfo {
+            pid: 1234,
+            uid: 0,
+            comm: "kworker/0:1".to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'R',
+            wchan: None,
+            starttime: now,
+            cpu_time: std::time::Duration::ZERO,
+        };
+        let incident = Incident::new(1, "node-1".to_string(), &kworker, now, None);
+        let report = incident.report(now, Resolution::Unresolved);
+        assert!(report.contains("- Resolution: unresolved when the daemon exited\n"));
+        assert!(!report.contains("Kernel stack"));
+    }
+}
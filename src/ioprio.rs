@@ -0,0 +1,91 @@
+//! I/O scheduling priority of threads, see `ioprio_set(2)`.
+use anyhow::{anyhow, bail, Result};
+
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+/// An I/O scheduling class, as understood by e.g. the BFQ and mq-deadline schedulers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPrioClass {
+    /// Lowest best-effort priority level.
+    BestEffort,
+    /// Only gets disk time when no other program asked for it.
+    Idle,
+}
+
+impl IoPrioClass {
+    /// Returns the `ioprio` value as passed to `ioprio_set(2)`.
+    fn value(self) -> libc::c_int {
+        let (class, data) = match self {
+            IoPrioClass::BestEffort => (2, 7),
+            IoPrioClass::Idle => (3, 0),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | data
+    }
+}
+
+impl std::str::FromStr for IoPrioClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "best-effort" => Ok(IoPrioClass::BestEffort),
+            "idle" => Ok(IoPrioClass::Idle),
+            _ => Err(format!(
+                "invalid I/O priority class '{s}', expected 'best-effort' or 'idle'"
+            )),
+        }
+    }
+}
+
+/// Sets the I/O priority of the calling thread; `ioprio_set(2)` with a pid of 0 only affects it.
+fn set_current_thread(class: IoPrioClass) -> Result<()> {
+    // SAFETY: ioprio_set only takes integer arguments.
+    let ret = unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, class.value()) };
+    if ret < 0 {
+        bail!(
+            "ioprio_set({class:?}) failed: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+/// Runs `f` on a dedicated thread whose I/O priority is set to `class`, leaving the caller's
+/// own priority untouched.
+pub fn run_with_ioprio<R: Send>(class: IoPrioClass, f: impl FnOnce() -> R + Send) -> Result<R> {
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                set_current_thread(class)?;
+                Ok(f())
+            })
+            .join()
+            .map_err(|_| anyhow!("ioprio thread panicked"))?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_thread_ioprio() -> libc::c_long {
+        // SAFETY: ioprio_get only takes integer arguments.
+        unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, 0) }
+    }
+
+    #[test]
+    fn test_parse_ioprio_class() {
+        assert_eq!("idle".parse(), Ok(IoPrioClass::Idle));
+        assert_eq!("best-effort".parse(), Ok(IoPrioClass::BestEffort));
+        assert!("realtime".parse::<IoPrioClass>().is_err());
+    }
+
+    #[test]
+    fn test_run_with_ioprio_sets_priority_on_dedicated_thread_only() {
+        let before = current_thread_ioprio();
+        let on_thread = run_with_ioprio(IoPrioClass::Idle, current_thread_ioprio).unwrap();
+        assert_eq!(on_thread, libc::c_long::from(IoPrioClass::Idle.value()));
+        assert_eq!(current_thread_ioprio(), before);
+    }
+}
@@ -0,0 +1,101 @@
+//! `--jitter`, randomizing the sleeps of the main loop so that hosts started together don't keep
+//! scanning `/proc` in lockstep.
+use crate::duration::format_duration;
+use log::debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parses a fraction of each sleep to randomize it by, from 0 (inclusive) to 1 (exclusive).
+pub fn parse_fraction(value: &str) -> Result<f64, String> {
+    match value.parse() {
+        Ok(fraction) if (0.0..1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!(
+            "invalid jitter '{value}', expected a fraction from 0 to 1, e.g. 0.1 for ±10%"
+        )),
+    }
+}
+
+/// Randomizes durations, using SplitMix64 as nothing here needs more than spreading hosts apart.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    state: u64,
+}
+
+impl Jitter {
+    /// Returns a generator that always produces the same sequence from the same `seed`.
+    pub fn new(seed: u64) -> Self {
+        Jitter { state: seed }
+    }
+
+    /// Returns a generator seeded from the current time and pid, which differ between hosts
+    /// even when started by the same event.
+    pub fn seeded() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Jitter::new(nanos ^ u64::from(std::process::id()).rotate_left(32))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number uniformly distributed in [0, 1).
+    fn next_unit(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa of an f64 exactly.
+        (self.next_u64() >> 11) as f64 / (1_u64 << 53) as f64
+    }
+
+    /// Returns `duration` scaled by a random factor within ±`fraction` of 1.
+    pub fn apply(&mut self, duration: Duration, fraction: f64) -> Duration {
+        if duration.is_zero() || fraction == 0.0 {
+            return duration;
+        }
+        let factor = (1.0 + fraction * (2.0 * self.next_unit() - 1.0)).max(0.0);
+        let jittered = duration.mul_f64(factor);
+        debug!(
+            "Jittered a sleep of {} to {}",
+            format_duration(duration),
+            format_duration(jittered)
+        );
+        jittered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(parse_fraction("0"), Ok(0.0));
+        assert_eq!(parse_fraction("0.1"), Ok(0.1));
+        for invalid in ["1", "1.5", "-0.1", "NaN", "10%", ""] {
+            assert!(parse_fraction(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_jittered_sleeps_stay_within_the_band() {
+        let sleep = Duration::from_secs(60);
+        let mut jitter = Jitter::new(42);
+        let jittered: Vec<_> = (0..10_000).map(|_| jitter.apply(sleep, 0.1)).collect();
+        for d in &jittered {
+            assert!(
+                (Duration::from_secs(54)..=Duration::from_secs(66)).contains(d),
+                "{d:?}"
+            );
+        }
+        // Both halves of the band are used, not just one side of it.
+        assert!(jittered.iter().any(|d| *d < Duration::from_secs(57)));
+        assert!(jittered.iter().any(|d| *d > Duration::from_secs(63)));
+
+        let mut same_seed = Jitter::new(42);
+        assert_eq!(same_seed.apply(sleep, 0.1), jittered[0]);
+        assert_eq!(jitter.apply(Duration::ZERO, 0.1), Duration::ZERO);
+        assert_eq!(jitter.apply(sleep, 0.0), sleep);
+    }
+}
@@ -0,0 +1,296 @@
+//! `--log-target journald`, sending log records to the systemd journal with their priority and
+//! structured fields, for filtering with e.g. `journalctl KWORKER_COMM=...`.
+//!
+//! Implements the native journal protocol, which takes one datagram per record made of
+//! `FIELD=value` lines, sent to the socket systemd-journald listens on.
+use crate::labels::Labels;
+use anyhow::{Context, Result};
+use log::kv::{Error, Key, Value, VisitSource};
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+/// Where systemd-journald receives records in its native protocol.
+const JOURNAL_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+/// Where log records go.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogTarget {
+    /// Standard error, formatted as per `--log-format`.
+    #[default]
+    Stderr,
+    /// The systemd journal.
+    Journald,
+}
+
+impl std::fmt::Display for LogTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogTarget::Stderr => "stderr",
+            LogTarget::Journald => "journald",
+        })
+    }
+}
+
+impl std::str::FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            _ => Err(format!(
+                "invalid log target '{s}', expected 'stderr' or 'journald'"
+            )),
+        }
+    }
+}
+
+/// Returns the syslog priority journald files records of `level` under.
+fn priority(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// Returns `key` as a journal field name, which only has uppercase letters, digits and
+/// underscores, and starts with a letter.
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    // Leading underscores are reserved for fields journald adds itself.
+    let name = name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit());
+    if name.is_empty() {
+        "FIELD".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Appends the field `name` with `value` to `datagram`, in the binary form if the value spans
+/// several lines.
+fn push_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
+
+/// Collects the structured fields of a record as journal fields.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        self.0.push((field_name(key.as_str()), value.to_string()));
+        Ok(())
+    }
+}
+
+/// Encodes `record` as a journal datagram, with its message, priority, `identifier`, the
+/// `labels` as `LABEL_`-prefixed fields, then its structured fields.
+fn encode(record: &log::Record, identifier: &str, labels: &Labels) -> Vec<u8> {
+    let mut datagram = Vec::new();
+    push_field(&mut datagram, "MESSAGE", &record.args().to_string());
+    push_field(
+        &mut datagram,
+        "PRIORITY",
+        &priority(record.level()).to_string(),
+    );
+    push_field(&mut datagram, "SYSLOG_IDENTIFIER", identifier);
+    for label in labels.iter() {
+        let name = format!("LABEL_{}", field_name(&label.key));
+        push_field(&mut datagram, &name, &label.value);
+    }
+    let mut fields = Fields(Vec::new());
+    // Visiting a record's fields only fails if the visitor does.
+    let _ = record.key_values().visit(&mut fields);
+    for (name, value) in fields.0 {
+        // The fields above take precedence over structured fields of the same name.
+        if !["MESSAGE", "PRIORITY", "SYSLOG_IDENTIFIER"].contains(&name.as_str()) {
+            push_field(&mut datagram, &name, &value);
+        }
+    }
+    datagram
+}
+
+/// Sends log records to the journal.
+#[derive(Debug)]
+pub struct JournalLogger {
+    socket: UnixDatagram,
+    level: log::LevelFilter,
+    identifier: String,
+    labels: Labels,
+}
+
+impl JournalLogger {
+    /// Connects to the journal, logging records up to `level` with `labels`.
+    pub fn connect(level: log::LevelFilter, labels: Labels) -> Result<Self> {
+        Self::connect_to(Path::new(JOURNAL_SOCKET_PATH), level, labels)
+    }
+
+    fn connect_to(path: &Path, level: log::LevelFilter, labels: Labels) -> Result<Self> {
+        let socket = UnixDatagram::unbound().context("failed to create the journal socket")?;
+        socket
+            .connect(path)
+            .with_context(|| format!("failed to connect to the journal at {}", path.display()))?;
+        // journald may itself be stuck on the writeback stall, which must not wedge the daemon.
+        socket
+            .set_nonblocking(true)
+            .context("failed to make the journal socket non-blocking")?;
+        Ok(JournalLogger {
+            socket,
+            level,
+            identifier: env!("CARGO_PKG_NAME").to_string(),
+            labels,
+        })
+    }
+}
+
+impl log::Log for JournalLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            // There is nowhere left to report a record the journal didn't take, or had no room
+            // for, which is dropped rather than waited on.
+            let _ = self
+                .socket
+                .send(&encode(record, &self.identifier, &self.labels));
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_log_target() {
+        assert_eq!("stderr".parse(), Ok(LogTarget::Stderr));
+        assert_eq!("journald".parse(), Ok(LogTarget::Journald));
+        assert!("syslog".parse::<LogTarget>().is_err());
+    }
+
+    #[test]
+    fn test_field_name() {
+        assert_eq!(field_name("kworker_comm"), "KWORKER_COMM");
+        assert_eq!(field_name("runtime.s"), "RUNTIME_S");
+        assert_eq!(field_name("_private"), "PRIVATE");
+        assert_eq!(field_name("__"), "FIELD");
+    }
+
+    #[test]
+    fn test_encode_record_with_fields() {
+        let fields: &[(&str, Value)] = &[
+            ("kworker_comm", Value::from("kworker/u8:2+inode_switch_wbs")),
+            ("runtime_s", Value::from(40.5)),
+            ("priority", Value::from("ignored")),
+        ];
+        let labels = Labels::new(vec!["cluster=prod".parse().unwrap()]).unwrap();
+        let datagram = encode(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("Sync triggered"))
+                .key_values(&fields)
+                .build(),
+            "stuck_writeback_workaround",
+            &labels,
+        );
+        assert_eq!(
+            String::from_utf8(datagram).unwrap(),
+            "MESSAGE=Sync triggered\n\
+             PRIORITY=4\n\
+             SYSLOG_IDENTIFIER=stuck_writeback_workaround\n\
+             LABEL_CLUSTER=prod\n\
+             KWORKER_COMM=kworker/u8:2+inode_switch_wbs\n\
+             RUNTIME_S=40.5\n"
+        );
+    }
+
+    #[test]
+    fn test_logger_sends_enabled_records() {
+        let path = std::env::temp_dir().join(format!("stuck_wbs_{}.journal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+        let logger =
+            JournalLogger::connect_to(&path, log::LevelFilter::Info, Labels::default()).unwrap();
+        let record = |level| {
+            log::Record::builder()
+                .level(level)
+                .args(format_args!("Scanned"))
+                .build()
+        };
+        log::Log::log(&logger, &record(log::Level::Debug));
+        log::Log::log(&logger, &record(log::Level::Info));
+        std::fs::remove_file(&path).unwrap();
+
+        journal.set_nonblocking(true).unwrap();
+        let mut buf = [0; 1024];
+        let len = journal.recv(&mut buf).unwrap();
+        assert!(buf[..len].starts_with(b"MESSAGE=Scanned\nPRIORITY=6\n"));
+        assert!(
+            journal.recv(&mut buf).is_err(),
+            "debug records are filtered"
+        );
+    }
+
+    #[test]
+    fn test_logger_drops_records_a_stuck_journal_has_no_room_for() {
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_stuck.journal", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // Never read, as if journald were stuck.
+        let _journal = UnixDatagram::bind(&path).unwrap();
+        let logger =
+            JournalLogger::connect_to(&path, log::LevelFilter::Info, Labels::default()).unwrap();
+        let (done, finished) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            // Far more than the socket queues.
+            for _ in 0..10_000 {
+                log::Log::log(
+                    &logger,
+                    &log::Record::builder()
+                        .level(log::Level::Warn)
+                        .args(format_args!("Sync triggered"))
+                        .build(),
+                );
+            }
+            let _ = done.send(());
+        });
+        let result = finished.recv_timeout(std::time::Duration::from_secs(10));
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_ok(), "logging blocked on a full journal socket");
+    }
+
+    #[test]
+    fn test_encode_multiline_message() {
+        let datagram = encode(
+            &log::Record::builder()
+                .level(log::Level::Error)
+                .args(format_args!("a\nb"))
+                .build(),
+            "x",
+            &Labels::default(),
+        );
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\nPRIORITY=3\nSYSLOG_IDENTIFIER=x\n");
+        assert_eq!(datagram, expected);
+    }
+}
@@ -0,0 +1,202 @@
+//! Keeping what watches over the daemon, systemd's watchdog and the `--supervise` supervisor, from
+//! taking a long wait for the daemon wedging, however long waits are configured to be.
+use crate::supervisor::{self, Heartbeat};
+use crate::systemd::Notifier;
+use std::time::{Duration, Instant};
+
+/// Pings the systemd watchdog and sends heartbeats to the supervisor, either being optional.
+#[derive(Default)]
+pub struct KeepAlive {
+    notifier: Option<Notifier>,
+    heartbeat: Option<Heartbeat>,
+}
+
+impl KeepAlive {
+    pub fn new(notifier: Option<Notifier>, heartbeat: Option<Heartbeat>) -> Self {
+        KeepAlive {
+            notifier,
+            heartbeat,
+        }
+    }
+
+    /// Returns the systemd notifier, if this process runs under systemd with `Type=notify`.
+    pub fn notifier(&self) -> Option<&Notifier> {
+        self.notifier.as_ref()
+    }
+
+    /// Returns the heartbeat pipe, if this process runs under `--supervise`.
+    pub fn heartbeat(&self) -> Option<&Heartbeat> {
+        self.heartbeat.as_ref()
+    }
+
+    /// How often to ping during waits, if anything watches over the daemon.
+    fn interval(&self) -> Option<Duration> {
+        let watchdog = self.notifier.as_ref().and_then(Notifier::watchdog_interval);
+        let heartbeat = self
+            .heartbeat
+            .as_ref()
+            .map(|_| supervisor::HEARTBEAT_INTERVAL);
+        watchdog.into_iter().chain(heartbeat).min()
+    }
+
+    fn ping(&self) {
+        if let Some(notifier) = &self.notifier {
+            notifier.watchdog();
+        }
+        if let Some(heartbeat) = &self.heartbeat {
+            heartbeat.beat();
+        }
+    }
+
+    /// Sleeps for `duration`, pinging often enough while doing so.
+    pub fn sleep(&self, duration: Duration) {
+        self.wait(duration, |step| {
+            std::thread::sleep(step);
+            None::<()>
+        });
+    }
+
+    /// As `sleep`, without blocking the thread, for `--async`.
+    #[cfg(feature = "async")]
+    pub async fn sleep_async(&self, duration: Duration) {
+        let start = Instant::now();
+        loop {
+            let left = duration.saturating_sub(start.elapsed());
+            if left.is_zero() {
+                return;
+            }
+            tokio::time::sleep(self.interval().map_or(left, |interval| left.min(interval))).await;
+            if start.elapsed() < duration {
+                self.ping();
+            }
+        }
+    }
+
+    /// Calls `poll` until it returns something or `timeout` elapsed, pinging often enough
+    /// meanwhile, for waits that may outlast what watches over the daemon. `poll` is passed how
+    /// long it may block for.
+    pub fn wait<T>(&self, timeout: Duration, poll: impl FnMut(Duration) -> Option<T>) -> Option<T> {
+        let start = Instant::now();
+        wait_on(
+            timeout,
+            self.interval(),
+            || start.elapsed(),
+            || self.ping(),
+            poll,
+        )
+    }
+}
+
+/// Like `wait`, calling `ping` every `interval` of the time `elapsed` says passed since the start.
+fn wait_on<T>(
+    timeout: Duration,
+    interval: Option<Duration>,
+    mut elapsed: impl FnMut() -> Duration,
+    mut ping: impl FnMut(),
+    mut poll: impl FnMut(Duration) -> Option<T>,
+) -> Option<T> {
+    let mut pinged = Duration::ZERO;
+    loop {
+        let now = elapsed();
+        let left = timeout.saturating_sub(now);
+        let step = interval.map_or(left, |interval| {
+            left.min((pinged + interval).saturating_sub(now))
+        });
+        if let Some(value) = poll(step) {
+            return Some(value);
+        }
+        let now = elapsed();
+        if now >= timeout {
+            return None;
+        }
+        if interval.is_some_and(|interval| now >= pinged + interval) {
+            ping();
+            pinged = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+
+    #[test]
+    fn test_waits_beat_the_heartbeat() {
+        let (reader, writer) = rustix::pipe::pipe().unwrap();
+        let keepalive = KeepAlive::new(None, Some(Heartbeat(File::from(writer))));
+        assert_eq!(keepalive.interval(), Some(supervisor::HEARTBEAT_INTERVAL));
+        assert_eq!(
+            keepalive.wait(Duration::from_secs(5), |_| Some(42)),
+            Some(42)
+        );
+        keepalive.ping();
+        drop(keepalive);
+        let mut beats = String::new();
+        File::from(reader).read_to_string(&mut beats).unwrap();
+        // Waits that end right away don't beat.
+        assert_eq!(beats, ".");
+
+        assert_eq!(KeepAlive::default().interval(), None);
+    }
+
+    #[test]
+    fn test_waits_ping_often_enough() {
+        let clock = std::cell::Cell::new(Duration::ZERO);
+        let advance = |by| clock.set(clock.get() + by);
+        let interval = Some(Duration::from_millis(10));
+
+        // Sleeping for the whole step every time.
+        let mut pings = 0;
+        let found = wait_on(
+            Duration::from_millis(25),
+            interval,
+            || clock.get(),
+            || pings += 1,
+            |step| {
+                advance(step);
+                None::<()>
+            },
+        );
+        assert!(found.is_none());
+        assert_eq!(clock.get(), Duration::from_millis(25));
+        assert_eq!(pings, 2);
+
+        // Polls that return early don't ping more often, nor are they allowed to block past the
+        // next ping.
+        clock.set(Duration::ZERO);
+        let (mut pings, mut polls) = (0, 0);
+        let found = wait_on(
+            Duration::from_millis(35),
+            interval,
+            || clock.get(),
+            || pings += 1,
+            |step| {
+                assert!(step <= Duration::from_millis(10));
+                polls += 1;
+                advance(step.min(Duration::from_millis(3)));
+                None::<()>
+            },
+        );
+        assert!(found.is_none());
+        assert!(polls > 10);
+        assert_eq!(pings, 3);
+
+        // Without a watchdog, the whole timeout is polled for at once.
+        clock.set(Duration::ZERO);
+        let mut steps = Vec::new();
+        wait_on(
+            Duration::from_millis(35),
+            None,
+            || clock.get(),
+            || panic!("pinged without a watchdog"),
+            |step| {
+                steps.push(step);
+                advance(step);
+                None::<()>
+            },
+        );
+        assert_eq!(steps, [Duration::from_millis(35)]);
+    }
+}
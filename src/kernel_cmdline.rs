@@ -0,0 +1,110 @@
+//! Configuration from `wb.*` parameters on the kernel command line, for appliance-style
+//! deployments where the daemon is launched before any configuration file is available.
+use crate::duration::parse_threshold;
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+/// Where the kernel exposes its command line.
+const PROC_CMDLINE_PATH: &str = "/proc/cmdline";
+
+/// The prefix of parameters meant for this daemon.
+const PREFIX: &str = "wb.";
+
+/// Settings found on the kernel command line, `None` when absent.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KernelCmdline {
+    /// From `wb.glob=<glob>`.
+    pub process_glob: Option<String>,
+    /// From `wb.threshold=<duration>`, `Some(None)` if "off".
+    pub runtime_threshold: Option<Option<chrono::Duration>>,
+}
+
+/// Splits a kernel command line into parameters, honoring double quotes like the kernel does.
+fn split_params(cmdline: &str) -> Vec<String> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for c in cmdline.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    params.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        params.push(current);
+    }
+    params
+}
+
+impl KernelCmdline {
+    /// Parses the `wb.*` parameters of `cmdline`, ignoring unrelated ones.
+    pub fn parse(cmdline: &str) -> Result<Self> {
+        let mut parsed = KernelCmdline::default();
+        for param in split_params(cmdline) {
+            let Some(param) = param.strip_prefix(PREFIX) else {
+                continue;
+            };
+            let Some((key, value)) = param.split_once('=') else {
+                bail!("kernel parameter '{PREFIX}{param}' has no value");
+            };
+            match key {
+                "glob" => parsed.process_glob = Some(value.to_string()),
+                "threshold" => {
+                    let threshold = parse_threshold(value)
+                        .map_err(|e| anyhow::anyhow!("invalid {PREFIX}threshold: {e}"))?;
+                    parsed.runtime_threshold = Some(threshold);
+                }
+                _ => warn!("Ignoring unknown kernel parameter '{PREFIX}{key}'"),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Reads and parses the running kernel's command line.
+    pub fn read() -> Result<Self> {
+        let cmdline = std::fs::read_to_string(PROC_CMDLINE_PATH)
+            .with_context(|| format!("failed to read {PROC_CMDLINE_PATH}"))?;
+        Self::parse(&cmdline).with_context(|| format!("failed to parse {PROC_CMDLINE_PATH}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_representative_cmdline() {
+        let cmdline = "BOOT_IMAGE=/vmlinuz-6.6 root=/dev/mapper/root ro quiet \
+                       wb.threshold=1m wb.glob=\"kworker/*inode_switch_wbs*\" splash\n";
+        assert_eq!(
+            KernelCmdline::parse(cmdline).unwrap(),
+            KernelCmdline {
+                process_glob: Some("kworker/*inode_switch_wbs*".to_string()),
+                runtime_threshold: Some(Some(chrono::Duration::seconds(60))),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ignores_unrelated_and_unknown_params() {
+        let parsed = KernelCmdline::parse("quiet wbx.glob=nope wb.unknown=1 init=/bin/sh").unwrap();
+        assert_eq!(parsed, KernelCmdline::default());
+    }
+
+    #[test]
+    fn test_parse_quoted_value_with_spaces() {
+        let parsed = KernelCmdline::parse("wb.glob=\"my worker*\" quiet").unwrap();
+        assert_eq!(parsed.process_glob.as_deref(), Some("my worker*"));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_values() {
+        assert!(KernelCmdline::parse("wb.threshold=soon").is_err());
+        assert!(KernelCmdline::parse("wb.glob").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! `--label` deployment labels, attached to every log line, metric and webhook report so the
+//! output of a fleet can be aggregated.
+use serde::ser::{Serialize, Serializer};
+
+/// Label names the daemon's own metrics already use.
+const RESERVED_KEYS: [&str; 5] = ["reason", "result", "status", "test", "version"];
+
+/// A `key=value` label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+impl std::str::FromStr for Label {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((key, value)) = s.split_once('=') else {
+            return Err(format!("invalid label '{s}', expected KEY=VALUE"));
+        };
+        // The Prometheus rules for label names, the strictest of the output channels.
+        let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !key.starts_with("__");
+        if !valid {
+            return Err(format!(
+                "invalid label name '{key}', expected letters, digits and underscores, not \
+                 starting with a digit or two underscores"
+            ));
+        }
+        if RESERVED_KEYS.contains(&key) {
+            return Err(format!("label name '{key}' is reserved"));
+        }
+        Ok(Label {
+            key: key.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+/// The labels attached to all output, in the order they were given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Labels(Vec<Label>);
+
+impl Labels {
+    /// Returns the set of `labels`, whose keys must be unique.
+    pub fn new(labels: Vec<Label>) -> anyhow::Result<Self> {
+        for (i, label) in labels.iter().enumerate() {
+            if labels[..i].iter().any(|l| l.key == label.key) {
+                anyhow::bail!("label '{}' given more than once", label.key);
+            }
+        }
+        Ok(Labels(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Label> {
+        self.0.iter()
+    }
+
+    /// Formats the labels as in the Prometheus text exposition format, e.g.
+    /// `cluster="prod",role="storage"`.
+    pub fn to_prometheus(&self) -> String {
+        let escape = |value: &str| {
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        };
+        self.0
+            .iter()
+            .map(|l| format!("{}=\"{}\"", l.key, escape(&l.value)))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// Formats the labels for log lines, e.g. `cluster=prod role=storage`.
+impl std::fmt::Display for Labels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, label) in self.0.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{separator}{}={}", label.key, label.value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes as a map from keys to values.
+impl Serialize for Labels {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|l| (&l.key, &l.value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(labels: &[&str]) -> Labels {
+        Labels::new(labels.iter().map(|l| l.parse().unwrap()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(
+            "cluster=prod".parse(),
+            Ok(Label {
+                key: "cluster".to_string(),
+                value: "prod".to_string(),
+            })
+        );
+        assert_eq!("note=a=b".parse::<Label>().unwrap().value, "a=b");
+        assert_eq!("empty=".parse::<Label>().unwrap().value, "");
+        for invalid in [
+            "cluster",
+            "=prod",
+            "1st=a",
+            "rack-id=4",
+            "__name__=x",
+            "status=x",
+        ] {
+            assert!(invalid.parse::<Label>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys_are_rejected() {
+        let parsed = ["role=a", "role=b"].map(|l| l.parse().unwrap());
+        assert!(Labels::new(parsed.to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_formats() {
+        let labels = labels(&["cluster=prod", "role=storage \"hot\""]);
+        assert_eq!(labels.to_string(), "cluster=prod role=storage \"hot\"");
+        assert_eq!(
+            labels.to_prometheus(),
+            "cluster=\"prod\",role=\"storage \\\"hot\\\"\""
+        );
+        assert_eq!(
+            serde_json::to_value(&labels).unwrap(),
+            serde_json::json!({"cluster": "prod", "role": "storage \"hot\""})
+        );
+    }
+}
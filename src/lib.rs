@@ -0,0 +1,4229 @@
+//! # Stuck Writeback Workaround
+//!
+//! A userspace daemon to mitigate indefinite `inode_switch_wbs` stalls in the Linux kernel.
+//!
+//! This tool works around a kernel bug where writeback operations stall indefinitely, hogging
+//! gradually more and more CPUs, until there's none left. The daemon monitors `kworker` threads
+//! executing `inode_switch_wbs` that appear stuck and issues a `sync()` to free them up.
+//!
+//! The daemon's binary parses its flags into a [`Config`] and runs the main loop, calling
+//! [`workaround`] on every iteration. Other tools can drive it too, against their own
+//! [`System`] to simulate processes and observe the actions taken: each call
+//! returns an [`Outcome`], telling how long to wait before the next with [`Timings`].
+pub mod action;
+pub mod affinity;
+pub mod async_loop;
+pub mod canary;
+pub mod capabilities;
+pub mod cgroup;
+pub mod clock;
+pub mod config_file;
+pub mod daemon_state;
+pub mod duration;
+pub mod episode;
+pub mod event_log;
+pub mod events;
+pub mod fs_status;
+pub mod histogram;
+pub mod incident;
+pub mod ioprio;
+pub mod jitter;
+pub mod journald;
+pub mod keepalive;
+pub mod kernel_cmdline;
+pub mod labels;
+pub mod log_color;
+pub mod log_dedup;
+pub mod log_format;
+pub mod metrics;
+pub mod metrics_server;
+pub mod oom;
+pub mod pattern_file;
+pub mod percent;
+pub mod pidfile;
+pub mod prefilter;
+pub mod privileges;
+pub mod reload;
+pub mod rules;
+pub mod shutdown;
+pub mod signature;
+pub mod starttime_check;
+pub mod state_file;
+pub mod status;
+pub mod status_socket;
+pub mod supervisor;
+pub mod sync_limit;
+pub mod sync_mode;
+pub mod system;
+pub mod systemd;
+pub mod tracker;
+pub mod version;
+pub mod webhook;
+
+use action::{check_signal_target, Action, PatternAction};
+use anyhow::Context;
+use capabilities::{Capability, Requirement};
+use daemon_state::DaemonState;
+use duration::{format_duration, format_signed_duration};
+use episode::Crossing;
+use event_log::Event;
+use incident::{Incident, Resolution};
+use labels::Labels;
+use log::{debug, error, info, warn};
+use metrics::Metrics;
+use prefilter::CommPrefilter;
+use signature::{check_glob, matches_glob, ProcessGlob, Signature};
+use status::Status;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sync_mode::SyncMode;
+use system::{ProcInfo, Scan, SyncStillBlocked, SyncTimedOut, System};
+
+/// The default polling interval when a matching `kworker` process is running but has not yet
+/// exceeded its time threshold. This is a tight loop to catch it as soon as it does.
+const BUSY_POLLING: Duration = Duration::from_secs(1);
+
+/// The default polling interval after an error has occurred. This is a back-off to prevent
+/// spamming logs with repeated errors.
+const IDLE_POLLING: Duration = Duration::from_secs(60);
+
+/// On a busy system, the kernel may drop netlink events. To safeguard against this, by default
+/// we'll periodically re-scan the full process list to ensure we haven't missed a stuck `kworker`.
+const MAX_MONITOR_DURATION: Duration = Duration::from_secs(60);
+
+/// After triggering a `sync`, by default we'll pause monitoring for this duration to allow the
+/// system to recover and stabilize.
+const EXPECTED_RECOVERY_TIME: Duration = Duration::from_secs(30);
+
+/// How long the `--verify-command` and `--escalation-command` may run before they are killed and
+/// considered to have failed.
+const VERIFY_COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the `--action-command` may run before it is killed and the remediation fails, twice
+/// as long as a sync may block for by default.
+const ACTION_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long `--require-no-progress` watches a stuck process's CPU time.
+const PROGRESS_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The most CPU time a process may use over `PROGRESS_SAMPLE_INTERVAL` without being considered
+/// to make progress. One clock tick, as that is the resolution of CPU time accounting.
+const NO_PROGRESS_CPU_TIME: Duration = Duration::from_millis(10);
+
+/// The `/proc/<pid>/stat` states of the processes monitored unless `--any-state` is given: running
+/// and uninterruptible sleep, the latter being typical of the stall. A sleeping process whose comm
+/// still matches isn't stuck on writeback.
+const MONITORED_STATES: [char; 2] = ['R', 'D'];
+
+/// The default glob identifying the `kworker` threads stuck in `inode_switch_wbs`.
+const DEFAULT_PROCESS_GLOB: &str = "kworker/*inode_switch_wbs*";
+
+/// The default maximum runtime of a monitored process before action is taken.
+const DEFAULT_RUNTIME_THRESHOLD: chrono::Duration = chrono::Duration::seconds(30);
+
+/// Runtime thresholds below this likely act on kworkers doing their work as usual.
+const MIN_SENSIBLE_THRESHOLD: chrono::Duration = chrono::Duration::seconds(1);
+
+/// Runtime thresholds above this likely never act, as a stall would be noticed well before.
+const MAX_SENSIBLE_THRESHOLD: chrono::Duration = chrono::Duration::hours(1);
+
+/// The exit status of `--once` when it acted on a stuck process, distinct from the 1 of errors.
+pub const EXIT_REMEDIATED: u8 = 10;
+
+/// The exit status of the daemon once it reached `--max-syncs`, after which `--supervise` doesn't
+/// restart it.
+pub const EXIT_MAX_SYNCS: u8 = 11;
+
+/// The default number of syncs in a row that may leave the same process stuck.
+const DEFAULT_MAX_INEFFECTIVE_SYNCS: usize = 3;
+
+/// The default least time between two syncs.
+const DEFAULT_SYNC_COOLDOWN: chrono::Duration = chrono::Duration::seconds(10);
+
+/// The default path on the filesystem `--min-free-percent` checks.
+const DEFAULT_SYNC_PATH: &str = "/";
+
+/// What the daemon does on its very first iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupBehavior {
+    /// Scan processes immediately.
+    Scan,
+    /// Wait for a new matching kworker before the first scan.
+    Wait,
+}
+
+impl std::str::FromStr for StartupBehavior {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scan" => Ok(StartupBehavior::Scan),
+            "wait" => Ok(StartupBehavior::Wait),
+            _ => Err(format!(
+                "invalid startup behavior '{s}', expected 'scan' or 'wait'"
+            )),
+        }
+    }
+}
+
+/// The settings the workaround acts upon.
+///
+/// Serializes to TOML for `--dump-config`, with keys named after the flags setting them.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Globs identifying the monitored `kworker` processes, with their own thresholds.
+    #[serde(rename = "process-glob")]
+    pub process_globs: Vec<ProcessGlob>,
+    /// How long a monitored process may run before action is taken, or `None` if only other
+    /// triggers, and the thresholds of globs and signatures having their own, apply.
+    #[serde(serialize_with = "duration::serialize_threshold")]
+    pub runtime_threshold: Option<chrono::Duration>,
+    /// If set, how long a monitored process may run before a warning is logged, without acting.
+    #[serde(
+        serialize_with = "duration::serialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub warn_threshold: Option<chrono::Duration>,
+    /// Stalls with their own criteria, threshold and action, the first match wins.
+    #[serde(rename = "signature", skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<Signature>,
+    /// Signatures from `--rules`, after the `--signature`s. Not part of the dumped configuration
+    /// since they are read from their own file.
+    #[serde(skip)]
+    pub rules: Vec<Signature>,
+    /// Additional monitored globs and the action to take for them, the first match wins.
+    #[serde(rename = "pattern-action")]
+    pub pattern_actions: Vec<PatternAction>,
+    /// Additional monitored globs from `--pattern-file`, using the default action. Not part of
+    /// the dumped configuration since the file is reloaded at runtime.
+    #[serde(skip)]
+    pub file_globs: Vec<String>,
+    /// If set, also trigger when the ages of all matching processes sum to more than this.
+    #[serde(
+        serialize_with = "duration::serialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub sum_age_threshold: Option<chrono::Duration>,
+    /// If set, also trigger when a matching process consumed more CPU time than this.
+    #[serde(
+        serialize_with = "duration::serialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cpu_threshold: Option<chrono::Duration>,
+    /// If set, also trigger when at least this many processes match, however long each ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_stuck_count: Option<usize>,
+    /// If set, triggers within this long of each other are reported as a single episode.
+    #[serde(
+        serialize_with = "duration::serialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub episode_gap: Option<chrono::Duration>,
+    /// If set, no action is taken before the system has been up for this long.
+    #[serde(
+        serialize_with = "duration::serialize_opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub first_action_after_boot: Option<chrono::Duration>,
+    /// Whether to monitor matching processes whatever their state, rather than only those
+    /// running or in uninterruptible sleep.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub any_state: bool,
+    /// The users whose processes are monitored, unless `any_uid` is set.
+    #[serde(rename = "uid")]
+    pub uids: Vec<u32>,
+    /// Whether to monitor matching processes whatever their user.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub any_uid: bool,
+    /// Whether to only act on stuck processes whose CPU time does not grow.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub require_no_progress: bool,
+    /// If set, only stuck processes whose wchan contains this are acted on, if their wchan is
+    /// known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_wchan: Option<String>,
+    /// The action taken for `--process-glob`s, pattern file globs, and crossings of thresholds
+    /// other than runtime by processes without a signature.
+    #[serde(rename = "action")]
+    pub default_action: Action,
+    /// If set, the shell command run by `command` actions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action_command: Option<String>,
+    /// What a `sync` action flushes.
+    #[serde(skip_serializing_if = "SyncMode::is_global")]
+    pub sync_mode: SyncMode,
+    /// The least time between two syncs.
+    #[serde(serialize_with = "duration::serialize")]
+    pub sync_cooldown: chrono::Duration,
+    /// If set, the percentage of hosts that act, the others running detect-only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canary_percent: Option<u8>,
+    /// Whether this host is outside the canary set, and so only reports stuck processes.
+    #[serde(skip)]
+    pub detect_only: bool,
+    /// Whether to only report stuck processes, whatever the canary.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// If set, a shell command whose exit status tells whether a remediation worked.
+    pub verify_command: Option<String>,
+    /// How many syncs in a row may leave the same process stuck before escalating, 0 for never.
+    pub max_ineffective_syncs: usize,
+    /// If set, a shell command run when escalating.
+    pub escalation_command: Option<String>,
+    /// Whether to request an emergency sync through `/proc/sysrq-trigger` when escalating.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub escalate_sysrq: bool,
+    /// If set, the least percentage of free space the filesystem a `sync` flushes needs to be
+    /// synced, syncs being only reported otherwise.
+    pub min_free_percent: Option<u8>,
+    /// If set, a path on the filesystem `min_free_percent` checks when syncing every filesystem,
+    /// instead of the root one.
+    pub sync_path: Option<PathBuf>,
+    /// If set, a URL to POST a JSON report to on the first trigger of every episode.
+    pub webhook: Option<String>,
+    /// If set, a directory to write a report of every episode to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub incident_dir: Option<PathBuf>,
+    /// Whether to capture the kernel stack of stuck processes right before acting on them.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub capture_stack: bool,
+    /// If set, a directory to write captured stacks to, rather than logging them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack_dir: Option<PathBuf>,
+    /// How long the main loop sleeps or waits.
+    #[serde(flatten)]
+    pub timings: Timings,
+    /// If set, the fraction each sleep of the main loop is randomized by, either way.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<f64>,
+    /// Attached to all output. Last, as it serializes to a TOML table.
+    #[serde(rename = "label", skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            process_globs: vec![ProcessGlob::new(DEFAULT_PROCESS_GLOB)],
+            runtime_threshold: Some(DEFAULT_RUNTIME_THRESHOLD),
+            warn_threshold: None,
+            signatures: Vec::new(),
+            rules: Vec::new(),
+            pattern_actions: Vec::new(),
+            file_globs: Vec::new(),
+            sum_age_threshold: None,
+            cpu_threshold: None,
+            min_stuck_count: None,
+            episode_gap: None,
+            first_action_after_boot: None,
+            any_state: false,
+            uids: vec![0],
+            any_uid: false,
+            require_no_progress: false,
+            require_wchan: None,
+            default_action: Action::Sync,
+            action_command: None,
+            sync_mode: SyncMode::Global,
+            sync_cooldown: DEFAULT_SYNC_COOLDOWN,
+            canary_percent: None,
+            dry_run: false,
+            detect_only: false,
+            verify_command: None,
+            max_ineffective_syncs: DEFAULT_MAX_INEFFECTIVE_SYNCS,
+            escalation_command: None,
+            escalate_sysrq: false,
+            min_free_percent: None,
+            sync_path: None,
+            webhook: None,
+            incident_dir: None,
+            capture_stack: false,
+            stack_dir: None,
+            timings: Timings::default(),
+            jitter: None,
+            labels: Labels::default(),
+        }
+    }
+}
+
+/// How long the main loop sleeps or waits, depending on what the last iteration found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Timings {
+    /// Between scans while a matching process runs below its threshold.
+    #[serde(serialize_with = "duration::serialize_std")]
+    pub busy_poll: Duration,
+    /// After an iteration failed.
+    #[serde(serialize_with = "duration::serialize_std")]
+    pub error_backoff: Duration,
+    /// The longest wait for a new kworker before scanning again. Shortened to keep the systemd
+    /// watchdog fed.
+    #[serde(serialize_with = "duration::serialize_std")]
+    pub rescan_interval: Duration,
+    /// After a remediation.
+    #[serde(serialize_with = "duration::serialize_std")]
+    pub recovery_time: Duration,
+}
+
+impl Default for Timings {
+    fn default() -> Self {
+        Self {
+            busy_poll: BUSY_POLLING,
+            error_backoff: IDLE_POLLING,
+            rescan_interval: MAX_MONITOR_DURATION,
+            recovery_time: EXPECTED_RECOVERY_TIME,
+        }
+    }
+}
+
+impl Timings {
+    /// Fails if busy polling would not be more frequent than rescans, defeating its purpose.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.busy_poll >= self.rescan_interval {
+            anyhow::bail!(
+                "--busy-poll ({}) must be shorter than --rescan-interval ({})",
+                format_duration(self.busy_poll),
+                format_duration(self.rescan_interval)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Fails on settings that contradict each other.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.timings.validate()?;
+        for glob in self.globs() {
+            check_glob(glob).map_err(|e| anyhow::anyhow!("invalid glob: {e}"))?;
+        }
+        if let Some((setting, threshold)) = self
+            .runtime_thresholds()
+            .into_iter()
+            .find(|(_, threshold)| *threshold < chrono::Duration::zero())
+        {
+            anyhow::bail!(
+                "{setting} must not be negative, got {}",
+                format_signed_duration(threshold)
+            );
+        }
+        if let Some((warn, threshold)) = self
+            .warn_threshold
+            .zip(self.runtime_threshold)
+            .filter(|(warn, threshold)| warn > threshold)
+        {
+            anyhow::bail!(
+                "--warn-threshold ({}) must not exceed --runtime-threshold ({})",
+                format_signed_duration(warn),
+                format_signed_duration(threshold)
+            );
+        }
+        if self.min_stuck_count == Some(0) {
+            anyhow::bail!("--min-stuck-count must be at least 1");
+        }
+        let commands = self.default_action == Action::Command
+            || self
+                .signatures()
+                .iter()
+                .any(|s| s.action == Action::Command);
+        if commands && self.action_command.is_none() {
+            anyhow::bail!("the 'command' action requires --action-command");
+        }
+        let runtime_triggers = self.runtime_threshold.is_some()
+            || self.signatures().iter().any(|s| s.threshold.is_some());
+        if !runtime_triggers
+            && self.cpu_threshold.is_none()
+            && self.sum_age_threshold.is_none()
+            && self.min_stuck_count.is_none()
+        {
+            anyhow::bail!(
+                "--runtime-threshold is off and nothing else can trigger: set --cpu-threshold, \
+                 --sum-age-threshold, --min-stuck-count, or a threshold for a --process-glob or \
+                 --signature"
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns whether a trigger counts every matching process. Re-reading only the processes
+    /// that matched in the last scan would then miss the ones that started matching since.
+    fn counts_kworkers(&self) -> bool {
+        self.sum_age_threshold.is_some() || self.min_stuck_count.is_some()
+    }
+
+    /// Returns every runtime threshold, with the setting it comes from.
+    fn runtime_thresholds(&self) -> Vec<(String, chrono::Duration)> {
+        let signatures = self.signatures();
+        let own = signatures.iter().filter_map(|s| {
+            s.threshold
+                .map(|t| (format!("the threshold of '{}'", s.glob), t))
+        });
+        self.runtime_threshold
+            .map(|t| (String::from("--runtime-threshold"), t))
+            .into_iter()
+            .chain(own)
+            .collect()
+    }
+
+    /// Returns warnings about runtime thresholds so short or long that they are likely mistakes,
+    /// though they are honored.
+    pub fn threshold_warnings(&self) -> Vec<String> {
+        self.runtime_thresholds()
+            .into_iter()
+            .filter_map(|(setting, threshold)| {
+                let (bound, consequence) = if threshold < MIN_SENSIBLE_THRESHOLD {
+                    (
+                        format!("below {}", format_signed_duration(MIN_SENSIBLE_THRESHOLD)),
+                        "processes doing their work as usual will likely be acted on",
+                    )
+                } else if threshold > MAX_SENSIBLE_THRESHOLD {
+                    (
+                        format!("above {}", format_signed_duration(MAX_SENSIBLE_THRESHOLD)),
+                        "stalls will likely never be acted on",
+                    )
+                } else {
+                    return None;
+                };
+                Some(format!(
+                    "{setting} ({}) is {bound}, so {consequence}",
+                    format_signed_duration(threshold)
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns every glob identifying monitored processes.
+    pub fn globs(&self) -> impl Iterator<Item = &str> {
+        self.process_globs
+            .iter()
+            .map(|pg| pg.glob.as_str())
+            .chain(self.signatures.iter().map(|s| s.glob.as_str()))
+            .chain(self.rules.iter().map(|s| s.glob.as_str()))
+            .chain(self.pattern_actions.iter().map(|pa| pa.glob.as_str()))
+            .chain(self.file_globs.iter().map(String::as_str))
+    }
+
+    /// Returns every signature, in order of precedence: the `--signature`s and `--rules`, then the
+    /// `--pattern-action`s, `--process-glob`s and pattern file globs, matching on the glob alone.
+    fn signatures(&self) -> Vec<Signature> {
+        self.signatures
+            .iter()
+            .chain(&self.rules)
+            .cloned()
+            .chain(
+                self.pattern_actions
+                    .iter()
+                    .map(|pa| Signature::new(&pa.glob, pa.action)),
+            )
+            .chain(self.process_globs.iter().map(|pg| Signature {
+                action: self.default_action,
+                ..pg.signature()
+            }))
+            .chain(
+                self.file_globs
+                    .iter()
+                    .map(|glob| Signature::new(glob, self.default_action)),
+            )
+            .collect()
+    }
+}
+
+/// Which threshold was crossed.
+enum Cause {
+    /// A matching process ran for longer than its signature's threshold.
+    Runtime,
+    /// A matching process consumed more CPU time than `--cpu-threshold`.
+    CpuTime,
+    /// The ages of `count` matching processes summed to more than `--sum-age-threshold`.
+    SummedAge { count: usize },
+    /// `count` processes matched, at least `--min-stuck-count`.
+    Count { count: usize, min: usize },
+}
+
+/// A stuck process that crossed the threshold, and what is done about it.
+struct Trigger<'a> {
+    /// The stuck process, or the oldest matching one for `Cause::SummedAge` and `Cause::Count`.
+    kworker: &'a ProcInfo,
+    /// When the threshold was found to be crossed.
+    now: chrono::DateTime<chrono::Utc>,
+    cause: Cause,
+    /// The runtime compared to `threshold`: the process's own, its CPU time for `Cause::CpuTime`,
+    /// or the sum for `Cause::SummedAge`. For `Cause::Count`, the process's own compared to its
+    /// runtime threshold, which it may not have crossed, or zero if it has none.
+    runtime: chrono::Duration,
+    threshold: chrono::Duration,
+    action: Action,
+    /// Whether this is a synthetic event from `--emit-test-event`, for which nothing is done.
+    test: bool,
+}
+
+/// Describes what crossed which threshold for `trigger`.
+fn trigger_details(trigger: &Trigger) -> String {
+    match trigger.cause {
+        Cause::Runtime => format!(
+            "kworker '{}' (pid {}) has been running for {} (threshold: {})",
+            trigger.kworker.comm,
+            trigger.kworker.pid,
+            format_signed_duration(trigger.runtime),
+            format_signed_duration(trigger.threshold)
+        ),
+        Cause::CpuTime => format!(
+            "kworker '{}' (pid {}) has used {} of CPU time (CPU threshold: {})",
+            trigger.kworker.comm,
+            trigger.kworker.pid,
+            format_signed_duration(trigger.runtime),
+            format_signed_duration(trigger.threshold)
+        ),
+        Cause::SummedAge { count } => format!(
+            "{count} kworkers have been running for a combined {} (sum threshold: {}), \
+             oldest is '{}' (pid {})",
+            format_signed_duration(trigger.runtime),
+            format_signed_duration(trigger.threshold),
+            trigger.kworker.comm,
+            trigger.kworker.pid
+        ),
+        Cause::Count { count, min } => format!(
+            "{count} kworkers are matching (min stuck count: {min}), oldest is '{}' (pid {}) \
+             running for {}",
+            trigger.kworker.comm,
+            trigger.kworker.pid,
+            format_signed_duration(trigger.runtime)
+        ),
+    }
+}
+
+/// Reports a trigger through every notification channel, returning the crossing it is unless it
+/// is a test event.
+///
+/// Triggers continuing an episode are only logged, at a lower level, and not sent to the webhook.
+fn notify_trigger(
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    trigger: &Trigger,
+) -> Option<Crossing> {
+    let what = match trigger.action {
+        Action::Sync => String::from("Sync"),
+        action => format!("Action '{action}'"),
+    };
+    let (marker, crossing) = if trigger.test {
+        ("[TEST EVENT, no action taken] ", None)
+    } else {
+        let crossing = metrics.record_crossing(trigger.now, config.episode_gap);
+        ("", Some(crossing))
+    };
+    let dry_run = if config.dry_run && !trigger.test {
+        " (dry-run, no action taken)"
+    } else {
+        ""
+    };
+    let details = trigger_details(trigger);
+    metrics.record_trigger(trigger.test);
+    // Structured fields, for `--log-format json`.
+    let comm = trigger.kworker.comm.as_str();
+    let pid = trigger.kworker.pid;
+    let runtime_s = trigger.runtime.num_milliseconds() as f64 / 1000.0;
+    let threshold_s = trigger.threshold.num_milliseconds() as f64 / 1000.0;
+    let action = trigger.action.to_string();
+    record_event(
+        state,
+        trigger.now,
+        Event::Trigger {
+            pid,
+            comm: comm.to_string(),
+            runtime_s,
+            threshold_s,
+            action: action.clone(),
+            test: trigger.test,
+        },
+    );
+    if let Some(crossing) = crossing.filter(|c| !c.is_new_episode()) {
+        info!(
+            kworker_comm = comm, kworker_pid = pid, runtime_s, threshold_s,
+            action = action.as_str(), episode = crossing.episode;
+            "{what} triggered{dry_run} again in episode #{} (trigger {}): {details}",
+            crossing.episode, crossing.crossing
+        );
+        return Some(crossing);
+    }
+    warn!(
+        kworker_comm = comm, kworker_pid = pid, runtime_s, threshold_s,
+        action = action.as_str(), test = trigger.test;
+        "{marker}{what} triggered{dry_run}: {details}"
+    );
+    if let Some(url) = &config.webhook {
+        webhook::send(url, &webhook_report(metrics, config, trigger));
+    }
+    crossing
+}
+
+/// Appends `event`, which happened `at`, to the `--event-log`, only warning if that fails.
+fn record_event(state: &DaemonState, at: chrono::DateTime<chrono::Utc>, event: Event) {
+    if let Err(e) = state.record_event(at, &event) {
+        warn!("Failed to record the event: {e:?}");
+    }
+}
+
+/// Adds `trigger` to the timeline of the incident for `--incident-dir`, starting a new incident
+/// if it starts a new episode.
+fn record_incident<T: System>(
+    system: &T,
+    state: &DaemonState,
+    dir: &Path,
+    trigger: &Trigger,
+    crossing: Crossing,
+) {
+    let mut incident = state.incident();
+    if crossing.is_new_episode() {
+        if let Some(previous) = incident.take() {
+            write_incident(dir, &previous, trigger.now, Resolution::Superseded);
+        }
+        let stack = match system.stack(trigger.kworker.pid) {
+            Ok(stack) => Some(stack),
+            Err(e) => {
+                debug!(
+                    "Not capturing the stack of '{}': {e:#}",
+                    trigger.kworker.comm
+                );
+                None
+            }
+        };
+        *incident = Some(Incident::new(
+            crossing.episode,
+            webhook::hostname(),
+            trigger.kworker,
+            trigger.now,
+            stack,
+        ));
+    }
+    if let Some(incident) = incident.as_mut() {
+        let details = trigger_details(trigger);
+        incident.record(
+            trigger.now,
+            format!("Triggered {}: {details}", trigger.action),
+        );
+    }
+}
+
+/// Captures the kernel stack of `kworker` for `--capture-stack`, logging it or writing it to the
+/// `--stack-dir`. Failures are only logged, as they shouldn't delay the remediation.
+fn capture_stack<T: System>(
+    system: &T,
+    config: &Config,
+    kworker: &ProcInfo,
+    now: chrono::DateTime<chrono::Utc>,
+) {
+    let stack = match system.stack(kworker.pid) {
+        // The kernel doesn't walk the stack of a task running on another CPU.
+        Ok(stack) if stack.trim().is_empty() => {
+            info!(
+                "Not capturing the stack of '{}' (pid {}), it is empty as the process is running",
+                kworker.comm, kworker.pid
+            );
+            return;
+        }
+        Ok(stack) => stack,
+        Err(e) => {
+            warn!(
+                "Failed to capture the stack of '{}' (pid {}), which requires CAP_SYS_ADMIN: {e:#}",
+                kworker.comm, kworker.pid
+            );
+            return;
+        }
+    };
+    let Some(dir) = &config.stack_dir else {
+        info!(
+            "Kernel stack of '{}' (pid {}):\n{}",
+            kworker.comm,
+            kworker.pid,
+            stack.trim_end()
+        );
+        return;
+    };
+    let path = dir.join(format!(
+        "stack-{}-{}.txt",
+        clock::local(now).format("%Y%m%dT%H%M%S"),
+        kworker.pid
+    ));
+    let header = format!(
+        "{} (pid {}) at {}\n",
+        kworker.comm,
+        kworker.pid,
+        clock::local(now).to_rfc3339()
+    );
+    match std::fs::create_dir_all(dir).and_then(|()| std::fs::write(&path, header + &stack)) {
+        Ok(()) => info!(
+            "Wrote the stack of '{}' (pid {}) to {}",
+            kworker.comm,
+            kworker.pid,
+            path.display()
+        ),
+        Err(e) => warn!("Failed to write {}: {e}", path.display()),
+    }
+}
+
+/// Ends the incident being recorded for `--incident-dir`, if any, as nothing is stuck anymore.
+fn resolve_incident(state: &DaemonState, config: &Config, now: chrono::DateTime<chrono::Utc>) {
+    let Some(dir) = &config.incident_dir else {
+        return;
+    };
+    if let Some(mut incident) = state.incident().take() {
+        incident.record(now, String::from("No process past its threshold anymore"));
+        write_incident(dir, &incident, now, Resolution::Resolved);
+    }
+}
+
+/// Writes the report of `incident`, which ended at `ended`, to `dir`.
+pub fn write_incident(
+    dir: &Path,
+    incident: &Incident,
+    ended: chrono::DateTime<chrono::Utc>,
+    resolution: Resolution,
+) {
+    match incident.write(dir, ended, resolution) {
+        Ok(path) => info!("Wrote incident report {}", path.display()),
+        Err(e) => warn!("Failed to write incident report: {e:?}"),
+    }
+}
+
+/// Describes `trigger` for the `--webhook`.
+fn webhook_report(metrics: &Metrics, config: &Config, trigger: &Trigger) -> webhook::Report {
+    let (cause, kworkers) = match trigger.cause {
+        Cause::Runtime => ("runtime", 1),
+        Cause::CpuTime => ("cpu_time", 1),
+        Cause::SummedAge { count } => ("summed_age", count),
+        Cause::Count { count, .. } => ("count", count),
+    };
+    webhook::Report {
+        event: if trigger.test {
+            webhook::Event::TestTrigger
+        } else {
+            webhook::Event::Trigger
+        },
+        host: webhook::hostname(),
+        timestamp: clock::local(trigger.now).to_rfc3339(),
+        comm: trigger.kworker.comm.clone(),
+        pid: trigger.kworker.pid,
+        cause,
+        runtime_seconds: trigger.runtime.num_seconds(),
+        threshold_seconds: trigger.threshold.num_seconds(),
+        action: trigger.action.to_string(),
+        kworkers,
+        triggers_total: metrics.triggers(),
+        labels: config.labels.clone(),
+    }
+}
+
+/// Sends a clearly-marked synthetic trigger through the notification channels, so operators can
+/// validate their pipeline without waiting for a real stall. Never remediates.
+pub fn emit_test_event<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+) {
+    let now = system.now();
+    let threshold = config
+        .runtime_threshold
+        .unwrap_or(DEFAULT_RUNTIME_THRESHOLD);
+    let kworker = ProcInfo {
+        pid: 0,
+        uid: 0,
+        comm: String::from("test-event"),
+        cmdline: None,
+        kernel_thread: true,
+        state: 'R',
+        wchan: None,
+        starttime: now - threshold,
+        cpu_time: Duration::ZERO,
+    };
+    notify_trigger(
+        metrics,
+        state,
+        config,
+        &Trigger {
+            kworker: &kworker,
+            now,
+            cause: Cause::Runtime,
+            runtime: threshold,
+            threshold,
+            action: Action::Sync,
+            test: true,
+        },
+    );
+}
+
+/// Returns the capabilities needed by the features `config` enables.
+pub fn required_capabilities(config: &Config) -> Vec<Requirement> {
+    let mut requirements = Vec::new();
+    let signatures = config.signatures();
+    if signatures
+        .iter()
+        .any(|s| matches!(s.action, Action::Signal(_)))
+    {
+        requirements.push(Requirement {
+            capability: Capability::Kill,
+            feature: "signal actions",
+            fatal: true,
+        });
+    }
+    if signatures.iter().any(|s| s.stack.is_some()) {
+        requirements.push(Requirement {
+            capability: Capability::SysAdmin,
+            feature: "--signature stack criteria",
+            fatal: true,
+        });
+    } else {
+        if config.incident_dir.is_some() {
+            requirements.push(Requirement {
+                capability: Capability::SysAdmin,
+                feature: "kernel stacks in --incident-dir reports",
+                fatal: false,
+            });
+        }
+        if config.capture_stack {
+            requirements.push(Requirement {
+                capability: Capability::SysAdmin,
+                feature: "--capture-stack",
+                fatal: false,
+            });
+        }
+    }
+    if config.escalate_sysrq {
+        requirements.push(Requirement {
+            capability: Capability::DacOverride,
+            feature: "--escalate-sysrq",
+            fatal: false,
+        });
+    }
+    requirements
+}
+
+/// Formats `scan` for `--dump-processes`, one process per line ordered by pid, with whether it is
+/// monitored or why it was skipped.
+pub fn format_scan(scan: &Scan) -> String {
+    let mut lines: Vec<(i32, &str, &str)> = scan
+        .kworkers
+        .iter()
+        .map(|p| (p.pid, p.comm.as_str(), "monitored"))
+        .chain(scan.skipped.iter().map(|s| {
+            let comm = s.comm.as_deref().unwrap_or("?");
+            (s.pid, comm, s.reason.as_str())
+        }))
+        .collect();
+    lines.sort_unstable();
+    lines
+        .into_iter()
+        .map(|(pid, comm, verdict)| format!("{pid}\t{comm}\t{verdict}\n"))
+        .collect()
+}
+
+/// Describes the settings that differ between `old` and `new`, one `key: old -> new` line each
+/// with keys and values as printed by `--dump-config`, for logging configuration reloads.
+pub fn config_changes(old: &Config, new: &Config) -> Vec<String> {
+    let table = |config: &Config| toml::Table::try_from(config).unwrap_or_default();
+    let (old, new) = (table(old), table(new));
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort_unstable();
+    keys.dedup();
+    let show = |value: Option<&toml::Value>| value.map_or("unset".to_string(), |v| v.to_string());
+    keys.into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| format!("{key}: {} -> {}", show(old.get(key)), show(new.get(key))))
+        .collect()
+}
+
+/// Returns the first of `signatures` whose every criterion `p` matches, reading its stack only if
+/// one of them needs it.
+fn signature_of<'a, T: System>(
+    system: &T,
+    signatures: &'a [Signature],
+    p: &ProcInfo,
+) -> Option<&'a Signature> {
+    let stack = std::cell::OnceCell::new();
+    let read_stack = || match system.stack(p.pid) {
+        Ok(stack) => Some(stack),
+        Err(e) => {
+            debug!("Failed to read the stack of '{}': {e:#}", p.comm);
+            None
+        }
+    };
+    signatures
+        .iter()
+        .find(|s| s.matches(p, || stack.get_or_init(read_stack).clone()))
+}
+
+/// Returns whether `p` waits where `--require-wchan` requires, or may as its wchan is unknown.
+fn in_required_wchan(config: &Config, p: &ProcInfo) -> bool {
+    let Some(required) = &config.require_wchan else {
+        return true;
+    };
+    p.wchan
+        .as_deref()
+        .is_none_or(|wchan| wchan.contains(required.as_str()))
+}
+
+/// Returns whether `p` is one of the processes the daemon monitors, which it is if it matches a
+/// glob even if it matches no signature's other criteria, belongs to one of the `--uid`s and is
+/// in one of `MONITORED_STATES`, unless `--any-uid` and `--any-state` are given respectively. A
+/// signature with a uid of its own also monitors the processes of that uid matching its glob.
+pub fn is_monitored(config: &Config, p: &ProcInfo) -> bool {
+    let by_own_uid = || {
+        config
+            .signatures
+            .iter()
+            .chain(&config.rules)
+            .any(|s| s.uid == Some(p.uid) && matches_glob(&s.glob, p))
+    };
+    (config.any_state || MONITORED_STATES.contains(&p.state))
+        && if config.any_uid || config.uids.contains(&p.uid) {
+            config.globs().any(|glob| matches_glob(glob, p))
+        } else {
+            by_own_uid()
+        }
+}
+
+/// Sums the ages of `kworkers` at `now`, ignoring any that seem to have started in the future.
+fn sum_ages(kworkers: &[ProcInfo], now: &chrono::DateTime<chrono::Utc>) -> chrono::Duration {
+    kworkers
+        .iter()
+        .map(|p| now.signed_duration_since(p.starttime))
+        .filter(|age| *age > chrono::Duration::zero())
+        .fold(chrono::Duration::zero(), |sum, age| sum + age)
+}
+
+/// Returns why `action` shouldn't sync for `kworker`, if `--min-free-percent` is set and the
+/// filesystem it would flush is nearly full or read-only: the flushed one with `--sync-mode fs`,
+/// that of `--sync-path` for syncs of every filesystem.
+fn unsyncable<T: System>(
+    system: &T,
+    config: &Config,
+    kworker: &ProcInfo,
+    action: Action,
+) -> Option<String> {
+    let min_free_percent = config.min_free_percent.filter(|_| action == Action::Sync)?;
+    let flushed = match config.sync_mode {
+        SyncMode::Filesystem => filesystem_of(system, kworker),
+        SyncMode::Global => None,
+    };
+    let path = flushed.as_deref().unwrap_or_else(|| {
+        config
+            .sync_path
+            .as_deref()
+            .unwrap_or(Path::new(DEFAULT_SYNC_PATH))
+    });
+    match system.fs_status(path) {
+        Ok(status) => status
+            .unsyncable(min_free_percent)
+            .map(|why| format!("{} is unhealthy, {why}", path.display())),
+        Err(e) => {
+            warn!("Failed to check {}, syncing anyway: {e:#}", path.display());
+            None
+        }
+    }
+}
+
+/// Applies `action` to the stuck `kworker`.
+///
+/// Falls back to a `sync` if the process is not something we are willing to signal, or its
+/// filesystem can't be flushed alone. Not if flushing it blocked, as a `sync` would too, nor if
+/// the `--action-command` failed, as it may have done part of its job.
+fn remediate<T: System>(
+    system: &T,
+    config: &Config,
+    kworker: &ProcInfo,
+    action: Action,
+) -> anyhow::Result<()> {
+    match action {
+        Action::Signal(signal) => match check_signal_target(kworker) {
+            Ok(()) => return system.signal(kworker.pid, signal),
+            Err(e) => error!("Not running {action}, syncing instead: {e:#}"),
+        },
+        Action::Command => {
+            let command = config
+                .action_command
+                .as_deref()
+                .context("no --action-command to run")?;
+            if !system.run_command(command, ACTION_COMMAND_TIMEOUT)? {
+                anyhow::bail!("the action command failed");
+            }
+            return Ok(());
+        }
+        Action::Sync => {}
+    }
+    if config.sync_mode == SyncMode::Filesystem {
+        if let Some(mount) = filesystem_of(system, kworker) {
+            match system.sync_fs(&mount) {
+                Ok(()) => return Ok(()),
+                Err(e) if e.is::<SyncTimedOut>() || e.is::<SyncStillBlocked>() => return Err(e),
+                Err(e) => warn!("Syncing every filesystem instead: {e:#}"),
+            }
+        }
+    }
+    system.sync()
+}
+
+/// Returns where the filesystem `kworker` is flushing is mounted, if that can be determined.
+fn filesystem_of<T: System>(system: &T, kworker: &ProcInfo) -> Option<PathBuf> {
+    let Some((major, minor)) = sync_mode::flush_device(&kworker.comm) else {
+        debug!(
+            "'{}' doesn't name its device, syncing every filesystem",
+            kworker.comm
+        );
+        return None;
+    };
+    match system.mount_of((major, minor)) {
+        Ok(Some(mount)) => Some(mount),
+        Ok(None) => {
+            debug!("Device {major}:{minor} is not mounted, syncing every filesystem");
+            None
+        }
+        Err(e) => {
+            debug!(
+                "Failed to find where {major}:{minor} is mounted, syncing every filesystem: {e:#}"
+            );
+            None
+        }
+    }
+}
+
+/// What an iteration of the main loop decided.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// A stuck process was found and remediated with this action.
+    Remediated(Action),
+    /// A stuck process was found but only reported, on a dry run or outside the canary, rather
+    /// than remediated with this action.
+    Reported(Action),
+    /// A stuck process was found but not acted on yet: the system only just booted, the process
+    /// is making progress, or the last sync is too recent.
+    Deferred,
+    /// A stuck process was found, but no sync was started for it, as an earlier one is still
+    /// blocked.
+    SyncBlocked,
+    /// Matching processes were found, none of them stuck.
+    BelowThreshold,
+    /// No matching process was found, so the iteration waited for one to appear, in vain, or
+    /// didn't wait at all on a `once` pass.
+    NoKworker,
+    /// The iteration waited for a matching process to appear without scanning first, in vain.
+    WaitedForKworker,
+}
+
+impl Outcome {
+    /// Returns how long to wait before the next iteration.
+    pub fn sleep_duration(self, timings: &Timings) -> Duration {
+        match self {
+            Outcome::Remediated(_) | Outcome::Reported(_) | Outcome::SyncBlocked => {
+                timings.recovery_time
+            }
+            Outcome::Deferred | Outcome::BelowThreshold => timings.busy_poll,
+            // The wait already took its time, and ended without a process to check, so one may
+            // have been missed and a scan is due.
+            Outcome::NoKworker | Outcome::WaitedForKworker => Duration::ZERO,
+        }
+    }
+
+    /// Returns the exit status of `--once` for this outcome: `EXIT_REMEDIATED` if a stuck process
+    /// was acted on, 0 otherwise.
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Outcome::Remediated(_) => EXIT_REMEDIATED,
+            _ => 0,
+        }
+    }
+}
+
+/// The core logic of the workaround.
+///
+/// This function scans for `kworker` processes, checks if they are stuck, and triggers a `sync`
+/// if necessary. It returns what it decided, which tells how long to wait before the next check.
+pub fn workaround<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+) -> anyhow::Result<Outcome> {
+    scan_and_evaluate(
+        system,
+        metrics,
+        state,
+        config,
+        Some(config.timings.rescan_interval),
+    )
+}
+
+/// A single evaluation pass, for `--once`: as `workaround`, but returning `Outcome::NoKworker`
+/// right away if no matching process is found, rather than waiting for one to appear.
+pub fn once<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+) -> anyhow::Result<Outcome> {
+    scan_and_evaluate(system, metrics, state, config, None)
+}
+
+/// Scans for matching processes and evaluates them, waiting for up to `wait` for one to appear
+/// if there are none.
+fn scan_and_evaluate<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    wait: Option<Duration>,
+) -> anyhow::Result<Outcome> {
+    // Captured before scanning so every process's age uses the same reference point, even if the
+    // scan itself is slow.
+    let now = system.now();
+    let prefilter = CommPrefilter::new(config.globs());
+    let is_kworker = |p: &ProcInfo| is_monitored(config, p);
+    // Only the processes matching in the last scan are re-read between full scans, unless none
+    // of them still does, as waiting for one to appear needs to know that none does. Nor when
+    // every matching process counts, which also goes for measuring what the last sync cleared.
+    let candidates = state
+        .tracker()
+        .candidates(now, config.timings.rescan_interval)
+        .filter(|_| !config.counts_kworkers() && !metrics.measuring_cleared());
+    let refreshed = match candidates {
+        Some(pids) => Some(
+            system
+                .refresh_kworkers(&pids, &prefilter, is_kworker)
+                .context("failed to re-read the matching kworker processes")?,
+        )
+        .filter(|scan| !scan.kworkers.is_empty()),
+        None => None,
+    };
+    let (scan, full_scan) = match refreshed {
+        Some(scan) => (scan, false),
+        None => (
+            system
+                .find_all_kworkers(&prefilter, is_kworker)
+                .context("failed to scan for matching kworker processes")?,
+            true,
+        ),
+    };
+    metrics.record_scan(&now);
+    metrics.record_skips(&scan.skipped);
+    let found = Found {
+        kworkers: scan.kworkers,
+        now,
+        full_scan,
+    };
+    evaluate(system, metrics, state, config, found, wait)
+}
+
+/// The matching processes at `now`, found by a scan of every process if `full_scan`, else of the
+/// last ones.
+struct Found {
+    kworkers: Vec<ProcInfo>,
+    now: chrono::DateTime<chrono::Utc>,
+    full_scan: bool,
+}
+
+/// Checks whether any of the `found` processes is stuck and acts on it. If there are none, waits
+/// for up to `wait` for one to appear.
+fn evaluate<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    found: Found,
+    wait: Option<Duration>,
+) -> anyhow::Result<Outcome> {
+    let Found {
+        kworkers,
+        now,
+        full_scan,
+    } = found;
+    let count = kworkers.len();
+    if let Some(cleared) = metrics.record_kworker_count(count) {
+        info!(
+            "Last sync cleared {} kworkers ({} left), {:.1} per sync on average",
+            cleared.kworkers, count, cleared.average_per_sync
+        );
+    }
+    let summed_age = config
+        .sum_age_threshold
+        .map(|_| (sum_ages(&kworkers, &now), count));
+    // Runtimes are how long each process has been matching, which may be shorter than it has
+    // been running.
+    let since = {
+        let mut tracker = state.tracker();
+        if let Some(last_scan) = tracker.last_scan().filter(|last_scan| *last_scan > now) {
+            warn!(
+                "The clock went back by {} since the last scan, runtimes are underestimated",
+                format_signed_duration(last_scan - now)
+            );
+        }
+        if full_scan {
+            tracker.observe(&kworkers, now)
+        } else {
+            tracker.observe_candidates(&kworkers, now)
+        }
+    };
+    // Clamped, as a process can't have been running for less than nothing even if clocks say so.
+    let runtime_of = |p: &ProcInfo| {
+        now.signed_duration_since(since.get(&tracker::key(p)).copied().unwrap_or(p.starttime))
+            .max(chrono::Duration::zero())
+    };
+    let mut kworkers = kworkers;
+    kworkers.sort_by_key(|p| std::cmp::Reverse(runtime_of(p)));
+    metrics.record_kworkers(count, kworkers.first().map(runtime_of));
+
+    if let Some(oldest) = kworkers.first() {
+        debug!(
+            "Oldest kworker runtime: {}",
+            format_signed_duration(runtime_of(oldest))
+        );
+
+        let signatures = config.signatures();
+        let threshold_of = |s: &Signature| s.threshold.or(config.runtime_threshold);
+        let crossed = |s: &Signature, runtime| threshold_of(s).is_some_and(|t| runtime > t);
+        // The oldest process to have run for longer than its signature allows, if it has a
+        // threshold. Stacks are only read for processes that are old enough under some signature
+        // their state matches.
+        let stuck = kworkers
+            .iter()
+            .filter(|p| in_required_wchan(config, p))
+            .find_map(|p| {
+                let runtime = runtime_of(p);
+                signatures
+                    .iter()
+                    .any(|s| s.matches_cheaply(p) && crossed(s, runtime))
+                    .then(|| signature_of(system, &signatures, p))
+                    .flatten()
+                    .filter(|s| crossed(s, runtime))
+                    .and_then(|s| Some((p, runtime, threshold_of(s)?, s.action)))
+            });
+        // The process that consumed the most CPU time, if more than allowed.
+        let cpu_trigger = config.cpu_threshold.and_then(|cpu_threshold| {
+            kworkers
+                .iter()
+                .filter(|p| in_required_wchan(config, p))
+                .filter_map(|p| Some((p, duration::to_chrono(p.cpu_time).ok()?)))
+                .filter(|(_, cpu_time)| *cpu_time > cpu_threshold)
+                .max_by_key(|(_, cpu_time)| *cpu_time)
+                .map(|(p, cpu_time)| (p, cpu_time, cpu_threshold))
+        });
+        let summed_trigger = summed_age
+            .zip(config.sum_age_threshold)
+            .filter(|((sum, _), sum_threshold)| sum > sum_threshold);
+        let count_trigger = config.min_stuck_count.filter(|&min| count >= min);
+        let (kworker, cause, runtime, threshold, action) =
+            if let Some((kworker, runtime, threshold, action)) = stuck {
+                (kworker, Cause::Runtime, runtime, threshold, action)
+            } else if let Some((kworker, cpu_time, cpu_threshold)) = cpu_trigger {
+                let action = signature_of(system, &signatures, kworker)
+                    .map_or(config.default_action, |s| s.action);
+                (kworker, Cause::CpuTime, cpu_time, cpu_threshold, action)
+            } else if let Some(((sum, count), sum_threshold)) = summed_trigger {
+                let action = signature_of(system, &signatures, oldest)
+                    .map_or(config.default_action, |s| s.action);
+                (
+                    oldest,
+                    Cause::SummedAge { count },
+                    sum,
+                    sum_threshold,
+                    action,
+                )
+            } else if let Some(min) = count_trigger {
+                let signature = signature_of(system, &signatures, oldest);
+                (
+                    oldest,
+                    Cause::Count { count, min },
+                    runtime_of(oldest),
+                    signature
+                        .map_or(config.runtime_threshold, threshold_of)
+                        .unwrap_or_else(chrono::Duration::zero),
+                    signature.map_or(config.default_action, |s| s.action),
+                )
+            } else {
+                if let Some(warn_threshold) = config.warn_threshold {
+                    let runtime = runtime_of(oldest);
+                    if runtime > warn_threshold && metrics.record_warning(tracker::key(oldest)) {
+                        let follows = config.runtime_threshold.map_or_else(
+                            || String::from("--runtime-threshold is off"),
+                            |t| format!("action follows at {}", format_signed_duration(t)),
+                        );
+                        warn!(
+                            "'{}' (pid {}) has been running for {}, past --warn-threshold ({}), \
+                             {follows}",
+                            oldest.comm,
+                            oldest.pid,
+                            format_signed_duration(runtime),
+                            format_signed_duration(warn_threshold),
+                        );
+                    }
+                }
+                resolve_incident(state, config, now);
+                metrics.set_status(Status::Watching);
+                return Ok(Outcome::BelowThreshold);
+            };
+
+        if let Some(first_action) = config.first_action_after_boot {
+            match system.uptime() {
+                Ok(uptime) if uptime < first_action => {
+                    info!(
+                        "Not acting on '{}' yet, the system has only been up for {} \
+                         (--first-action-after-boot: {})",
+                        kworker.comm,
+                        format_signed_duration(uptime),
+                        format_signed_duration(first_action)
+                    );
+                    metrics.set_status(Status::Watching);
+                    return Ok(Outcome::Deferred);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Ignoring --first-action-after-boot: {e:?}"),
+            }
+        }
+
+        if config.require_no_progress {
+            match system.cpu_time_over(kworker.pid, PROGRESS_SAMPLE_INTERVAL) {
+                Ok(cpu_time) if cpu_time > NO_PROGRESS_CPU_TIME => {
+                    info!(
+                        "Not acting on '{}', it used {} of CPU time over {} so it is making \
+                         progress",
+                        kworker.comm,
+                        format_duration(cpu_time),
+                        format_duration(PROGRESS_SAMPLE_INTERVAL)
+                    );
+                    metrics.set_status(Status::Watching);
+                    return Ok(Outcome::Deferred);
+                }
+                Ok(_) => {}
+                // Most likely, it exited in the meantime. The next scan tells.
+                Err(e) => {
+                    info!(
+                        "Not acting on '{}' yet, failed to sample its CPU time: {e:#}",
+                        kworker.comm
+                    );
+                    metrics.set_status(Status::Watching);
+                    return Ok(Outcome::Deferred);
+                }
+            }
+        }
+
+        if action == Action::Sync {
+            let since_last_sync = state
+                .last_sync()
+                .map(|last| now.signed_duration_since(last));
+            if let Some(since) = since_last_sync.filter(|since| *since < config.sync_cooldown) {
+                debug!(
+                    "Not syncing for '{}', the last sync was only {} ago (--sync-cooldown: {})",
+                    kworker.comm,
+                    format_signed_duration(since),
+                    format_signed_duration(config.sync_cooldown)
+                );
+                metrics.set_status(Status::Watching);
+                return Ok(Outcome::Deferred);
+            }
+        }
+
+        let trigger = Trigger {
+            kworker,
+            now,
+            cause,
+            runtime,
+            threshold,
+            action,
+            test: false,
+        };
+        let crossing = notify_trigger(metrics, state, config, &trigger);
+        if let (Some(dir), Some(crossing)) = (&config.incident_dir, crossing) {
+            record_incident(system, state, dir, &trigger, crossing);
+        }
+        if config.dry_run || config.detect_only {
+            let why = if config.dry_run {
+                "this is a dry run"
+            } else {
+                "this host is outside the canary"
+            };
+            info!("Not acting on '{}', {why}", kworker.comm);
+            if let Some(incident) = state.incident().as_mut() {
+                incident.record(now, format!("Not acting, {why}"));
+            }
+            metrics.set_status(Status::Watching);
+            return Ok(Outcome::Reported(action));
+        }
+        if let Some(why) = unsyncable(system, config, kworker, action) {
+            warn!("Not syncing for '{}', only detecting: {why}", kworker.comm);
+            if let Some(incident) = state.incident().as_mut() {
+                incident.record(now, format!("Not syncing, {why}"));
+            }
+            metrics.set_status(Status::Watching);
+            return Ok(Outcome::Reported(action));
+        }
+        if config.capture_stack {
+            capture_stack(system, config, kworker, now);
+        }
+        let timed_out = match remediate(system, config, kworker, action) {
+            Ok(()) => None,
+            Err(e) if e.is::<SyncStillBlocked>() => {
+                // As ineffective as the blocked one, which may be for an earlier episode.
+                warn!("Not syncing for '{}': {e}", kworker.comm);
+                let ineffective = state.record_synced_kworker(tracker::key(kworker));
+                check_escalation(system, metrics, state, config, kworker, ineffective);
+                return Ok(Outcome::SyncBlocked);
+            }
+            // It was issued all the same, and may still clear the stall once it returns.
+            Err(e) if e.is::<SyncTimedOut>() => {
+                metrics.record_sync_timeout();
+                Some(e)
+            }
+            Err(e) => return Err(e.context(format!("failed to run {action}"))),
+        };
+        if let Some(incident) = state.incident().as_mut() {
+            incident.record_action(system.now(), format!("Ran {action}"));
+        }
+        let ineffective = if action == Action::Sync {
+            metrics.record_sync(count, now);
+            if let Err(e) = state.record_sync(now, tracker::key(kworker)) {
+                warn!("Failed to record the sync: {e:?}");
+            }
+            state.record_synced_kworker(tracker::key(kworker))
+        } else {
+            0
+        };
+        metrics.set_status(Status::Remediating);
+        if let Some(e) = timed_out {
+            check_escalation(system, metrics, state, config, kworker, ineffective);
+            return Err(e.context(format!("failed to run {action}")));
+        }
+        if let Some(command) = &config.verify_command {
+            verify_remediation(system, metrics, state, command, action);
+        }
+        check_escalation(system, metrics, state, config, kworker, ineffective);
+        Ok(Outcome::Remediated(action))
+    } else {
+        resolve_incident(state, config, now);
+        metrics.set_status(Status::Idle);
+        let Some(timeout) = wait else {
+            info!("No matching kworkers found");
+            return Ok(Outcome::NoKworker);
+        };
+        info!("No matching kworkers found, waiting for a new one to appear");
+        let found = system
+            .wait_for_kworker(
+                |p: &ProcInfo| is_monitored(config, p),
+                |pid| state.tracker().forget(pid),
+                timeout,
+            )
+            .context("failed to wait for kworker process")?;
+        after_wait(system, metrics, state, config, found, Outcome::NoKworker)
+    }
+}
+
+/// Evaluates the process `wait_for_kworker` found right away, rather than scanning for it again,
+/// or returns `otherwise` if it found none.
+fn after_wait<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    found: Option<ProcInfo>,
+    otherwise: Outcome,
+) -> anyhow::Result<Outcome> {
+    let now = system.now();
+    match found {
+        Some(kworker) => {
+            record_event(
+                state,
+                now,
+                Event::KworkerAppeared {
+                    pid: kworker.pid,
+                    comm: kworker.comm.clone(),
+                    runtime_s: now
+                        .signed_duration_since(kworker.starttime)
+                        .max(chrono::Duration::zero())
+                        .num_milliseconds() as f64
+                        / 1000.0,
+                },
+            );
+            // Others may have appeared alongside it, which the next full scan finds.
+            let found = Found {
+                kworkers: vec![kworker],
+                now,
+                full_scan: false,
+            };
+            evaluate(system, metrics, state, config, found, None)
+        }
+        None => {
+            record_event(state, now, Event::WaitTimeout);
+            Ok(otherwise)
+        }
+    }
+}
+
+/// Runs the first iteration of the main loop, as `workaround` does but according to `behavior`.
+pub fn first_iteration<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    behavior: StartupBehavior,
+) -> anyhow::Result<Outcome> {
+    match behavior {
+        StartupBehavior::Scan => workaround(system, metrics, state, config),
+        StartupBehavior::Wait => {
+            info!("Waiting for a new kworker to appear before the first scan");
+            let found = system
+                .wait_for_kworker(
+                    |p: &ProcInfo| is_monitored(config, p),
+                    |pid| state.tracker().forget(pid),
+                    config.timings.rescan_interval,
+                )
+                .context("failed to wait for kworker process")?;
+            after_wait(
+                system,
+                metrics,
+                state,
+                config,
+                found,
+                Outcome::WaitedForKworker,
+            )
+        }
+    }
+}
+
+/// Returns how long to sleep after an iteration, backing off if it failed.
+pub fn sleep_duration_after(
+    result: anyhow::Result<Outcome>,
+    metrics: &Metrics,
+    timings: &Timings,
+) -> Duration {
+    match result {
+        Ok(outcome) => {
+            debug!("Iteration outcome: {outcome:?}");
+            outcome.sleep_duration(timings)
+        }
+        Err(e) => {
+            error!("An error occurred: {e:?}");
+            metrics.set_status(Status::Degraded);
+            timings.error_backoff
+        }
+    }
+}
+
+/// Runs the `--verify-command` after `action`, reporting whether the stall was resolved.
+///
+/// A remediation that didn't help leaves the daemon degraded.
+fn verify_remediation<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    command: &str,
+    action: Action,
+) {
+    let resolved = match system.run_command(command, VERIFY_COMMAND_TIMEOUT) {
+        Ok(true) => {
+            info!("Verify command reports the stall was resolved by {action}");
+            true
+        }
+        Ok(false) => {
+            warn!("Verify command reports the stall persists after {action}");
+            metrics.set_status(Status::Degraded);
+            false
+        }
+        Err(e) => {
+            warn!("Failed to run the verify command: {e:?}");
+            return;
+        }
+    };
+    metrics.record_verification(resolved);
+    if let Some(incident) = state.incident().as_mut() {
+        let verdict = if resolved { "was resolved" } else { "persists" };
+        incident.record(
+            system.now(),
+            format!("Verify command reports the stall {verdict}"),
+        );
+    }
+}
+
+/// Escalates if `ineffective` syncs in a row left `kworker` stuck, as many as allowed or more, as
+/// a reload may lower the limit or a restart restore a longer run. Only once per run.
+fn check_escalation<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    kworker: &ProcInfo,
+    ineffective: usize,
+) {
+    if config.max_ineffective_syncs > 0
+        && ineffective >= config.max_ineffective_syncs
+        && state.first_escalation()
+    {
+        escalate(system, metrics, state, config, kworker, ineffective);
+    }
+}
+
+/// Reports that `ineffective` syncs in a row left `kworker` stuck, requesting an emergency sync
+/// with `--escalate-sysrq` and running the `--escalation-command` if any.
+fn escalate<T: System>(
+    system: &T,
+    metrics: &Metrics,
+    state: &DaemonState,
+    config: &Config,
+    kworker: &ProcInfo,
+    ineffective: usize,
+) {
+    error!(
+        "'{}' (pid {}) is still stuck after {ineffective} syncs in a row, syncing doesn't help",
+        kworker.comm, kworker.pid
+    );
+    metrics.set_status(Status::Degraded);
+    if let Some(incident) = state.incident().as_mut() {
+        incident.record(
+            system.now(),
+            format!("Escalated after {ineffective} ineffective syncs"),
+        );
+    }
+    if config.escalate_sysrq {
+        match system.emergency_sync() {
+            Ok(()) => {
+                warn!("Requested an emergency sync from the kernel, with SysRq 's'");
+                if let Some(incident) = state.incident().as_mut() {
+                    incident.record_action(system.now(), String::from("Ran SysRq emergency sync"));
+                }
+            }
+            Err(e) => error!("Failed to request an emergency sync: {e:#}"),
+        }
+    }
+    let Some(command) = &config.escalation_command else {
+        return;
+    };
+    match system.run_command(command, VERIFY_COMMAND_TIMEOUT) {
+        Ok(true) => info!("Ran the escalation command"),
+        Ok(false) => warn!("The escalation command failed"),
+        Err(e) => warn!("Failed to run the escalation command: {e:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs_status::FsStatus;
+    use crate::keepalive::KeepAlive;
+    use crate::state_file::StateFile;
+    use crate::system::{IsKworkerFn, ProcInfo, SkipReason, Skipped, SyncRunner, System};
+    use anyhow::Result;
+    use rustix::process::Signal;
+    use std::cell::{Cell, RefCell};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    struct MockSystem {
+        kworker: Option<ProcInfo>,
+        /// Further matching processes, besides `kworker`.
+        other_kworkers: Vec<ProcInfo>,
+        now: chrono::DateTime<chrono::Utc>,
+        uptime: chrono::Duration,
+        /// How far the clock advances while `find_all_kworkers` runs.
+        scan_latency: chrono::Duration,
+        elapsed: Cell<chrono::Duration>,
+        scan_calls: Cell<usize>,
+        /// How many scans re-read only the given processes, which `scan_calls` doesn't count.
+        refresh_calls: Cell<usize>,
+        wait_calls: Cell<usize>,
+        sync_calls: Cell<usize>,
+        signals: RefCell<Vec<(i32, Signal)>>,
+        commands: RefCell<Vec<String>>,
+        command_result: Result<bool, String>,
+        /// What `emergency_sync` returns, e.g. an error for kernels without SysRq.
+        emergency_sync_result: Result<(), String>,
+        emergency_syncs: Cell<usize>,
+        /// What `cpu_time_over` returns, the interval elapsing either way.
+        cpu_time: Result<Duration, String>,
+        /// The kernel stacks `stack` returns by pid, others being unreadable.
+        stacks: Vec<(i32, &'static str)>,
+        stack_reads: Cell<usize>,
+        /// The mount points `mount_of` returns by device, others being unmounted.
+        mounts: Vec<((u32, u32), &'static str)>,
+        sync_fs_calls: RefCell<Vec<PathBuf>>,
+        sync_fs_result: Result<(), String>,
+        sync_result: Result<(), String>,
+        /// How long `sync` blocks, timing out past 50ms.
+        sync_blocked_for: Duration,
+        sync_runner: SyncRunner,
+        /// What `wait_for_kworker` returns: the process that appeared, if any.
+        wait_for_kworker_result: Result<Option<ProcInfo>, String>,
+        /// What `fs_status` returns, whatever the filesystem.
+        fs_status: Result<FsStatus, String>,
+        fs_status_calls: RefCell<Vec<PathBuf>>,
+    }
+
+    impl Default for MockSystem {
+        fn default() -> Self {
+            Self {
+                kworker: None,
+                other_kworkers: Vec::new(),
+                now: chrono::Utc::now(),
+                uptime: chrono::Duration::days(1),
+                scan_latency: chrono::Duration::zero(),
+                elapsed: Cell::new(chrono::Duration::zero()),
+                scan_calls: Cell::new(0),
+                refresh_calls: Cell::new(0),
+                wait_calls: Cell::new(0),
+                sync_calls: Cell::new(0),
+                signals: RefCell::new(Vec::new()),
+                commands: RefCell::new(Vec::new()),
+                command_result: Ok(true),
+                emergency_sync_result: Ok(()),
+                emergency_syncs: Cell::new(0),
+                cpu_time: Ok(Duration::ZERO),
+                stacks: Vec::new(),
+                stack_reads: Cell::new(0),
+                mounts: Vec::new(),
+                sync_fs_calls: RefCell::new(Vec::new()),
+                sync_fs_result: Ok(()),
+                sync_result: Ok(()),
+                sync_blocked_for: Duration::ZERO,
+                sync_runner: SyncRunner::default(),
+                wait_for_kworker_result: Ok(None),
+                fs_status: Ok(FsStatus {
+                    available: 500,
+                    total: 1000,
+                    read_only: false,
+                }),
+                fs_status_calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl MockSystem {
+        /// Scans the processes `included` keeps, as `find_all_kworkers` and `refresh_kworkers` do.
+        fn scan<F: IsKworkerFn>(
+            &self,
+            included: impl Fn(&ProcInfo) -> bool,
+            prefilter: &CommPrefilter,
+            is_kworker: F,
+        ) -> Result<Scan> {
+            self.elapsed.set(self.elapsed.get() + self.scan_latency);
+            // Applied like the live system does, so tests catch a prefilter rejecting matches.
+            let (kworkers, skipped): (Vec<_>, Vec<_>) = self
+                .kworker
+                .iter()
+                .chain(&self.other_kworkers)
+                .filter(|p| included(p))
+                .cloned()
+                .partition(|p| {
+                    (p.cmdline.is_some() || prefilter.may_match(&p.comm)) && is_kworker(p)
+                });
+            let skipped = skipped
+                .into_iter()
+                .map(|p| Skipped {
+                    pid: p.pid,
+                    comm: Some(p.comm),
+                    reason: SkipReason::NotMonitored,
+                })
+                .collect();
+            Ok(Scan { kworkers, skipped })
+        }
+    }
+
+    impl System for MockSystem {
+        fn find_all_kworkers<F: IsKworkerFn>(
+            &self,
+            prefilter: &CommPrefilter,
+            is_kworker: F,
+        ) -> Result<Scan> {
+            self.scan_calls.set(self.scan_calls.get() + 1);
+            self.scan(|_| true, prefilter, is_kworker)
+        }
+
+        fn refresh_kworkers<F: IsKworkerFn>(
+            &self,
+            pids: &[i32],
+            prefilter: &CommPrefilter,
+            is_kworker: F,
+        ) -> Result<Scan> {
+            self.refresh_calls.set(self.refresh_calls.get() + 1);
+            self.scan(|p| pids.contains(&p.pid), prefilter, is_kworker)
+        }
+
+        fn now(&self) -> chrono::DateTime<chrono::Utc> {
+            self.now + self.elapsed.get()
+        }
+
+        fn uptime(&self) -> Result<chrono::Duration> {
+            Ok(self.uptime + self.elapsed.get())
+        }
+
+        fn wait_for_kworker<F: IsKworkerFn, X: FnMut(i32)>(
+            &self,
+            _is_kworker: F,
+            _exited: X,
+            _timeout: Duration,
+        ) -> Result<Option<ProcInfo>> {
+            self.wait_calls.set(self.wait_calls.get() + 1);
+            self.wait_for_kworker_result
+                .clone()
+                .map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn sync(&self) -> Result<()> {
+            self.sync_calls.set(self.sync_calls.get() + 1);
+            let blocked_for = self.sync_blocked_for;
+            self.sync_runner.run(
+                Duration::from_millis(50),
+                &KeepAlive::default(),
+                move || std::thread::sleep(blocked_for),
+            )?;
+            self.sync_result.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn sync_fs(&self, mount: &Path) -> Result<()> {
+            self.sync_fs_calls.borrow_mut().push(mount.to_path_buf());
+            self.sync_fs_result.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn mount_of(&self, device: (u32, u32)) -> Result<Option<PathBuf>> {
+            Ok(self
+                .mounts
+                .iter()
+                .find(|(d, _)| *d == device)
+                .map(|(_, mount)| PathBuf::from(mount)))
+        }
+
+        fn fs_status(&self, path: &Path) -> Result<FsStatus> {
+            self.fs_status_calls.borrow_mut().push(path.to_path_buf());
+            self.fs_status.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn signal(&self, pid: i32, signal: Signal) -> Result<()> {
+            self.signals.borrow_mut().push((pid, signal));
+            Ok(())
+        }
+
+        fn cpu_time_over(&self, _pid: i32, interval: Duration) -> Result<Duration> {
+            self.elapsed
+                .set(self.elapsed.get() + chrono::Duration::from_std(interval).unwrap());
+            self.cpu_time.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn stack(&self, pid: i32) -> Result<String> {
+            self.stack_reads.set(self.stack_reads.get() + 1);
+            self.stacks
+                .iter()
+                .find(|(p, _)| *p == pid)
+                .map(|(_, stack)| stack.to_string())
+                .ok_or_else(|| anyhow::anyhow!("permission denied"))
+        }
+
+        fn run_command(&self, command: &str, _timeout: Duration) -> Result<bool> {
+            self.commands.borrow_mut().push(command.to_string());
+            self.command_result.clone().map_err(|e| anyhow::anyhow!(e))
+        }
+
+        fn emergency_sync(&self) -> Result<()> {
+            self.emergency_syncs.set(self.emergency_syncs.get() + 1);
+            self.emergency_sync_result
+                .clone()
+                .map_err(|e| anyhow::anyhow!(e))
+        }
+    }
+
+    fn proc_info(comm: &str, starttime: chrono::DateTime<chrono::Utc>) -> ProcInfo {
+        ProcInfo {
+            pid: 1000,
+            uid: 0,
+            comm: comm.to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'R',
+            wchan: None,
+            starttime,
+            cpu_time: Duration::ZERO,
+        }
+    }
+
+    fn test_config(process_glob: &str) -> Config {
+        Config {
+            process_globs: vec![ProcessGlob::new(process_glob)],
+            ..Config::default()
+        }
+    }
+
+    /// Runs `workaround` with fresh metrics and state, as the first iteration of a daemon.
+    fn workaround_afresh<T: System>(system: &T, config: &Config) -> anyhow::Result<Outcome> {
+        workaround(system, &Metrics::default(), &DaemonState::default(), config)
+    }
+
+    #[test]
+    fn test_monitor_and_sync_no_kworker() {
+        let system = MockSystem::default();
+
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::NoKworker);
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            Duration::from_secs(0)
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_once_exits_without_waiting() {
+        let now = chrono::Utc::now();
+        let config = test_config("kworker/*");
+        let run_once = |kworker_age: Option<chrono::Duration>, sync_result| {
+            let system = MockSystem {
+                kworker: kworker_age.map(|age| proc_info("kworker/0:1", now - age)),
+                now,
+                sync_result,
+                ..MockSystem::default()
+            };
+            let result = once(
+                &system,
+                &Metrics::default(),
+                &DaemonState::default(),
+                &config,
+            );
+            assert_eq!(system.wait_calls.get(), 0);
+            result.map(Outcome::exit_code)
+        };
+
+        assert_eq!(run_once(None, Ok(())).unwrap(), 0);
+        assert_eq!(
+            run_once(Some(chrono::Duration::seconds(10)), Ok(())).unwrap(),
+            0
+        );
+        assert_eq!(
+            run_once(Some(chrono::Duration::seconds(40)), Ok(())).unwrap(),
+            EXIT_REMEDIATED
+        );
+        assert!(run_once(Some(chrono::Duration::seconds(40)), Err("EIO".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_monitor_and_sync_kworker_below_threshold() {
+        let now = chrono::Utc::now();
+        let proc = proc_info("kworker/0:1", now - chrono::Duration::seconds(10));
+        let system = MockSystem {
+            kworker: Some(proc),
+            now,
+            ..MockSystem::default()
+        };
+
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_kworker_above_threshold() {
+        let now = chrono::Utc::now();
+        let proc = proc_info("kworker/0:1", now - chrono::Duration::seconds(40));
+        let system = MockSystem {
+            kworker: Some(proc),
+            now,
+            ..MockSystem::default()
+        };
+
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_failed_sync_backs_off_instead_of_waiting_for_recovery() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            sync_result: Err("Input/output error".to_string()),
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+
+        let result = workaround(&system, &metrics, &state, &test_config("kworker/*"));
+        assert!(format!("{:#}", result.as_ref().unwrap_err()).contains("Input/output error"));
+        assert_eq!(
+            sleep_duration_after(result, &metrics, &Timings::default()),
+            IDLE_POLLING
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+        assert_eq!(metrics.last_sync(), None);
+    }
+
+    #[test]
+    fn test_blocked_sync_times_out_and_monitoring_resumes() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            sync_blocked_for: Duration::from_secs(2),
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+
+        let started = std::time::Instant::now();
+        let result = workaround(&system, &metrics, &state, &test_config("kworker/*"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(result.as_ref().unwrap_err().is::<SyncTimedOut>());
+        assert_eq!(
+            sleep_duration_after(result, &metrics, &Timings::default()),
+            IDLE_POLLING
+        );
+        assert!(metrics
+            .render()
+            .contains("\nstuck_wbs_sync_timeouts_total 1\n"));
+        // It was issued all the same, for --sync-cooldown and --max-syncs.
+        assert_eq!(metrics.last_sync(), Some(now));
+        assert!(metrics.render().contains("\nstuck_wbs_sync_total 1\n"));
+
+        let config = test_config("kworker/*");
+        let retry = workaround(&system, &metrics, &state, &config);
+        assert_eq!(retry.unwrap(), Outcome::Deferred);
+
+        // Retrying past the cooldown while it is still blocked doesn't start another.
+        system.elapsed.set(config.sync_cooldown);
+        let started = std::time::Instant::now();
+        let retry = workaround(&system, &metrics, &state, &config);
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(retry.unwrap(), Outcome::SyncBlocked);
+        assert!(metrics
+            .render()
+            .contains("\nstuck_wbs_sync_timeouts_total 1\n"));
+    }
+
+    #[test]
+    fn test_warn_threshold_warns_before_acting() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            warn_threshold: Some(chrono::Duration::seconds(20)),
+            ..test_config("kworker/*")
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        // The same process, `age` seconds after it started.
+        let run = |age| {
+            let system = MockSystem {
+                kworker: Some(proc_info("kworker/0:1", now)),
+                now: now + chrono::Duration::seconds(age),
+                ..MockSystem::default()
+            };
+            let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+            (outcome, system.sync_calls.get())
+        };
+        let warnings = || {
+            metrics
+                .render()
+                .lines()
+                .find_map(|l| l.strip_prefix("stuck_wbs_warnings_total "))
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(run(10), (Outcome::BelowThreshold, 0));
+        assert_eq!(warnings(), "0");
+        assert_eq!(run(25), (Outcome::BelowThreshold, 0));
+        assert_eq!(warnings(), "1");
+        // The same process is only warned about once.
+        assert_eq!(run(25), (Outcome::BelowThreshold, 0));
+        assert_eq!(warnings(), "1");
+        assert_eq!(run(40), (Outcome::Remediated(Action::Sync), 1));
+        assert_eq!(warnings(), "1");
+    }
+
+    #[test]
+    fn test_warn_threshold_must_not_exceed_runtime_threshold() {
+        let config = |warn| Config {
+            warn_threshold: Some(chrono::Duration::seconds(warn)),
+            ..Config::default()
+        };
+        assert!(config(30).validate().is_ok());
+        assert_eq!(
+            config(60).validate().unwrap_err().to_string(),
+            "--warn-threshold (1m) must not exceed --runtime-threshold (30s)"
+        );
+    }
+
+    #[test]
+    fn test_absurd_runtime_thresholds_warn() {
+        let config = |threshold| Config {
+            runtime_threshold: Some(threshold),
+            ..Config::default()
+        };
+        let second = chrono::Duration::seconds(1);
+        let hour = chrono::Duration::hours(1);
+        assert_eq!(
+            config(second - chrono::Duration::milliseconds(1)).threshold_warnings(),
+            [
+                "--runtime-threshold (999ms) is below 1s, so processes doing their work as usual \
+              will likely be acted on"
+            ]
+        );
+        assert!(config(second).threshold_warnings().is_empty());
+        assert!(config(hour).threshold_warnings().is_empty());
+        assert_eq!(
+            config(hour + second).threshold_warnings(),
+            ["--runtime-threshold (1h 1s) is above 1h, so stalls will likely never be acted on"]
+        );
+        // Still honored.
+        assert!(config(hour + second).validate().is_ok());
+
+        let per_signature = Config {
+            signatures: vec![Signature {
+                threshold: Some(chrono::Duration::hours(2)),
+                ..Signature::new("jbd2/*", Action::Sync)
+            }],
+            ..Config::default()
+        };
+        assert_eq!(
+            per_signature.threshold_warnings(),
+            ["the threshold of 'jbd2/*' (2h) is above 1h, so stalls will likely never be acted on"]
+        );
+
+        assert_eq!(
+            config(-second).validate().unwrap_err().to_string(),
+            "--runtime-threshold must not be negative, got -1s"
+        );
+    }
+
+    #[test]
+    fn test_first_action_after_boot_is_anchored_to_uptime() {
+        let now = chrono::Utc::now();
+        let stuck_at_uptime = |uptime| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            uptime,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            first_action_after_boot: Some(chrono::Duration::minutes(5)),
+            ..test_config("kworker/*")
+        };
+
+        // Stuck for longer than the threshold, but too soon after boot.
+        let system = stuck_at_uptime(chrono::Duration::minutes(4));
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Deferred);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        let system = stuck_at_uptime(chrono::Duration::minutes(5));
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+
+        // Without the option, uptime doesn't matter.
+        let system = stuck_at_uptime(chrono::Duration::seconds(10));
+        workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_require_no_progress_spares_progressing_workers() {
+        let now = chrono::Utc::now();
+        let stuck_using = |cpu_time| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            cpu_time,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            require_no_progress: true,
+            ..test_config("kworker/*")
+        };
+
+        // Busy, so progressing.
+        let system = stuck_using(Ok(Duration::from_millis(800)));
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Deferred);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        // Gone while sampled.
+        let system = stuck_using(Err("no such process".to_string()));
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Deferred);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        // Stalled: no more than accounting noise.
+        for cpu_time in [Duration::ZERO, NO_PROGRESS_CPU_TIME] {
+            let system = stuck_using(Ok(cpu_time));
+            let outcome = workaround_afresh(&system, &config).unwrap();
+            assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+            assert_eq!(
+                outcome.sleep_duration(&Timings::default()),
+                EXPECTED_RECOVERY_TIME
+            );
+            assert_eq!(system.sync_calls.get(), 1);
+        }
+    }
+
+    #[test]
+    fn test_detect_only_reports_without_acting() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let config = Config {
+            canary_percent: Some(10),
+            detect_only: true,
+            ..test_config("kworker/*")
+        };
+
+        let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::Reported(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+        assert_eq!(metrics.triggers(), 1);
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_syncing() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let config = Config {
+            dry_run: true,
+            ..test_config("kworker/*")
+        };
+
+        let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::Reported(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+        assert_eq!(metrics.triggers(), 1);
+    }
+
+    #[test]
+    fn test_runtime_counts_from_when_a_process_started_matching() {
+        let start = chrono::Utc::now();
+        let at = |s| start + chrono::Duration::seconds(s);
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let config = test_config("kworker/*");
+        let scan = |now, kworker: Option<ProcInfo>| {
+            let system = MockSystem {
+                kworker,
+                now,
+                ..MockSystem::default()
+            };
+            let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+            (outcome, system.sync_calls.get())
+        };
+
+        // Started an hour ago, but only just picked up the matching work.
+        let pooled = proc_info("kworker/0:1", at(-3600));
+        assert_eq!(scan(at(0), None), (Outcome::NoKworker, 0));
+        assert_eq!(
+            scan(at(1), Some(pooled.clone())),
+            (Outcome::BelowThreshold, 0)
+        );
+        assert_eq!(
+            scan(at(31), Some(pooled)),
+            (Outcome::Remediated(Action::Sync), 1)
+        );
+
+        // Around for more than the threshold in total, but the pid was reused in between.
+        let first = proc_info("kworker/0:1", at(35));
+        let reused = proc_info("kworker/0:1", at(60));
+        assert_eq!(scan(at(45), Some(first)), (Outcome::BelowThreshold, 0));
+        assert_eq!(scan(at(70), Some(reused)), (Outcome::BelowThreshold, 0));
+    }
+
+    #[test]
+    fn test_sync_cooldown_suppresses_rapid_syncs() {
+        let now = chrono::Utc::now();
+        let kworker = proc_info("kworker/0:1", now - chrono::Duration::seconds(40));
+        let stuck_at = |now| MockSystem {
+            kworker: Some(kworker.clone()),
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let config = test_config("kworker/*");
+
+        let system = stuck_at(now);
+        assert_eq!(
+            workaround(&system, &metrics, &state, &config).unwrap(),
+            Outcome::Remediated(Action::Sync)
+        );
+        assert_eq!(
+            workaround(&system, &metrics, &state, &config).unwrap(),
+            Outcome::Deferred
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+        assert_eq!(metrics.triggers(), 1);
+
+        let later = stuck_at(now + DEFAULT_SYNC_COOLDOWN);
+        workaround(&later, &metrics, &state, &config).unwrap();
+        assert_eq!(later.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_state_file_carries_syncs_across_restarts() {
+        let path = std::env::temp_dir().join(format!("stuck_wbs_{}.state", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let now = chrono::Utc::now();
+        let config = Config {
+            escalation_command: Some("page-oncall".to_string()),
+            ..test_config("kworker/*")
+        };
+        // Each run of the daemon syncs once, then is restarted.
+        let restarted_at = |n| {
+            let metrics = Metrics::default();
+            let state = DaemonState::default();
+            state.restore_syncs(StateFile::open(&path, now).unwrap());
+            let system = MockSystem {
+                kworker: Some(proc_info(
+                    "kworker/0:1",
+                    now - chrono::Duration::seconds(40),
+                )),
+                now: now + chrono::Duration::seconds(n),
+                ..MockSystem::default()
+            };
+            let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+            (outcome, system.commands.take())
+        };
+
+        assert_eq!(restarted_at(0).0, Outcome::Remediated(Action::Sync));
+        // Still within the cooldown of the sync before the restart.
+        assert_eq!(restarted_at(5).0, Outcome::Deferred);
+        for n in 1..DEFAULT_MAX_INEFFECTIVE_SYNCS as i64 {
+            let (outcome, commands) = restarted_at(n * 10);
+            assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+            assert!(commands.is_empty());
+        }
+        let (outcome, commands) = restarted_at(DEFAULT_MAX_INEFFECTIVE_SYNCS as i64 * 10);
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(commands, vec!["page-oncall".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_ineffective_syncs_escalate() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            escalation_command: Some("page-oncall".to_string()),
+            ..test_config("kworker/*")
+        };
+        let stuck = proc_info("kworker/0:1", now - chrono::Duration::seconds(40));
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let sync_for = |kworker: &ProcInfo, n| {
+            let system = MockSystem {
+                kworker: Some(kworker.clone()),
+                now: now + DEFAULT_SYNC_COOLDOWN * n,
+                ..MockSystem::default()
+            };
+            assert_eq!(
+                workaround(&system, &metrics, &state, &config).unwrap(),
+                Outcome::Remediated(Action::Sync)
+            );
+            system.commands.take()
+        };
+
+        for n in 0..DEFAULT_MAX_INEFFECTIVE_SYNCS as i32 {
+            assert!(sync_for(&stuck, n).is_empty());
+        }
+        // The same process is still stuck after as many syncs as allowed.
+        assert_eq!(sync_for(&stuck, 3), vec!["page-oncall".to_string()]);
+        assert!(metrics
+            .render()
+            .contains("stuck_wbs_status{status=\"degraded\"} 1\n"));
+        // Only once per run of ineffective syncs.
+        assert!(sync_for(&stuck, 4).is_empty());
+
+        // Another process starts the count over, once it has been matching for long enough.
+        let other = ProcInfo {
+            pid: 2000,
+            ..proc_info("kworker/0:2", now - chrono::Duration::seconds(40))
+        };
+        for n in 8..11 {
+            assert!(sync_for(&other, n).is_empty());
+        }
+        assert_eq!(sync_for(&other, 11), vec!["page-oncall".to_string()]);
+    }
+
+    #[test]
+    fn test_ineffective_syncs_escalate_past_a_lowered_limit() {
+        let now = chrono::Utc::now();
+        let config = |max_ineffective_syncs| Config {
+            max_ineffective_syncs,
+            escalation_command: Some("page-oncall".to_string()),
+            ..test_config("kworker/*")
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let sync_at = |n, config: &Config| {
+            let system = MockSystem {
+                kworker: Some(proc_info(
+                    "kworker/0:1",
+                    now - chrono::Duration::seconds(40),
+                )),
+                now: now + DEFAULT_SYNC_COOLDOWN * n,
+                ..MockSystem::default()
+            };
+            workaround(&system, &metrics, &state, config).unwrap();
+            system.commands.take()
+        };
+
+        for n in 0..4 {
+            assert!(sync_at(n, &config(5)).is_empty());
+        }
+        // Reloaded below the 4 syncs that already left it stuck.
+        assert_eq!(sync_at(4, &config(2)), vec!["page-oncall".to_string()]);
+        assert!(sync_at(5, &config(2)).is_empty());
+    }
+
+    #[test]
+    fn test_ineffective_syncs_escalate_to_sysrq() {
+        let now = chrono::Utc::now();
+        let stuck = proc_info("kworker/0:1", now - chrono::Duration::seconds(40));
+        let emergency_syncs = |config: &Config, emergency_sync_result: Result<(), String>| {
+            let metrics = Metrics::default();
+            let state = DaemonState::default();
+            (0..=DEFAULT_MAX_INEFFECTIVE_SYNCS as i32 + 1)
+                .map(|n| {
+                    let system = MockSystem {
+                        kworker: Some(stuck.clone()),
+                        now: now + DEFAULT_SYNC_COOLDOWN * n,
+                        emergency_sync_result: emergency_sync_result.clone(),
+                        ..MockSystem::default()
+                    };
+                    let outcome = workaround(&system, &metrics, &state, config).unwrap();
+                    assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+                    system.emergency_syncs.get()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Only on explicit opt-in.
+        let config = test_config("kworker/*");
+        assert_eq!(emergency_syncs(&config, Ok(())), vec![0, 0, 0, 0, 0]);
+        let sysrq = Config {
+            escalate_sysrq: true,
+            ..test_config("kworker/*")
+        };
+        // Once per run of ineffective syncs, when escalating.
+        assert_eq!(emergency_syncs(&sysrq, Ok(())), vec![0, 0, 0, 1, 0]);
+        // A kernel without SysRq doesn't stop the daemon.
+        let missing = Err("no /proc/sysrq-trigger".to_string());
+        assert_eq!(emergency_syncs(&sysrq, missing), vec![0, 0, 0, 1, 0]);
+
+        let never = Config {
+            max_ineffective_syncs: 0,
+            ..sysrq
+        };
+        assert_eq!(emergency_syncs(&never, Ok(())), vec![0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_metrics_listen_serves_syncs() {
+        use std::io::{Read, Write};
+
+        let scrape = |addr| {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let metrics = Arc::new(Metrics::default());
+        let state = DaemonState::default();
+        let addr =
+            metrics_server::spawn("127.0.0.1:0".parse().unwrap(), Arc::clone(&metrics)).unwrap();
+        let before = scrape(addr);
+        assert!(before.starts_with("HTTP/1.1 200 OK\r\n"), "{before}");
+        assert!(before.contains("\nstuck_wbs_sync_total 0\n"), "{before}");
+
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&system, &metrics, &state, &test_config("kworker/*")).unwrap();
+        assert_eq!(system.sync_calls.get(), 1);
+        let after = scrape(addr);
+        assert!(after.contains("\nstuck_wbs_sync_total 1\n"), "{after}");
+        assert!(
+            after.contains("\nstuck_wbs_matching_kworkers 1\n"),
+            "{after}"
+        );
+        assert!(
+            after.contains("\nstuck_wbs_oldest_kworker_runtime_seconds 40.000\n"),
+            "{after}"
+        );
+    }
+
+    #[test]
+    fn test_monitor_and_sync_ages_are_relative_to_scan_start() {
+        let now = chrono::Utc::now();
+        let proc = proc_info("kworker/0:1", now - chrono::Duration::seconds(25));
+        // The scan takes long enough that an age computed after it would cross the threshold.
+        let system = MockSystem {
+            kworker: Some(proc),
+            now,
+            scan_latency: chrono::Duration::seconds(10),
+            ..MockSystem::default()
+        };
+
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_wait_for_kworker_error() {
+        let system = MockSystem {
+            wait_for_kworker_result: Err("test error".to_string()),
+            ..MockSystem::default()
+        };
+
+        let result = workaround_afresh(&system, &test_config("kworker/*"));
+        assert!(result.is_err());
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_busy_polls_only_reread_the_matching_kworkers() {
+        let now = chrono::Utc::now();
+        let mut other = proc_info("kworker/1:0", now - chrono::Duration::seconds(2));
+        other.pid = 1001;
+        let mut unrelated = proc_info("systemd", now - chrono::Duration::days(1));
+        unrelated.pid = 1;
+        let system = MockSystem {
+            kworker: Some(proc_info("kworker/0:1", now - chrono::Duration::seconds(1))),
+            other_kworkers: vec![other, unrelated],
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        // Never stuck, as the scan after a sync reads every process to measure what it cleared.
+        let config = Config {
+            runtime_threshold: Some(chrono::Duration::days(1)),
+            ..test_config("kworker/*")
+        };
+        let busy_poll = chrono::Duration::from_std(config.timings.busy_poll).unwrap();
+
+        let iterations = 10;
+        for _ in 0..iterations {
+            let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+            assert_eq!(outcome, Outcome::BelowThreshold);
+            system.elapsed.set(system.elapsed.get() + busy_poll);
+        }
+        // Only the first iteration read every process, the others the two candidates.
+        assert_eq!(system.scan_calls.get(), 1);
+        assert_eq!(system.refresh_calls.get(), iterations - 1);
+        assert_eq!(metrics.snapshot().matching_kworkers, 2);
+
+        // Every process is read again once per rescan interval.
+        system
+            .elapsed
+            .set(chrono::Duration::from_std(config.timings.rescan_interval).unwrap());
+        workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(system.scan_calls.get(), 2);
+        assert_eq!(system.refresh_calls.get(), iterations - 1);
+
+        // Exited candidates are dropped, and once none is left every process is read again.
+        let exited = MockSystem {
+            kworker: None,
+            other_kworkers: Vec::new(),
+            now: system.now(),
+            ..MockSystem::default()
+        };
+        let outcome = workaround(&exited, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::NoKworker);
+        assert_eq!(exited.refresh_calls.get(), 1);
+        assert_eq!(exited.scan_calls.get(), 1);
+        assert_eq!(
+            state
+                .tracker()
+                .candidates(exited.now(), config.timings.rescan_interval),
+            None
+        );
+    }
+
+    #[test]
+    fn test_event_log_records_every_decision() {
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_decisions.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let now = chrono::Utc::now();
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        state.set_event_log(event_log::EventLog::open(&path).unwrap());
+        let config = test_config("kworker/*");
+
+        let stuck = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&stuck, &metrics, &state, &config).unwrap();
+        let appearing = MockSystem {
+            now,
+            wait_for_kworker_result: Ok(Some(proc_info(
+                "kworker/0:2",
+                now - chrono::Duration::seconds(2),
+            ))),
+            ..MockSystem::default()
+        };
+        workaround(&appearing, &metrics, &state, &config).unwrap();
+        workaround(&MockSystem::default(), &metrics, &state, &config).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 3, "{contents}");
+        for (event, name) in events
+            .iter()
+            .zip(["trigger", "kworker_appeared", "wait_timeout"])
+        {
+            assert_eq!(event["event"], name);
+            chrono::DateTime::parse_from_rfc3339(event["ts"].as_str().unwrap()).unwrap();
+        }
+        assert_eq!(events[0]["pid"], 1000);
+        assert_eq!(events[0]["comm"], "kworker/0:1");
+        assert_eq!(events[0]["runtime_s"], 40.0);
+        assert_eq!(events[0]["threshold_s"], 30.0);
+        assert_eq!(events[0]["action"], "sync");
+        assert_eq!(events[0]["test"], false);
+        assert_eq!(events[1]["comm"], "kworker/0:2");
+        assert_eq!(events[1]["runtime_s"], 2.0);
+        assert_eq!(events[2].as_object().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_kworker_found_by_the_wait_is_evaluated_without_rescanning() {
+        let now = chrono::Utc::now();
+        let appearing = |age| MockSystem {
+            now,
+            wait_for_kworker_result: Ok(Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(age),
+            ))),
+            ..MockSystem::default()
+        };
+
+        let system = appearing(0);
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(system.scan_calls.get(), 1);
+
+        // Started long ago, but only matching since the scan that found none.
+        let system = appearing(40);
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(system.scan_calls.get(), 1);
+
+        // Without a scan before, all that is known is when it started.
+        let system = appearing(40);
+        let outcome = first_iteration(
+            &system,
+            &Metrics::default(),
+            &DaemonState::default(),
+            &test_config("kworker/*"),
+            StartupBehavior::Wait,
+        )
+        .unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(system.scan_calls.get(), 0);
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_matches_userspace_cmdline() {
+        let now = chrono::Utc::now();
+        let userspace_proc = |cmdline: Option<&str>| ProcInfo {
+            cmdline: cmdline.map(str::to_string),
+            kernel_thread: false,
+            ..proc_info("python3", now - chrono::Duration::seconds(40))
+        };
+
+        let system = MockSystem {
+            kworker: Some(userspace_proc(Some("python3 /opt/app/flusher.py --once"))),
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &test_config("**/flusher.py*")).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+
+        // Without --match-cmdline the command line is not read, and the comm alone doesn't match.
+        let system = MockSystem {
+            kworker: Some(userspace_proc(None)),
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &test_config("**/flusher.py*")).unwrap();
+        assert_eq!(outcome, Outcome::NoKworker);
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            Duration::from_secs(0)
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_process_globs_have_their_own_thresholds() {
+        let config = Config {
+            process_globs: vec![ProcessGlob::new("kworker/*"), "jbd2/*=2m".parse().unwrap()],
+            ..Config::default()
+        };
+        let now = chrono::Utc::now();
+        let syncs_with = |comm, age| {
+            let system = MockSystem {
+                kworker: Some(proc_info(comm, now - chrono::Duration::seconds(age))),
+                now,
+                ..MockSystem::default()
+            };
+            workaround_afresh(&system, &config).unwrap();
+            system.sync_calls.get()
+        };
+
+        // The default threshold applies to the glob without one.
+        assert_eq!(syncs_with("kworker/0:1", 40), 1);
+        assert_eq!(syncs_with("jbd2/sda1-8", 40), 0);
+        assert_eq!(syncs_with("jbd2/sda1-8", 130), 1);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_dispatches_per_pattern_action() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            pattern_actions: vec!["stuckd=signal:SIGKILL".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        workaround_afresh(&system, &config).unwrap();
+        assert_eq!(system.sync_calls.get(), 1);
+        assert!(system.signals.borrow().is_empty());
+
+        let system = MockSystem {
+            kworker: Some(ProcInfo {
+                pid: 4242,
+                kernel_thread: false,
+                ..proc_info("stuckd", now - chrono::Duration::seconds(40))
+            }),
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Signal(Signal::KILL)));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+        assert_eq!(*system.signals.borrow(), vec![(4242, Signal::KILL)]);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_syncs_once_for_several_stuck_patterns() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            pattern_actions: vec!["jbd2/*=sync".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+        // Both patterns are above the threshold, but sync is global so one is enough.
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "jbd2/sda1-8",
+                now - chrono::Duration::seconds(50),
+            )),
+            other_kworkers: vec![proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )],
+            now,
+            ..MockSystem::default()
+        };
+
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_starts_one_sync_across_episodes() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            pattern_actions: vec!["jbd2/*=sync".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+        let first = MockSystem {
+            kworker: Some(proc_info(
+                "jbd2/sda1-8",
+                now - chrono::Duration::seconds(50),
+            )),
+            now,
+            sync_blocked_for: Duration::from_secs(2),
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let result = workaround(&first, &metrics, &state, &config);
+        assert!(result.unwrap_err().is::<SyncTimedOut>());
+
+        // Another process, long after, while the first episode's sync is still blocked.
+        let later = now + chrono::Duration::minutes(5);
+        let second = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                later - chrono::Duration::seconds(40),
+            )),
+            now: later,
+            sync_runner: first.sync_runner.clone(),
+            ..MockSystem::default()
+        };
+        let started = std::time::Instant::now();
+        let outcome = workaround(&second, &metrics, &state, &config).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(50));
+        assert_eq!(outcome, Outcome::SyncBlocked);
+        assert_eq!(metrics.episodes(), 2);
+        assert!(metrics
+            .render()
+            .contains("\nstuck_wbs_sync_timeouts_total 1\n"));
+    }
+
+    #[test]
+    fn test_monitor_and_sync_acts_per_first_fully_matching_signature() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            signatures: vec![
+                "glob=stuckd,stack=fuse_wait,action=signal:SIGKILL"
+                    .parse()
+                    .unwrap(),
+                "glob=stuckd,state=D,action=signal:SIGTERM".parse().unwrap(),
+            ],
+            ..test_config("kworker/*")
+        };
+        let stuckd = |state| ProcInfo {
+            pid: 4242,
+            kernel_thread: false,
+            state,
+            ..proc_info("stuckd", now - chrono::Duration::seconds(40))
+        };
+
+        // Matches the first signature's glob and the second's every criterion.
+        let system = MockSystem {
+            kworker: Some(stuckd('D')),
+            stacks: vec![(4242, "[<0>] wb_wait_for_completion+0x5a/0x90\n")],
+            now,
+            ..MockSystem::default()
+        };
+        workaround_afresh(&system, &config).unwrap();
+        assert_eq!(*system.signals.borrow(), vec![(4242, Signal::TERM)]);
+
+        // Matches both, the first wins.
+        let system = MockSystem {
+            kworker: Some(stuckd('D')),
+            stacks: vec![(4242, "[<0>] fuse_wait_on_page_writeback+0x5a/0x90\n")],
+            now,
+            ..MockSystem::default()
+        };
+        workaround_afresh(&system, &config).unwrap();
+        assert_eq!(*system.signals.borrow(), vec![(4242, Signal::KILL)]);
+
+        // Matches neither, so is only watched.
+        let system = MockSystem {
+            kworker: Some(stuckd('R')),
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert!(system.signals.borrow().is_empty());
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_uses_per_signature_thresholds() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            signatures: vec![
+                "glob=kworker/*,threshold=1m".parse().unwrap(),
+                "glob=jbd2/*,stack=jbd2_journal_commit,threshold=10s"
+                    .parse()
+                    .unwrap(),
+            ],
+            ..test_config("kworker/*")
+        };
+        let system = |jbd2_age| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            other_kworkers: vec![ProcInfo {
+                pid: 1001,
+                ..proc_info("jbd2/sda1-8", now - chrono::Duration::seconds(jbd2_age))
+            }],
+            stacks: vec![(1001, "[<0>] jbd2_journal_commit_transaction+0x11/0x22\n")],
+            now,
+            ..MockSystem::default()
+        };
+
+        // The kworker is past `--runtime-threshold` but not its signature's, and the younger
+        // jbd2 thread is not yet past its own, so its stack isn't even read.
+        let system_before = system(5);
+        let outcome = workaround_afresh(&system_before, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system_before.sync_calls.get(), 0);
+        assert_eq!(system_before.stack_reads.get(), 0);
+
+        let system_after = system(20);
+        let outcome = workaround_afresh(&system_after, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system_after.sync_calls.get(), 1);
+        assert_eq!(system_after.stack_reads.get(), 1);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_reports_resolved_episodes_to_incident_dir() {
+        let dir = std::env::temp_dir().join(format!("stuck_wbs_{}_incidents", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let now = chrono::Utc::now();
+        let config = Config {
+            incident_dir: Some(dir.clone()),
+            ..test_config("kworker/*")
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let stuck = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            stacks: vec![(1000, "[<0>] inode_switch_wbs_work_fn+0x2a/0x4a0\n")],
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&stuck, &metrics, &state, &config).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let cleared = MockSystem {
+            now: now + chrono::Duration::seconds(30),
+            ..MockSystem::default()
+        };
+        workaround(&cleared, &metrics, &state, &config).unwrap();
+        let reports: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert!(report.starts_with("# Stall episode #1 on "), "{report}");
+        assert!(report.contains("after 30s\n- Resolution: resolved\n- Remediations: 1\n"));
+        assert!(report.contains(" Ran sync\n"));
+        assert!(report.contains("inode_switch_wbs_work_fn"));
+        assert!(state.incident().is_none());
+    }
+
+    #[test]
+    fn test_monitor_and_sync_fs_mode_syncs_the_flushed_filesystem() {
+        let now = chrono::Utc::now();
+        let config = |sync_mode| Config {
+            sync_mode,
+            ..test_config("kworker/*")
+        };
+        let system = |comm, sync_fs_result| MockSystem {
+            kworker: Some(proc_info(comm, now - chrono::Duration::seconds(40))),
+            mounts: vec![((259, 3), "/data")],
+            sync_fs_result,
+            now,
+            ..MockSystem::default()
+        };
+
+        let flusher = system("kworker/u16:1+flush-259:3", Ok(()));
+        workaround_afresh(&flusher, &config(SyncMode::Filesystem)).unwrap();
+        assert_eq!(
+            *flusher.sync_fs_calls.borrow(),
+            vec![PathBuf::from("/data")]
+        );
+        assert_eq!(flusher.sync_calls.get(), 0);
+
+        let flusher = system("kworker/u16:1+flush-259:3", Ok(()));
+        workaround_afresh(&flusher, &config(SyncMode::Global)).unwrap();
+        assert!(flusher.sync_fs_calls.borrow().is_empty());
+        assert_eq!(flusher.sync_calls.get(), 1);
+
+        // Falls back to a global sync when the filesystem is unknown, or can't be synced.
+        for comm in ["kworker/u8:2+inode_switch_wbs", "kworker/u16:1+flush-8:0"] {
+            let other = system(comm, Ok(()));
+            workaround_afresh(&other, &config(SyncMode::Filesystem)).unwrap();
+            assert!(other.sync_fs_calls.borrow().is_empty(), "{comm}");
+            assert_eq!(other.sync_calls.get(), 1, "{comm}");
+        }
+        let failing = system("kworker/u16:1+flush-259:3", Err("EIO".to_string()));
+        workaround_afresh(&failing, &config(SyncMode::Filesystem)).unwrap();
+        assert_eq!(failing.sync_fs_calls.borrow().len(), 1);
+        assert_eq!(failing.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_require_wchan() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            require_wchan: Some("inode_switch_wbs".to_string()),
+            ..test_config("kworker/*")
+        };
+        let stuck_in = |wchan: Option<&str>| MockSystem {
+            kworker: Some(ProcInfo {
+                wchan: wchan.map(str::to_string),
+                ..proc_info("kworker/0:1", now - chrono::Duration::seconds(40))
+            }),
+            now,
+            ..MockSystem::default()
+        };
+
+        let moved_on = stuck_in(Some("worker_thread"));
+        let outcome = workaround_afresh(&moved_on, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(moved_on.sync_calls.get(), 0);
+
+        for wchan in [Some("inode_switch_wbs_work_fn"), None] {
+            let system = stuck_in(wchan);
+            let outcome = workaround_afresh(&system, &config).unwrap();
+            assert_eq!(outcome, Outcome::Remediated(Action::Sync), "{wchan:?}");
+            assert_eq!(
+                outcome.sleep_duration(&Timings::default()),
+                EXPECTED_RECOVERY_TIME
+            );
+            assert_eq!(system.sync_calls.get(), 1, "{wchan:?}");
+        }
+    }
+
+    #[test]
+    fn test_is_monitored_includes_pattern_file_globs() {
+        let config = Config {
+            file_globs: vec!["jbd2/*".to_string()],
+            ..test_config("kworker/*")
+        };
+        let now = chrono::Utc::now();
+        assert!(is_monitored(&config, &proc_info("kworker/0:1", now)));
+        assert!(is_monitored(&config, &proc_info("jbd2/sda1-8", now)));
+        assert!(!is_monitored(&config, &proc_info("ksoftirqd/0", now)));
+    }
+
+    #[test]
+    fn test_is_monitored_by_state() {
+        let now = chrono::Utc::now();
+        let in_state = |state| ProcInfo {
+            state,
+            ..proc_info("kworker/0:1", now)
+        };
+        let config = test_config("kworker/*");
+        let any_state = Config {
+            any_state: true,
+            ..test_config("kworker/*")
+        };
+        for (state, monitored) in [
+            ('R', true),
+            ('D', true),
+            ('S', false),
+            ('I', false),
+            ('T', false),
+            ('Z', false),
+        ] {
+            assert_eq!(
+                is_monitored(&config, &in_state(state)),
+                monitored,
+                "{state}"
+            );
+            assert!(is_monitored(&any_state, &in_state(state)), "{state}");
+        }
+    }
+
+    #[test]
+    fn test_is_monitored_by_uid() {
+        let now = chrono::Utc::now();
+        let mapped_root = ProcInfo {
+            uid: 100_000,
+            ..proc_info("kworker/0:1", now)
+        };
+        let root = proc_info("kworker/0:1", now);
+        let config = test_config("kworker/*");
+        assert!(is_monitored(&config, &root));
+        assert!(!is_monitored(&config, &mapped_root));
+
+        let config = Config {
+            uids: vec![0, 100_000],
+            ..test_config("kworker/*")
+        };
+        assert!(is_monitored(&config, &root));
+        assert!(is_monitored(&config, &mapped_root));
+        assert!(!is_monitored(
+            &config,
+            &ProcInfo {
+                uid: 1000,
+                ..root.clone()
+            }
+        ));
+
+        let config = Config {
+            uids: vec![100_000],
+            any_uid: true,
+            ..test_config("kworker/*")
+        };
+        assert!(is_monitored(&config, &root));
+    }
+
+    #[test]
+    fn test_monitor_and_sync_refuses_to_signal_low_pids() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            pattern_actions: vec!["stuckd=signal:SIGKILL".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+        let system = MockSystem {
+            kworker: Some(ProcInfo {
+                pid: 2,
+                kernel_thread: false,
+                ..proc_info("stuckd", now - chrono::Duration::seconds(40))
+            }),
+            now,
+            ..MockSystem::default()
+        };
+
+        workaround_afresh(&system, &config).unwrap();
+        assert!(system.signals.borrow().is_empty());
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_rules_trigger_independently() {
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_rules.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[[rule]]\n\
+             glob = \"md*_raid*\"\n\
+             runtime-threshold = \"2m\"\n\
+             \n\
+             [[rule]]\n\
+             glob = \"jbd2/*\"\n\
+             runtime-threshold = \"1m\"\n\
+             action = \"command\"\n\
+             \n\
+             [[rule]]\n\
+             glob = \"stuckd\"\n\
+             uid = 1000\n\
+             runtime-threshold = \"10s\"\n\
+             action = \"signal:SIGTERM\"\n",
+        )
+        .unwrap();
+        let rules = rules::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let config = Config {
+            rules: rules.unwrap(),
+            action_command: Some("jbd2-debug".to_string()),
+            ..test_config("kworker/*")
+        };
+        assert!(config.validate().is_ok());
+        let now = chrono::Utc::now();
+        let process = |pid, comm, uid, age| ProcInfo {
+            pid,
+            uid,
+            kernel_thread: uid == 0,
+            ..proc_info(comm, now - chrono::Duration::seconds(age))
+        };
+        let running = |processes: Vec<ProcInfo>| MockSystem {
+            other_kworkers: processes,
+            now,
+            ..MockSystem::default()
+        };
+
+        // The md thread is the oldest, but only the jbd2 one is past its rule's threshold.
+        let system = running(vec![
+            process(2000, "md0_raid1", 0, 90),
+            process(3000, "jbd2/sda1-8", 0, 70),
+        ]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Command));
+        assert_eq!(*system.commands.borrow(), vec!["jbd2-debug".to_string()]);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        let system = running(vec![process(2000, "md0_raid1", 0, 130)]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert!(system.commands.borrow().is_empty());
+
+        // Monitored for its rule's uid, which isn't one of the --uids, and given its action.
+        let system = running(vec![process(4242, "stuckd", 1000, 20)]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Signal(Signal::TERM)));
+        assert_eq!(*system.signals.borrow(), vec![(4242, Signal::TERM)]);
+        let system = running(vec![process(4242, "stuckd", 1001, 20)]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::NoKworker);
+    }
+
+    #[test]
+    fn test_monitor_and_run_action_command() {
+        let now = chrono::Utc::now();
+        let stuck = |command_result| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            command_result,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            default_action: Action::Command,
+            action_command: Some("echo s > /proc/sysrq-trigger".to_string()),
+            ..test_config("kworker/*")
+        };
+        assert!(config.validate().is_ok());
+
+        let system = stuck(Ok(true));
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Command));
+        assert_eq!(
+            *system.commands.borrow(),
+            vec!["echo s > /proc/sysrq-trigger".to_string()]
+        );
+        assert_eq!(system.sync_calls.get(), 0);
+        // Not a sync, so neither counted as one nor subject to the cooldown.
+        assert_eq!(metrics.last_sync(), None);
+        workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(system.commands.borrow().len(), 2);
+
+        let failing = stuck(Ok(false));
+        let error = workaround_afresh(&failing, &config).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "failed to run command: the action command failed"
+        );
+        assert_eq!(failing.sync_calls.get(), 0);
+
+        let missing = Config {
+            action_command: None,
+            ..config
+        };
+        assert!(missing.validate().is_err());
+    }
+
+    #[test]
+    fn test_first_iteration_scan_behavior_scans_immediately() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+
+        let outcome = first_iteration(
+            &system,
+            &Metrics::default(),
+            &DaemonState::default(),
+            &test_config("kworker/*"),
+            StartupBehavior::Scan,
+        )
+        .unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.scan_calls.get(), 1);
+        assert_eq!(system.wait_calls.get(), 0);
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_first_iteration_wait_behavior_waits_before_scanning() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+
+        let outcome = first_iteration(
+            &system,
+            &Metrics::default(),
+            &DaemonState::default(),
+            &test_config("kworker/*"),
+            StartupBehavior::Wait,
+        )
+        .unwrap();
+        assert_eq!(outcome, Outcome::WaitedForKworker);
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            Duration::from_secs(0)
+        );
+        assert_eq!(system.scan_calls.get(), 0);
+        assert_eq!(system.wait_calls.get(), 1);
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_parse_startup_behavior() {
+        assert_eq!("scan".parse(), Ok(StartupBehavior::Scan));
+        assert_eq!("wait".parse(), Ok(StartupBehavior::Wait));
+        assert!("later".parse::<StartupBehavior>().is_err());
+    }
+
+    #[test]
+    fn test_status_reflects_scenario() {
+        fn status_after(system: &MockSystem) -> String {
+            let metrics = Metrics::default();
+            let state = DaemonState::default();
+            let result = workaround(system, &metrics, &state, &test_config("kworker/*"));
+            sleep_duration_after(result, &metrics, &Timings::default());
+            let rendered = metrics.render();
+            let active = rendered
+                .lines()
+                .find(|l| l.starts_with("stuck_wbs_status{") && l.ends_with(" 1"))
+                .unwrap();
+            active.to_string()
+        }
+        let now = chrono::Utc::now();
+        let with_kworker = |age: i64| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(age),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+
+        assert!(status_after(&MockSystem::default()).contains("\"idle\""));
+        assert!(status_after(&with_kworker(10)).contains("\"watching\""));
+        assert!(status_after(&with_kworker(40)).contains("\"remediating\""));
+        let failing = MockSystem {
+            wait_for_kworker_result: Err("test error".to_string()),
+            ..MockSystem::default()
+        };
+        assert!(status_after(&failing).contains("\"degraded\""));
+    }
+
+    #[test]
+    fn test_emit_test_event_is_marked_and_does_not_sync() {
+        let system = MockSystem::default();
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+
+        emit_test_event(&system, &metrics, &state, &test_config("kworker/*"));
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_triggers_total{test=\"true\"} 1\n"));
+        assert!(rendered.contains("stuck_wbs_triggers_total{test=\"false\"} 0\n"));
+        assert_eq!(system.sync_calls.get(), 0);
+        assert_eq!(system.scan_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_webhook_report_describes_trigger() {
+        let now = chrono::Utc::now();
+        let kworker = proc_info("kworker/0:1", now - chrono::Duration::seconds(25));
+        let metrics = Metrics::default();
+        metrics.record_trigger(false);
+
+        let config = Config {
+            labels: Labels::new(vec!["role=storage".parse().unwrap()]).unwrap(),
+            ..test_config("kworker/*")
+        };
+
+        let report = webhook_report(
+            &metrics,
+            &config,
+            &Trigger {
+                kworker: &kworker,
+                now,
+                cause: Cause::SummedAge { count: 5 },
+                runtime: chrono::Duration::seconds(125),
+                threshold: chrono::Duration::seconds(100),
+                action: "signal:SIGKILL".parse().unwrap(),
+                test: false,
+            },
+        );
+        assert_eq!(report.event, webhook::Event::Trigger);
+        assert_eq!(report.timestamp, clock::local(now).to_rfc3339());
+        assert_eq!((report.comm.as_str(), report.pid), ("kworker/0:1", 1000));
+        assert_eq!((report.cause, report.kworkers), ("summed_age", 5));
+        assert_eq!(
+            (report.runtime_seconds, report.threshold_seconds),
+            (125, 100)
+        );
+        assert_eq!(report.action, "signal:SIGKILL");
+        assert_eq!(report.triggers_total, 1);
+        assert_eq!(report.labels, config.labels);
+    }
+
+    #[test]
+    fn test_capture_stack_writes_to_stack_dir() {
+        let dir = std::env::temp_dir().join(format!("stuck_wbs_stacks_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let now = chrono::Utc::now();
+        let config = Config {
+            capture_stack: true,
+            stack_dir: Some(dir.clone()),
+            ..test_config("kworker/*")
+        };
+        let stuck = |stacks| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            stacks,
+            ..MockSystem::default()
+        };
+
+        let system = stuck(vec![(1000, "[<0>] wb_wait_for_completion+0x5a/0x90\n")]);
+        assert_eq!(
+            workaround_afresh(&system, &config).unwrap(),
+            Outcome::Remediated(Action::Sync)
+        );
+        let written: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(written.len(), 1);
+        assert!(
+            written[0].starts_with("kworker/0:1 (pid 1000) at "),
+            "{written:?}"
+        );
+        assert!(written[0].ends_with("\n[<0>] wb_wait_for_completion+0x5a/0x90\n"));
+
+        // An unreadable or empty stack never holds the sync back.
+        for stacks in [vec![], vec![(1000, "")]] {
+            let system = stuck(stacks);
+            workaround_afresh(&system, &config).unwrap();
+            assert_eq!(system.sync_calls.get(), 1);
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trigger_details_name_the_pid() {
+        let now = chrono::Utc::now();
+        let kworker = proc_info("kworker/0:1", now - chrono::Duration::seconds(45));
+        let trigger = |cause| Trigger {
+            kworker: &kworker,
+            now,
+            cause,
+            runtime: chrono::Duration::seconds(45),
+            threshold: chrono::Duration::seconds(30),
+            action: Action::Sync,
+            test: false,
+        };
+        assert_eq!(
+            trigger_details(&trigger(Cause::Runtime)),
+            "kworker 'kworker/0:1' (pid 1000) has been running for 45s (threshold: 30s)"
+        );
+        for cause in [
+            Cause::CpuTime,
+            Cause::SummedAge { count: 2 },
+            Cause::Count { count: 3, min: 3 },
+        ] {
+            let details = trigger_details(&trigger(cause));
+            assert!(details.contains("'kworker/0:1' (pid 1000)"), "{details}");
+        }
+    }
+
+    #[test]
+    fn test_required_capabilities_follow_enabled_features() {
+        let capabilities = |config: &Config| -> Vec<Capability> {
+            required_capabilities(config)
+                .iter()
+                .map(|r| r.capability)
+                .collect()
+        };
+        let config = test_config("kworker/*");
+        assert_eq!(capabilities(&config), vec![]);
+
+        let config = Config {
+            pattern_actions: vec!["jbd2/*=sync".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+        assert_eq!(capabilities(&config), vec![]);
+
+        let config = Config {
+            pattern_actions: vec![
+                "jbd2/*=sync".parse().unwrap(),
+                "stuckd=signal:SIGTERM".parse().unwrap(),
+            ],
+            ..test_config("kworker/*")
+        };
+        assert_eq!(capabilities(&config), vec![Capability::Kill]);
+        assert!(required_capabilities(&config)[0].fatal);
+
+        let config = Config {
+            signatures: vec!["glob=jbd2/*,stack=jbd2_journal_commit".parse().unwrap()],
+            ..test_config("kworker/*")
+        };
+        assert_eq!(capabilities(&config), vec![Capability::SysAdmin]);
+
+        let config = Config {
+            capture_stack: true,
+            ..test_config("kworker/*")
+        };
+        assert_eq!(capabilities(&config), vec![Capability::SysAdmin]);
+        assert!(!required_capabilities(&config)[0].fatal);
+    }
+
+    #[test]
+    fn test_workaround_follows_the_config_it_is_given() {
+        let now = chrono::Utc::now();
+        let system = MockSystem {
+            kworker: Some(proc_info("kworker/0:1", now - chrono::Duration::minutes(2))),
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let mut config = Config {
+            runtime_threshold: Some(chrono::Duration::minutes(5)),
+            ..test_config("kworker/*")
+        };
+        let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+
+        // As on a reload, between iterations.
+        let reloaded = Config {
+            runtime_threshold: Some(chrono::Duration::minutes(1)),
+            ..config.clone()
+        };
+        assert_eq!(
+            config_changes(&config, &reloaded),
+            vec!["runtime-threshold: \"5m\" -> \"1m\""]
+        );
+        config = reloaded;
+        let outcome = workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(system.sync_calls.get(), 1);
+    }
+
+    #[test]
+    fn test_config_changes() {
+        let old = test_config("kworker/*");
+        assert!(config_changes(&old, &old).is_empty());
+        let new = Config {
+            warn_threshold: Some(chrono::Duration::seconds(20)),
+            any_uid: true,
+            ..test_config("jbd2/*")
+        };
+        assert_eq!(
+            config_changes(&old, &new),
+            vec![
+                "any-uid: unset -> true",
+                "process-glob: [\"kworker/*\"] -> [\"jbd2/*\"]",
+                "warn-threshold: unset -> \"20s\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_scan_orders_by_pid() {
+        let now = chrono::Utc::now();
+        let scan = Scan {
+            kworkers: vec![ProcInfo {
+                pid: 30,
+                ..proc_info("kworker/0:1", now)
+            }],
+            skipped: vec![
+                Skipped {
+                    pid: 40,
+                    comm: None,
+                    reason: SkipReason::Unreadable,
+                },
+                Skipped {
+                    pid: 1,
+                    comm: Some("systemd".to_string()),
+                    reason: SkipReason::NotMonitored,
+                },
+            ],
+        };
+        assert_eq!(
+            format_scan(&scan),
+            "1\tsystemd\tnot_monitored\n30\tkworker/0:1\tmonitored\n40\t?\tunreadable\n"
+        );
+    }
+
+    #[test]
+    fn test_monitor_and_sync_summed_age_threshold() {
+        let now = chrono::Utc::now();
+        let kworkers_aged = |ages: &[i64]| MockSystem {
+            other_kworkers: ages
+                .iter()
+                .map(|age| proc_info("kworker/0:1", now - chrono::Duration::seconds(*age)))
+                .collect(),
+            now,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            sum_age_threshold: Some(chrono::Duration::seconds(100)),
+            ..test_config("kworker/*")
+        };
+
+        // Five workers each below the 30s threshold, 125s combined.
+        let system = kworkers_aged(&[25, 25, 25, 25, 25]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(
+            outcome.sleep_duration(&Timings::default()),
+            EXPECTED_RECOVERY_TIME
+        );
+        assert_eq!(system.sync_calls.get(), 1);
+
+        // Same workers, 75s combined.
+        let system = kworkers_aged(&[25, 25, 25]);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(outcome.sleep_duration(&Timings::default()), BUSY_POLLING);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        // The per-worker threshold still applies on its own.
+        let system = kworkers_aged(&[40]);
+        workaround_afresh(&system, &config).unwrap();
+        assert_eq!(system.sync_calls.get(), 1);
+
+        // Without the option, the summed age is ignored.
+        let system = kworkers_aged(&[25, 25, 25, 25, 25]);
+        workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_cpu_threshold() {
+        let now = chrono::Utc::now();
+        // Each only just started, well below the runtime threshold.
+        let spinning = |pid, cpu_time| ProcInfo {
+            pid,
+            cpu_time,
+            ..proc_info("kworker/0:1", now - chrono::Duration::seconds(10))
+        };
+        let config = Config {
+            cpu_threshold: Some(chrono::Duration::seconds(5)),
+            ..test_config("kworker/*")
+        };
+
+        let system = MockSystem {
+            kworker: Some(spinning(1000, Duration::from_millis(4_990))),
+            other_kworkers: vec![spinning(2000, Duration::from_secs(8))],
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(system.sync_calls.get(), 1);
+
+        let system = MockSystem {
+            kworker: Some(spinning(1000, Duration::from_millis(4_990))),
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+
+        // Without the option, CPU time is ignored.
+        let system = MockSystem {
+            kworker: Some(spinning(2000, Duration::from_secs(8))),
+            now,
+            ..MockSystem::default()
+        };
+        workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(system.sync_calls.get(), 0);
+    }
+
+    #[test]
+    fn test_monitor_and_sync_min_stuck_count() {
+        let now = chrono::Utc::now();
+        let kworkers = |count: i32| MockSystem {
+            other_kworkers: (0..count)
+                .map(|i| ProcInfo {
+                    pid: 1000 + i,
+                    ..proc_info("kworker/0:1", now - chrono::Duration::seconds(5))
+                })
+                .collect(),
+            now,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            min_stuck_count: Some(4),
+            ..test_config("kworker/*")
+        };
+
+        // Each only just started, but there are as many as allowed.
+        let system = kworkers(4);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(system.sync_calls.get(), 1);
+
+        let system = kworkers(3);
+        let outcome = workaround_afresh(&system, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(system.sync_calls.get(), 0);
+
+        // The runtime threshold still applies on its own.
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        workaround_afresh(&system, &config).unwrap();
+        assert_eq!(system.sync_calls.get(), 1);
+
+        let invalid = Config {
+            min_stuck_count: Some(0),
+            ..test_config("kworker/*")
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_kworkers_appearing_between_rescans_are_counted() {
+        let now = chrono::Utc::now();
+        let kworker = |pid| ProcInfo {
+            pid,
+            ..proc_info("kworker/0:1", now - chrono::Duration::seconds(5))
+        };
+        let busy_poll = chrono::Duration::from_std(Timings::default().busy_poll).unwrap();
+        let config = Config {
+            min_stuck_count: Some(2),
+            ..test_config("kworker/*")
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let one = MockSystem {
+            other_kworkers: vec![kworker(1000)],
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround(&one, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+
+        // A second one appears before the next rescan, and is read all the same.
+        let two = MockSystem {
+            other_kworkers: vec![kworker(1000), kworker(1001)],
+            now: now + busy_poll,
+            ..MockSystem::default()
+        };
+        let outcome = workaround(&two, &metrics, &state, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!((two.scan_calls.get(), two.refresh_calls.get()), (1, 0));
+
+        // Nor are scans restricted to the last ones when measuring what a sync cleared.
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let stuck = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&stuck, &metrics, &state, &test_config("kworker/*")).unwrap();
+        assert!(metrics.measuring_cleared());
+        let after = MockSystem {
+            other_kworkers: vec![kworker(1001)],
+            now: now + busy_poll,
+            ..MockSystem::default()
+        };
+        workaround(&after, &metrics, &state, &test_config("kworker/*")).unwrap();
+        assert_eq!((after.scan_calls.get(), after.refresh_calls.get()), (1, 0));
+        assert!(!metrics.measuring_cleared());
+    }
+
+    #[test]
+    fn test_monitor_and_sync_runtime_threshold_off() {
+        let now = chrono::Utc::now();
+        let system = |count: i32, age| MockSystem {
+            other_kworkers: (0..count)
+                .map(|i| ProcInfo {
+                    pid: 1000 + i,
+                    ..proc_info("kworker/0:1", now - age)
+                })
+                .collect(),
+            now,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            runtime_threshold: None,
+            min_stuck_count: Some(3),
+            ..test_config("kworker/*")
+        };
+        assert!(config.validate().is_ok());
+
+        // However long a process ran, only the other triggers apply.
+        let old = system(2, chrono::Duration::days(1));
+        let outcome = workaround_afresh(&old, &config).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert_eq!(old.sync_calls.get(), 0);
+        let many = system(3, chrono::Duration::seconds(5));
+        let outcome = workaround_afresh(&many, &config).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+        assert_eq!(many.sync_calls.get(), 1);
+
+        // As do globs with their own threshold.
+        let glob_threshold = Config {
+            runtime_threshold: None,
+            process_globs: vec!["kworker/*=1h".parse().unwrap()],
+            ..Config::default()
+        };
+        assert!(glob_threshold.validate().is_ok());
+        let outcome = workaround_afresh(&old, &glob_threshold).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+
+        let nothing_left = Config {
+            runtime_threshold: None,
+            ..test_config("kworker/*")
+        };
+        assert!(nothing_left
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("nothing else can trigger"));
+    }
+
+    #[test]
+    fn test_clock_behind_start_times() {
+        let now = chrono::Utc::now();
+        let ahead = ProcInfo {
+            pid: 2000,
+            ..proc_info("kworker/0:2", now + chrono::Duration::hours(1))
+        };
+        let system = MockSystem {
+            other_kworkers: vec![ahead.clone()],
+            now,
+            ..MockSystem::default()
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let outcome = workaround(&system, &metrics, &state, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::BelowThreshold);
+        assert!(metrics
+            .render()
+            .contains("\nstuck_wbs_oldest_kworker_runtime_seconds 0.000\n"));
+
+        // A process that seems to start in the future doesn't hide one that is stuck.
+        let system = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            other_kworkers: vec![ahead],
+            now,
+            ..MockSystem::default()
+        };
+        let outcome = workaround_afresh(&system, &test_config("kworker/*")).unwrap();
+        assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+
+        // The clock going back between scans isn't fatal either.
+        let system = MockSystem {
+            now: now - chrono::Duration::hours(2),
+            ..MockSystem::default()
+        };
+        assert!(workaround(&system, &metrics, &state, &test_config("kworker/*")).is_ok());
+    }
+
+    #[test]
+    fn test_sum_ages_ignores_future_starttimes() {
+        let now = chrono::Utc::now();
+        let kworkers = [
+            proc_info("kworker/0:1", now - chrono::Duration::seconds(10)),
+            proc_info("kworker/0:2", now + chrono::Duration::seconds(50)),
+            proc_info("kworker/0:3", now - chrono::Duration::seconds(5)),
+        ];
+        assert_eq!(sum_ages(&kworkers, &now), chrono::Duration::seconds(15));
+    }
+
+    #[test]
+    fn test_verify_command_outcome_feeds_status() {
+        let now = chrono::Utc::now();
+        let config = Config {
+            verify_command: Some("check-writes".to_string()),
+            ..test_config("kworker/*")
+        };
+        let system_verifying = |command_result| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            command_result,
+            ..MockSystem::default()
+        };
+
+        let system = system_verifying(Ok(true));
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        workaround(&system, &metrics, &state, &config).unwrap();
+        assert_eq!(*system.commands.borrow(), vec!["check-writes".to_string()]);
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_verifications_total{result=\"resolved\"} 1\n"));
+        assert!(rendered.contains("stuck_wbs_status{status=\"remediating\"} 1\n"));
+
+        let system = system_verifying(Ok(false));
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        workaround(&system, &metrics, &state, &config).unwrap();
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_verifications_total{result=\"stuck\"} 1\n"));
+        assert!(rendered.contains("stuck_wbs_status{status=\"degraded\"} 1\n"));
+
+        // The command is only run after a remediation.
+        let system = MockSystem::default();
+        workaround_afresh(&system, &config).unwrap();
+        assert!(system.commands.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_episode_gap_groups_triggers() {
+        let now = chrono::Utc::now();
+        let stuck_at = |now| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            now,
+            ..MockSystem::default()
+        };
+        let config = Config {
+            episode_gap: Some(chrono::Duration::minutes(2)),
+            ..test_config("kworker/*")
+        };
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+
+        for offset in [0, 60, 300] {
+            let system = stuck_at(now + chrono::Duration::seconds(offset));
+            workaround(&system, &metrics, &state, &config).unwrap();
+            // Every trigger is still acted upon.
+            assert_eq!(system.sync_calls.get(), 1);
+        }
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_triggers_total{test=\"false\"} 3\n"));
+        assert!(rendered.contains("stuck_wbs_episodes_total 2\n"));
+    }
+
+    #[test]
+    fn test_sync_effect_is_measured_by_next_scan() {
+        let now = chrono::Utc::now();
+        let stuck = |age| proc_info("kworker/0:1", now - chrono::Duration::seconds(age));
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let config = test_config("kworker/*");
+
+        let system = MockSystem {
+            kworker: Some(stuck(40)),
+            other_kworkers: vec![stuck(20), stuck(10)],
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&system, &metrics, &state, &config).unwrap();
+        // The sync left a single worker behind.
+        let system = MockSystem {
+            kworker: Some(stuck(5)),
+            now,
+            ..MockSystem::default()
+        };
+        workaround(&system, &metrics, &state, &config).unwrap();
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_measured_syncs_total 1\n"));
+        assert!(rendered.contains("stuck_wbs_cleared_kworkers_total 2\n"));
+    }
+
+    #[test]
+    fn test_last_scan_timestamp_advances_across_scans() {
+        let metrics = Metrics::default();
+        let state = DaemonState::default();
+        let mut system = MockSystem::default();
+
+        workaround(&system, &metrics, &state, &test_config("kworker/*")).unwrap();
+        let first = metrics.render();
+        system.now += chrono::Duration::seconds(5);
+        workaround(&system, &metrics, &state, &test_config("kworker/*")).unwrap();
+        let second = metrics.render();
+
+        let expected = |now: chrono::DateTime<chrono::Utc>| {
+            format!(
+                "stuck_wbs_last_scan_timestamp_seconds {:.3}\n",
+                now.timestamp_millis() as f64 / 1000.0
+            )
+        };
+        assert!(first.contains(&expected(system.now - chrono::Duration::seconds(5))));
+        assert!(second.contains(&expected(system.now)));
+    }
+
+    #[test]
+    fn test_unhealthy_filesystems_are_only_detected() {
+        let now = chrono::Utc::now();
+        let config = |min_free_percent| Config {
+            min_free_percent,
+            sync_path: Some(PathBuf::from("/data")),
+            ..test_config("kworker/*")
+        };
+        let system = |fs_status| MockSystem {
+            kworker: Some(proc_info(
+                "kworker/0:1",
+                now - chrono::Duration::seconds(40),
+            )),
+            fs_status,
+            now,
+            ..MockSystem::default()
+        };
+        let status = |available, read_only| {
+            Ok(FsStatus {
+                available,
+                total: 1000,
+                read_only,
+            })
+        };
+
+        let healthy = system(status(500, false));
+        workaround(
+            &healthy,
+            &Metrics::default(),
+            &DaemonState::default(),
+            &config(Some(5)),
+        )
+        .unwrap();
+        assert_eq!(healthy.sync_calls.get(), 1);
+        assert_eq!(*healthy.fs_status_calls.borrow(), [PathBuf::from("/data")]);
+
+        for unhealthy in [system(status(10, false)), system(status(500, true))] {
+            let metrics = Metrics::default();
+            let state = DaemonState::default();
+            let outcome = workaround(&unhealthy, &metrics, &state, &config(Some(5))).unwrap();
+            assert_eq!(outcome, Outcome::Reported(Action::Sync));
+            assert_eq!(unhealthy.sync_calls.get(), 0);
+            assert!(metrics
+                .render()
+                .contains("stuck_wbs_status{status=\"watching\"} 1\n"));
+        }
+
+        // Unless disabled.
+        let full = system(status(10, false));
+        workaround_afresh(&full, &config(None)).unwrap();
+        assert_eq!(full.sync_calls.get(), 1);
+        assert!(full.fs_status_calls.borrow().is_empty());
+
+        // Failing to check doesn't hold back the sync.
+        let unknown = system(Err("ENOSYS".to_string()));
+        workaround(
+            &unknown,
+            &Metrics::default(),
+            &DaemonState::default(),
+            &config(Some(5)),
+        )
+        .unwrap();
+        assert_eq!(unknown.sync_calls.get(), 1);
+
+        // With `--sync-mode fs`, the flushed filesystem is checked instead.
+        let flusher = MockSystem {
+            kworker: Some(proc_info(
+                "kworker/u16:1+flush-259:3",
+                now - chrono::Duration::seconds(40),
+            )),
+            mounts: vec![((259, 3), "/srv")],
+            ..system(status(10, false))
+        };
+        let fs_config = Config {
+            sync_mode: SyncMode::Filesystem,
+            ..config(Some(5))
+        };
+        workaround_afresh(&flusher, &fs_config).unwrap();
+        assert!(flusher.sync_fs_calls.borrow().is_empty());
+        assert_eq!(*flusher.fs_status_calls.borrow(), [PathBuf::from("/srv")]);
+    }
+}
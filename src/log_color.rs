@@ -0,0 +1,95 @@
+//! `--color`, coloring text log lines by level when they are watched on a terminal, so that the
+//! warnings of syncs stand out from routine lines during an incident.
+use env_logger::fmt::style::{AnsiColor, Style};
+
+/// When to color log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal, and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        })
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "invalid color choice '{s}', expected 'auto', 'always' or 'never'"
+            )),
+        }
+    }
+}
+
+impl ColorChoice {
+    /// Returns whether to color lines written to a `terminal` or not, `no_color` telling whether
+    /// the `NO_COLOR` environment variable asks not to, which only an explicit choice overrides.
+    pub fn colors(self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Returns whether the `NO_COLOR` environment variable is set, to anything but an empty string as
+/// <https://no-color.org> specifies.
+pub fn no_color_requested() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Returns the style of the level of a line, as env_logger colors it.
+pub fn level_style(level: log::Level) -> Style {
+    match level {
+        log::Level::Trace => AnsiColor::Cyan.on_default(),
+        log::Level::Debug => AnsiColor::Blue.on_default(),
+        log::Level::Info => AnsiColor::Green.on_default(),
+        log::Level::Warn => AnsiColor::Yellow.on_default(),
+        log::Level::Error => AnsiColor::Red.on_default().bold(),
+    }
+}
+
+/// Returns the style of the message of a line: red for warnings, such as syncs being triggered,
+/// and errors, gray for debugging, which is mostly noise while watching an incident.
+pub fn message_style(level: log::Level) -> Style {
+    match level {
+        log::Level::Trace | log::Level::Debug => AnsiColor::BrightBlack.on_default(),
+        log::Level::Info => Style::new(),
+        log::Level::Warn => AnsiColor::Red.on_default(),
+        log::Level::Error => AnsiColor::Red.on_default().bold(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_terminals_are_colored_by_default() {
+        let choices = ["auto", "always", "never"].map(|s| s.parse::<ColorChoice>().unwrap());
+        let colors = |terminal, no_color| choices.map(|c| c.colors(terminal, no_color));
+        assert_eq!(colors(true, false), [true, true, false]);
+        assert_eq!(colors(false, false), [false, true, false]);
+        // NO_COLOR only changes the default.
+        assert_eq!(colors(true, true), [false, true, false]);
+        assert_eq!(choices.map(|c| c.to_string()), ["auto", "always", "never"]);
+        assert!("yes".parse::<ColorChoice>().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! `--log-dedup-window`, collapsing identical consecutive log lines, such as "No matching kworkers
+//! found" on every rescan, into a summary of how many times they repeated.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The last message logged, and how many times it repeated since it or its last summary was.
+#[derive(Debug)]
+struct Last {
+    level: log::Level,
+    target: String,
+    message: String,
+    repeats: u64,
+    since: Instant,
+}
+
+impl Last {
+    fn is_repeated_by(&self, record: &log::Record, message: &str) -> bool {
+        self.level == record.level() && self.target == record.target() && self.message == message
+    }
+}
+
+/// Wraps a logger, only passing on the first of identical consecutive messages, then a summary of
+/// their repeats at most once per window and once a different message is logged.
+#[derive(Debug)]
+pub struct Deduplicating<L> {
+    inner: L,
+    window: Duration,
+    last: Mutex<Option<Last>>,
+}
+
+impl<L: log::Log> Deduplicating<L> {
+    pub fn new(inner: L, window: Duration) -> Self {
+        Deduplicating {
+            inner,
+            window,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Logs the repeats of `last`, if there were any since its last summary.
+    fn summarize(&self, last: &mut Last, now: Instant) {
+        if last.repeats > 0 {
+            let times = match last.repeats {
+                1 => String::from("once"),
+                n => format!("{n} times"),
+            };
+            self.inner.log(
+                &log::Record::builder()
+                    .level(last.level)
+                    .target(&last.target)
+                    .args(format_args!("{} (repeated {times})", last.message))
+                    .build(),
+            );
+        }
+        last.repeats = 0;
+        last.since = now;
+    }
+
+    fn log_at(&self, record: &log::Record, now: Instant) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let mut last = self.last.lock().unwrap();
+        match last.as_mut() {
+            Some(last) if last.is_repeated_by(record, &message) => {
+                last.repeats += 1;
+                if now.duration_since(last.since) >= self.window {
+                    self.summarize(last, now);
+                }
+                return;
+            }
+            Some(last) => self.summarize(last, now),
+            None => {}
+        }
+        self.inner.log(record);
+        *last = Some(Last {
+            level: record.level(),
+            target: record.target().to_string(),
+            message,
+            repeats: 0,
+            since: now,
+        });
+    }
+}
+
+impl<L: log::Log> log::Log for Deduplicating<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.log_at(record, Instant::now());
+    }
+
+    fn flush(&self) {
+        if let Some(last) = self.last.lock().unwrap().as_mut() {
+            self.summarize(last, Instant::now());
+        }
+        self.inner.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Log;
+
+    /// Keeps the messages it is given.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl log::Log for &Recorder {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Info
+        }
+
+        fn log(&self, record: &log::Record) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_repeats_are_counted_and_summarized() {
+        let recorder = Recorder::default();
+        let logger = Deduplicating::new(&recorder, Duration::from_secs(60));
+        let start = Instant::now();
+        let log = |level, message: &str, secs| {
+            logger.log_at(
+                &log::Record::builder()
+                    .level(level)
+                    .args(format_args!("{message}"))
+                    .build(),
+                start + Duration::from_secs(secs),
+            );
+        };
+
+        for secs in 0..3 {
+            log(log::Level::Info, "No matching kworkers found", secs);
+        }
+        // Filtered out by the inner logger, so neither logged nor breaking the run.
+        log(log::Level::Debug, "Scanned processes", 3);
+        for secs in 4..62 {
+            log(log::Level::Info, "No matching kworkers found", secs);
+        }
+        log(log::Level::Info, "No matching kworkers found", 62);
+        log(log::Level::Warn, "No matching kworkers found", 63);
+        log(log::Level::Warn, "No matching kworkers found", 64);
+        log(log::Level::Info, "Sync triggered", 65);
+        logger.flush();
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "No matching kworkers found",
+                // The window elapsed at 60s, with 59 repeats since the first line.
+                "No matching kworkers found (repeated 59 times)",
+                "No matching kworkers found (repeated 2 times)",
+                "No matching kworkers found",
+                "No matching kworkers found (repeated once)",
+                "Sync triggered",
+            ]
+        );
+    }
+}
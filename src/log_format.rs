@@ -0,0 +1,158 @@
+//! `--log-format json`, one JSON object per log line for ingestion into log aggregators.
+use crate::labels::Labels;
+use log::kv::{Error, Key, Value, VisitSource};
+use serde_json::Number;
+
+/// How log lines are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// env_logger's human-readable lines.
+    #[default]
+    Text,
+    /// A JSON object per line.
+    Json,
+}
+
+impl std::fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "invalid log format '{s}', expected 'text' or 'json'"
+            )),
+        }
+    }
+}
+
+/// Collects the structured fields of a record, keeping numbers and booleans as such.
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = if let Some(b) = value.to_bool() {
+            serde_json::Value::Bool(b)
+        } else if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64().and_then(Number::from_f64) {
+            serde_json::Value::Number(n)
+        } else {
+            value.to_string().into()
+        };
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+/// Writes `record` as a JSON line with its level, message, `timestamp` if given, `labels` if
+/// any, then its structured fields.
+pub fn write_json(
+    out: &mut impl std::io::Write,
+    timestamp: Option<impl std::fmt::Display>,
+    labels: &Labels,
+    record: &log::Record,
+) -> std::io::Result<()> {
+    let mut line: Vec<(String, serde_json::Value)> = Vec::new();
+    if let Some(timestamp) = timestamp {
+        line.push(("ts".to_string(), timestamp.to_string().into()));
+    }
+    line.push(("level".to_string(), record.level().as_str().into()));
+    line.push(("msg".to_string(), record.args().to_string().into()));
+    if !labels.is_empty() {
+        let labels = serde_json::to_value(labels).map_err(std::io::Error::other)?;
+        line.push(("labels".to_string(), labels));
+    }
+    let mut fields = Fields(Vec::new());
+    // Visiting a record's fields only fails if the visitor does.
+    let _ = record.key_values().visit(&mut fields);
+    for (key, value) in fields.0 {
+        // The keys above take precedence over fields of the same name.
+        if !line.iter().any(|(k, _)| *k == key) {
+            line.push((key, value));
+        }
+    }
+    // Written by hand, as `serde_json` maps don't keep the order of their keys.
+    out.write_all(b"{")?;
+    for (i, (key, value)) in line.iter().enumerate() {
+        if i > 0 {
+            out.write_all(b",")?;
+        }
+        serde_json::to_writer(&mut *out, key).map_err(std::io::Error::other)?;
+        out.write_all(b":")?;
+        serde_json::to_writer(&mut *out, value).map_err(std::io::Error::other)?;
+    }
+    out.write_all(b"}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_line(timestamp: Option<&str>, labels: &Labels, record: &log::Record) -> String {
+        let mut out = Vec::new();
+        write_json(&mut out, timestamp, labels, record).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_parse_log_format() {
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("logfmt".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_json_line_with_fields() {
+        let fields: &[(&str, Value)] = &[
+            ("kworker_comm", Value::from("kworker/u8:2+inode_switch_wbs")),
+            ("kworker_pid", Value::from(1234)),
+            ("runtime_s", Value::from(40.5)),
+            ("msg", Value::from("ignored")),
+        ];
+        let labels = Labels::new(vec!["cluster=prod".parse().unwrap()]).unwrap();
+        let line = json_line(
+            Some("2026-10-14T10:00:00Z"),
+            &labels,
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("Sync triggered"))
+                .key_values(&fields)
+                .build(),
+        );
+        assert_eq!(
+            line,
+            "{\"ts\":\"2026-10-14T10:00:00Z\",\"level\":\"WARN\",\"msg\":\"Sync triggered\",\
+             \"labels\":{\"cluster\":\"prod\"},\"kworker_comm\":\"kworker/u8:2+inode_switch_wbs\",\
+             \"kworker_pid\":1234,\"runtime_s\":40.5}\n"
+        );
+    }
+
+    #[test]
+    fn test_json_line_without_timestamp() {
+        let line = json_line(
+            None,
+            &Labels::default(),
+            &log::Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("quoted \"text\""))
+                .build(),
+        );
+        assert_eq!(
+            line,
+            "{\"level\":\"INFO\",\"msg\":\"quoted \\\"text\\\"\"}\n"
+        );
+    }
+}
@@ -1,53 +1,215 @@
-//! # Stuck Writeback Workaround
-//!
-//! A userspace daemon to mitigate indefinite `inode_switch_wbs` stalls in the Linux kernel.
-//!
-//! This tool works around a kernel bug where writeback operations stall indefinitely, hogging
-//! gradually more and more CPUs, until there's none left. The daemon monitors `kworker` threads
-//! executing `inode_switch_wbs` that appear stuck and issues a `sync()` to free them up.
-mod system;
-
+//! The `stuck_writeback_workaround` daemon, which parses its flags and runs the main loop of the
+//! library of the same name.
 use anyhow::Context;
-use glob_match::glob_match;
-use log::{debug, error, info, warn};
-use std::thread::sleep;
+use log::{info, warn};
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use system::{LiveSystem, System};
-
-/// The polling interval when a matching `kworker` process is running but has not yet exceeded
-/// its time threshold. This is a tight loop to catch it as soon as it does.
-const BUSY_POLLING: Duration = Duration::from_secs(1);
-
-/// The polling interval after an error has occurred. This is a back-off to prevent spamming
-/// logs with repeated errors.
-const IDLE_POLLING: Duration = Duration::from_secs(60);
-
-/// On a busy system, the kernel may drop netlink events. To safeguard against this, we'll
-/// periodically re-scan the full process list to ensure we haven't missed a stuck `kworker`.
-const MAX_MONITOR_DURATION: Duration = Duration::from_secs(60);
-
-/// After triggering a `sync`, we'll pause monitoring for this duration to allow the system to
-/// recover and stabilize.
-const EXPECTED_RECOVERY_TIME: Duration = Duration::from_secs(30);
+use stuck_writeback_workaround::action::{Action, PatternAction};
+use stuck_writeback_workaround::affinity::{self, CpuList};
+use stuck_writeback_workaround::async_loop;
+use stuck_writeback_workaround::clock::{self, BootClock};
+use stuck_writeback_workaround::config_file::ConfigFile;
+use stuck_writeback_workaround::daemon_state::DaemonState;
+use stuck_writeback_workaround::duration::{
+    self, parse_duration, parse_std_duration, parse_threshold,
+};
+use stuck_writeback_workaround::event_log::EventLog;
+use stuck_writeback_workaround::incident::Resolution;
+use stuck_writeback_workaround::ioprio::IoPrioClass;
+use stuck_writeback_workaround::journald::{JournalLogger, LogTarget};
+use stuck_writeback_workaround::keepalive::KeepAlive;
+use stuck_writeback_workaround::kernel_cmdline::KernelCmdline;
+use stuck_writeback_workaround::labels::{Label, Labels};
+use stuck_writeback_workaround::log_color::{self, ColorChoice};
+use stuck_writeback_workaround::log_dedup::Deduplicating;
+use stuck_writeback_workaround::log_format::{self, LogFormat};
+use stuck_writeback_workaround::metrics::Metrics;
+use stuck_writeback_workaround::oom::{self, OomScoreAdj};
+use stuck_writeback_workaround::pattern_file::PatternFile;
+use stuck_writeback_workaround::percent::parse_percent;
+use stuck_writeback_workaround::pidfile::PidFile;
+use stuck_writeback_workaround::prefilter::CommPrefilter;
+use stuck_writeback_workaround::shutdown::{self, ExitReason, Teardown};
+use stuck_writeback_workaround::signature::{check_glob, ProcessGlob, Signature};
+use stuck_writeback_workaround::state_file::StateFile;
+use stuck_writeback_workaround::status_socket::StatusSocket;
+use stuck_writeback_workaround::sync_limit::SyncLimit;
+use stuck_writeback_workaround::sync_mode::SyncMode;
+use stuck_writeback_workaround::system::{self, LiveSystem, ProcInfo, SyncRunner, System};
+use stuck_writeback_workaround::{
+    canary, capabilities, config_changes, emit_test_event, first_iteration, format_scan,
+    is_monitored, jitter, metrics_server, once, privileges, reload, required_capabilities, rules,
+    sleep_duration_after, starttime_check, supervisor, systemd, version, webhook, workaround,
+    write_incident, Config, Outcome, StartupBehavior, Timings, EXIT_MAX_SYNCS,
+};
 
 /// Command-line arguments
-#[derive(argh::FromArgs, Debug)]
+#[derive(argh::FromArgs, Debug, Clone)]
 /// Monitors `kworker` threads and triggers a system-wide `sync` if they appear to be stuck.
 /// This is a workaround for a kernel bug where writeback operations can stall indefinitely.
 #[argh(help_triggers("-h", "--help"))]
 struct Args {
-    /// a glob pattern to identify the target `kworker` process names.
-    #[argh(option, default = "String::from(\"kworker/*inode_switch_wbs*\")")]
-    process_glob: String,
+    /// reads settings from this TOML file, with keys named after the flags as in the output of
+    /// `--dump-config`. Flags take precedence over the file.
+    #[argh(option)]
+    config: Option<PathBuf>,
+
+    /// a glob pattern to identify the target `kworker` process names, optionally with its own
+    /// runtime threshold as `<GLOB>=<DURATION>` (e.g. "jbd2/*=2m"). Repeatable (default:
+    /// "kworker/*inode_switch_wbs*").
+    #[argh(option)]
+    process_glob: Vec<ProcessGlob>,
 
     /// the maximum permissible runtime for a monitored `kworker` process before a `sync` is
-    /// triggered. The value is parsed as a human-readable duration (e.g., "30s", "1m").
-    #[argh(
-        option,
-        from_str_fn(parse_duration),
-        default = "chrono::Duration::seconds(30)"
-    )]
-    runtime_threshold: chrono::Duration,
+    /// triggered. The value is parsed as a human-readable duration (e.g., "30s", "1m"; default:
+    /// "30s"), or "off" to only act on the other triggers.
+    #[argh(option, from_str_fn(parse_threshold))]
+    runtime_threshold: Option<Option<chrono::Duration>>,
+
+    /// logs a warning once a monitored process has run for this long, without acting on it yet,
+    /// to correlate stalls with other events before the sync. At most `--runtime-threshold`.
+    #[argh(option, from_str_fn(parse_duration))]
+    warn_threshold: Option<chrono::Duration>,
+
+    /// also triggers when the ages of all matching kworkers sum to more than this, capturing
+    /// several workers that are each just under `--runtime-threshold`.
+    #[argh(option, from_str_fn(parse_duration))]
+    sum_age_threshold: Option<chrono::Duration>,
+
+    /// also triggers when a matching kworker has consumed this much CPU time, as stuck ones spin
+    /// rather than sleep.
+    #[argh(option, from_str_fn(parse_duration))]
+    cpu_threshold: Option<chrono::Duration>,
+
+    /// also triggers when at least this many kworkers match at once, however long each has been
+    /// running, as a cascading stall spawns many.
+    #[argh(option)]
+    min_stuck_count: Option<usize>,
+
+    /// never acts before the system has been up for this long, however long kworkers have been
+    /// stuck, as the first sync after boot is special.
+    #[argh(option, from_str_fn(parse_duration))]
+    first_action_after_boot: Option<chrono::Duration>,
+
+    /// only acts on a stuck process if its CPU time does not grow over a second, as one still
+    /// consuming CPU is working rather than wedged.
+    #[argh(switch)]
+    require_no_progress: bool,
+
+    /// only acts on a process stuck for longer than its threshold if the kernel function it
+    /// waits in, from `/proc/<pid>/wchan`, contains this. Processes whose wchan is unknown
+    /// (unreadable, or running) are acted on as without it.
+    #[argh(option)]
+    require_wchan: Option<String>,
+
+    /// monitors matching processes whatever their state, rather than only those running (R) or
+    /// in uninterruptible sleep (D), which a stalled kworker is in.
+    #[argh(switch)]
+    any_state: bool,
+
+    /// only monitors processes of this user, e.g. the one root maps to in a user namespace.
+    /// Repeatable (default: 0).
+    #[argh(option)]
+    uid: Vec<u32>,
+
+    /// monitors matching processes whatever their user, overriding `--uid`.
+    #[argh(switch)]
+    any_uid: bool,
+
+    /// only acts on this percentage of hosts, chosen by a stable hash of the hostname, the others
+    /// running detect-only. For rolling out remediation to a fleet gradually.
+    #[argh(option, from_str_fn(parse_percent))]
+    canary_percent: Option<u8>,
+
+    /// groups triggers within this long of each other into a single episode, which is only
+    /// reported once. Should exceed the 30s recovery time to have any effect.
+    #[argh(option, from_str_fn(parse_duration))]
+    episode_gap: Option<chrono::Duration>,
+
+    /// bounds how long a process scan may take, on pathologically large or slow `/proc`. Past it,
+    /// the scan is truncated and only the processes read so far are considered.
+    #[argh(option, from_str_fn(parse_std_duration))]
+    scan_budget: Option<Duration>,
+
+    /// bounds how many candidate processes, whose comm may match, a scan reads in full. Past it,
+    /// the others are left out with a warning.
+    #[argh(option)]
+    max_examined: Option<usize>,
+
+    /// waits for new kworkers by sleeping until the next rescan, rather than on process events
+    /// from the kernel connector, which needs `CAP_NET_ADMIN` and `CONFIG_PROC_EVENTS`. Also done
+    /// automatically if listening to them fails after startup.
+    #[argh(switch)]
+    no_netlink: bool,
+
+    /// reads processes from the procfs mounted there, e.g. the host's bind-mounted into a
+    /// container (default: "/proc").
+    #[argh(option)]
+    procfs_root: Option<PathBuf>,
+
+    /// how far process ages derived from `/proc` may be off, as checked at startup on the
+    /// daemon's own process, before warning that they cannot be trusted (default: "5s").
+    #[argh(option, from_str_fn(parse_duration))]
+    starttime_tolerance: Option<chrono::Duration>,
+
+    /// how often to scan while a matching process is running but has not yet exceeded its
+    /// threshold (default: "1s").
+    #[argh(option, from_str_fn(parse_std_duration))]
+    busy_poll: Option<Duration>,
+
+    /// how long to wait after an iteration failed before trying again (default: "1m").
+    #[argh(option, from_str_fn(parse_std_duration))]
+    error_backoff: Option<Duration>,
+
+    /// the longest wait for a new kworker to appear before scanning again anyway, in case the
+    /// kernel dropped its event (default: "1m").
+    #[argh(option, from_str_fn(parse_std_duration))]
+    rescan_interval: Option<Duration>,
+
+    /// how long to pause monitoring after a remediation, for the system to recover (default:
+    /// "30s").
+    #[argh(option, from_str_fn(parse_std_duration))]
+    recovery_time: Option<Duration>,
+
+    /// randomizes each sleep of the main loop by up to this fraction either way, e.g. 0.1 for
+    /// ±10%, so that hosts started together don't keep scanning in lockstep.
+    #[argh(option, from_str_fn(jitter::parse_fraction))]
+    jitter: Option<f64>,
+
+    /// a shell command run after each remediation to check whether it worked: exiting with 0
+    /// means the stall is resolved, anything else that it persists.
+    #[argh(option)]
+    verify_command: Option<String>,
+
+    /// how many syncs in a row may leave the same process stuck before an error is logged and
+    /// the `--escalation-command` is run, 0 to never escalate (default: 3).
+    #[argh(option)]
+    max_ineffective_syncs: Option<usize>,
+
+    /// a shell command run when syncs keep leaving the same process stuck, e.g. to page someone.
+    #[argh(option)]
+    escalation_command: Option<String>,
+
+    /// also requests an emergency sync from the kernel when escalating, by writing "s" to
+    /// `/proc/sysrq-trigger`. Needs a kernel built with `CONFIG_MAGIC_SYSRQ`.
+    #[argh(switch)]
+    escalate_sysrq: bool,
+
+    /// exits with status 11 once this many syncs were issued, dry runs counting those they would
+    /// have issued, so that an orchestrator drains or replaces the node.
+    #[argh(option)]
+    max_syncs: Option<u64>,
+
+    /// reads `wb.glob=` and `wb.threshold=` from the kernel command line, for settings not given
+    /// as flags.
+    #[argh(switch)]
+    from_cmdline: bool,
 
     /// enables INFO-level logging.
     #[argh(switch, short = 'v')]
@@ -57,209 +219,1441 @@ struct Args {
     #[argh(switch, short = 'd')]
     debug: bool,
 
+    /// only logs errors, leaving out the warnings issued on every sync.
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+
     /// omits timestamps from log output.
     #[argh(switch)]
     no_timestamps: bool,
+
+    /// how log lines are written: "text" for humans, or "json" for one JSON object per line, for
+    /// log aggregators (default: "text").
+    #[argh(option)]
+    log_format: Option<LogFormat>,
+
+    /// when to color text log lines by level: "auto" when stderr is a terminal and `NO_COLOR`
+    /// isn't set, "always" or "never" (default: "auto").
+    #[argh(option)]
+    color: Option<ColorChoice>,
+
+    /// where log lines go: "stderr", or "journald" for the systemd journal, with priorities and
+    /// structured fields such as `KWORKER_COMM` (default: "stderr").
+    #[argh(option)]
+    log_target: Option<LogTarget>,
+
+    /// collapses identical consecutive log lines into the first one, then a "(repeated N times)"
+    /// summary at most once per this long, e.g. "10m". Off by default.
+    #[argh(option, from_str_fn(parse_std_duration))]
+    log_dedup_window: Option<Duration>,
+
+    /// also matches `--process-glob` against the full command line, for monitoring userspace
+    /// processes. Off by default since kworkers have an empty command line.
+    #[argh(switch)]
+    match_cmdline: bool,
+
+    /// runs the `sync` on a dedicated thread with this I/O priority class ("idle" or
+    /// "best-effort"), so it doesn't starve foreground I/O.
+    #[argh(option)]
+    sync_ioprio: Option<IoPrioClass>,
+
+    /// what the `sync` action flushes: "global" for every filesystem, or "fs" for only the one
+    /// a stuck flusher kworker names in its comm (e.g. "flush-8:0"), falling back to every
+    /// filesystem for others (default: "global").
+    #[argh(option)]
+    sync_mode: Option<SyncMode>,
+
+    /// the least time between two syncs, triggers within it being ignored, so that a broken
+    /// kernel can't get the daemon to sync back to back (default: 10s).
+    #[argh(option, from_str_fn(parse_duration))]
+    sync_cooldown: Option<chrono::Duration>,
+
+    /// how long to wait for a `sync` to return, as it may itself block on the stuck writeback.
+    /// Past it, the sync is left running and monitoring resumes after an error (default: 30s).
+    #[argh(option, from_str_fn(parse_std_duration))]
+    sync_timeout: Option<Duration>,
+
+    /// only detects, rather than syncs, while the filesystem a `sync` would flush has less than
+    /// this percentage of its space free or is read-only, as after errors (default: disabled).
+    #[argh(option, from_str_fn(parse_percent))]
+    min_free_percent: Option<u8>,
+
+    /// a path on the filesystem whose state `--min-free-percent` checks when syncing every
+    /// filesystem (default: "/").
+    #[argh(option)]
+    sync_path: Option<PathBuf>,
+
+    /// the action taken when a process matching `--process-glob` is stuck: "sync", "command" to
+    /// run `--action-command`, or "signal:<SIGNAL>" as for `--pattern-action` (default: "sync").
+    #[argh(option)]
+    action: Option<Action>,
+
+    /// the shell command run by "command" actions, e.g. "echo s > /proc/sysrq-trigger". A
+    /// failure or a timeout after 1m is an error.
+    #[argh(option)]
+    action_command: Option<String>,
+
+    /// maps processes matching a glob to the action taken when they are stuck, as
+    /// "<glob>=sync", "<glob>=command" or "<glob>=signal:<SIGNAL>". Matching processes are
+    /// monitored in addition to `--process-glob`, which uses `--action`. May be repeated, the
+    /// first match wins.
+    #[argh(option)]
+    pattern_action: Vec<PatternAction>,
+
+    /// identifies a distinct stall as "glob=<GLOB>", optionally followed by ",uid=<UID>" it runs
+    /// as, ",stack=<SUBSTRING>" of the kernel stack, ",state=<STATES>" (e.g. "RD"),
+    /// ",threshold=<DURATION>" and ",action=<ACTION>". A process belongs to the first signature
+    /// whose every criterion it matches, signatures coming before `--pattern-action` and
+    /// `--process-glob`. Repeatable.
+    #[argh(option)]
+    signature: Vec<Signature>,
+
+    /// a TOML file of `[[rule]]`s, each monitoring its own `glob`, with optional `uid`, `stack`,
+    /// `state`, `runtime-threshold` and `action` as for `--signature`, which they come after.
+    #[argh(option)]
+    rules: Option<PathBuf>,
+
+    /// a file listing additional globs to monitor, one per line, with blank lines and `#`
+    /// comments ignored. It is re-read whenever its mtime changes.
+    #[argh(option)]
+    pattern_file: Option<PathBuf>,
+
+    /// pins the daemon to these CPUs (e.g. "0" or "0-1,4"), so it keeps a reserved core while
+    /// stuck kworkers consume the others.
+    #[argh(option)]
+    cpu_affinity: Option<CpuList>,
+
+    /// adjusts the daemon's OOM score, from -1000 to 1000, e.g. -900 so that the OOM killer
+    /// spares it when memory pressure rises during a stall.
+    #[argh(option)]
+    oom_score_adj: Option<OomScoreAdj>,
+
+    /// what the first iteration does: "scan" processes immediately, or "wait" for a new kworker
+    /// to appear first so as not to act on a transient startup state (default: "scan").
+    #[argh(option)]
+    startup_behavior: Option<StartupBehavior>,
+
+    /// at startup, reports a clearly-marked test trigger through the logs and metrics, without
+    /// syncing, to validate the notification pipeline.
+    #[argh(switch)]
+    emit_test_event: bool,
+
+    /// reports stuck processes through the logs, metrics and webhook as usual, but never acts on
+    /// them, to observe how often the workaround would fire.
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// runs the monitor as a child of a minimal supervisor process, which restarts it with
+    /// backoff if it dies or stops sending heartbeats.
+    #[argh(switch)]
+    supervise: bool,
+
+    /// drives the monitor loop from a tokio runtime, running iterations on blocking tasks and
+    /// ending waits between them at once on termination signals. Requires building with the
+    /// `async` feature.
+    #[argh(switch, long = "async")]
+    async_loop: bool,
+
+    /// notifies systemd once started and pings its watchdog every loop, for `Type=notify`
+    /// services with `WatchdogSec=`. Enabled whenever `NOTIFY_SOCKET` is set, this makes it
+    /// mandatory.
+    #[argh(switch)]
+    systemd: bool,
+
+    /// writes the daemon's pid to this file and keeps it locked while running, refusing to start
+    /// if another instance holds it. For systemd's `PIDFile=`.
+    #[argh(option)]
+    pidfile: Option<PathBuf>,
+
+    /// records every sync to this file, and restores the last ones from it on startup, so that
+    /// `--sync-cooldown` and `--max-ineffective-syncs` outlive restarts.
+    #[argh(option)]
+    state_file: Option<PathBuf>,
+
+    /// appends every trigger, kworker appearing and wait ending without one to this file, as one
+    /// JSON object per line.
+    #[argh(option)]
+    event_log: Option<PathBuf>,
+
+    /// once its sockets and files are open, switches the daemon to this user, by name or uid,
+    /// keeping only the capabilities its configuration needs.
+    #[argh(option)]
+    drop_to: Option<String>,
+
+    /// prints the version, with the git revision and time it was built from, and exits.
+    #[argh(switch)]
+    version: bool,
+
+    /// prints the effective configuration, once flags and the kernel command line were applied
+    /// over defaults, as TOML and exits.
+    #[argh(switch)]
+    dump_config: bool,
+
+    /// prints every process a scan finds, with whether it is monitored or why it is skipped, and
+    /// exits. For debugging globs matching too much or too little.
+    #[argh(switch)]
+    dump_processes: bool,
+
+    /// runs a single evaluation pass without waiting for new kworkers, and exits with 0 if there
+    /// was nothing to do, 10 if a stuck process was acted on or 1 on errors. For cron jobs and
+    /// integration tests.
+    #[argh(switch)]
+    once: bool,
+
+    /// writes Prometheus metrics to this file after every loop, for the node_exporter textfile
+    /// collector.
+    #[argh(option)]
+    metrics_textfile: Option<PathBuf>,
+
+    /// serves Prometheus metrics over HTTP at `/metrics` on this address, e.g. `127.0.0.1:9469`,
+    /// for scraping without the node_exporter.
+    #[argh(option)]
+    metrics_listen: Option<SocketAddr>,
+
+    /// answers every connection to a Unix socket at this path with a JSON snapshot of what the
+    /// daemon is doing, for local inspection with e.g. `socat`.
+    #[argh(option)]
+    status_socket: Option<PathBuf>,
+
+    /// logs percentiles of the runtime of the oldest matching kworker, over every scan that found
+    /// one, when the daemon exits.
+    #[argh(switch)]
+    print_stats_on_exit: bool,
+
+    /// POSTs a JSON report to this URL once per stall episode, on its first trigger, for ChatOps
+    /// and incident tooling. Requires building with the `webhook` feature.
+    #[argh(option)]
+    webhook: Option<String>,
+
+    /// writes a Markdown report of every stall episode to this directory once it ends, with its
+    /// timeline, remediations and the stuck process's kernel stack.
+    #[argh(option)]
+    incident_dir: Option<PathBuf>,
+
+    /// logs the kernel stack of a stuck process right before acting on it, which is the evidence
+    /// kernel developers ask for.
+    #[argh(switch)]
+    capture_stack: bool,
+
+    /// writes the stacks captured by `--capture-stack` to files in this directory rather than to
+    /// the logs. Implies `--capture-stack`.
+    #[argh(option)]
+    stack_dir: Option<PathBuf>,
+
+    /// attaches this `key=value` label to every log line, metric and webhook report, e.g.
+    /// `cluster=prod`, for aggregating the output of a fleet. Repeatable.
+    #[argh(option)]
+    label: Vec<Label>,
 }
 
 impl Args {
-    fn log_level(&self) -> log::LevelFilter {
-        match (self.verbose, self.debug) {
-            (false, false) => log::LevelFilter::Warn,
-            (true, false) => log::LevelFilter::Info,
-            (_, true) => log::LevelFilter::Debug,
+    /// Resolves the configuration, reading the kernel command line if requested.
+    fn config(&self) -> anyhow::Result<Config> {
+        if self.webhook.is_some() && !webhook::SUPPORTED {
+            anyhow::bail!("--webhook requires building with the `webhook` feature");
         }
+        if self.async_loop && !async_loop::SUPPORTED {
+            anyhow::bail!("--async requires building with the `async` feature");
+        }
+        if self.max_syncs == Some(0) {
+            anyhow::bail!("--max-syncs must be at least 1");
+        }
+        let kernel = if self.from_cmdline {
+            KernelCmdline::read()?
+        } else {
+            KernelCmdline::default()
+        };
+        let mut config = self.config_with(kernel);
+        if let Some(path) = &self.rules {
+            config.rules = rules::load(path)?;
+        }
+        config.validate()?;
+        config.labels = Labels::new(self.label.clone())?;
+        if let Some(percent) = config.canary_percent {
+            config.detect_only = !canary::includes(&webhook::hostname(), percent);
+        }
+        Ok(config)
     }
-}
 
-fn parse_duration(s: &str) -> Result<chrono::Duration, String> {
-    let d = humantime::parse_duration(s).map_err(|e| format!("invalid duration: {e}"))?;
-    chrono::Duration::from_std(d).map_err(|e| format!("duration conversion error: {e}"))
-}
+    /// Resolves the configuration, flags taking precedence over `kernel`, then defaults.
+    fn config_with(&self, kernel: KernelCmdline) -> Config {
+        let defaults = Config::default();
+        Config {
+            process_globs: if !self.process_glob.is_empty() {
+                self.process_glob.clone()
+            } else if let Some(glob) = kernel.process_glob {
+                vec![ProcessGlob::new(&glob)]
+            } else {
+                defaults.process_globs
+            },
+            runtime_threshold: self
+                .runtime_threshold
+                .or(kernel.runtime_threshold)
+                .unwrap_or(defaults.runtime_threshold),
+            signatures: self.signature.clone(),
+            rules: Vec::new(),
+            pattern_actions: self.pattern_action.clone(),
+            file_globs: Vec::new(),
+            warn_threshold: self.warn_threshold,
+            sum_age_threshold: self.sum_age_threshold,
+            cpu_threshold: self.cpu_threshold,
+            min_stuck_count: self.min_stuck_count,
+            episode_gap: self.episode_gap,
+            first_action_after_boot: self.first_action_after_boot,
+            require_no_progress: self.require_no_progress,
+            require_wchan: self.require_wchan.clone(),
+            any_state: self.any_state,
+            uids: if self.uid.is_empty() {
+                defaults.uids
+            } else {
+                self.uid.clone()
+            },
+            any_uid: self.any_uid,
+            default_action: self.action.unwrap_or(defaults.default_action),
+            action_command: self.action_command.clone(),
+            sync_mode: self.sync_mode.unwrap_or_default(),
+            sync_cooldown: self.sync_cooldown.unwrap_or(defaults.sync_cooldown),
+            canary_percent: self.canary_percent,
+            dry_run: self.dry_run,
+            detect_only: false,
+            verify_command: self.verify_command.clone(),
+            max_ineffective_syncs: self
+                .max_ineffective_syncs
+                .unwrap_or(defaults.max_ineffective_syncs),
+            escalation_command: self.escalation_command.clone(),
+            escalate_sysrq: self.escalate_sysrq,
+            min_free_percent: self.min_free_percent,
+            sync_path: self.sync_path.clone(),
+            webhook: self.webhook.clone(),
+            incident_dir: self.incident_dir.clone(),
+            capture_stack: self.capture_stack || self.stack_dir.is_some(),
+            stack_dir: self.stack_dir.clone(),
+            timings: Timings {
+                busy_poll: self.busy_poll.unwrap_or(defaults.timings.busy_poll),
+                error_backoff: self.error_backoff.unwrap_or(defaults.timings.error_backoff),
+                rescan_interval: self
+                    .rescan_interval
+                    .unwrap_or(defaults.timings.rescan_interval),
+                recovery_time: self.recovery_time.unwrap_or(defaults.timings.recovery_time),
+            },
+            jitter: self.jitter,
+            labels: Labels::default(),
+        }
+    }
 
-/// The core logic of the workaround.
-///
-/// This function scans for `kworker` processes, checks if they are stuck, and triggers a `sync`
-/// if necessary. It returns the recommended duration to wait before the next check.
-fn workaround<T: System>(
-    system: &T,
-    process_glob: &str,
-    runtime_threshold: &chrono::Duration,
-) -> anyhow::Result<Duration> {
-    let is_kworker = |p: &crate::system::ProcInfo| p.uid == 0 && glob_match(process_glob, &p.comm);
-
-    let oldest_kworker = system
-        .find_oldest_kworker(is_kworker)
-        .context("failed to scan for matching kworker processes")?;
-
-    if let Some(kworker) = oldest_kworker {
-        let now = system.now();
-        let oldest_runtime = now.signed_duration_since(kworker.starttime);
-        debug!("Oldest kworker runtime: {}s", oldest_runtime.num_seconds());
-
-        if oldest_runtime > *runtime_threshold {
-            warn!(
-                "Sync triggered: oldest kworker '{}' has been running for {}s (threshold: {}s)",
-                kworker.comm,
-                oldest_runtime.num_seconds(),
-                runtime_threshold.num_seconds()
-            );
-            system.sync();
-            Ok(EXPECTED_RECOVERY_TIME)
-        } else {
-            Ok(BUSY_POLLING)
+    /// Fills in the settings not given as flags from `file`.
+    fn merge(&mut self, file: ConfigFile) {
+        fn merge_vec<T>(flags: &mut Vec<T>, file: Vec<T>) {
+            if flags.is_empty() {
+                *flags = file;
+            }
+        }
+        merge_vec(&mut self.process_glob, file.process_glob);
+        self.runtime_threshold = self.runtime_threshold.or(file.runtime_threshold);
+        self.warn_threshold = self.warn_threshold.or(file.warn_threshold);
+        self.sum_age_threshold = self.sum_age_threshold.or(file.sum_age_threshold);
+        self.cpu_threshold = self.cpu_threshold.or(file.cpu_threshold);
+        self.min_stuck_count = self.min_stuck_count.or(file.min_stuck_count);
+        self.first_action_after_boot = self
+            .first_action_after_boot
+            .or(file.first_action_after_boot);
+        self.require_no_progress |= file.require_no_progress;
+        self.require_wchan = self.require_wchan.take().or(file.require_wchan);
+        self.any_state |= file.any_state;
+        merge_vec(&mut self.uid, file.uid);
+        self.any_uid |= file.any_uid;
+        self.canary_percent = self.canary_percent.or(file.canary_percent);
+        self.episode_gap = self.episode_gap.or(file.episode_gap);
+        self.scan_budget = self.scan_budget.or(file.scan_budget);
+        self.max_examined = self.max_examined.or(file.max_examined);
+        self.no_netlink |= file.no_netlink;
+        self.procfs_root = self.procfs_root.take().or(file.procfs_root);
+        self.starttime_tolerance = self.starttime_tolerance.or(file.starttime_tolerance);
+        self.busy_poll = self.busy_poll.or(file.busy_poll);
+        self.error_backoff = self.error_backoff.or(file.error_backoff);
+        self.rescan_interval = self.rescan_interval.or(file.rescan_interval);
+        self.recovery_time = self.recovery_time.or(file.recovery_time);
+        self.jitter = self.jitter.or(file.jitter);
+        self.verify_command = self.verify_command.take().or(file.verify_command);
+        self.max_ineffective_syncs = self.max_ineffective_syncs.or(file.max_ineffective_syncs);
+        self.escalation_command = self.escalation_command.take().or(file.escalation_command);
+        self.escalate_sysrq |= file.escalate_sysrq;
+        self.max_syncs = self.max_syncs.or(file.max_syncs);
+        self.from_cmdline |= file.from_cmdline;
+        self.verbose |= file.verbose;
+        self.debug |= file.debug;
+        self.quiet |= file.quiet;
+        self.no_timestamps |= file.no_timestamps;
+        self.log_format = self.log_format.or(file.log_format);
+        self.color = self.color.or(file.color);
+        self.log_target = self.log_target.or(file.log_target);
+        self.log_dedup_window = self.log_dedup_window.or(file.log_dedup_window);
+        self.match_cmdline |= file.match_cmdline;
+        self.sync_ioprio = self.sync_ioprio.or(file.sync_ioprio);
+        self.sync_mode = self.sync_mode.or(file.sync_mode);
+        self.sync_cooldown = self.sync_cooldown.or(file.sync_cooldown);
+        self.sync_timeout = self.sync_timeout.or(file.sync_timeout);
+        self.min_free_percent = self.min_free_percent.or(file.min_free_percent);
+        self.sync_path = self.sync_path.take().or(file.sync_path);
+        self.action = self.action.or(file.action);
+        self.action_command = self.action_command.take().or(file.action_command);
+        merge_vec(&mut self.pattern_action, file.pattern_action);
+        merge_vec(&mut self.signature, file.signature);
+        self.rules = self.rules.take().or(file.rules);
+        self.pattern_file = self.pattern_file.take().or(file.pattern_file);
+        self.cpu_affinity = self.cpu_affinity.take().or(file.cpu_affinity);
+        self.oom_score_adj = self.oom_score_adj.or(file.oom_score_adj);
+        self.startup_behavior = self.startup_behavior.or(file.startup_behavior);
+        self.dry_run |= file.dry_run;
+        self.systemd |= file.systemd;
+        self.pidfile = self.pidfile.take().or(file.pidfile);
+        self.state_file = self.state_file.take().or(file.state_file);
+        self.event_log = self.event_log.take().or(file.event_log);
+        self.drop_to = self.drop_to.take().or(file.drop_to);
+        self.metrics_textfile = self.metrics_textfile.take().or(file.metrics_textfile);
+        self.metrics_listen = self.metrics_listen.or(file.metrics_listen);
+        self.status_socket = self.status_socket.take().or(file.status_socket);
+        self.print_stats_on_exit |= file.print_stats_on_exit;
+        self.webhook = self.webhook.take().or(file.webhook);
+        self.incident_dir = self.incident_dir.take().or(file.incident_dir);
+        self.capture_stack |= file.capture_stack;
+        self.stack_dir = self.stack_dir.take().or(file.stack_dir);
+        merge_vec(&mut self.label, file.label);
+    }
+
+    /// Returns the most verbose level asked for, `--debug` including what `--verbose` logs.
+    fn log_level(&self) -> anyhow::Result<log::LevelFilter> {
+        match (self.quiet, self.verbose, self.debug) {
+            (false, false, false) => Ok(log::LevelFilter::Warn),
+            (false, true, false) => Ok(log::LevelFilter::Info),
+            (false, _, true) => Ok(log::LevelFilter::Debug),
+            (true, false, false) => Ok(log::LevelFilter::Error),
+            (true, _, _) => anyhow::bail!("--quiet cannot be combined with --verbose or --debug"),
         }
+    }
+}
+
+/// Writes a log line as env_logger's default format does, but with `labels` after the level, and
+/// the message `colored` by level too.
+fn write_log_line(
+    out: &mut impl std::io::Write,
+    timestamp: Option<impl std::fmt::Display>,
+    level: log::Level,
+    labels: &Labels,
+    args: &std::fmt::Arguments,
+    colored: bool,
+) -> std::io::Result<()> {
+    let (level_style, message_style) = if colored {
+        (
+            log_color::level_style(level),
+            log_color::message_style(level),
+        )
     } else {
-        info!("No matching kworkers found, waiting for a new one to appear");
-        system
-            .wait_for_kworker(is_kworker, MAX_MONITOR_DURATION)
-            .context("failed to wait for kworker process")?;
-        Ok(Duration::from_secs(0))
+        Default::default()
+    };
+    let level = format!("{level_style}{level:<5}{level_style:#}");
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{labels} ")
+    };
+    let message = format!("{message_style}{args}{message_style:#}");
+    match timestamp {
+        Some(timestamp) => writeln!(out, "[{timestamp} {level}] {labels}{message}"),
+        None => writeln!(out, "[{level}] {labels}{message}"),
     }
 }
 
 fn init_logger(args: &Args) -> anyhow::Result<()> {
-    let log_level = args.log_level();
+    let log_level = args.log_level()?;
     let timestamp_precision = if args.no_timestamps {
         None
     } else {
         Some(env_logger::fmt::TimestampPrecision::Seconds)
     };
-    env_logger::Builder::from_default_env()
+    let mut builder = env_logger::Builder::from_default_env();
+    builder
         .filter_level(log_level)
         .format_timestamp(timestamp_precision)
-        .format_target(false)
-        .try_init()
-        .context("failed to initialize logger")
+        .format_target(false);
+    let labels = Labels::new(args.label.clone())?;
+    let (logger, max_level): (Box<dyn log::Log>, _) =
+        if args.log_target == Some(LogTarget::Journald) {
+            if args.log_format == Some(LogFormat::Json) {
+                anyhow::bail!("--log-format json only applies to --log-target stderr");
+            }
+            let logger = JournalLogger::connect(log_level, labels)?;
+            (Box::new(logger), log_level)
+        } else {
+            let timestamps = !args.no_timestamps;
+            let json = args.log_format == Some(LogFormat::Json);
+            let colored = !json
+                && args.color.unwrap_or_default().colors(
+                    std::io::stderr().is_terminal(),
+                    log_color::no_color_requested(),
+                );
+            // Also keeps env_logger from coloring lines in its default format.
+            builder.write_style(if colored {
+                env_logger::WriteStyle::Always
+            } else {
+                env_logger::WriteStyle::Never
+            });
+            if json {
+                builder.format(move |buf, record| {
+                    let timestamp = timestamps.then(|| buf.timestamp_seconds());
+                    log_format::write_json(buf, timestamp, &labels, record)
+                });
+            } else if colored || !labels.is_empty() {
+                builder.format(move |buf, record| {
+                    let timestamp = timestamps.then(|| buf.timestamp_seconds());
+                    let level = record.level();
+                    write_log_line(buf, timestamp, level, &labels, record.args(), colored)
+                });
+            }
+            let logger = builder.build();
+            // Which may be raised over `log_level` by `RUST_LOG`.
+            let max_level = logger.filter();
+            (Box::new(logger), max_level)
+        };
+    let logger = match args.log_dedup_window {
+        Some(window) => Box::new(Deduplicating::new(logger, window)),
+        None => logger,
+    };
+    log::set_boxed_logger(logger).context("failed to initialize logger")?;
+    log::set_max_level(max_level);
+    Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let args: Args = argh::from_env();
+fn main() -> anyhow::Result<ExitCode> {
+    // The reference for the start time self-check: the process has been running for about this
+    // long.
+    let started = std::time::Instant::now();
+    let mut args: Args = argh::from_env();
+    if args.version {
+        println!("{}", version::long());
+        return Ok(ExitCode::SUCCESS);
+    }
+    // What a reload merges the config file over again.
+    let flags = args.clone();
+    // Loaded before the logger is set up, as it may configure it.
+    let mut unknown_keys = Vec::new();
+    if let Some(path) = &args.config {
+        let file = ConfigFile::load(path)?;
+        unknown_keys.extend(file.unknown_keys().map(str::to_string));
+        args.merge(file);
+    }
 
     init_logger(&args)?;
+    for key in unknown_keys {
+        warn!("Ignoring unknown key '{key}' in the config file");
+    }
 
-    let system = LiveSystem;
-    loop {
-        let sleep_duration = match workaround(&system, &args.process_glob, &args.runtime_threshold)
-        {
-            Ok(duration) => duration,
-            Err(e) => {
-                error!("An error occurred: {e:?}");
-                IDLE_POLLING
-            }
-        };
-        sleep(sleep_duration);
+    if args.supervise {
+        if args.once {
+            anyhow::bail!("--once cannot be supervised, as it exits on its own");
+        }
+        // Fails early on invalid settings, rather than restarting a child that cannot start.
+        args.config()?;
+        let code = supervisor::supervise()?;
+        return Ok(ExitCode::from(code));
     }
+    // Before any thread is spawned, as reading them removes them from the environment, which
+    // other threads may read meanwhile.
+    let heartbeat = supervisor::Heartbeat::from_env()?;
+    let notifier = systemd::Notifier::from_env(args.systemd)?;
+    let keepalive = Arc::new(KeepAlive::new(notifier, heartbeat));
+    // Before any other thread is spawned, so that none of them is terminated by SIGHUP.
+    let reload_requested = reload::handle_reload_signal()?;
+    // Dropped on every return, and finished by the signal handler otherwise.
+    let teardown = Arc::new(Mutex::new(Teardown::default()));
+    let runtime = if args.async_loop {
+        Some(async_loop::Runtime::new()?)
+    } else {
+        shutdown::handle_termination_signals(Arc::downgrade(&teardown))?;
+        None
+    };
+    let result = monitor(
+        &args,
+        &flags,
+        &teardown,
+        started,
+        &reload_requested,
+        keepalive,
+        runtime,
+    );
+    if let Err(e) = &result {
+        shutdown::lock(&teardown).finish(&ExitReason::Failed(format!("{e:#}")));
+    }
+    result
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::system::{IsKworkerFn, ProcInfo, System};
-    use anyhow::Result;
-    use std::cell::Cell;
-    use std::time::Duration;
-
-    struct MockSystem {
-        kworker: Option<ProcInfo>,
-        now: chrono::DateTime<chrono::Local>,
-        sync_calls: Cell<usize>,
-        wait_for_kworker_result: Result<(), String>,
-    }
-
-    impl Default for MockSystem {
-        fn default() -> Self {
-            Self {
-                kworker: None,
-                now: chrono::Local::now(),
-                sync_calls: Cell::new(0),
-                wait_for_kworker_result: Ok(()),
+/// Checks upfront that the daemon holds the privileges its configuration needs, so that missing
+/// ones fail startup with how to remedy them, rather than surfacing deep in the loop. With
+/// `--drop-to`, switches users first, so that the privileges checked are those kept.
+fn init_system(system: &LiveSystem, config: &Config, args: &Args) -> anyhow::Result<()> {
+    let requirements = required_capabilities(config);
+    // Only waiting for new kworkers uses process events.
+    let process_events = !args.no_netlink && !args.once;
+    if let Some(user) = &args.drop_to {
+        let retained = privileges::retained(&requirements, process_events, system.read_wchan);
+        privileges::lookup(user)
+            .and_then(|credentials| privileges::drop_to(&credentials, &retained))
+            .with_context(|| format!("failed to switch to user '{user}'"))?;
+    }
+    capabilities::check(&requirements)?;
+    if config.escalate_sysrq {
+        if let Err(e) = system.check_sysrq() {
+            warn!("--escalate-sysrq won't work: {e:#}");
+        }
+    }
+    if process_events {
+        system.check_process_events()?;
+    }
+    Ok(())
+}
+
+/// Bounds the rescan interval of `config` by the systemd watchdog's, if any, so that idle
+/// iterations ping it in time.
+fn bound_rescan_interval(config: &mut Config, notifier: Option<&systemd::Notifier>) {
+    if let Some(interval) = notifier.and_then(|n| n.watchdog_interval()) {
+        let rescan = &mut config.timings.rescan_interval;
+        *rescan = (*rescan).min(interval);
+    }
+}
+
+/// Re-reads the `--config` file over `flags`, the arguments as given on the command line, and
+/// returns them along with the configuration to use instead of `current`. Settings only applied
+/// at startup are kept as they are.
+fn reloaded_config(
+    flags: &Args,
+    current: &Config,
+    notifier: Option<&systemd::Notifier>,
+) -> anyhow::Result<(Args, Config)> {
+    let mut args = flags.clone();
+    if let Some(path) = &flags.config {
+        let file = ConfigFile::load(path)?;
+        for key in file.unknown_keys() {
+            warn!("Ignoring unknown key '{key}' in the config file");
+        }
+        args.merge(file);
+    }
+    let mut config = args.config()?;
+    capabilities::check(&required_capabilities(&config))?;
+    if config.labels != current.labels || config.incident_dir != current.incident_dir {
+        warn!("Not reloading labels and the incident directory, which need a restart");
+    }
+    config.labels = current.labels.clone();
+    config.incident_dir = current.incident_dir.clone();
+    config.file_globs = current.file_globs.clone();
+    bound_rescan_interval(&mut config, notifier);
+    Ok((args, config))
+}
+
+/// Returns the flags only applied at startup that differ between the `startup` arguments and the
+/// `reloaded` ones.
+fn restart_only_changes(startup: &Args, reloaded: &Args) -> Vec<&'static str> {
+    [
+        ("no-netlink", startup.no_netlink != reloaded.no_netlink),
+        ("procfs-root", startup.procfs_root != reloaded.procfs_root),
+        (
+            "pattern-file",
+            startup.pattern_file != reloaded.pattern_file,
+        ),
+        ("max-syncs", startup.max_syncs != reloaded.max_syncs),
+        ("verbose", startup.verbose != reloaded.verbose),
+        ("debug", startup.debug != reloaded.debug),
+        ("quiet", startup.quiet != reloaded.quiet),
+        (
+            "no-timestamps",
+            startup.no_timestamps != reloaded.no_timestamps,
+        ),
+        ("log-format", startup.log_format != reloaded.log_format),
+        ("color", startup.color != reloaded.color),
+        ("log-target", startup.log_target != reloaded.log_target),
+        (
+            "log-dedup-window",
+            startup.log_dedup_window != reloaded.log_dedup_window,
+        ),
+        (
+            "starttime-tolerance",
+            startup.starttime_tolerance != reloaded.starttime_tolerance,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(key, changed)| changed.then_some(key))
+    .collect()
+}
+
+/// Applies the settings `system` holds from `args`, returning the keys of those that changed.
+fn reconfigure_system(system: &mut LiveSystem, args: &Args) -> Vec<&'static str> {
+    let sync_timeout = args.sync_timeout.unwrap_or(system::DEFAULT_SYNC_TIMEOUT);
+    let changes = [
+        ("match-cmdline", system.read_cmdline != args.match_cmdline),
+        ("sync-ioprio", system.sync_ioprio != args.sync_ioprio),
+        ("sync-timeout", system.sync_timeout != sync_timeout),
+        ("scan-budget", system.scan_budget != args.scan_budget),
+        ("max-examined", system.max_examined != args.max_examined),
+    ];
+    system.read_cmdline = args.match_cmdline;
+    system.sync_ioprio = args.sync_ioprio;
+    system.sync_timeout = sync_timeout;
+    system.scan_budget = args.scan_budget;
+    system.max_examined = args.max_examined;
+    changes
+        .into_iter()
+        .filter_map(|(key, changed)| changed.then_some(key))
+        .collect()
+}
+
+/// Reloads `config`, and the settings `system` holds, on `SIGHUP`, logging what changed, or keeps
+/// them if the reloaded configuration is invalid. `startup` are the arguments the daemon started
+/// with, for warning about changes to those only applied then.
+fn reload(
+    flags: &Args,
+    startup: &Args,
+    config: &mut Config,
+    system: &mut LiveSystem,
+    notifier: Option<&systemd::Notifier>,
+) {
+    let (args, reloaded) = match reloaded_config(flags, config, notifier) {
+        Ok(reloaded) => reloaded,
+        Err(e) => {
+            warn!("Keeping the previous configuration, as reloading it failed: {e:#}");
+            return;
+        }
+    };
+    let restart_only = restart_only_changes(startup, &args);
+    if !restart_only.is_empty() {
+        warn!(
+            "Not reloading {}, which need a restart",
+            restart_only.join(", ")
+        );
+    }
+    let mut changes = config_changes(config, &reloaded);
+    changes.extend(
+        reconfigure_system(system, &args)
+            .into_iter()
+            .map(String::from),
+    );
+    if changes.is_empty() {
+        info!("Reloaded the configuration, unchanged");
+    }
+    for change in changes {
+        info!("Reloaded {change}");
+    }
+    for warning in reloaded.threshold_warnings() {
+        warn!("{warning}");
+    }
+    system.read_wchan = reloaded.require_wchan.is_some();
+    *config = reloaded;
+}
+
+/// Runs the monitor until it fails or reaches `--max-syncs`, as it only otherwise exits on
+/// signals, or returns the exit status of a one-shot flag. `keepalive` is read from the
+/// environment beforehand, and the main loop runs on `runtime` with `--async`.
+fn monitor(
+    args: &Args,
+    flags: &Args,
+    teardown: &Mutex<Teardown>,
+    started: std::time::Instant,
+    reload_requested: &AtomicBool,
+    keepalive: Arc<KeepAlive>,
+    runtime: Option<async_loop::Runtime>,
+) -> anyhow::Result<ExitCode> {
+    let mut config = args.config()?;
+    // Before anything that leaves a trace, as it only prints what the daemon would run with.
+    if args.dump_config {
+        print!(
+            "{}",
+            toml::to_string(&config).context("failed to serialize the configuration")?
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(cpus) = &args.cpu_affinity {
+        affinity::pin_to(cpus)?;
+    }
+    if let Some(adj) = args.oom_score_adj {
+        oom::adjust(adj);
+    }
+
+    let system = LiveSystem {
+        read_cmdline: args.match_cmdline,
+        read_wchan: args.require_wchan.is_some(),
+        sync_ioprio: args.sync_ioprio,
+        sync_timeout: args.sync_timeout.unwrap_or(system::DEFAULT_SYNC_TIMEOUT),
+        sync_runner: SyncRunner::default(),
+        scan_budget: args.scan_budget,
+        max_examined: args.max_examined,
+        poll_only: AtomicBool::new(args.no_netlink),
+        process_events: Mutex::default(),
+        keepalive: Arc::clone(&keepalive),
+        clock: BootClock::anchored(),
+        procfs_root: args
+            .procfs_root
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(system::DEFAULT_PROCFS_ROOT)),
+    };
+    // Released last, once everything else is torn down. The dump may run alongside the daemon.
+    if let Some(path) = args.pidfile.as_ref().filter(|_| !args.dump_processes) {
+        let pidfile = PidFile::acquire(path)?;
+        shutdown::lock(teardown).register("remove the pid file", move || pidfile.release());
+    }
+    let metrics = Arc::new(Metrics::new(config.labels.clone()));
+    let state = Arc::new(DaemonState::default());
+    shutdown::lock(teardown).set_metrics(Arc::clone(&metrics));
+    if args.print_stats_on_exit {
+        let metrics = Arc::clone(&metrics);
+        let step = move || match metrics.runtime_percentiles() {
+            Some(percentiles) => info!("Runtimes of the oldest matching kworker: {percentiles}"),
+            None => info!("No scan found a matching kworker, so there are no runtime statistics"),
+        };
+        shutdown::lock(teardown).register("print runtime statistics", step);
+    }
+    if let Some(path) = args.metrics_textfile.clone() {
+        let metrics = Arc::clone(&metrics);
+        let step = move || {
+            if let Err(e) = metrics.write_textfile(&path) {
+                warn!("Failed to export metrics: {e:?}");
             }
+        };
+        shutdown::lock(teardown).register("flush the metrics textfile", step);
+    }
+    if let Some(dir) = config.incident_dir.clone() {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let state = Arc::clone(&state);
+        let step = move || {
+            if let Some(incident) = state.incident().take() {
+                write_incident(&dir, &incident, chrono::Utc::now(), Resolution::Unresolved);
+            }
+        };
+        shutdown::lock(teardown).register("write the ongoing incident report", step);
+    }
+    let pattern_file = args
+        .pattern_file
+        .clone()
+        .map(PatternFile::load)
+        .transpose()?;
+    if let Some(patterns) = &pattern_file {
+        config.file_globs = patterns.globs().to_vec();
+    }
+    if args.dump_processes {
+        let scan = system
+            .find_all_kworkers(&CommPrefilter::new(config.globs()), |p: &ProcInfo| {
+                is_monitored(&config, p)
+            })
+            .context("failed to scan processes")?;
+        print!("{}", format_scan(&scan));
+        return Ok(ExitCode::SUCCESS);
+    }
+    bound_rescan_interval(&mut config, keepalive.notifier());
+    if config.dry_run {
+        warn!("Running as a dry run, stuck processes are only reported");
+    } else if config.detect_only {
+        warn!(
+            "This host is outside the {}% canary, running detect-only",
+            config.canary_percent.unwrap_or_default()
+        );
+    }
+    // Bound before privileges are dropped, as the port or path may be privileged.
+    if let Some(addr) = args.metrics_listen {
+        let bound = metrics_server::spawn(addr, Arc::clone(&metrics))?;
+        info!("Serving metrics at http://{bound}/metrics");
+    }
+    if let Some(path) = &args.status_socket {
+        let socket = StatusSocket::spawn(path, Arc::clone(&metrics))?;
+        shutdown::lock(teardown).register("remove the status socket", move || socket.release());
+        info!("Serving the status at {}", path.display());
+    }
+    if let Some(path) = &args.state_file {
+        let file = StateFile::open(path, system.now())?;
+        if let Some(last) = file.records().last() {
+            info!(
+                "Restored {} syncs from {}, the last at {}",
+                file.records().len(),
+                path.display(),
+                clock::local(last.at).to_rfc3339()
+            );
+            metrics.restore_last_sync(last.at);
         }
+        state.restore_syncs(file);
     }
+    if let Some(path) = &args.event_log {
+        state.set_event_log(EventLog::open(path)?);
+        info!("Recording events to {}", path.display());
+    }
+    init_system(&system, &config, args)?;
+    let globs: Vec<String> = config.globs().map(|glob| format!("'{glob}'")).collect();
+    info!("Monitoring processes matching {}", globs.join(", "));
+    for warning in config
+        .globs()
+        .filter_map(|glob| check_glob(glob).ok())
+        .flatten()
+    {
+        warn!("{warning}");
+    }
+    for warning in config.threshold_warnings() {
+        warn!("{warning}");
+    }
+    if args.once {
+        info!("Running a single evaluation pass");
+    } else if args.no_netlink {
+        info!(
+            "Polling for new kworkers every {}",
+            duration::format_duration(config.timings.rescan_interval)
+        );
+    } else {
+        info!("Waiting for new kworkers on process events from the kernel");
+    }
+    let tolerance = args
+        .starttime_tolerance
+        .unwrap_or(starttime_check::DEFAULT_TOLERANCE);
+    if let Err(e) = starttime_check::check(duration::to_chrono(started.elapsed())?, tolerance) {
+        warn!("Not checking process start times: {e:?}");
+    }
+    if args.emit_test_event {
+        emit_test_event(&system, &metrics, &state, &config);
+    }
+    if args.once {
+        let outcome = once(&system, &metrics, &state, &config)?;
+        return Ok(ExitCode::from(outcome.exit_code()));
+    }
+    if let Some(notifier) = keepalive.notifier() {
+        notifier.ready();
+    }
+    let main_loop = MainLoop {
+        args,
+        flags,
+        teardown,
+        reload_requested,
+        keepalive: &keepalive,
+        metrics,
+        state,
+        jitter: jitter::Jitter::seeded(),
+        sync_limit: args.max_syncs.map(SyncLimit::new),
+        pattern_file,
+    };
+    let startup = args.startup_behavior.unwrap_or(StartupBehavior::Scan);
+    Ok(match runtime {
+        None => main_loop.run(config, system, startup),
+        #[cfg(feature = "async")]
+        Some(runtime) => runtime
+            .block_on(|termination| main_loop.run_async(config, system, startup, termination)),
+        #[cfg(not(feature = "async"))]
+        Some(runtime) => match runtime {},
+    })
+}
 
-    impl System for MockSystem {
-        fn find_oldest_kworker<F: IsKworkerFn>(&self, is_kworker: F) -> Result<Option<ProcInfo>> {
-            Ok(self.kworker.clone().filter(|p| is_kworker(p)))
+/// What the main loop keeps across iterations, besides the configuration and the system they act
+/// on, which `--async` hands over to the blocking task of each.
+struct MainLoop<'a> {
+    args: &'a Args,
+    flags: &'a Args,
+    teardown: &'a Mutex<Teardown>,
+    reload_requested: &'a AtomicBool,
+    keepalive: &'a KeepAlive,
+    metrics: Arc<Metrics>,
+    state: Arc<DaemonState>,
+    jitter: jitter::Jitter,
+    sync_limit: Option<SyncLimit>,
+    pattern_file: Option<PatternFile>,
+}
+
+impl MainLoop<'_> {
+    /// Runs iterations until `--max-syncs` is reached, the first one as `startup` says, returning
+    /// the exit status.
+    fn run(
+        mut self,
+        mut config: Config,
+        mut system: LiveSystem,
+        startup: StartupBehavior,
+    ) -> ExitCode {
+        let mut result = first_iteration(&system, &self.metrics, &self.state, &config, startup);
+        loop {
+            let sleep_duration = match self.settle(result, &config) {
+                ControlFlow::Continue(duration) => duration,
+                ControlFlow::Break(code) => return code,
+            };
+            self.keepalive.sleep(sleep_duration);
+            self.refresh(&mut config, &mut system);
+            result = workaround(&system, &self.metrics, &self.state, &config);
         }
+    }
 
-        fn now(&self) -> chrono::DateTime<chrono::Local> {
-            self.now
+    /// As `run`, but with each iteration on a blocking task, and tearing the daemon down then
+    /// exiting as soon as `termination` sees a signal, whether during an iteration or the sleep
+    /// after it.
+    #[cfg(feature = "async")]
+    async fn run_async(
+        mut self,
+        mut config: Config,
+        mut system: LiveSystem,
+        startup: StartupBehavior,
+        mut termination: async_loop::Termination,
+    ) -> ExitCode {
+        let mut startup = Some(startup);
+        loop {
+            let (metrics, state) = (Arc::clone(&self.metrics), Arc::clone(&self.state));
+            let behavior = startup.take();
+            let iteration = async_loop::blocking(move || {
+                let result = match behavior {
+                    Some(behavior) => first_iteration(&system, &metrics, &state, &config, behavior),
+                    None => workaround(&system, &metrics, &state, &config),
+                };
+                (config, system, result)
+            });
+            let result;
+            (config, system, result) = tokio::select! {
+                iterated = iteration => iterated,
+                name = termination.recv() => shutdown::terminate(self.teardown, name),
+            };
+            let sleep_duration = match self.settle(result, &config) {
+                ControlFlow::Continue(duration) => duration,
+                ControlFlow::Break(code) => return code,
+            };
+            tokio::select! {
+                () = self.keepalive.sleep_async(sleep_duration) => {}
+                name = termination.recv() => shutdown::terminate(self.teardown, name),
+            }
+            self.refresh(&mut config, &mut system);
         }
+    }
 
-        fn wait_for_kworker<F: IsKworkerFn>(
-            &self,
-            _is_kworker: F,
-            _timeout: Duration,
-        ) -> Result<()> {
-            self.wait_for_kworker_result.clone().map_err(|e| anyhow::anyhow!(e))
+    /// Accounts for `result`, the outcome of an iteration, returning how long to sleep before the
+    /// next one, or the exit status once `--max-syncs` is reached.
+    fn settle(
+        &mut self,
+        result: anyhow::Result<Outcome>,
+        config: &Config,
+    ) -> ControlFlow<ExitCode, Duration> {
+        if let Some(limit) = &mut self.sync_limit {
+            if limit.record(&result) {
+                shutdown::lock(self.teardown).finish(&ExitReason::MaxSyncs(limit.syncs()));
+                return ControlFlow::Break(ExitCode::from(EXIT_MAX_SYNCS));
+            }
+        }
+        // Only a successful iteration shows the monitor is working, though sleeping after a
+        // failed one pings as well so a transient error doesn't get the daemon restarted.
+        if let (Ok(_), Some(notifier)) = (&result, self.keepalive.notifier()) {
+            notifier.watchdog();
+        }
+        let mut sleep_duration = sleep_duration_after(result, &self.metrics, &config.timings);
+        if let Some(fraction) = config.jitter {
+            sleep_duration = self.jitter.apply(sleep_duration, fraction);
         }
+        if let Some(heartbeat) = self.keepalive.heartbeat() {
+            heartbeat.beat();
+        }
+        if let Some(path) = &self.args.metrics_textfile {
+            if let Err(e) = self.metrics.write_textfile(path) {
+                warn!("Failed to export metrics: {e:?}");
+            }
+        }
+        ControlFlow::Continue(sleep_duration)
+    }
 
-        fn sync(&self) {
-            self.sync_calls.set(self.sync_calls.get() + 1);
+    /// Picks up the pattern file and configuration changes made while sleeping, before the next
+    /// iteration.
+    fn refresh(&mut self, config: &mut Config, system: &mut LiveSystem) {
+        if let Some(patterns) = &mut self.pattern_file {
+            if patterns.reload_if_changed() {
+                config.file_globs = patterns.globs().to_vec();
+            }
+        }
+        if reload::requested(self.reload_requested) {
+            reload(
+                self.flags,
+                self.args,
+                config,
+                system,
+                self.keepalive.notifier(),
+            );
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stuck_writeback_workaround::action::Action;
+    use stuck_writeback_workaround::Outcome;
 
     #[test]
-    fn test_monitor_and_sync_no_kworker() {
-        let system = MockSystem::default();
-        let threshold = chrono::Duration::seconds(30);
+    fn test_log_lines_carry_labels() {
+        let labels = Labels::new(vec![
+            "cluster=prod".parse().unwrap(),
+            "role=storage".parse().unwrap(),
+        ])
+        .unwrap();
+        let line = |timestamp: Option<&str>, labels: &Labels, colored| {
+            let mut out = Vec::new();
+            write_log_line(
+                &mut out,
+                timestamp,
+                log::Level::Warn,
+                labels,
+                &format_args!("Stuck"),
+                colored,
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            line(Some("2025-01-02T03:04:05Z"), &labels, false),
+            "[2025-01-02T03:04:05Z WARN ] cluster=prod role=storage Stuck\n"
+        );
+        assert_eq!(
+            line(None, &labels, false),
+            "[WARN ] cluster=prod role=storage Stuck\n"
+        );
+        assert_eq!(line(None, &Labels::default(), false), "[WARN ] Stuck\n");
+    }
 
-        let sleep_duration = workaround(&system, "kworker/*", &threshold).unwrap();
-        assert_eq!(sleep_duration, Duration::from_secs(0));
-        assert_eq!(system.sync_calls.get(), 0);
+    #[test]
+    fn test_log_lines_are_only_colored_when_asked() {
+        let line = |colored| {
+            let mut out = Vec::new();
+            write_log_line(
+                &mut out,
+                None::<&str>,
+                log::Level::Warn,
+                &Labels::default(),
+                &format_args!("Sync triggered"),
+                colored,
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // A yellow level, then a red message.
+        assert_eq!(
+            line(true),
+            "[\x1b[33mWARN \x1b[0m] \x1b[31mSync triggered\x1b[0m\n"
+        );
+        assert_eq!(line(false), "[WARN ] Sync triggered\n");
     }
 
     #[test]
-    fn test_monitor_and_sync_kworker_below_threshold() {
-        let now = chrono::Local::now();
-        let proc = ProcInfo {
-            uid: 0,
-            comm: "kworker/0:1".to_string(),
-            starttime: now - chrono::Duration::seconds(10),
+    fn test_timings_are_configurable() {
+        use argh::FromArgs;
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &["--busy-poll", "5s", "--recovery-time", "2m"],
+        )
+        .unwrap();
+        let config = args.config().unwrap();
+        let timings = config.timings;
+        assert_eq!(
+            Outcome::BelowThreshold.sleep_duration(&timings),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            Outcome::Remediated(Action::Sync).sleep_duration(&timings),
+            Duration::from_secs(120)
+        );
+        assert_eq!(timings.error_backoff, Timings::default().error_backoff);
+        assert_eq!(timings.rescan_interval, Timings::default().rescan_interval);
+
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &["--busy-poll", "1m", "--rescan-interval", "30s"],
+        )
+        .unwrap();
+        let error = args.config().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "--busy-poll (1m) must be shorter than --rescan-interval (30s)"
+        );
+    }
+
+    #[test]
+    fn test_config_layers_flags_over_kernel_cmdline_over_defaults() {
+        use argh::FromArgs;
+        let kernel = || KernelCmdline {
+            process_glob: Some("kworker/*cmdline*".to_string()),
+            runtime_threshold: Some(Some(chrono::Duration::seconds(90))),
         };
-        let system = MockSystem {
-            kworker: Some(proc),
-            now,
-            ..MockSystem::default()
+
+        let args = Args::from_args(&["stuck_writeback_workaround"], &[]).unwrap();
+        let config = args.config_with(KernelCmdline::default());
+        let defaults = Config::default();
+        assert_eq!(config.process_globs, defaults.process_globs);
+        assert_eq!(config.runtime_threshold, defaults.runtime_threshold);
+
+        let config = args.config_with(kernel());
+        assert_eq!(
+            config.process_globs,
+            vec![ProcessGlob::new("kworker/*cmdline*")]
+        );
+        assert_eq!(
+            config.runtime_threshold,
+            Some(chrono::Duration::seconds(90))
+        );
+
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &["--runtime-threshold", "10s"],
+        )
+        .unwrap();
+        let config = args.config_with(kernel());
+        assert_eq!(
+            config.process_globs,
+            vec![ProcessGlob::new("kworker/*cmdline*")]
+        );
+        assert_eq!(
+            config.runtime_threshold,
+            Some(chrono::Duration::seconds(10))
+        );
+
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &["--process-glob", "kworker/*", "--process-glob", "jbd2/*=2m"],
+        )
+        .unwrap();
+        let config = args.config_with(kernel());
+        assert_eq!(
+            config.process_globs,
+            vec![ProcessGlob::new("kworker/*"), "jbd2/*=2m".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_dump_config_shows_effective_config() {
+        use argh::FromArgs;
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &[
+                "--runtime-threshold",
+                "90s",
+                "--pattern-action",
+                "stuckd=signal:kill",
+                "--signature",
+                "glob=jbd2/*,state=D",
+                "--sum-age-threshold",
+                "2m",
+            ],
+        )
+        .unwrap();
+        let config = args.config_with(KernelCmdline {
+            process_glob: Some("kworker/*cmdline*".to_string()),
+            runtime_threshold: Some(Some(chrono::Duration::seconds(10))),
+        });
+
+        assert_eq!(
+            toml::to_string(&config).unwrap(),
+            "process-glob = [\"kworker/*cmdline*\"]\n\
+             runtime-threshold = \"1m 30s\"\n\
+             signature = [\"glob=jbd2/*,state=D,action=sync\"]\n\
+             pattern-action = [\"stuckd=signal:SIGKILL\"]\n\
+             sum-age-threshold = \"2m\"\n\
+             uid = [0]\n\
+             action = \"sync\"\n\
+             sync-cooldown = \"10s\"\n\
+             max-ineffective-syncs = 3\n\
+             busy-poll = \"1s\"\n\
+             error-backoff = \"1m\"\n\
+             rescan-interval = \"1m\"\n\
+             recovery-time = \"30s\"\n"
+        );
+
+        // Dumping leaves no trace, as the daemon running alongside owns these.
+        let dir = std::env::temp_dir().join(format!("stuck_wbs_{}_dump", std::process::id()));
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &[
+                "--dump-config",
+                "--pidfile",
+                &path("pid"),
+                "--metrics-textfile",
+                &path("metrics.prom"),
+                "--incident-dir",
+                &path("incidents"),
+                "--print-stats-on-exit",
+            ],
+        )
+        .unwrap();
+        let teardown = Mutex::new(Teardown::default());
+        let code = monitor(
+            &args,
+            &args,
+            &teardown,
+            std::time::Instant::now(),
+            &AtomicBool::new(false),
+            Arc::default(),
+            None,
+        );
+        assert_eq!(code.unwrap(), ExitCode::SUCCESS);
+        drop(teardown);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn test_log_level_of_each_flag_combination() {
+        use argh::FromArgs;
+        let level = |flags: &[&str]| {
+            Args::from_args(&["stuck_writeback_workaround"], flags)
+                .unwrap()
+                .log_level()
+                .map_err(|e| e.to_string())
         };
-        let threshold = chrono::Duration::seconds(30);
+        assert_eq!(level(&[]), Ok(log::LevelFilter::Warn));
+        assert_eq!(level(&["--quiet"]), Ok(log::LevelFilter::Error));
+        assert_eq!(level(&["-v"]), Ok(log::LevelFilter::Info));
+        assert_eq!(level(&["-d"]), Ok(log::LevelFilter::Debug));
+        assert_eq!(level(&["-v", "-d"]), Ok(log::LevelFilter::Debug));
+        for contradictory in [&["-q", "-v"][..], &["-q", "-d"], &["-q", "-v", "-d"]] {
+            assert_eq!(
+                level(contradictory),
+                Err("--quiet cannot be combined with --verbose or --debug".to_string()),
+                "{contradictory:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_config_file_settings_yield_to_flags() {
+        use argh::FromArgs;
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_config.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "process-glob = \"jbd2/*\"\n\
+             runtime-threshold = \"2m\"\n\
+             debug = true\n\
+             sync-mode = \"fs\"\n",
+        )
+        .unwrap();
+        let mut args = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &[
+                "--config",
+                path.to_str().unwrap(),
+                "--runtime-threshold",
+                "45s",
+            ],
+        )
+        .unwrap();
+        let file = ConfigFile::load(args.config.as_ref().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        args.merge(file.unwrap());
+        let config = args.config_with(KernelCmdline {
+            process_glob: Some("kworker/*cmdline*".to_string()),
+            runtime_threshold: None,
+        });
 
-        let sleep_duration = workaround(&system, "kworker/*", &threshold).unwrap();
-        assert_eq!(sleep_duration, BUSY_POLLING);
-        assert_eq!(system.sync_calls.get(), 0);
+        assert_eq!(config.process_globs, vec![ProcessGlob::new("jbd2/*")]);
+        assert_eq!(
+            config.runtime_threshold,
+            Some(chrono::Duration::seconds(45))
+        );
+        assert_eq!(config.sync_mode, SyncMode::Filesystem);
+        assert_eq!(args.log_level().unwrap(), log::LevelFilter::Debug);
     }
 
     #[test]
-    fn test_monitor_and_sync_kworker_above_threshold() {
-        let now = chrono::Local::now();
-        let proc = ProcInfo {
-            uid: 0,
-            comm: "kworker/0:1".to_string(),
-            starttime: now - chrono::Duration::seconds(40),
+    fn test_reload_rereads_the_config_file() {
+        use argh::FromArgs;
+        let path =
+            std::env::temp_dir().join(format!("stuck_wbs_{}_reload.toml", std::process::id()));
+        std::fs::write(&path, "runtime-threshold = \"2m\"\n").unwrap();
+        let flags = Args::from_args(
+            &["stuck_writeback_workaround"],
+            &[
+                "--config",
+                path.to_str().unwrap(),
+                "--warn-threshold",
+                "10s",
+            ],
+        )
+        .unwrap();
+        let mut args = flags.clone();
+        args.merge(ConfigFile::load(&path).unwrap());
+        let config = Config {
+            file_globs: vec!["jbd2/*".to_string()],
+            ..args.config().unwrap()
         };
-        let system = MockSystem {
-            kworker: Some(proc),
-            now,
-            ..MockSystem::default()
+        assert_eq!(config.runtime_threshold, Some(chrono::Duration::minutes(2)));
+
+        std::fs::write(
+            &path,
+            "runtime-threshold = \"5m\"\nwarn-threshold = \"1m\"\n",
+        )
+        .unwrap();
+        let (_, reloaded) = reloaded_config(&flags, &config, None).unwrap();
+        assert_eq!(
+            reloaded.runtime_threshold,
+            Some(chrono::Duration::minutes(5))
+        );
+        // Flags still take precedence, and the pattern file's globs are kept.
+        assert_eq!(reloaded.warn_threshold, Some(chrono::Duration::seconds(10)));
+        assert_eq!(reloaded.file_globs, config.file_globs);
+
+        // Settings the system holds are applied too, and those only read at startup reported.
+        std::fs::write(
+            &path,
+            "sync-timeout = \"5s\"\nmax-examined = 100\nno-netlink = true\nmax-syncs = 3\n\
+             debug = true\nlog-dedup-window = \"1m\"\n",
+        )
+        .unwrap();
+        let (reloaded_args, _) = reloaded_config(&flags, &config, None).unwrap();
+        let mut system = LiveSystem {
+            read_cmdline: false,
+            read_wchan: false,
+            sync_ioprio: None,
+            sync_timeout: system::DEFAULT_SYNC_TIMEOUT,
+            sync_runner: SyncRunner::default(),
+            scan_budget: None,
+            max_examined: None,
+            poll_only: AtomicBool::new(false),
+            process_events: Mutex::default(),
+            keepalive: Arc::default(),
+            clock: BootClock::anchored(),
+            procfs_root: PathBuf::from(system::DEFAULT_PROCFS_ROOT),
         };
-        let threshold = chrono::Duration::seconds(30);
+        assert_eq!(
+            reconfigure_system(&mut system, &reloaded_args),
+            ["sync-timeout", "max-examined"]
+        );
+        assert_eq!(system.sync_timeout, Duration::from_secs(5));
+        assert_eq!(system.max_examined, Some(100));
+        assert!(reconfigure_system(&mut system, &reloaded_args).is_empty());
+        assert_eq!(
+            restart_only_changes(&args, &reloaded_args),
+            ["no-netlink", "max-syncs", "debug", "log-dedup-window"]
+        );
 
-        let sleep_duration = workaround(&system, "kworker/*", &threshold).unwrap();
-        assert_eq!(sleep_duration, EXPECTED_RECOVERY_TIME);
-        assert_eq!(system.sync_calls.get(), 1);
+        std::fs::write(&path, "min-stuck-count = 0\n").unwrap();
+        let invalid = reloaded_config(&flags, &config, None);
+        std::fs::remove_file(&path).unwrap();
+        assert!(invalid.is_err());
     }
 
     #[test]
-    fn test_monitor_and_sync_wait_for_kworker_error() {
-        let system = MockSystem {
-            wait_for_kworker_result: Err("test error".to_string()),
-            ..MockSystem::default()
-        };
-        let threshold = chrono::Duration::seconds(30);
+    fn test_dumped_config_loads_back() {
+        use argh::FromArgs;
+        let flags = [
+            "--runtime-threshold",
+            "90s",
+            "--signature",
+            "glob=jbd2/*,state=D",
+            "--require-no-progress",
+            "--canary-percent",
+            "20",
+            "--label",
+            "cluster=prod",
+        ];
+        let args = Args::from_args(&["stuck_writeback_workaround"], &flags).unwrap();
+        let mut config = args.config_with(KernelCmdline::default());
+        config.labels = Labels::new(args.label.clone()).unwrap();
+        let dumped = toml::to_string(&config).unwrap();
 
-        let result = workaround(&system, "kworker/*", &threshold);
-        assert!(result.is_err());
-        assert_eq!(system.sync_calls.get(), 0);
+        let file = ConfigFile::parse(&dumped).unwrap();
+        assert_eq!(file.unknown_keys().count(), 0, "{dumped}");
+        let mut loaded = Args::from_args(&["stuck_writeback_workaround"], &[]).unwrap();
+        loaded.merge(file);
+        let mut reloaded = loaded.config_with(KernelCmdline::default());
+        reloaded.labels = Labels::new(loaded.label.clone()).unwrap();
+        assert_eq!(toml::to_string(&reloaded).unwrap(), dumped);
     }
 }
@@ -0,0 +1,499 @@
+//! Prometheus metrics, rendered in the text exposition format.
+use crate::clock::local;
+use crate::episode::{Crossing, Episodes};
+use crate::histogram::{Histogram, Percentiles};
+use crate::labels::Labels;
+use crate::status::Status;
+use crate::system::{SkipReason, Skipped};
+use crate::tracker::Key;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// What the daemon is doing, as served by `--status-socket`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Snapshot {
+    /// The current status, e.g. "watching".
+    pub status: &'static str,
+    /// How many matching kworkers the last scan found.
+    pub matching_kworkers: u64,
+    /// How long the oldest of them had been running then, 0 if there were none.
+    pub oldest_kworker_runtime_seconds: f64,
+    pub syncs_total: u64,
+    /// When the last sync was issued, in RFC 3339 format.
+    pub last_sync: Option<String>,
+    /// Of the runtime of the oldest matching kworker, over every scan that found one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oldest_kworker_runtime_percentiles: Option<Percentiles>,
+    /// The `--label`s, as an object.
+    #[serde(skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+}
+
+/// Prefix shared by every metric exported by this daemon.
+const PREFIX: &str = "stuck_wbs";
+
+/// Counters and gauges describing the daemon's activity.
+///
+/// Values are stored in atomics so the handle can be shared with whichever sink exports them.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Unix timestamp, in milliseconds, of the last successful process scan.
+    last_scan_timestamp_ms: AtomicU64,
+    /// Index of the current status in `Status::ALL`.
+    status: AtomicU64,
+    /// Number of stuck processes that triggered a remediation.
+    triggers: AtomicU64,
+    /// Number of synthetic triggers sent by `--emit-test-event`.
+    test_triggers: AtomicU64,
+    /// Number of remediations the `--verify-command` reported as resolving the stall.
+    verified_resolved: AtomicU64,
+    /// Number of remediations the `--verify-command` reported as not resolving the stall.
+    verified_stuck: AtomicU64,
+    /// Number of processes that ran past `--warn-threshold`.
+    warnings: AtomicU64,
+    /// The last of them, to warn about each only once.
+    warned: Mutex<Option<Key>>,
+    /// Number of syncs issued.
+    syncs: AtomicU64,
+    /// Number of syncs still blocked past `--sync-timeout`.
+    sync_timeouts: AtomicU64,
+    /// Number of processes the last scan found to match.
+    matching_kworkers: AtomicU64,
+    /// Runtime, in milliseconds, of the oldest of them, 0 if there were none.
+    oldest_kworker_runtime_ms: AtomicU64,
+    /// The runtimes of the oldest matching kworker, over every scan that found one.
+    runtimes: Mutex<Histogram>,
+    /// Matching kworkers when the last sync was issued, until the next scan counts them again.
+    kworkers_before_sync: Mutex<Option<u64>>,
+    /// When the last sync was issued, as reported.
+    last_sync: Mutex<Option<chrono::DateTime<chrono::Utc>>>,
+    /// Number of syncs whose effect on the kworker count was measured.
+    measured_syncs: AtomicU64,
+    /// Total decrease in the kworker count across measured syncs.
+    cleared_kworkers: AtomicU64,
+    /// The current stall episode.
+    episodes: Mutex<Episodes>,
+    /// Number of stall episodes, each grouping one or more triggers.
+    episodes_total: AtomicU64,
+    /// Number of processes left out of scans, indexed by `SkipReason`.
+    skipped: [AtomicU64; SkipReason::ALL.len()],
+    /// Added to every sample.
+    labels: Labels,
+}
+
+/// How many kworkers a sync cleared, as measured by the scan following its recovery time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cleared {
+    /// How many fewer matching kworkers there were after the sync.
+    pub kworkers: u64,
+    /// The average of `kworkers` over every measured sync.
+    pub average_per_sync: f64,
+}
+
+/// Adds `labels`, formatted for Prometheus, to every sample in `rendered`.
+fn add_labels(rendered: &str, labels: &str) -> String {
+    let mut out = String::with_capacity(rendered.len());
+    for line in rendered.lines() {
+        // Metric names contain neither braces nor spaces, so the first one ends the name.
+        let _ = if line.starts_with('#') {
+            writeln!(out, "{line}")
+        } else if let Some((name, rest)) = line.split_once('{') {
+            writeln!(out, "{name}{{{labels},{rest}")
+        } else if let Some((name, value)) = line.split_once(' ') {
+            writeln!(out, "{name}{{{labels}}} {value}")
+        } else {
+            writeln!(out, "{line}")
+        };
+    }
+    out
+}
+
+impl Metrics {
+    /// Returns metrics whose samples all carry `labels`.
+    pub fn new(labels: Labels) -> Self {
+        Self {
+            labels,
+            ..Self::default()
+        }
+    }
+
+    /// Records that a process scan completed at `now`, whether of every process or only of the
+    /// ones that matched in the last scan.
+    pub fn record_scan(&self, now: &chrono::DateTime<chrono::Utc>) {
+        let ms = u64::try_from(now.timestamp_millis()).unwrap_or(0);
+        self.last_scan_timestamp_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// Records the processes a scan left out.
+    pub fn record_skips(&self, skipped: &[Skipped]) {
+        for s in skipped {
+            self.skipped[s.reason as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records what the daemon is currently doing.
+    pub fn set_status(&self, status: Status) {
+        self.status.store(status as u64, Ordering::Relaxed);
+    }
+
+    /// Records that a stuck process triggered a remediation, or a test event if `test` is set.
+    pub fn record_trigger(&self, test: bool) {
+        let counter = if test {
+            &self.test_triggers
+        } else {
+            &self.triggers
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a stuck process triggered at `now`, grouping triggers within `gap` of each
+    /// other into one episode.
+    pub fn record_crossing(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+        gap: Option<chrono::Duration>,
+    ) -> Crossing {
+        let crossing = self.episodes.lock().unwrap().record(now, gap);
+        self.episodes_total
+            .store(crossing.episode, Ordering::Relaxed);
+        crossing
+    }
+
+    /// Returns how many stuck processes triggered a remediation, test events excluded.
+    pub fn triggers(&self) -> u64 {
+        self.triggers.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many stall episodes there were.
+    pub fn episodes(&self) -> u64 {
+        self.episodes_total.load(Ordering::Relaxed)
+    }
+
+    /// Records that `kworker` ran past `--warn-threshold`, returning whether it is the first time.
+    pub fn record_warning(&self, kworker: Key) -> bool {
+        let mut warned = self.warned.lock().unwrap();
+        if *warned == Some(kworker) {
+            return false;
+        }
+        *warned = Some(kworker);
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Records that a sync was issued at `at` while `kworkers` matching kworkers were running.
+    pub fn record_sync(&self, kworkers: usize, at: chrono::DateTime<chrono::Utc>) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        *self.last_sync.lock().unwrap() = Some(at);
+        *self.kworkers_before_sync.lock().unwrap() = Some(kworkers as u64);
+    }
+
+    /// Restores the last sync, as reported, to `at`.
+    pub fn restore_last_sync(&self, at: chrono::DateTime<chrono::Utc>) {
+        *self.last_sync.lock().unwrap() = Some(at);
+    }
+
+    /// Records that a sync was still blocked past `--sync-timeout`.
+    pub fn record_sync_timeout(&self) {
+        self.sync_timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns when the last sync was issued, if any was.
+    pub fn last_sync(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        *self.last_sync.lock().unwrap()
+    }
+
+    /// Records that a scan found `kworkers` matching processes, the oldest of which had been
+    /// running for `oldest_runtime`.
+    pub fn record_kworkers(&self, kworkers: usize, oldest_runtime: Option<chrono::Duration>) {
+        let ms = oldest_runtime.map_or(0, |d| u64::try_from(d.num_milliseconds()).unwrap_or(0));
+        self.matching_kworkers
+            .store(kworkers as u64, Ordering::Relaxed);
+        self.oldest_kworker_runtime_ms.store(ms, Ordering::Relaxed);
+        if oldest_runtime.is_some() {
+            self.runtimes.lock().unwrap().observe(ms as f64 / 1000.0);
+        }
+    }
+
+    /// Returns the percentiles of the runtime of the oldest matching kworker, over every scan
+    /// that found one, if any did.
+    pub fn runtime_percentiles(&self) -> Option<Percentiles> {
+        self.runtimes.lock().unwrap().percentiles()
+    }
+
+    /// Returns whether the next scan measures how many kworkers the last sync cleared.
+    pub fn measuring_cleared(&self) -> bool {
+        self.kworkers_before_sync.lock().unwrap().is_some()
+    }
+
+    /// Records how many matching kworkers a scan found, returning how many the last sync cleared
+    /// if this is the first scan after it.
+    pub fn record_kworker_count(&self, kworkers: usize) -> Option<Cleared> {
+        let before = self.kworkers_before_sync.lock().unwrap().take()?;
+        let cleared = before.saturating_sub(kworkers as u64);
+        let measured = self.measured_syncs.fetch_add(1, Ordering::Relaxed) + 1;
+        let total = self.cleared_kworkers.fetch_add(cleared, Ordering::Relaxed) + cleared;
+        Some(Cleared {
+            kworkers: cleared,
+            average_per_sync: total as f64 / measured as f64,
+        })
+    }
+
+    /// Records the outcome of a `--verify-command` run.
+    pub fn record_verification(&self, resolved: bool) {
+        let counter = if resolved {
+            &self.verified_resolved
+        } else {
+            &self.verified_stuck
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns what the daemon is doing, as of the last iteration.
+    pub fn snapshot(&self) -> Snapshot {
+        let status = Status::ALL[self.status.load(Ordering::Relaxed) as usize];
+        let runtime_ms = self.oldest_kworker_runtime_ms.load(Ordering::Relaxed);
+        Snapshot {
+            status: status.as_str(),
+            matching_kworkers: self.matching_kworkers.load(Ordering::Relaxed),
+            oldest_kworker_runtime_seconds: runtime_ms as f64 / 1000.0,
+            syncs_total: self.syncs.load(Ordering::Relaxed),
+            last_sync: self.last_sync().map(|t| local(t).to_rfc3339()),
+            oldest_kworker_runtime_percentiles: self.runtime_percentiles(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let last_scan = self.last_scan_timestamp_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+        // Writing to a String cannot fail.
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_build_info Build information, the value is always 1.\n\
+             # TYPE {PREFIX}_build_info gauge\n\
+             {PREFIX}_build_info{{version=\"{version}\"}} 1\n\
+             # HELP {PREFIX}_last_scan_timestamp_seconds Unix time of the last process scan.\n\
+             # TYPE {PREFIX}_last_scan_timestamp_seconds gauge\n\
+             {PREFIX}_last_scan_timestamp_seconds {last_scan:.3}\n",
+            version = env!("CARGO_PKG_VERSION"),
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_triggers_total Stuck processes that triggered a remediation, \
+             test=\"true\" for synthetic test events.\n\
+             # TYPE {PREFIX}_triggers_total counter\n\
+             {PREFIX}_triggers_total{{test=\"false\"}} {}\n\
+             {PREFIX}_triggers_total{{test=\"true\"}} {}\n",
+            self.triggers.load(Ordering::Relaxed),
+            self.test_triggers.load(Ordering::Relaxed),
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_warnings_total Monitored processes that ran past --warn-threshold.\n\
+             # TYPE {PREFIX}_warnings_total counter\n\
+             {PREFIX}_warnings_total {}\n",
+            self.warnings.load(Ordering::Relaxed),
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_sync_total Syncs issued to remediate stuck processes.\n\
+             # TYPE {PREFIX}_sync_total counter\n\
+             {PREFIX}_sync_total {}\n\
+             # HELP {PREFIX}_sync_timeouts_total Syncs still blocked past --sync-timeout, left \
+             running.\n\
+             # TYPE {PREFIX}_sync_timeouts_total counter\n\
+             {PREFIX}_sync_timeouts_total {}\n\
+             # HELP {PREFIX}_matching_kworkers Processes matching the monitored globs in the last \
+             scan.\n\
+             # TYPE {PREFIX}_matching_kworkers gauge\n\
+             {PREFIX}_matching_kworkers {}\n\
+             # HELP {PREFIX}_oldest_kworker_runtime_seconds Runtime of the oldest matching \
+             process in the last scan, 0 if there was none.\n\
+             # TYPE {PREFIX}_oldest_kworker_runtime_seconds gauge\n\
+             {PREFIX}_oldest_kworker_runtime_seconds {:.3}\n",
+            self.syncs.load(Ordering::Relaxed),
+            self.sync_timeouts.load(Ordering::Relaxed),
+            self.matching_kworkers.load(Ordering::Relaxed),
+            self.oldest_kworker_runtime_ms.load(Ordering::Relaxed) as f64 / 1000.0,
+        );
+        self.runtimes.lock().unwrap().render(
+            &mut out,
+            &format!("{PREFIX}_observed_kworker_runtime_seconds"),
+            "Runtime of the oldest matching process, in every scan that found one.",
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_verifications_total Outcomes of the verify command run after \
+             remediations.\n\
+             # TYPE {PREFIX}_verifications_total counter\n\
+             {PREFIX}_verifications_total{{result=\"resolved\"}} {}\n\
+             {PREFIX}_verifications_total{{result=\"stuck\"}} {}\n",
+            self.verified_resolved.load(Ordering::Relaxed),
+            self.verified_stuck.load(Ordering::Relaxed),
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_episodes_total Stall episodes, grouping triggers within \
+             --episode-gap of each other.\n\
+             # TYPE {PREFIX}_episodes_total counter\n\
+             {PREFIX}_episodes_total {}\n",
+            self.episodes_total.load(Ordering::Relaxed),
+        );
+        let _ = write!(
+            out,
+            "# HELP {PREFIX}_measured_syncs_total Syncs whose effect on the kworker count was \
+             measured.\n\
+             # TYPE {PREFIX}_measured_syncs_total counter\n\
+             {PREFIX}_measured_syncs_total {}\n\
+             # HELP {PREFIX}_cleared_kworkers_total Decrease in the matching kworker count across \
+             measured syncs.\n\
+             # TYPE {PREFIX}_cleared_kworkers_total counter\n\
+             {PREFIX}_cleared_kworkers_total {}\n",
+            self.measured_syncs.load(Ordering::Relaxed),
+            self.cleared_kworkers.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP {PREFIX}_scan_skipped_total Processes left out of scans, by reason.\n\
+             # TYPE {PREFIX}_scan_skipped_total counter"
+        );
+        for reason in SkipReason::ALL {
+            let _ = writeln!(
+                out,
+                "{PREFIX}_scan_skipped_total{{reason=\"{}\"}} {}",
+                reason.as_str(),
+                self.skipped[reason as usize].load(Ordering::Relaxed)
+            );
+        }
+        let current = self.status.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "# HELP {PREFIX}_status Current status of the daemon, 1 for the active one.\n\
+             # TYPE {PREFIX}_status gauge"
+        );
+        for status in Status::ALL {
+            let value = u64::from(status as u64 == current);
+            let _ = writeln!(
+                out,
+                "{PREFIX}_status{{status=\"{}\"}} {value}",
+                status.as_str()
+            );
+        }
+        if self.labels.is_empty() {
+            return out;
+        }
+        add_labels(&out, &self.labels.to_prometheus())
+    }
+
+    /// Writes the metrics to `path` for the node_exporter textfile collector.
+    ///
+    /// The file is replaced atomically so the collector never reads a partial write.
+    pub fn write_textfile(&self, path: &Path) -> Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, self.render())
+            .with_context(|| format!("failed to write {}", Path::new(&tmp).display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("failed to rename into {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_render_includes_build_info_and_heartbeat() {
+        let metrics = Metrics::default();
+        let now = chrono::Utc.timestamp_millis_opt(1_700_000_000_250).unwrap();
+        metrics.record_scan(&now);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(&format!(
+            "stuck_wbs_build_info{{version=\"{}\"}} 1\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(rendered.contains("stuck_wbs_last_scan_timestamp_seconds 1700000000.250\n"));
+    }
+
+    #[test]
+    fn test_render_adds_labels_to_every_sample() {
+        let labels = ["cluster=prod", "role=storage"].map(|l| l.parse().unwrap());
+        let metrics = Metrics::new(Labels::new(labels.to_vec()).unwrap());
+        metrics.set_status(Status::Watching);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "stuck_wbs_status{cluster=\"prod\",role=\"storage\",status=\"watching\"} 1\n"
+        ));
+        assert!(
+            rendered.contains("stuck_wbs_episodes_total{cluster=\"prod\",role=\"storage\"} 0\n")
+        );
+        assert!(rendered.contains("# TYPE stuck_wbs_status gauge\n"));
+        for sample in rendered.lines().filter(|line| !line.starts_with('#')) {
+            assert!(
+                sample.contains("{cluster=\"prod\",role=\"storage\""),
+                "{sample}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_render_status_as_state_gauge() {
+        let metrics = Metrics::default();
+        metrics.set_status(Status::Remediating);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_status{status=\"idle\"} 0\n"));
+        assert!(rendered.contains("stuck_wbs_status{status=\"watching\"} 0\n"));
+        assert!(rendered.contains("stuck_wbs_status{status=\"remediating\"} 1\n"));
+        assert!(rendered.contains("stuck_wbs_status{status=\"degraded\"} 0\n"));
+    }
+
+    #[test]
+    fn test_cleared_kworkers_per_sync() {
+        let metrics = Metrics::default();
+        // Scans without a preceding sync measure nothing.
+        assert_eq!(metrics.record_kworker_count(5), None);
+
+        let mut averages = Vec::new();
+        // A sync that freed most workers, one that didn't help, and one while more piled up.
+        for (before, after) in [(5, 1), (3, 3), (2, 4)] {
+            metrics.record_sync(before, chrono::Utc::now());
+            let cleared = metrics.record_kworker_count(after).unwrap();
+            averages.push((cleared.kworkers, cleared.average_per_sync));
+            // Only the first scan after a sync measures it.
+            assert_eq!(metrics.record_kworker_count(after), None);
+        }
+        assert_eq!(averages, vec![(4, 4.0), (0, 2.0), (0, 4.0 / 3.0)]);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_measured_syncs_total 3\n"));
+        assert!(rendered.contains("stuck_wbs_cleared_kworkers_total 4\n"));
+    }
+
+    #[test]
+    fn test_render_skips_by_reason() {
+        let metrics = Metrics::default();
+        let skipped = |pid, reason| Skipped {
+            pid,
+            comm: None,
+            reason,
+        };
+        metrics.record_skips(&[
+            skipped(1, SkipReason::NotMonitored),
+            skipped(2, SkipReason::FrozenCgroup),
+            skipped(3, SkipReason::NotMonitored),
+        ]);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("stuck_wbs_scan_skipped_total{reason=\"not_monitored\"} 2\n"));
+        assert!(rendered.contains("stuck_wbs_scan_skipped_total{reason=\"unreadable\"} 0\n"));
+        assert!(rendered.contains("stuck_wbs_scan_skipped_total{reason=\"frozen_cgroup\"} 1\n"));
+    }
+}
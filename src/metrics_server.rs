@@ -0,0 +1,104 @@
+//! `--metrics-listen`, serving the metrics over HTTP for Prometheus to scrape directly, on hosts
+//! without a node_exporter to collect `--metrics-textfile`.
+use crate::metrics::Metrics;
+use anyhow::{Context, Result};
+use log::debug;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a client may take to send its request, so a stalled one can't hold up the next.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request header line read, scrapers send much shorter ones.
+const MAX_LINE: u64 = 8192;
+
+/// Binds `addr` and serves `metrics` from a background thread, returning the bound address.
+pub fn spawn(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<SocketAddr> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to listen on {addr}"))?;
+    let bound = listener.local_addr()?;
+    std::thread::Builder::new()
+        .name("metrics-server".to_string())
+        .spawn(move || serve(&listener, &metrics))
+        .context("failed to start the metrics server")?;
+    Ok(bound)
+}
+
+/// Answers connections one at a time, which is plenty for a scraper every few seconds.
+fn serve(listener: &TcpListener, metrics: &Metrics) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle(stream, metrics));
+        if let Err(e) = result {
+            debug!("Failed to serve metrics: {e}");
+        }
+    }
+}
+
+fn handle(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_LINE)
+        .read_line(&mut request_line)?;
+    // Headers are read to leave nothing unread behind, which would reset the connection.
+    let mut header = String::new();
+    loop {
+        header.clear();
+        let read = reader.by_ref().take(MAX_LINE).read_line(&mut header)?;
+        if read == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+    let (status, body) = respond(&request_line, metrics);
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+/// Returns the status and body answering `request_line`, e.g. "GET /metrics HTTP/1.1".
+fn respond(request_line: &str, metrics: &Metrics) -> (&'static str, String) {
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next(), parts.next());
+    // Query strings, such as Prometheus' optional parameters, don't change what is served.
+    match (method, path.map(|p| p.split('?').next().unwrap_or(p))) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render()),
+        (Some("GET"), _) => (
+            "404 Not Found",
+            "Metrics are served at /metrics\n".to_string(),
+        ),
+        _ => ("405 Method Not Allowed", String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        let metrics = Metrics::default();
+        let (status, body) = respond("GET /metrics HTTP/1.1\r\n", &metrics);
+        assert_eq!(status, "200 OK");
+        assert!(body.contains("stuck_wbs_sync_total 0\n"));
+        assert_eq!(
+            respond("GET /metrics?x=1 HTTP/1.1\r\n", &metrics).0,
+            "200 OK"
+        );
+        assert_eq!(respond("GET / HTTP/1.1\r\n", &metrics).0, "404 Not Found");
+        assert_eq!(
+            respond("POST /metrics HTTP/1.1\r\n", &metrics).0,
+            "405 Method Not Allowed"
+        );
+        assert_eq!(respond("", &metrics).0, "405 Method Not Allowed");
+    }
+}
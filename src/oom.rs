@@ -0,0 +1,62 @@
+//! `--oom-score-adj`, making the daemon a less likely target of the OOM killer, as memory
+//! pressure tends to rise precisely while writeback is stalled.
+use log::{info, warn};
+use std::fmt;
+
+/// Where the kernel reads how to bias the OOM killer for this process.
+const OOM_SCORE_ADJ_PATH: &str = "/proc/self/oom_score_adj";
+
+/// An adjustment of the OOM killer's score, from -1000 (never killed) to 1000 (killed first).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomScoreAdj(i16);
+
+impl std::str::FromStr for OomScoreAdj {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let adj: i16 = s
+            .trim()
+            .parse()
+            .map_err(|e| format!("invalid OOM score adjustment '{s}': {e}"))?;
+        if !(-1000..=1000).contains(&adj) {
+            return Err(format!(
+                "invalid OOM score adjustment {adj}, expected -1000 to 1000"
+            ));
+        }
+        Ok(OomScoreAdj(adj))
+    }
+}
+
+impl fmt::Display for OomScoreAdj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Applies `adj` to this process. Lowering the score requires `CAP_SYS_RESOURCE`, without which
+/// this only warns, as the daemon works all the same.
+pub fn adjust(adj: OomScoreAdj) {
+    match std::fs::write(OOM_SCORE_ADJ_PATH, format!("{adj}\n")) {
+        Ok(()) => info!("Set the OOM score adjustment to {adj}"),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => warn!(
+            "Not setting the OOM score adjustment to {adj}, lowering it requires \
+             CAP_SYS_RESOURCE: {e}"
+        ),
+        Err(e) => warn!("Failed to set the OOM score adjustment to {adj}: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_oom_score_adj() {
+        assert_eq!("-1000".parse(), Ok(OomScoreAdj(-1000)));
+        assert_eq!("500".parse(), Ok(OomScoreAdj(500)));
+        assert_eq!(OomScoreAdj(-900).to_string(), "-900");
+        for invalid in ["-1001", "1001", "40000", "low", ""] {
+            assert!(invalid.parse::<OomScoreAdj>().is_err(), "{invalid}");
+        }
+    }
+}
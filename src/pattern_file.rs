@@ -0,0 +1,170 @@
+//! Globs loaded from a `--pattern-file`, re-read whenever it changes so fleet tooling can update
+//! them without restarting the daemon.
+use crate::signature::check_glob;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A file listing one glob per line, with blank lines and `#` comments ignored.
+#[derive(Debug)]
+pub struct PatternFile {
+    path: PathBuf,
+    /// When the file was last modified as of the last successful load.
+    mtime: SystemTime,
+    globs: Vec<String>,
+    /// Whether the last reload failed, so a lasting error is only warned about once.
+    failing: bool,
+}
+
+/// Extracts the globs from the contents of a pattern file, failing on malformed ones.
+fn parse(contents: &str) -> Result<Vec<String>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|glob| {
+            check_glob(glob).map_err(anyhow::Error::msg)?;
+            Ok(String::from(glob))
+        })
+        .collect()
+}
+
+/// Returns when `path` was last modified.
+fn mtime(path: &Path) -> Result<SystemTime> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("failed to stat {}", path.display()))?;
+    metadata
+        .modified()
+        .with_context(|| format!("failed to get the mtime of {}", path.display()))
+}
+
+/// Reads the globs from `path`, along with its mtime.
+fn read(path: &Path) -> Result<(SystemTime, Vec<String>)> {
+    // Taken first, so a write racing with the read is picked up by the next reload.
+    let mtime = mtime(path)?;
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let globs = parse(&contents).with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok((mtime, globs))
+}
+
+impl PatternFile {
+    /// Loads the globs from `path`, which must be readable.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let (mtime, globs) = read(&path)?;
+        info!("Loaded {} patterns from {}", globs.len(), path.display());
+        Ok(Self {
+            path,
+            mtime,
+            globs,
+            failing: false,
+        })
+    }
+
+    /// Re-reads the file if its mtime changed, returning whether the globs were reloaded.
+    ///
+    /// If the file cannot be read, the last good globs are kept.
+    pub fn reload_if_changed(&mut self) -> bool {
+        let reloaded = match mtime(&self.path) {
+            Ok(mtime) if mtime == self.mtime => return false,
+            Ok(_) => read(&self.path),
+            Err(e) => Err(e),
+        };
+        match reloaded {
+            Ok((mtime, globs)) => {
+                info!(
+                    "Reloaded {} patterns from {}",
+                    globs.len(),
+                    self.path.display()
+                );
+                self.mtime = mtime;
+                self.globs = globs;
+                self.failing = false;
+                true
+            }
+            Err(e) => {
+                if self.failing {
+                    debug!("Still keeping the previous patterns: {e:?}");
+                } else {
+                    warn!("Keeping the previous patterns: {e:?}");
+                }
+                self.failing = true;
+                false
+            }
+        }
+    }
+
+    /// The globs from the last successful load.
+    pub fn globs(&self) -> &[String] {
+        &self.globs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A pattern file in a fresh temporary path, removed on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("stuck_wbs_{}_{name}.patterns", std::process::id()));
+            Self(path)
+        }
+
+        fn write(&self, contents: &str, mtime: SystemTime) {
+            std::fs::write(&self.0, contents).unwrap();
+            let file = std::fs::File::options().write(true).open(&self.0).unwrap();
+            file.set_modified(mtime).unwrap();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_ignores_blanks_and_comments() {
+        let contents = "# Stuck writeback\nkworker/*inode_switch_wbs*\n\n  \n  # indented\n\
+                        \t**/flusher.py* \n";
+        assert_eq!(
+            parse(contents).unwrap(),
+            vec!["kworker/*inode_switch_wbs*", "**/flusher.py*"]
+        );
+        assert!(parse("kworker/*\nkworker/[0-9*\n").is_err());
+    }
+
+    #[test]
+    fn test_load_fails_on_missing_file() {
+        assert!(PatternFile::load(TempFile::new("missing").0.clone()).is_err());
+    }
+
+    #[test]
+    fn test_reload_on_mtime_change() {
+        let file = TempFile::new("reload");
+        let epoch = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        file.write("kworker/*\n", epoch);
+        let mut patterns = PatternFile::load(file.0.clone()).unwrap();
+        assert_eq!(patterns.globs(), ["kworker/*"]);
+
+        // Unchanged mtime, even though the contents changed.
+        file.write("jbd2/*\n", epoch);
+        assert!(!patterns.reload_if_changed());
+        assert_eq!(patterns.globs(), ["kworker/*"]);
+
+        file.write("jbd2/*\n", epoch + Duration::from_secs(1));
+        assert!(patterns.reload_if_changed());
+        assert_eq!(patterns.globs(), ["jbd2/*"]);
+
+        // Errors keep the last good patterns.
+        std::fs::remove_file(&file.0).unwrap();
+        assert!(!patterns.reload_if_changed());
+        assert_eq!(patterns.globs(), ["jbd2/*"]);
+    }
+}
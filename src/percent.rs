@@ -0,0 +1,25 @@
+//! Parsing of user-facing percentages, as taken by `--canary-percent` and `--min-free-percent`.
+
+/// Parses a percentage, from 0 to 100.
+pub fn parse_percent(value: &str) -> Result<u8, String> {
+    match value.parse() {
+        Ok(percent) if percent <= 100 => Ok(percent),
+        _ => Err(format!(
+            "invalid percentage '{value}', expected an integer from 0 to 100"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("0"), Ok(0));
+        assert_eq!(parse_percent("100"), Ok(100));
+        assert!(parse_percent("101").is_err());
+        assert!(parse_percent("-1").is_err());
+        assert!(parse_percent("10%").is_err());
+    }
+}
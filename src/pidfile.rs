@@ -0,0 +1,103 @@
+//! `--pidfile`, recording the daemon's pid in a file it keeps locked, so that a second instance,
+//! which would sync and scan in duplicate, refuses to start.
+//!
+//! The lock is an advisory `flock(2)`, which the kernel releases when the daemon dies, so a file
+//! left behind by a crash doesn't prevent restarts.
+use anyhow::{bail, Context, Result};
+use log::warn;
+use rustix::fs::{flock, FlockOperation};
+use rustix::io::Errno;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// A locked pid file, held until released.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    /// Holds the lock while open.
+    file: File,
+}
+
+impl PidFile {
+    /// Locks the file at `path`, creating it if needed, and writes the pid of this process to it.
+    /// Fails if another process holds the lock.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        loop {
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o644)
+                .open(path)
+                .with_context(|| format!("failed to open the pid file {}", path.display()))?;
+            match flock(&file, FlockOperation::NonBlockingLockExclusive) {
+                Ok(()) => {}
+                Err(Errno::WOULDBLOCK) => {
+                    let holder = std::fs::read_to_string(path).unwrap_or_default();
+                    bail!(
+                        "another instance (pid {}) is already running, as it holds {}",
+                        holder.trim(),
+                        path.display()
+                    );
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("failed to lock the pid file {}", path.display()))
+                }
+            }
+            // The previous holder may have removed the file between its opening and locking, in
+            // which case the lock is on a file no other instance can find.
+            let locked = file.metadata()?.ino();
+            if std::fs::metadata(path).map(|m| m.ino()).ok() != Some(locked) {
+                continue;
+            }
+            file.set_len(0)
+                .and_then(|()| writeln!(file, "{}", std::process::id()))
+                .with_context(|| format!("failed to write the pid file {}", path.display()))?;
+            return Ok(PidFile {
+                path: path.to_path_buf(),
+                file,
+            });
+        }
+    }
+
+    /// Removes the file, then releases the lock.
+    pub fn release(self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove the pid file {}: {e}", self.path.display());
+        }
+        drop(self.file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_second_instance_is_refused() {
+        let path = std::env::temp_dir().join(format!("stuck_wbs_{}.pid", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let first = PidFile::acquire(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        let error = PidFile::acquire(&path).unwrap_err();
+        assert!(
+            error.to_string().contains(&format!(
+                "another instance (pid {}) is already running",
+                std::process::id()
+            )),
+            "{error:#}"
+        );
+
+        first.release();
+        assert!(!path.exists());
+        PidFile::acquire(&path).unwrap().release();
+    }
+}
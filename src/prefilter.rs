@@ -0,0 +1,98 @@
+//! Cheap rejection of processes that no glob can match, from their comm alone, so scans skip
+//! reading everything else about them.
+
+/// Returns the part of `glob` before its first special character, which any match starts with.
+fn literal_prefix(glob: &str) -> &str {
+    // Negated globs match whatever the rest doesn't, with no prefix in common.
+    if glob.starts_with('!') {
+        return "";
+    }
+    let end = glob.find(['*', '?', '[', '{', '\\']).unwrap_or(glob.len());
+    &glob[..end]
+}
+
+/// Rejects comms that none of a set of globs can match.
+///
+/// It is conservative: a comm it accepts may still not match, but one it rejects never does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommPrefilter {
+    /// The literal prefixes of the globs. An empty one accepts every comm.
+    prefixes: Vec<String>,
+}
+
+impl CommPrefilter {
+    /// Returns a prefilter for comms that any of `globs` may match.
+    pub fn new<'a>(globs: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut prefixes: Vec<String> = globs
+            .into_iter()
+            .map(|glob| literal_prefix(glob).to_string())
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+        Self { prefixes }
+    }
+
+    /// Returns whether one of the globs may match `comm`.
+    pub fn may_match(&self, comm: &str) -> bool {
+        self.prefixes.iter().any(|prefix| comm.starts_with(prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glob_match::glob_match;
+
+    #[test]
+    fn test_literal_prefix() {
+        assert_eq!(literal_prefix("kworker/*inode_switch_wbs*"), "kworker/");
+        assert_eq!(literal_prefix("jbd2/sd?1-8"), "jbd2/sd");
+        assert_eq!(literal_prefix("[jk]worker"), "");
+        assert_eq!(literal_prefix("kswapd0"), "kswapd0");
+        assert_eq!(literal_prefix("!kworker/*"), "");
+    }
+
+    #[test]
+    fn test_never_rejects_a_match() {
+        let globs = [
+            "kworker/*inode_switch_wbs*",
+            "jbd2/*",
+            "k{swapd,compactd}*",
+            "ksoftirqd/\\*",
+        ];
+        let prefilter = CommPrefilter::new(globs);
+        // Matching whatever the rest doesn't.
+        let negated = CommPrefilter::new(["!kworker/*"]);
+        let comms = [
+            "kworker/0:1+inode_switch_wbs",
+            "kworker/u8:2",
+            "jbd2/sda1-8",
+            "kswapd0",
+            "kcompactd0",
+            "ksoftirqd/*",
+            "bash",
+            "systemd",
+        ];
+        for comm in comms {
+            if globs.iter().any(|glob| glob_match(glob, comm)) {
+                assert!(prefilter.may_match(comm), "{comm} was rejected");
+            }
+            if glob_match("!kworker/*", comm) {
+                assert!(
+                    negated.may_match(comm),
+                    "{comm} was rejected by the negation"
+                );
+            }
+        }
+        assert!(!prefilter.may_match("bash"));
+        assert!(!prefilter.may_match("systemd"));
+        assert!(negated.may_match("bash"));
+    }
+
+    #[test]
+    fn test_leading_wildcard_accepts_everything() {
+        let prefilter = CommPrefilter::new(["kworker/*", "*wbs*"]);
+        assert!(prefilter.may_match("bash"));
+        assert!(!CommPrefilter::new([]).may_match("bash"));
+    }
+}
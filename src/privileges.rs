@@ -0,0 +1,215 @@
+//! `--drop-to`, switching the daemon to an unprivileged user once its sockets and files are open,
+//! keeping only the capabilities its configuration needs.
+//!
+//! The user and groups are switched through libc, which applies `setuid(2)` and friends to every
+//! thread, whereas the kernel (and `rustix`) only applies them to the calling one. Capabilities
+//! are then kept by the calling thread alone, and inherited by the threads it spawns later.
+use crate::capabilities::{Capability, Requirement};
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use rustix::thread::{set_capabilities, set_keep_capabilities, CapabilityFlags, CapabilitySets};
+
+/// Where users are looked up.
+const PASSWD_PATH: &str = "/etc/passwd";
+/// Where supplementary groups are looked up.
+const GROUP_PATH: &str = "/etc/group";
+
+/// A user to switch to, with its groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, including the primary one.
+    pub groups: Vec<u32>,
+}
+
+/// Returns the fields of the non-comment lines of `contents`, a file in `/etc/passwd` format.
+fn entries(contents: &str) -> impl Iterator<Item = Vec<&str>> {
+    contents
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.split(':').collect())
+}
+
+/// Looks up `user`, a name or a uid, in the contents of `/etc/passwd` and `/etc/group`. A uid
+/// without an entry, as handed out to containers, gets the group of the same id.
+fn lookup_in(user: &str, passwd: &str, group: &str) -> Result<Credentials> {
+    let uid = user.parse::<u32>().ok();
+    let entry = entries(passwd).find(|fields| match uid {
+        Some(uid) => fields.get(2).and_then(|f| f.parse().ok()) == Some(uid),
+        None => fields[0] == user,
+    });
+    let (name, uid, gid) = match (entry, uid) {
+        (Some(fields), _) => {
+            let id = |i: usize| -> Result<u32> {
+                let field = fields.get(i).copied().unwrap_or_default();
+                field
+                    .parse()
+                    .with_context(|| format!("invalid id '{field}' for user '{}'", fields[0]))
+            };
+            (Some(fields[0]), id(2)?, id(3)?)
+        }
+        (None, Some(uid)) => (None, uid, uid),
+        (None, None) => bail!("no user '{user}' in {PASSWD_PATH}"),
+    };
+    let mut groups = vec![gid];
+    for fields in entries(group) {
+        let (Some(id), Some(members)) = (fields.get(2), fields.get(3)) else {
+            continue;
+        };
+        let Ok(id) = id.parse() else {
+            continue;
+        };
+        if !groups.contains(&id) && members.split(',').any(|m| Some(m) == name) {
+            groups.push(id);
+        }
+    }
+    Ok(Credentials { uid, gid, groups })
+}
+
+/// Looks up `user`, a name or a uid, in `/etc/passwd` and `/etc/group`.
+pub fn lookup(user: &str) -> Result<Credentials> {
+    let read =
+        |path| std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"));
+    // A system without groups file only has primary groups.
+    let group = read(GROUP_PATH).unwrap_or_default();
+    lookup_in(user, &read(PASSWD_PATH)?, &group)
+}
+
+/// Returns what to keep once running as another user: the capabilities `requirements` lists,
+/// `CAP_NET_ADMIN` if waiting on `process_events`, and `CAP_SYS_PTRACE` if reading where root's
+/// processes are blocked (`read_wchan`) or their stacks.
+pub fn retained(
+    requirements: &[Requirement],
+    process_events: bool,
+    read_wchan: bool,
+) -> Vec<Capability> {
+    let mut retained: Vec<Capability> = requirements.iter().map(|r| r.capability).collect();
+    if process_events {
+        retained.push(Capability::NetAdmin);
+    }
+    if read_wchan || retained.contains(&Capability::SysAdmin) {
+        retained.push(Capability::SysPtrace);
+    }
+    retained.dedup();
+    retained
+}
+
+/// Returns the result of a libc call returning 0 on success.
+fn check_libc(ret: libc::c_int, what: &str) -> Result<()> {
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("failed to set {what}"));
+    }
+    Ok(())
+}
+
+/// Switches every thread to `credentials`, then keeps `retained` on the calling thread only.
+pub fn drop_to(credentials: &Credentials, retained: &[Capability]) -> Result<()> {
+    set_keep_capabilities(true).context("failed to keep capabilities")?;
+    // SAFETY: `groups` points to `groups.len()` gids, and the others only take integers.
+    unsafe {
+        let groups = &credentials.groups;
+        check_libc(
+            libc::setgroups(groups.len(), groups.as_ptr()),
+            "the supplementary groups",
+        )?;
+        check_libc(libc::setgid(credentials.gid), "the group")?;
+        check_libc(libc::setuid(credentials.uid), "the user")?;
+    }
+    set_keep_capabilities(false).context("failed to stop keeping capabilities")?;
+    let flags = retained.iter().fold(CapabilityFlags::empty(), |flags, c| {
+        flags | CapabilityFlags::from_bits_retain(1 << c.number())
+    });
+    let names: Vec<&str> = retained.iter().map(|c| c.name()).collect();
+    set_capabilities(
+        None,
+        CapabilitySets {
+            effective: flags,
+            permitted: flags,
+            inheritable: CapabilityFlags::empty(),
+        },
+    )
+    .map_err(|e| anyhow!("failed to keep {}: {e}", names.join(", ")))?;
+    info!(
+        "Switched to uid {} and gid {}, keeping {}",
+        credentials.uid,
+        credentials.gid,
+        if names.is_empty() {
+            "no capabilities".to_string()
+        } else {
+            names.join(", ")
+        }
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWD: &str = "root:x:0:0:root:/root:/bin/bash\n\
+                          # A comment\n\
+                          stuckd:x:990:985::/var/lib/stuckd:/usr/sbin/nologin\n\
+                          broken:x:991:nope::/:/bin/false\n";
+    const GROUP: &str = "root:x:0:\n\
+                         stuckd:x:985:\n\
+                         systemd-journal:x:190:stuckd\n\
+                         adm:x:4:syslog,stuckd,root\n\
+                         wheel:x:10:root\n";
+
+    #[test]
+    fn test_lookup_by_name_or_uid() {
+        let stuckd = Credentials {
+            uid: 990,
+            gid: 985,
+            groups: vec![985, 190, 4],
+        };
+        assert_eq!(lookup_in("stuckd", PASSWD, GROUP).unwrap(), stuckd);
+        assert_eq!(lookup_in("990", PASSWD, GROUP).unwrap(), stuckd);
+        assert_eq!(
+            lookup_in("root", PASSWD, GROUP).unwrap().groups,
+            vec![0, 4, 10]
+        );
+        assert_eq!(
+            lookup_in("65534", PASSWD, GROUP).unwrap(),
+            Credentials {
+                uid: 65534,
+                gid: 65534,
+                groups: vec![65534],
+            }
+        );
+    }
+
+    #[test]
+    fn test_lookup_errors() {
+        let error = lookup_in("nobody", PASSWD, GROUP).unwrap_err();
+        assert_eq!(error.to_string(), "no user 'nobody' in /etc/passwd");
+        let error = lookup_in("broken", PASSWD, GROUP).unwrap_err();
+        assert_eq!(error.to_string(), "invalid id 'nope' for user 'broken'");
+    }
+
+    #[test]
+    fn test_retained_capabilities() {
+        let kill = Requirement {
+            capability: Capability::Kill,
+            feature: "signal actions",
+            fatal: true,
+        };
+        let stacks = Requirement {
+            capability: Capability::SysAdmin,
+            feature: "kernel stacks",
+            fatal: false,
+        };
+        assert_eq!(retained(&[], false, false), vec![]);
+        assert_eq!(
+            retained(&[kill], true, false),
+            vec![Capability::Kill, Capability::NetAdmin]
+        );
+        assert_eq!(
+            retained(&[stacks], false, false),
+            vec![Capability::SysAdmin, Capability::SysPtrace]
+        );
+        assert_eq!(retained(&[], false, true), vec![Capability::SysPtrace]);
+    }
+}
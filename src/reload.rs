@@ -0,0 +1,63 @@
+//! `SIGHUP`, asking the daemon to re-read its configuration file, which the monitor loop does
+//! before its next iteration.
+use anyhow::{bail, Context, Result};
+use log::debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Changes the signal mask of the calling thread as `pthread_sigmask(3)` does, returning the
+/// previous one.
+fn set_mask(how: libc::c_int, set: &libc::sigset_t) -> Result<libc::sigset_t> {
+    // SAFETY: a zeroed signal set is valid storage for the previous mask.
+    let mut previous = unsafe { std::mem::zeroed() };
+    // SAFETY: `set` is a valid signal set and `previous` a valid output location.
+    let err = unsafe { libc::pthread_sigmask(how, set, &mut previous) };
+    if err != 0 {
+        bail!(
+            "failed to change the signal mask: {}",
+            std::io::Error::from_raw_os_error(err)
+        );
+    }
+    Ok(previous)
+}
+
+/// Listens for `SIGHUP`, returning a flag set whenever it is received, for the monitor loop to
+/// clear once it reloaded.
+///
+/// Must be called before spawning any thread, as those would otherwise keep `SIGHUP` unblocked,
+/// and whichever thread it is delivered to would be terminated along with the daemon.
+pub fn handle_reload_signal() -> Result<Arc<AtomicBool>> {
+    // SAFETY: sigemptyset and sigfillset initialize the sets, and sigaddset is given a valid
+    // signal number.
+    let (set, all) = unsafe {
+        let (mut set, mut all) = (std::mem::zeroed(), std::mem::zeroed());
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGHUP);
+        libc::sigfillset(&mut all);
+        (set, all)
+    };
+    // The listener is spawned with every signal blocked, so that it doesn't take termination
+    // signals from the thread waiting for them, which is spawned later.
+    let previous = set_mask(libc::SIG_BLOCK, &all)?;
+    let requested = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&requested);
+    let spawned = std::thread::Builder::new()
+        .name("reload".to_string())
+        .spawn(move || loop {
+            let mut signal = 0;
+            // SAFETY: `set` is a valid signal set and `signal` a valid output location.
+            if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+                debug!("Received SIGHUP, reloading the configuration");
+                flag.store(true, Ordering::Relaxed);
+            }
+        });
+    set_mask(libc::SIG_SETMASK, &previous)?;
+    set_mask(libc::SIG_BLOCK, &set)?;
+    spawned.context("failed to start the SIGHUP listener")?;
+    Ok(requested)
+}
+
+/// Returns whether a reload was requested since the last call.
+pub fn requested(flag: &AtomicBool) -> bool {
+    flag.swap(false, Ordering::Relaxed)
+}
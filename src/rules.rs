@@ -0,0 +1,137 @@
+//! `--rules`, a TOML file of rules monitoring other stuck kernel threads than kworkers, such as
+//! `md`, `jbd2` or `xfsaild` ones, each with its own glob, uid, threshold and action.
+//!
+//! Each `[[rule]]` table becomes a signature, as `--signature` would give:
+//!
+//! ```toml
+//! [[rule]]
+//! glob = "md*_raid*"
+//! runtime-threshold = "2m"
+//! action = "sync"
+//! ```
+use crate::duration::parse_duration;
+use crate::signature::{check_glob, Signature};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// The contents of a rules file, which rejects unknown keys, as a misspelled threshold would
+/// otherwise silently fall back to the default one.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+
+/// A rule, with values as on the command line.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Rule {
+    glob: String,
+    uid: Option<u32>,
+    stack: Option<String>,
+    state: Option<String>,
+    runtime_threshold: Option<String>,
+    action: Option<String>,
+}
+
+impl Rule {
+    fn signature(self) -> Result<Signature, String> {
+        check_glob(&self.glob)?;
+        Ok(Signature {
+            glob: self.glob,
+            uid: self.uid,
+            stack: self.stack,
+            states: self.state,
+            threshold: self
+                .runtime_threshold
+                .as_deref()
+                .map(parse_duration)
+                .transpose()?,
+            action: self.action.as_deref().unwrap_or("sync").parse()?,
+        })
+    }
+}
+
+/// Parses the contents of a rules file into signatures, in the order of the rules.
+pub fn parse(contents: &str) -> Result<Vec<Signature>> {
+    let file: RulesFile = toml::from_str(contents)?;
+    if file.rule.is_empty() {
+        anyhow::bail!("no [[rule]] defined");
+    }
+    file.rule
+        .into_iter()
+        .enumerate()
+        .map(|(i, rule)| {
+            rule.signature()
+                .map_err(|e| anyhow::anyhow!("invalid rule #{}: {e}", i + 1))
+        })
+        .collect()
+}
+
+/// Loads the rules from `path`.
+pub fn load(path: &Path) -> Result<Vec<Signature>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read the rules file {}", path.display()))?;
+    parse(&contents).with_context(|| format!("failed to parse the rules file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Action;
+
+    #[test]
+    fn test_parse_rules() {
+        let signatures = parse(
+            "[[rule]]\n\
+             glob = \"md*_raid*\"\n\
+             uid = 0\n\
+             runtime-threshold = \"2m\"\n\
+             action = \"command\"\n\
+             \n\
+             [[rule]]\n\
+             glob = \"jbd2/*\"\n\
+             state = \"D\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            signatures,
+            vec![
+                Signature {
+                    uid: Some(0),
+                    threshold: Some(chrono::Duration::minutes(2)),
+                    ..Signature::new("md*_raid*", Action::Command)
+                },
+                Signature {
+                    states: Some("D".to_string()),
+                    ..Signature::new("jbd2/*", Action::Sync)
+                },
+            ]
+        );
+
+        for (invalid, error) in [
+            ("", "no [[rule]] defined"),
+            (
+                "[[rule]]\nglob = \"xfsaild/*\"\nthreshold = \"1m\"\n",
+                "unknown field",
+            ),
+            (
+                "[[rule]]\nglob = \"xfsaild/[*\"\n",
+                "invalid rule #1: unclosed '['",
+            ),
+            (
+                "[[rule]]\nglob = \"a\"\n[[rule]]\nglob = \"b\"\naction = \"reboot\"\n",
+                "invalid rule #2",
+            ),
+            (
+                "[[rule]]\nglob = \"a\"\nruntime-threshold = \"soon\"\n",
+                "invalid rule #1",
+            ),
+        ] {
+            let e = parse(invalid).unwrap_err();
+            assert!(format!("{e:#}").contains(error), "{invalid}: {e:#}");
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Deterministic release of the daemon's resources on every termination path, including
+//! termination signals.
+use crate::duration::format_duration;
+use crate::metrics::Metrics;
+use anyhow::{bail, Result};
+use log::{debug, error, info};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::{Duration, Instant};
+
+/// Signals that terminate the daemon gracefully.
+const TERMINATION_SIGNALS: [(libc::c_int, &str); 2] =
+    [(libc::SIGTERM, "SIGTERM"), (libc::SIGINT, "SIGINT")];
+
+/// A cleanup step, run at most once.
+type Step = Box<dyn FnOnce() + Send>;
+
+/// Why the daemon exits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitReason {
+    /// A termination signal, by name, was received.
+    Signal(&'static str),
+    /// The daemon failed with this error.
+    Failed(String),
+    /// The daemon issued this many syncs, reaching `--max-syncs`.
+    MaxSyncs(u64),
+}
+
+impl std::fmt::Display for ExitReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitReason::Signal(name) => write!(f, "received {name}"),
+            ExitReason::Failed(e) => write!(f, "failed: {e}"),
+            ExitReason::MaxSyncs(n) => write!(f, "reached --max-syncs with {n} syncs"),
+        }
+    }
+}
+
+/// What the daemon did over its lifetime, for the final log line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Summary {
+    uptime: Duration,
+    triggers: u64,
+    episodes: u64,
+}
+
+/// Returns the last line the daemon logs, so operators have one line to look for.
+fn final_line(reason: &ExitReason, summary: &Summary) -> String {
+    format!(
+        "Exiting, {reason}, after running for {}: {} triggers in {} episodes",
+        format_duration(summary.uptime),
+        summary.triggers,
+        summary.episodes
+    )
+}
+
+/// Cleanup steps to run when the daemon terminates.
+///
+/// Steps run in the reverse order of their registration, like destructors, so a resource is
+/// released before those it depends on.
+pub struct Teardown {
+    steps: Vec<(&'static str, Step)>,
+    started: Instant,
+    /// Where the final log line gets its statistics from.
+    metrics: Option<Arc<Metrics>>,
+    finished: bool,
+}
+
+impl Default for Teardown {
+    fn default() -> Self {
+        Self {
+            steps: Vec::new(),
+            started: Instant::now(),
+            metrics: None,
+            finished: false,
+        }
+    }
+}
+
+impl Teardown {
+    /// Makes the final log line summarize `metrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Registers `step`, described by `name` in logs, to run on termination.
+    pub fn register(&mut self, name: &'static str, step: impl FnOnce() + Send + 'static) {
+        self.steps.push((name, Box::new(step)));
+    }
+
+    /// Runs every registered step that did not run yet.
+    pub fn run(&mut self) {
+        while let Some((name, step)) = self.steps.pop() {
+            debug!("Teardown: {name}");
+            step();
+        }
+    }
+
+    /// Runs every registered step, then logs why the daemon exits and flushes the logs. Only the
+    /// first call logs.
+    pub fn finish(&mut self, reason: &ExitReason) {
+        self.run();
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let summary = Summary {
+            uptime: self.started.elapsed(),
+            triggers: self.metrics.as_ref().map_or(0, |m| m.triggers()),
+            episodes: self.metrics.as_ref().map_or(0, |m| m.episodes()),
+        };
+        let line = final_line(reason, &summary);
+        match reason {
+            ExitReason::Signal(_) => info!("{line}"),
+            ExitReason::Failed(_) | ExitReason::MaxSyncs(_) => error!("{line}"),
+        }
+        // Standard error may be buffered, for instance when redirected.
+        log::logger().flush();
+    }
+}
+
+/// Locks `teardown`, even if a panicking step poisoned it.
+pub fn lock(teardown: &Mutex<Teardown>) -> MutexGuard<'_, Teardown> {
+    teardown.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        self.run();
+    }
+}
+
+/// Returns the signal set containing `TERMINATION_SIGNALS`.
+fn termination_sigset() -> libc::sigset_t {
+    // SAFETY: sigemptyset initializes the set, and sigaddset is given valid signal numbers.
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for (signal, _) in TERMINATION_SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    }
+}
+
+/// Finishes `teardown`, unless it was already dropped, then exits when a termination signal is
+/// received.
+///
+/// Signals are handled on a dedicated thread, which sees them even while the main loop is
+/// blocked. This must be called before spawning any other thread, since threads inherit the
+/// signal mask that routes termination signals to the dedicated one. Commands spawned through
+/// `std::process::Command` start with an empty mask regardless.
+pub fn handle_termination_signals(teardown: Weak<Mutex<Teardown>>) -> Result<()> {
+    let set = termination_sigset();
+    // SAFETY: `set` is a valid signal set, and the old mask is not needed.
+    let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if err != 0 {
+        bail!(
+            "failed to block termination signals: {}",
+            std::io::Error::from_raw_os_error(err)
+        );
+    }
+    std::thread::spawn(move || {
+        let mut signal = 0;
+        // SAFETY: `set` is a valid signal set and `signal` a valid output location.
+        let err = unsafe { libc::sigwait(&set, &mut signal) };
+        let name = TERMINATION_SIGNALS
+            .iter()
+            .find(|(s, _)| err == 0 && *s == signal)
+            .map_or("an unknown signal", |(_, name)| name);
+        match teardown.upgrade() {
+            Some(teardown) => terminate(&teardown, name),
+            None => std::process::exit(0),
+        }
+    });
+    Ok(())
+}
+
+/// Finishes `teardown` on the termination signal `name`, then exits.
+pub fn terminate(teardown: &Mutex<Teardown>, name: &'static str) -> ! {
+    debug!("Received {name}, shutting down");
+    lock(teardown).finish(&ExitReason::Signal(name));
+    std::process::exit(0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_teardown_runs_steps_once_in_reverse_order() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let mut teardown = Teardown::default();
+        for name in ["netlink socket", "pidfile", "state file"] {
+            let ran = Arc::clone(&ran);
+            teardown.register(name, move || ran.lock().unwrap().push(name));
+        }
+
+        teardown.run();
+        teardown.run();
+        drop(teardown);
+        assert_eq!(
+            *ran.lock().unwrap(),
+            ["state file", "pidfile", "netlink socket"]
+        );
+    }
+
+    #[test]
+    fn test_final_line_per_exit_reason() {
+        let summary = Summary {
+            uptime: Duration::from_secs(3 * 3600 + 5),
+            triggers: 4,
+            episodes: 2,
+        };
+        assert_eq!(
+            final_line(&ExitReason::Signal("SIGTERM"), &summary),
+            "Exiting, received SIGTERM, after running for 3h 5s: 4 triggers in 2 episodes"
+        );
+        assert_eq!(
+            final_line(
+                &ExitReason::Failed("failed to receive process event".to_string()),
+                &summary
+            ),
+            "Exiting, failed: failed to receive process event, after running for 3h 5s: \
+             4 triggers in 2 episodes"
+        );
+        assert_eq!(
+            final_line(&ExitReason::MaxSyncs(5), &summary),
+            "Exiting, reached --max-syncs with 5 syncs, after running for 3h 5s: \
+             4 triggers in 2 episodes"
+        );
+    }
+
+    #[test]
+    fn test_finish_runs_steps_then_logs_once() {
+        let ran = Arc::new(Mutex::new(0));
+        let mut teardown = Teardown::default();
+        let counter = Arc::clone(&ran);
+        teardown.register("counter", move || *counter.lock().unwrap() += 1);
+
+        teardown.finish(&ExitReason::Signal("SIGINT"));
+        assert!(teardown.finished);
+        teardown.finish(&ExitReason::Failed("late".to_string()));
+        assert_eq!(*ran.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_teardown_runs_on_drop() {
+        let ran = Arc::new(Mutex::new(0));
+        {
+            let mut teardown = Teardown::default();
+            let ran = Arc::clone(&ran);
+            teardown.register("counter", move || *ran.lock().unwrap() += 1);
+        }
+        assert_eq!(*ran.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,397 @@
+//! Signatures of the distinct stalls the daemon knows about: which processes each one matches,
+//! and the threshold and action that apply to them.
+use crate::action::Action;
+use crate::duration::{format_signed_duration, parse_duration};
+use crate::system::ProcInfo;
+use glob_match::glob_match;
+use std::fmt;
+
+/// Returns whether `glob` matches the process's comm or, when it was read, its command line.
+pub fn matches_glob(glob: &str, p: &ProcInfo) -> bool {
+    glob_match(glob, &p.comm) || p.cmdline.as_deref().is_some_and(|c| glob_match(glob, c))
+}
+
+/// A process name that no glob meant for stalled kernel threads should match.
+const PROBE_COMM: &str = "systemd";
+
+/// Checks that `glob` is well-formed, failing if it is empty, has an unclosed `[...]`, which
+/// `glob_match` silently never matches, or unbalanced `{...}`, which it matches inconsistently.
+/// Returns warnings about what looks like a mistake, though may be intended.
+pub fn check_glob(glob: &str) -> Result<Vec<String>, String> {
+    if glob.is_empty() {
+        return Err(String::from("empty glob"));
+    }
+    let (mut braces, mut in_class) = (0_usize, false);
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '[' if !in_class => in_class = true,
+            ']' if in_class => in_class = false,
+            '{' if !in_class => braces += 1,
+            '}' if !in_class => {
+                braces = braces
+                    .checked_sub(1)
+                    .ok_or_else(|| format!("unmatched '}}' in glob '{glob}'"))?;
+            }
+            _ => {}
+        }
+    }
+    if in_class {
+        return Err(format!("unclosed '[' in glob '{glob}'"));
+    }
+    if braces > 0 {
+        return Err(format!("unclosed '{{' in glob '{glob}'"));
+    }
+    let mut warnings = Vec::new();
+    if !glob.starts_with("kworker") {
+        warnings.push(format!(
+            "Glob '{glob}' doesn't start with 'kworker', so it monitors other processes than the \
+             kworkers this workaround is meant for"
+        ));
+    }
+    if glob_match(glob, PROBE_COMM) {
+        warnings.push(format!(
+            "Glob '{glob}' matches '{PROBE_COMM}', so likely any process, which would be acted on \
+             once past the threshold"
+        ));
+    }
+    Ok(warnings)
+}
+
+/// Identifies one kind of stall, as `glob=<GLOB>[,uid=<UID>][,stack=<SUBSTRING>]`
+/// `[,state=<STATES>][,threshold=<DURATION>][,action=<ACTION>]`.
+///
+/// A process matches when its comm (or command line) matches `glob`, it runs as `uid`, its state
+/// is one of `states` and its kernel stack contains `stack`, the last three only if given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub glob: String,
+    /// The uid the process runs as, which it is then monitored with even if not one of the
+    /// `--uid`s.
+    pub uid: Option<u32>,
+    /// A substring of `/proc/<pid>/stack`, e.g. a kernel function name.
+    pub stack: Option<String>,
+    /// The `/proc/<pid>/stat` states the process may be in, e.g. "RD".
+    pub states: Option<String>,
+    /// How long a matching process may run before action is taken, `--runtime-threshold` if
+    /// unset.
+    pub threshold: Option<chrono::Duration>,
+    pub action: Action,
+}
+
+impl Signature {
+    /// Returns a signature matching processes on `glob` alone.
+    pub fn new(glob: &str, action: Action) -> Self {
+        Signature {
+            glob: glob.to_string(),
+            uid: None,
+            stack: None,
+            states: None,
+            threshold: None,
+            action,
+        }
+    }
+
+    /// Returns whether `p` matches every criterion but the stack, which is costlier to check.
+    pub fn matches_cheaply(&self, p: &ProcInfo) -> bool {
+        matches_glob(&self.glob, p)
+            && self.uid.is_none_or(|uid| uid == p.uid)
+            && self
+                .states
+                .as_deref()
+                .is_none_or(|states| states.contains(p.state))
+    }
+
+    /// Returns whether `p` matches every criterion, calling `stack` for its kernel stack (`None`
+    /// if unreadable) only if needed.
+    pub fn matches(&self, p: &ProcInfo, stack: impl FnOnce() -> Option<String>) -> bool {
+        self.matches_cheaply(p)
+            && self
+                .stack
+                .as_deref()
+                .is_none_or(|wanted| stack().is_some_and(|s| s.contains(wanted)))
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "glob={}", self.glob)?;
+        if let Some(uid) = self.uid {
+            write!(f, ",uid={uid}")?;
+        }
+        if let Some(stack) = &self.stack {
+            write!(f, ",stack={stack}")?;
+        }
+        if let Some(states) = &self.states {
+            write!(f, ",state={states}")?;
+        }
+        if let Some(threshold) = self.threshold {
+            write!(f, ",threshold={}", format_signed_duration(threshold))?;
+        }
+        write!(f, ",action={}", self.action)
+    }
+}
+
+/// Serialized as on the command line, e.g. "glob=kworker/*,state=R,action=sync".
+impl serde::Serialize for Signature {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// A `--process-glob`, as `<GLOB>[=<THRESHOLD>]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessGlob {
+    pub glob: String,
+    /// How long a matching process may run before it is synced, `--runtime-threshold` if unset.
+    pub threshold: Option<chrono::Duration>,
+}
+
+impl ProcessGlob {
+    pub fn new(glob: &str) -> Self {
+        ProcessGlob {
+            glob: glob.to_string(),
+            threshold: None,
+        }
+    }
+
+    /// Returns the signature matching on the glob alone, with a sync action.
+    pub fn signature(&self) -> Signature {
+        Signature {
+            threshold: self.threshold,
+            ..Signature::new(&self.glob, Action::Sync)
+        }
+    }
+}
+
+impl fmt::Display for ProcessGlob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.glob)?;
+        match self.threshold {
+            Some(threshold) => write!(f, "={}", format_signed_duration(threshold)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Serialized as on the command line, e.g. "jbd2/*=2m".
+impl serde::Serialize for ProcessGlob {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// The threshold follows the last `=`, so a glob containing one must be given a threshold.
+impl std::str::FromStr for ProcessGlob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (glob, threshold) = match s.rsplit_once('=') {
+            Some((glob, threshold)) => (glob, Some(parse_duration(threshold)?)),
+            None => (s, None),
+        };
+        if glob.is_empty() {
+            return Err(format!("empty glob in process glob '{s}'"));
+        }
+        Ok(ProcessGlob {
+            threshold,
+            ..ProcessGlob::new(glob)
+        })
+    }
+}
+
+/// Splits `s` on the commas that are not within a glob's `{a,b}` alternatives.
+fn split_fields(s: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let (mut depth, mut start) = (0_usize, 0);
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                fields.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&s[start..]);
+    fields
+}
+
+impl std::str::FromStr for Signature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut glob = None;
+        let mut signature = Signature::new("", Action::Sync);
+        for field in split_fields(s) {
+            let Some((key, value)) = field.split_once('=') else {
+                return Err(format!(
+                    "invalid field '{field}' in signature '{s}', expected KEY=VALUE"
+                ));
+            };
+            if value.is_empty() {
+                return Err(format!("empty {key} in signature '{s}'"));
+            }
+            match key {
+                "glob" => glob = Some(value),
+                "uid" => {
+                    signature.uid = Some(
+                        value
+                            .parse()
+                            .map_err(|_| format!("invalid uid '{value}' in signature '{s}'"))?,
+                    )
+                }
+                "stack" => signature.stack = Some(value.to_string()),
+                "state" => signature.states = Some(value.to_string()),
+                "threshold" => signature.threshold = Some(parse_duration(value)?),
+                "action" => signature.action = value.parse()?,
+                _ => {
+                    return Err(format!(
+                        "unknown field '{key}' in signature '{s}', expected glob, uid, stack, \
+                         state, threshold or action"
+                    ))
+                }
+            }
+        }
+        let Some(glob) = glob else {
+            return Err(format!("missing glob in signature '{s}'"));
+        };
+        signature.glob = glob.to_string();
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::process::Signal;
+
+    fn proc_info(comm: &str, state: char) -> ProcInfo {
+        ProcInfo {
+            pid: 1000,
+            uid: 0,
+            comm: comm.to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state,
+            wchan: None,
+            starttime: chrono::Utc::now(),
+            cpu_time: std::time::Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_parse_signature() {
+        assert_eq!(
+            "glob=kworker/*,stack=inode_switch_wbs,state=RD,threshold=1m,action=signal:SIGKILL"
+                .parse(),
+            Ok(Signature {
+                glob: "kworker/*".to_string(),
+                uid: None,
+                stack: Some("inode_switch_wbs".to_string()),
+                states: Some("RD".to_string()),
+                threshold: Some(chrono::Duration::minutes(1)),
+                action: Action::Signal(Signal::KILL),
+            })
+        );
+        assert_eq!(
+            "glob=kworker/{u,}*".parse(),
+            Ok(Signature::new("kworker/{u,}*", Action::Sync))
+        );
+        for invalid in [
+            "",
+            "stack=inode_switch_wbs",
+            "glob=",
+            "glob=a,stack",
+            "glob=a,color=red",
+            "glob=a,threshold=soon",
+            "glob=a,action=reboot",
+            "glob=a,uid=root",
+        ] {
+            assert!(invalid.parse::<Signature>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for spec in [
+            "glob=kworker/{u,}*,action=sync",
+            "glob=jbd2/*,stack=jbd2_journal_commit,state=D,threshold=1m 30s,action=signal:SIGKILL",
+            "glob=md*_raid*,uid=0,action=sync",
+        ] {
+            let signature: Signature = spec.parse().unwrap();
+            assert_eq!(signature.to_string(), spec);
+        }
+    }
+
+    #[test]
+    fn test_parse_process_glob() {
+        assert_eq!(
+            "kworker/*inode_switch_wbs*".parse(),
+            Ok(ProcessGlob::new("kworker/*inode_switch_wbs*"))
+        );
+        let jbd2: ProcessGlob = "jbd2/*=2m".parse().unwrap();
+        assert_eq!(jbd2.threshold, Some(chrono::Duration::minutes(2)));
+        assert_eq!(jbd2.to_string(), "jbd2/*=2m");
+        assert_eq!(
+            jbd2.signature(),
+            Signature {
+                threshold: Some(chrono::Duration::minutes(2)),
+                ..Signature::new("jbd2/*", Action::Sync)
+            }
+        );
+        for invalid in ["", "=2m", "jbd2/*=soon", "jbd2/*="] {
+            assert!(invalid.parse::<ProcessGlob>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_check_glob() {
+        assert_eq!(check_glob("kworker/*inode_switch_wbs*"), Ok(vec![]));
+        assert_eq!(check_glob("kworker/{u,}[0-9]*:\\[*"), Ok(vec![]));
+        for invalid in ["", "kworker/[0-9*", "kworker/{u,*", "kworker/u}*"] {
+            assert!(check_glob(invalid).is_err(), "{invalid}");
+        }
+        // Otherwise never matching, which would go unnoticed.
+        assert!(!glob_match("kworker/[0-9*", "kworker/0:1"));
+        assert_eq!(check_glob("jbd2/*").unwrap().len(), 1);
+        let warnings = check_glob("*").unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[1].contains("likely any process"), "{warnings:?}");
+    }
+
+    #[test]
+    fn test_matches_every_criterion() {
+        let signature: Signature = "glob=kworker/*,stack=inode_switch_wbs,state=R"
+            .parse()
+            .unwrap();
+        let stack = || Some("[<0>] inode_switch_wbs_work_fn+0x2a/0x4a0\n".to_string());
+        assert!(signature.matches(&proc_info("kworker/0:1", 'R'), stack));
+        assert!(!signature.matches(&proc_info("jbd2/sda1-8", 'R'), stack));
+        assert!(!signature.matches(&proc_info("kworker/0:1", 'S'), stack));
+        assert!(!signature.matches(&proc_info("kworker/0:1", 'R'), || Some(
+            "[<0>] worker_thread+0xc2/0x3a0\n".to_string()
+        )));
+        // An unreadable stack can't be checked, so doesn't match.
+        assert!(!signature.matches(&proc_info("kworker/0:1", 'R'), || None));
+    }
+
+    #[test]
+    fn test_stack_is_only_read_when_needed() {
+        let read = std::cell::Cell::new(false);
+        let stack = || {
+            read.set(true);
+            None
+        };
+        let signature: Signature = "glob=kworker/*,stack=x,state=D".parse().unwrap();
+        assert!(!signature.matches(&proc_info("kworker/0:1", 'R'), stack));
+        assert!(!read.get());
+        assert!(Signature::new("kworker/*", Action::Sync)
+            .matches(&proc_info("kworker/0:1", 'R'), || unreachable!()));
+    }
+}
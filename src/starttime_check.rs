@@ -0,0 +1,95 @@
+//! A startup self-check that process start times read from `/proc` are trustworthy, as they are
+//! converted from clock ticks since boot using `USER_HZ` and the boot time, which some
+//! environments (e.g. certain containers) get wrong.
+use crate::duration::format_signed_duration;
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use procfs::process::Process;
+use procfs::WithCurrentSystemInfo;
+
+/// The default for `--starttime-tolerance`. The boot time `/proc` reports has a resolution of one
+/// second, so start times are only accurate to within that.
+pub const DEFAULT_TOLERANCE: chrono::Duration = chrono::Duration::seconds(5);
+
+/// Returns by how much `stat_age`, an age derived from a start time read from `/proc`, diverges
+/// from the `reference_age` of the same process, if that is more than `tolerance`.
+pub fn divergence(
+    stat_age: chrono::Duration,
+    reference_age: chrono::Duration,
+    tolerance: chrono::Duration,
+) -> Option<chrono::Duration> {
+    let divergence = stat_age - reference_age;
+    (divergence.abs() > tolerance).then_some(divergence)
+}
+
+/// Warns if the age of this process, as derived from its start time in `/proc`, diverges by more
+/// than `tolerance` from `reference_age`, measured independently by the process itself.
+///
+/// `reference_age` slightly underestimates the real age as it starts once the process is
+/// running, but by much less than `tolerance` is expected to be.
+pub fn check(reference_age: chrono::Duration, tolerance: chrono::Duration) -> Result<()> {
+    let stat = Process::myself()
+        .and_then(|p| p.stat())
+        .context("failed to read own process stat")?;
+    let starttime = stat
+        .starttime()
+        .get()
+        .context("failed to get own process start time")?;
+    let stat_age = chrono::Utc::now().signed_duration_since(starttime);
+    match divergence(stat_age, reference_age, tolerance) {
+        Some(divergence) => warn!(
+            "Process start times from /proc seem off by {}, so kworker ages may be wrong: this \
+             process appears to be {} old, but has been running for {}",
+            format_signed_duration(divergence),
+            format_signed_duration(stat_age),
+            format_signed_duration(reference_age)
+        ),
+        None => debug!(
+            "Process start times from /proc agree with the process's own clock (age: {})",
+            format_signed_duration(stat_age)
+        ),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: i64) -> chrono::Duration {
+        chrono::Duration::milliseconds(ms)
+    }
+
+    #[test]
+    fn test_agreement_within_tolerance() {
+        assert_eq!(divergence(ms(40), ms(30), DEFAULT_TOLERANCE), None);
+        // Boot time resolution makes start times up to a second early or late.
+        assert_eq!(divergence(ms(990), ms(30), DEFAULT_TOLERANCE), None);
+        assert_eq!(divergence(ms(-900), ms(30), DEFAULT_TOLERANCE), None);
+        assert_eq!(divergence(ms(5030), ms(30), DEFAULT_TOLERANCE), None);
+    }
+
+    #[test]
+    fn test_divergence_beyond_tolerance() {
+        // E.g. the container's boot time being the host's.
+        assert_eq!(
+            divergence(ms(3_600_030), ms(30), DEFAULT_TOLERANCE),
+            Some(ms(3_600_000))
+        );
+        // E.g. a wrong USER_HZ placing the start in the future.
+        assert_eq!(
+            divergence(ms(-60_000), ms(30), DEFAULT_TOLERANCE),
+            Some(ms(-60_030))
+        );
+        assert_eq!(
+            divergence(ms(5031), ms(30), DEFAULT_TOLERANCE),
+            Some(ms(5001))
+        );
+    }
+
+    #[test]
+    fn test_check_on_this_process() {
+        // The test harness has been running for a while, so any age is plausible.
+        assert!(check(chrono::Duration::zero(), chrono::Duration::weeks(1)).is_ok());
+    }
+}
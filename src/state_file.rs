@@ -0,0 +1,193 @@
+//! `--state-file`, recording every sync to a file, so that `--sync-cooldown` and
+//! `--max-ineffective-syncs` still apply when the daemon is restarted in a loop, e.g. by systemd
+//! after a crash.
+//!
+//! The file has one line per sync, `<when> <pid> <start time>`, the latter two identifying the
+//! process it was issued for, with times in RFC 3339 format. Lines are only appended, except when
+//! the file is compacted to drop those past the retention window.
+use crate::tracker::Key;
+use anyhow::{Context, Result};
+use log::warn;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+type Time = chrono::DateTime<chrono::Utc>;
+
+/// How long syncs are remembered for, well past any sensible `--sync-cooldown`.
+pub const RETENTION: chrono::Duration = chrono::Duration::days(1);
+
+/// A sync, as recorded in the state file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncRecord {
+    /// When the sync was issued.
+    pub at: Time,
+    /// The process it was issued for.
+    pub kworker: Key,
+}
+
+impl SyncRecord {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let at = chrono::DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        let pid = fields.next()?.parse().ok()?;
+        let starttime = chrono::DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(SyncRecord {
+            at: at.into(),
+            kworker: (pid, starttime.into()),
+        })
+    }
+
+    fn line(&self) -> String {
+        let (pid, starttime) = self.kworker;
+        format!(
+            "{} {pid} {}\n",
+            self.at.to_rfc3339(),
+            starttime.to_rfc3339()
+        )
+    }
+}
+
+/// An open state file, with the syncs it records.
+#[derive(Debug)]
+pub struct StateFile {
+    path: PathBuf,
+    /// Opened for appending, so that the daemon can still write to it once privileges are
+    /// dropped.
+    file: File,
+    /// The syncs recorded, oldest first.
+    records: Vec<SyncRecord>,
+}
+
+impl StateFile {
+    /// Opens the file at `path`, creating it if needed, and loads the syncs it records, dropping
+    /// those issued more than `RETENTION` before `now`.
+    pub fn open(path: &Path, now: Time) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .mode(0o644)
+            .open(path)
+            .with_context(|| format!("failed to open the state file {}", path.display()))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| format!("failed to read the state file {}", path.display()))?;
+        let mut records = Vec::new();
+        for line in contents.lines() {
+            match SyncRecord::parse(line) {
+                Some(record) => records.push(record),
+                // Likely written partially by a crash, and not worth refusing to start over.
+                None => warn!(
+                    "Ignoring an invalid line in the state file {}: {line:?}",
+                    path.display()
+                ),
+            }
+        }
+        let mut state = StateFile {
+            path: path.to_path_buf(),
+            file,
+            records,
+        };
+        state.compact(now)?;
+        Ok(state)
+    }
+
+    /// Returns the syncs recorded, oldest first.
+    pub fn records(&self) -> &[SyncRecord] {
+        &self.records
+    }
+
+    /// Records `record`, compacting the file once it keeps as many syncs past the retention
+    /// window as within it.
+    pub fn append(&mut self, record: SyncRecord) -> Result<()> {
+        self.records.push(record);
+        let oldest = self.records.first().map_or(record.at, |r| r.at);
+        if record.at.signed_duration_since(oldest) > RETENTION * 2 {
+            return self.compact(record.at);
+        }
+        self.file
+            .write_all(record.line().as_bytes())
+            .with_context(|| format!("failed to write the state file {}", self.path.display()))
+    }
+
+    /// Rewrites the file with only the syncs issued within `RETENTION` of `now`.
+    fn compact(&mut self, now: Time) -> Result<()> {
+        self.records
+            .retain(|r| now.signed_duration_since(r.at) <= RETENTION);
+        let contents: String = self.records.iter().map(SyncRecord::line).collect();
+        // Appends go to the end of the file, which is the start again once truncated.
+        self.file
+            .set_len(0)
+            .and_then(|()| self.file.write_all(contents.as_bytes()))
+            .with_context(|| format!("failed to rewrite the state file {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("stuck_wbs_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_records_survive_reopening() {
+        let path = temp_path("reopened.state");
+        let _ = std::fs::remove_file(&path);
+        let now = chrono::Utc::now();
+        let record = |ago| SyncRecord {
+            at: now - ago,
+            kworker: (1000, now - chrono::Duration::hours(3)),
+        };
+
+        let mut state = StateFile::open(&path, now).unwrap();
+        assert_eq!(state.records(), &[]);
+        state.append(record(RETENTION * 2)).unwrap();
+        state.append(record(chrono::Duration::minutes(5))).unwrap();
+        drop(state);
+        // Times round trip to the nanosecond.
+        let reopened = StateFile::open(&path, now).unwrap();
+        assert_eq!(reopened.records(), &[record(chrono::Duration::minutes(5))]);
+        drop(reopened);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .and_then(|mut f| f.write_all(b"2026-10-14T12:00:00+00:00 10"))
+            .unwrap();
+        let truncated = StateFile::open(&path, now).unwrap();
+        assert_eq!(truncated.records().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_appending_compacts_old_records() {
+        let path = temp_path("compacted.state");
+        let _ = std::fs::remove_file(&path);
+        let now = chrono::Utc::now();
+        let record = |at| SyncRecord {
+            at,
+            kworker: (1000, now),
+        };
+
+        let mut state = StateFile::open(&path, now).unwrap();
+        state.append(record(now)).unwrap();
+        state
+            .append(record(now + RETENTION + chrono::Duration::minutes(1)))
+            .unwrap();
+        assert_eq!(state.records().len(), 2);
+        state
+            .append(record(now + RETENTION * 2 + chrono::Duration::seconds(1)))
+            .unwrap();
+        assert_eq!(state.records().len(), 2);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+//! The operator-facing summary of what the daemon is currently doing.
+
+/// What the daemon is currently doing, from least to most concerning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// No matching kworkers.
+    Idle,
+    /// Matching kworkers are present but below the threshold.
+    Watching,
+    /// A remediation was just triggered, the system is given time to recover.
+    Remediating,
+    /// The daemon cannot do its job, e.g. because scanning processes fails or remediations are
+    /// reported ineffective.
+    Degraded,
+}
+
+impl Status {
+    /// Every status, in the order of their numeric value.
+    pub const ALL: [Status; 4] = [
+        Status::Idle,
+        Status::Watching,
+        Status::Remediating,
+        Status::Degraded,
+    ];
+
+    /// Returns the lowercase name of the status, as used in metrics labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Idle => "idle",
+            Status::Watching => "watching",
+            Status::Remediating => "remediating",
+            Status::Degraded => "degraded",
+        }
+    }
+}
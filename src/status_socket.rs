@@ -0,0 +1,115 @@
+//! `--status-socket`, answering every connection to a Unix socket with a JSON snapshot of what the
+//! daemon is doing, for local inspection with e.g. `socat - UNIX-CONNECT:<path>`, without opening
+//! a TCP port.
+use crate::metrics::Metrics;
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a client may take to read a snapshot, so a stalled one can't hold up the next.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A bound status socket, served until released.
+#[derive(Debug)]
+pub struct StatusSocket {
+    path: PathBuf,
+}
+
+impl StatusSocket {
+    /// Binds `path` and serves snapshots of `metrics` from a background thread. A socket left
+    /// behind by a crash is replaced, while one still listened to is an error.
+    pub fn spawn(path: &Path, metrics: Arc<Metrics>) -> Result<Self> {
+        let is_socket = std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket());
+        if is_socket {
+            if UnixStream::connect(path).is_ok() {
+                bail!("another instance serves its status at {}", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove the stale {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to listen on {}", path.display()))?;
+        std::thread::Builder::new()
+            .name("status-socket".to_string())
+            .spawn(move || serve(&listener, &metrics))
+            .context("failed to start the status socket")?;
+        Ok(StatusSocket {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Removes the socket, so that clients don't connect to a daemon that is gone.
+    pub fn release(self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!(
+                "Failed to remove the status socket {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Answers connections one at a time, as each only takes writing a line.
+fn serve(listener: &UnixListener, metrics: &Metrics) {
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| handle(stream, metrics));
+        if let Err(e) = result {
+            debug!("Failed to serve the status: {e}");
+        }
+    }
+}
+
+fn handle(mut stream: UnixStream, metrics: &Metrics) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    // The snapshot only holds strings and numbers, which always serialize.
+    let json = serde_json::to_string(&metrics.snapshot()).expect("failed to serialize snapshot");
+    writeln!(stream, "{json}")?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::Status;
+    use chrono::TimeZone;
+    use std::io::Read;
+
+    #[test]
+    fn test_serves_snapshots_until_released() {
+        let path = std::env::temp_dir().join(format!("stuck_wbs_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let metrics = Arc::new(Metrics::default());
+        metrics.record_kworkers(2, Some(chrono::Duration::milliseconds(90_500)));
+        metrics.set_status(Status::Watching);
+
+        let socket = StatusSocket::spawn(&path, Arc::clone(&metrics)).unwrap();
+        let read = || {
+            let mut out = String::new();
+            let mut stream = UnixStream::connect(&path).unwrap();
+            stream.read_to_string(&mut out).unwrap();
+            out
+        };
+        assert_eq!(
+            read(),
+            "{\"status\":\"watching\",\"matching_kworkers\":2,\
+             \"oldest_kworker_runtime_seconds\":90.5,\"syncs_total\":0,\"last_sync\":null,\
+             \"oldest_kworker_runtime_percentiles\":{\"p50\":90.0,\"p90\":114.0,\"p99\":119.4}}\n"
+        );
+        // Snapshots follow what the loop records.
+        let at = chrono::Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        metrics.record_sync(2, at);
+        let snapshot: serde_json::Value = serde_json::from_str(&read()).unwrap();
+        assert_eq!(snapshot["syncs_total"], 1);
+        assert_eq!(snapshot["last_sync"], crate::clock::local(at).to_rfc3339());
+
+        let error = StatusSocket::spawn(&path, Arc::clone(&metrics)).unwrap_err();
+        assert!(error.to_string().contains("another instance"), "{error:#}");
+        socket.release();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,259 @@
+//! `--supervise` mode, where a minimal parent process runs the real monitor as a child and
+//! restarts it if it dies or stops sending heartbeats.
+use crate::duration::format_duration;
+use crate::EXIT_MAX_SYNCS;
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use rustix::io::FdFlags;
+use rustix::pipe::{pipe_with, PipeFlags};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{Command, ExitStatus};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// The flag enabling this mode, dropped from the child's arguments.
+const FLAG: &str = "--supervise";
+
+/// Tells the child which inherited file descriptor to send heartbeats to.
+const HEARTBEAT_FD_ENV: &str = "STUCK_WBS_HEARTBEAT_FD";
+
+/// How long the child may go without a heartbeat before it is considered stuck. Heartbeats are
+/// sent during waits too, however long they are configured to be, so this only needs to outlast
+/// the work in between, such as a slow scan.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// How often the child sends heartbeats while waiting, well within `HEARTBEAT_TIMEOUT`.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The delay before the first restart, doubled on each consecutive failure.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay between restarts.
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// A child running for this long is considered healthy, resetting the backoff.
+const STABLE_UPTIME: Duration = Duration::from_secs(10 * 60);
+
+/// How a child stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChildOutcome {
+    /// The child exited on its own, or was killed by someone else.
+    Exited(ExitStatus),
+    /// The child stopped sending heartbeats and was killed.
+    Unresponsive,
+}
+
+impl std::fmt::Display for ChildOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChildOutcome::Exited(status) => write!(f, "{status}"),
+            ChildOutcome::Unresponsive => f.write_str("unresponsive"),
+        }
+    }
+}
+
+/// What the supervisor does after a child stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    /// Stop supervising with this exit status, as the child exited cleanly or reached
+    /// `--max-syncs`, which a restart would only start over.
+    Exit(u8),
+    /// Start a new child after this delay.
+    Restart(Duration),
+}
+
+/// The exponential backoff between restarts of a failing child.
+#[derive(Debug, Default)]
+struct Backoff {
+    /// How many times in a row the child failed without running for `STABLE_UPTIME`.
+    failures: u32,
+}
+
+impl Backoff {
+    /// Decides what to do about a child that stopped with `outcome` after running for `uptime`.
+    fn decide(&mut self, outcome: ChildOutcome, uptime: Duration) -> Decision {
+        if let ChildOutcome::Exited(status) = outcome {
+            if status.success() {
+                return Decision::Exit(0);
+            }
+            if status.code() == Some(i32::from(EXIT_MAX_SYNCS)) {
+                return Decision::Exit(EXIT_MAX_SYNCS);
+            }
+        }
+        if uptime >= STABLE_UPTIME {
+            self.failures = 0;
+        }
+        let delay = INITIAL_BACKOFF
+            .saturating_mul(2u32.saturating_pow(self.failures))
+            .min(MAX_BACKOFF);
+        self.failures = self.failures.saturating_add(1);
+        Decision::Restart(delay)
+    }
+}
+
+/// Runs `exe` with `args` until it exits or stops sending heartbeats.
+fn run_child(exe: &std::path::Path, args: &[OsString]) -> Result<ChildOutcome> {
+    let (reader, writer) =
+        pipe_with(PipeFlags::CLOEXEC).context("failed to create the heartbeat pipe")?;
+    // Unlike the original, the duplicate is inherited by the child.
+    let inherited = rustix::io::dup(&writer).context("failed to duplicate the heartbeat pipe")?;
+    drop(writer);
+    let mut child = Command::new(exe)
+        .args(args)
+        .env(HEARTBEAT_FD_ENV, inherited.as_raw_fd().to_string())
+        .spawn()
+        .with_context(|| format!("failed to start {}", exe.display()))?;
+    // So that the pipe reaches EOF once the child exits.
+    drop(inherited);
+    info!("Started the monitor as pid {}", child.id());
+
+    let (beats, heartbeats) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut reader = File::from(reader);
+        let mut buf = [0u8; 64];
+        while matches!(reader.read(&mut buf), Ok(n) if n > 0) {
+            if beats.send(()).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        match heartbeats.recv_timeout(HEARTBEAT_TIMEOUT) {
+            Ok(()) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let status = child.wait().context("failed to wait for the monitor")?;
+                return Ok(ChildOutcome::Exited(status));
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                error!(
+                    "The monitor sent no heartbeat for {}, killing it",
+                    format_duration(HEARTBEAT_TIMEOUT)
+                );
+                // It may have exited in the meantime, in which case there is nothing to kill.
+                let _ = child.kill();
+                child.wait().context("failed to wait for the monitor")?;
+                return Ok(ChildOutcome::Unresponsive);
+            }
+        }
+    }
+}
+
+/// Runs the monitor as a child of this process, with the same arguments but `FLAG`, restarting
+/// it with backoff whenever it fails. Returns the exit status of the child once it exits cleanly
+/// or reaches `--max-syncs`.
+pub fn supervise() -> Result<u8> {
+    let exe = std::env::current_exe().context("failed to find the current executable")?;
+    let args: Vec<OsString> = std::env::args_os()
+        .skip(1)
+        .filter(|arg| arg != FLAG)
+        .collect();
+    let mut backoff = Backoff::default();
+    loop {
+        let started = Instant::now();
+        let outcome = run_child(&exe, &args)?;
+        match backoff.decide(outcome, started.elapsed()) {
+            Decision::Exit(code) => return Ok(code),
+            Decision::Restart(delay) => {
+                warn!(
+                    "The monitor stopped ({outcome}), restarting it in {}",
+                    format_duration(delay)
+                );
+                std::thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// The child's end of the heartbeat pipe.
+pub struct Heartbeat(pub(crate) File);
+
+impl Heartbeat {
+    /// Returns the heartbeat pipe if this process runs under `--supervise`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(fd) = std::env::var_os(HEARTBEAT_FD_ENV) else {
+            return Ok(None);
+        };
+        // Commands the monitor runs have no business with it.
+        std::env::remove_var(HEARTBEAT_FD_ENV);
+        let fd: i32 = fd
+            .to_str()
+            .and_then(|fd| fd.parse().ok())
+            .with_context(|| format!("invalid {HEARTBEAT_FD_ENV}: {fd:?}"))?;
+        // SAFETY: the supervisor passes a file descriptor it opened for us, which nothing else
+        // in this process owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        rustix::io::fcntl_setfd(&fd, FdFlags::CLOEXEC)
+            .context("failed to make the heartbeat pipe close-on-exec")?;
+        Ok(Some(Heartbeat(File::from(fd))))
+    }
+
+    /// Tells the supervisor this process is alive.
+    pub fn beat(&self) {
+        if let Err(e) = (&self.0).write_all(b".") {
+            warn!("Failed to send heartbeat to the supervisor: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ChildOutcome {
+        ChildOutcome::Exited(ExitStatus::from_raw(code << 8))
+    }
+
+    #[test]
+    fn test_clean_exit_is_not_restarted() {
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.decide(exited(0), Duration::ZERO), Decision::Exit(0));
+        assert_eq!(
+            backoff.decide(exited(EXIT_MAX_SYNCS.into()), Duration::ZERO),
+            Decision::Exit(EXIT_MAX_SYNCS)
+        );
+    }
+
+    #[test]
+    fn test_failures_back_off_exponentially() {
+        let mut backoff = Backoff::default();
+        let delays: Vec<Decision> = [exited(1), ChildOutcome::Unresponsive, exited(101)]
+            .into_iter()
+            .map(|outcome| backoff.decide(outcome, Duration::from_secs(5)))
+            .collect();
+        assert_eq!(
+            delays,
+            [1, 2, 4].map(|s| Decision::Restart(Duration::from_secs(s)))
+        );
+
+        // Killed by a signal.
+        let killed = ChildOutcome::Exited(ExitStatus::from_raw(9));
+        for _ in 0..20 {
+            backoff.decide(killed, Duration::ZERO);
+        }
+        assert_eq!(
+            backoff.decide(killed, Duration::ZERO),
+            Decision::Restart(MAX_BACKOFF)
+        );
+    }
+
+    #[test]
+    fn test_stable_uptime_resets_backoff() {
+        let mut backoff = Backoff::default();
+        for _ in 0..5 {
+            backoff.decide(exited(1), Duration::ZERO);
+        }
+        assert_eq!(
+            backoff.decide(exited(1), STABLE_UPTIME),
+            Decision::Restart(INITIAL_BACKOFF)
+        );
+        assert_eq!(
+            backoff.decide(exited(1), Duration::ZERO),
+            Decision::Restart(INITIAL_BACKOFF * 2)
+        );
+    }
+}
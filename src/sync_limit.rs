@@ -0,0 +1,72 @@
+//! `--max-syncs`, a safety valve making the daemon exit once it synced this many times, so that an
+//! orchestrator drains or replaces the node rather than the workaround masking an escalating
+//! problem.
+use crate::action::Action;
+use crate::system::SyncTimedOut;
+use crate::Outcome;
+
+/// Counts the syncs issued by the main loop towards `--max-syncs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncLimit {
+    max: u64,
+    syncs: u64,
+}
+
+impl SyncLimit {
+    pub fn new(max: u64) -> Self {
+        SyncLimit { max, syncs: 0 }
+    }
+
+    /// Counts the sync the iteration that ended with `result` issued, or would have on a dry run
+    /// or outside the canary, returning whether it reached the limit. A sync that timed out was
+    /// issued, while one refused as an earlier one is still blocked was not.
+    pub fn record(&mut self, result: &anyhow::Result<Outcome>) -> bool {
+        let synced = match result {
+            Ok(outcome) => matches!(
+                outcome,
+                Outcome::Remediated(Action::Sync) | Outcome::Reported(Action::Sync)
+            ),
+            Err(e) => e.is::<SyncTimedOut>(),
+        };
+        if synced {
+            self.syncs += 1;
+        }
+        synced && self.syncs >= self.max
+    }
+
+    /// Returns how many syncs were counted.
+    pub fn syncs(&self) -> u64 {
+        self.syncs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_is_reached_after_as_many_syncs() {
+        let mut limit = SyncLimit::new(3);
+        let signal = Action::Signal(rustix::process::Signal::KILL);
+        let outcomes = [
+            Outcome::Remediated(Action::Sync),
+            Outcome::BelowThreshold,
+            Outcome::Deferred,
+            Outcome::Remediated(signal),
+            Outcome::Reported(signal),
+            // Dry runs count the syncs they would have issued.
+            Outcome::Reported(Action::Sync),
+            Outcome::NoKworker,
+            // No sync was started.
+            Outcome::SyncBlocked,
+        ];
+        for outcome in outcomes {
+            assert!(!limit.record(&Ok(outcome)), "{outcome:?}");
+        }
+        assert!(!limit.record(&Err(anyhow::anyhow!("the action command failed"))));
+        assert_eq!(limit.syncs(), 2);
+        let timed_out = anyhow::Error::new(SyncTimedOut(std::time::Duration::from_secs(30)))
+            .context("failed to run sync");
+        assert!(limit.record(&Err(timed_out)));
+    }
+}
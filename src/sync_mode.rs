@@ -0,0 +1,148 @@
+//! `--sync-mode`, which can narrow the remediation `sync` down to the filesystem the stuck
+//! kworker writes back, sparing the other disks the latency spike of a global flush.
+use std::path::PathBuf;
+
+/// Where the kernel lists the mounts visible to this process.
+pub const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// What a `sync` action flushes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Every mounted filesystem, with `sync()`.
+    #[default]
+    Global,
+    /// Only the filesystem of the stuck kworker, with `syncfs()`, when it can be determined.
+    Filesystem,
+}
+
+impl SyncMode {
+    pub fn is_global(&self) -> bool {
+        *self == SyncMode::Global
+    }
+}
+
+impl std::fmt::Display for SyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SyncMode::Global => "global",
+            SyncMode::Filesystem => "fs",
+        })
+    }
+}
+
+impl std::str::FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "global" => Ok(SyncMode::Global),
+            "fs" => Ok(SyncMode::Filesystem),
+            _ => Err(format!(
+                "invalid sync mode '{s}', expected 'global' or 'fs'"
+            )),
+        }
+    }
+}
+
+/// Serialized as on the command line, e.g. "fs".
+impl serde::Serialize for SyncMode {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+/// Returns the device a kworker is flushing, from the `flush-<MAJOR>:<MINOR>` description the
+/// kernel appends to the comm of writeback workers, e.g. "kworker/u16:1+flush-259:0".
+///
+/// Other kworkers, `inode_switch_wbs` ones included, don't name their device.
+pub fn flush_device(comm: &str) -> Option<(u32, u32)> {
+    let (_, device) = comm.split_once("+flush-")?;
+    let (major, minor) = device.split_once(':')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Undoes the octal escapes of spaces and the like in `/proc/<pid>/mountinfo` paths.
+fn unescape(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        let code = rest
+            .get(i + 1..i + 4)
+            .and_then(|c| u8::from_str_radix(c, 8).ok());
+        match code {
+            Some(byte) => {
+                out.push(char::from(byte));
+                rest = &rest[i + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns where `device` is mounted according to `mountinfo`, preferring a mount of the
+/// filesystem's root over bind mounts of a subdirectory.
+pub fn mount_point(mountinfo: &str, device: (u32, u32)) -> Option<PathBuf> {
+    let wanted = format!("{}:{}", device.0, device.1);
+    let mounts: Vec<(&str, &str)> = mountinfo
+        .lines()
+        .filter_map(|line| {
+            // Mount ID, parent ID, device, root within the filesystem, mount point, ...
+            let mut fields = line.split(' ').skip(2);
+            let (dev, root, mount_point) = (fields.next()?, fields.next()?, fields.next()?);
+            (dev == wanted).then_some((root, mount_point))
+        })
+        .collect();
+    let (_, mount_point) = mounts
+        .iter()
+        .find(|(root, _)| *root == "/")
+        .or(mounts.first())?;
+    Some(PathBuf::from(unescape(mount_point)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sync_mode() {
+        assert_eq!("global".parse(), Ok(SyncMode::Global));
+        assert_eq!("fs".parse(), Ok(SyncMode::Filesystem));
+        assert!("syncfs".parse::<SyncMode>().is_err());
+        assert_eq!(SyncMode::Filesystem.to_string(), "fs");
+    }
+
+    #[test]
+    fn test_flush_device() {
+        assert_eq!(flush_device("kworker/u16:1+flush-259:0"), Some((259, 0)));
+        assert_eq!(flush_device("kworker/u8:0+flush-8:16"), Some((8, 16)));
+        assert_eq!(flush_device("kworker/u8:2+inode_switch_wbs"), None);
+        assert_eq!(flush_device("kworker/u8:0+flush-btrfs-1"), None);
+        assert_eq!(flush_device("kworker/0:1"), None);
+    }
+
+    #[test]
+    fn test_mount_point() {
+        let mountinfo = "\
+            22 1 0:21 / /proc rw,nosuid shared:12 - proc proc rw\n\
+            29 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw\n\
+            40 29 259:3 /containers /var/lib/containers rw shared:20 - xfs /dev/nvme0n1p3 rw\n\
+            41 29 259:3 / /mnt/my\\040data rw shared:21 - xfs /dev/nvme0n1p3 rw\n\
+            42 29 259:4 /sub /srv rw shared:22 - xfs /dev/nvme0n1p4 rw\n";
+        assert_eq!(mount_point(mountinfo, (259, 2)), Some(PathBuf::from("/")));
+        assert_eq!(
+            mount_point(mountinfo, (259, 3)),
+            Some(PathBuf::from("/mnt/my data"))
+        );
+        assert_eq!(
+            mount_point(mountinfo, (259, 4)),
+            Some(PathBuf::from("/srv"))
+        );
+        assert_eq!(mount_point(mountinfo, (8, 0)), None);
+    }
+}
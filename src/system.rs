@@ -1,19 +1,106 @@
 //! Provides abstractions for system interactions, allowing for easier testing and mocking.
-use anyhow::{Context, Result};
-use cnproc::{PidEvent, PidMonitor};
-use log::debug;
-use procfs::process::{all_processes, Process};
-use procfs::WithCurrentSystemInfo;
+use crate::cgroup;
+use crate::clock::BootClock;
+use crate::duration::{format_duration, to_chrono};
+use crate::events::{self, ConnectorEvents};
+use crate::fs_status::FsStatus;
+use crate::ioprio::{run_with_ioprio, IoPrioClass};
+use crate::keepalive::KeepAlive;
+use crate::prefilter::CommPrefilter;
+use crate::sync_mode;
+use anyhow::{anyhow, bail, Context, Result};
+use cnproc::PidMonitor;
+use log::{debug, warn};
+use procfs::process::{all_processes_with_root, Process, StatFlags};
+use procfs::Current;
+use rustix::fs::{Mode, OFlags};
+use rustix::process::{kill_process, Pid, Signal};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+
+/// Where procfs is mounted, which processes are read from by default.
+pub const DEFAULT_PROCFS_ROOT: &str = "/proc";
+
+/// How long a `sync` may block before the daemon stops waiting for it, by default.
+pub const DEFAULT_SYNC_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 /// Contains essential information about a process for the purpose of this tool.
 #[derive(Debug, Clone)]
 pub struct ProcInfo {
+    /// The process ID.
+    pub pid: i32,
     /// The user ID of the process.
     pub uid: u32,
     /// The time the process started.
-    pub starttime: chrono::DateTime<chrono::Local>,
+    pub starttime: chrono::DateTime<chrono::Utc>,
+    /// The CPU time the process consumed so far, in user and kernel mode.
+    pub cpu_time: std::time::Duration,
     /// The command associated with the process.
     pub comm: String,
+    /// The full command line, space-separated. Only read when cmdline matching is enabled, and
+    /// `None` for kernel threads, which have an empty command line.
+    pub cmdline: Option<String>,
+    /// Whether this is a kernel thread, which cannot be signaled.
+    pub kernel_thread: bool,
+    /// The state from `/proc/<pid>/stat`, e.g. 'R' for running or 'D' for uninterruptible sleep.
+    pub state: char,
+    /// The kernel function the process is blocked in, from `/proc/<pid>/wchan`. Only read when
+    /// wchan matching is enabled, and `None` when unknown.
+    pub wchan: Option<String>,
+}
+
+/// Why a scan left a process out of its results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Not matched by any glob, or not owned by root.
+    NotMonitored,
+    /// Exited during the scan, or could not be read for another reason.
+    Unreadable,
+    /// In a frozen cgroup, where it would look stuck without being so.
+    FrozenCgroup,
+    /// A candidate past `--max-examined`, left unread.
+    NotExamined,
+}
+
+impl SkipReason {
+    /// Every reason, in the order of their numeric value.
+    pub const ALL: [SkipReason; 4] = [
+        SkipReason::NotMonitored,
+        SkipReason::Unreadable,
+        SkipReason::FrozenCgroup,
+        SkipReason::NotExamined,
+    ];
+
+    /// Returns the snake_case name of the reason, as used in metrics labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SkipReason::NotMonitored => "not_monitored",
+            SkipReason::Unreadable => "unreadable",
+            SkipReason::FrozenCgroup => "frozen_cgroup",
+            SkipReason::NotExamined => "not_examined",
+        }
+    }
+}
+
+/// A process that a scan left out of its results.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Skipped {
+    pub pid: i32,
+    /// The command associated with the process, if it could be read.
+    pub comm: Option<String>,
+    pub reason: SkipReason,
+}
+
+/// The outcome of a process scan.
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// The processes matching the predicate.
+    pub kworkers: Vec<ProcInfo>,
+    /// Every other process, and why it was left out.
+    pub skipped: Vec<Skipped>,
 }
 
 /// A predicate used to identify `kworker` processes that should be monitored.
@@ -28,97 +115,950 @@ impl<T: Fn(&ProcInfo) -> bool> IsKworkerFn for T {}
 /// This trait allows for a mock implementation to be used during testing, isolating the core
 /// logic from actual system calls.
 pub trait System {
-    /// Finds the oldest running process that matches the given predicate.
-    fn find_oldest_kworker<F: IsKworkerFn>(&self, is_kworker: F) -> Result<Option<ProcInfo>>;
+    /// Finds every running process that matches the given predicate.
+    ///
+    /// Processes whose comm `prefilter` rejects may be skipped without reading anything else
+    /// about them.
+    fn find_all_kworkers<F: IsKworkerFn>(
+        &self,
+        prefilter: &CommPrefilter,
+        is_kworker: F,
+    ) -> Result<Scan>;
+    /// Like `find_all_kworkers`, for the processes `pids` only, leaving out those that exited.
+    fn refresh_kworkers<F: IsKworkerFn>(
+        &self,
+        pids: &[i32],
+        prefilter: &CommPrefilter,
+        is_kworker: F,
+    ) -> Result<Scan> {
+        self.find_all_kworkers(prefilter, |p: &ProcInfo| {
+            pids.contains(&p.pid) && is_kworker(p)
+        })
+    }
     /// Returns the current system time.
-    fn now(&self) -> chrono::DateTime<chrono::Local>;
-    /// Blocks until a new `kworker` process appears or a timeout occurs.
+    fn now(&self) -> chrono::DateTime<chrono::Utc>;
+    /// Returns how long the system has been up, including time spent suspended.
+    fn uptime(&self) -> Result<chrono::Duration>;
+    /// Blocks until a new `kworker` process appears or a timeout occurs, returning the process if
+    /// it is known, and passing the pid of every process seen exiting meanwhile to `exited`.
     ///
     /// This method uses the `cnproc` kernel connector to avoid busy-polling, which is more
     /// efficient. The `timeout` ensures that even on a busy system where kernel events might be
     /// missed, a full process scan is periodically performed.
-    fn wait_for_kworker<F: IsKworkerFn>(
+    fn wait_for_kworker<F: IsKworkerFn, X: FnMut(i32)>(
         &self,
         is_kworker: F,
+        exited: X,
         timeout: std::time::Duration,
-    ) -> Result<()>;
+    ) -> Result<Option<ProcInfo>>;
     /// Triggers a system-wide `sync` to flush filesystem buffers.
-    fn sync(&self);
+    fn sync(&self) -> Result<()>;
+    /// Flushes the buffers of the filesystem mounted at `mount` only, with `syncfs`.
+    fn sync_fs(&self, mount: &Path) -> Result<()>;
+    /// Returns where the block device `device`, as a major and minor number, is mounted.
+    fn mount_of(&self, device: (u32, u32)) -> Result<Option<PathBuf>>;
+    /// Returns the free space and state of the filesystem `path` is on.
+    fn fs_status(&self, path: &Path) -> Result<FsStatus>;
+    /// Sends `signal` to the process `pid`.
+    fn signal(&self, pid: i32, signal: Signal) -> Result<()>;
+    /// Returns how much CPU time the process `pid` consumes over the next `interval`.
+    fn cpu_time_over(&self, pid: i32, interval: std::time::Duration)
+        -> Result<std::time::Duration>;
+    /// Returns the kernel stack of the process `pid`, as in `/proc/<pid>/stack`.
+    fn stack(&self, pid: i32) -> Result<String>;
+    /// Runs `command` through the shell, returning whether it exited successfully.
+    ///
+    /// A command still running after `timeout` is killed and counts as unsuccessful.
+    fn run_command(&self, command: &str, timeout: std::time::Duration) -> Result<bool>;
+    /// Requests an emergency sync of every filesystem from the kernel, with the magic SysRq `s`
+    /// command. It returns right away, the kernel flushing asynchronously.
+    fn emergency_sync(&self) -> Result<()>;
 }
 
 /// The production implementation of the `System` trait, interacting with the live system.
-pub struct LiveSystem;
-
-fn to_proc_info(p: Process) -> Result<ProcInfo> {
-    let stat = p.stat().context("failed to read process stat")?;
-    let uid = p.uid().context("failed to read process uid")?;
-    let starttime = stat
-        .starttime()
-        .get()
-        .context("failed to get process start time")?;
-    Ok(ProcInfo {
-        uid,
-        comm: stat.comm,
-        starttime,
+pub struct LiveSystem {
+    /// Whether to also read `/proc/<pid>/cmdline`, for matching userspace targets.
+    pub read_cmdline: bool,
+    /// Whether to also read `/proc/<pid>/wchan`, for checking where processes are blocked.
+    pub read_wchan: bool,
+    /// If set, `sync` runs on a dedicated thread with this I/O priority.
+    pub sync_ioprio: Option<IoPrioClass>,
+    /// How long to wait for a `sync` before giving up on it and resuming monitoring.
+    pub sync_timeout: std::time::Duration,
+    /// Where `sync` and `sync_fs` run, none starting while one that timed out is still blocked.
+    pub sync_runner: SyncRunner,
+    /// If set, scans stop after this long and only consider the processes read so far.
+    pub scan_budget: Option<std::time::Duration>,
+    /// If set, scans read at most this many candidates in full, leaving the others out.
+    pub max_examined: Option<usize>,
+    /// Whether `wait_for_kworker` only sleeps until the next scan, rather than waiting on process
+    /// events. Set once they fail to be listened to.
+    pub poll_only: AtomicBool,
+    /// The connection `wait_for_kworker` waits on process events with, kept across waits, which
+    /// each discard the events received before they started.
+    pub process_events: Mutex<Option<ConnectorEvents>>,
+    /// Pings during waits that may outlast what watches over the daemon, such as on syncs and
+    /// commands.
+    pub keepalive: Arc<KeepAlive>,
+    /// What `now` and process start times are read on.
+    pub clock: BootClock,
+    /// Where processes are read from: usually `DEFAULT_PROCFS_ROOT`, but possibly the host's
+    /// procfs mounted into a container, or a fixture.
+    pub procfs_root: PathBuf,
+}
+
+/// Converts `ticks` of CPU time, at `ticks_per_second`, to a duration.
+fn cpu_time(ticks: u64, ticks_per_second: u64) -> std::time::Duration {
+    std::time::Duration::from_secs(ticks / ticks_per_second)
+        + std::time::Duration::from_secs(ticks % ticks_per_second) / ticks_per_second as u32
+}
+
+/// Returns the error for failing to listen to process events, with how to remedy it.
+fn process_events_error(e: std::io::Error) -> anyhow::Error {
+    let hint = if e.kind() == std::io::ErrorKind::PermissionDenied {
+        "needs CAP_NET_ADMIN for process events; rerun as root or use --no-netlink"
+    } else {
+        "the kernel may lack CONFIG_PROC_EVENTS; use --no-netlink to poll for new kworkers"
+    };
+    anyhow::Error::new(e).context(format!("failed to listen to process events ({hint})"))
+}
+
+/// Returns whether `p`, read from the procfs at `procfs_root`, is in a frozen cgroup, where it
+/// would look stuck without being so.
+fn in_frozen_cgroup(procfs_root: &Path, p: &ProcInfo) -> bool {
+    // Kernel threads cannot be frozen through cgroups.
+    if p.kernel_thread {
+        return false;
+    }
+    match cgroup::in_frozen_cgroup(procfs_root, p.pid) {
+        Ok(true) => {
+            debug!(
+                "Ignoring '{}' (pid {}), its cgroup is frozen",
+                p.comm, p.pid
+            );
+            true
+        }
+        Ok(false) => false,
+        Err(e) => {
+            debug!("Assuming '{}' (pid {}) is not frozen: {e:#}", p.comm, p.pid);
+            false
+        }
+    }
+}
+
+/// Decides what a scan makes of the process `pid`, given its `comm` if it was read beforehand.
+///
+/// The process is only read in full, with `read`, if `prefilter` accepts its comm and the scan is
+/// not `capped`, and only checked for a `frozen` cgroup if `is_kworker` matches it.
+fn examine<F: IsKworkerFn>(
+    pid: i32,
+    comm: Option<String>,
+    prefilter: &CommPrefilter,
+    capped: bool,
+    read: impl FnOnce() -> Result<ProcInfo>,
+    is_kworker: &F,
+    frozen: impl FnOnce(&ProcInfo) -> bool,
+) -> std::result::Result<ProcInfo, Skipped> {
+    let skipped = |comm, reason| Skipped { pid, comm, reason };
+    if comm
+        .as_deref()
+        .is_some_and(|comm| !prefilter.may_match(comm))
+    {
+        return Err(skipped(comm, SkipReason::NotMonitored));
+    }
+    if capped {
+        return Err(skipped(comm, SkipReason::NotExamined));
+    }
+    let Ok(info) = read() else {
+        return Err(skipped(comm, SkipReason::Unreadable));
+    };
+    if !is_kworker(&info) {
+        return Err(skipped(Some(info.comm), SkipReason::NotMonitored));
+    }
+    if frozen(&info) {
+        return Err(skipped(Some(info.comm), SkipReason::FrozenCgroup));
+    }
+    Ok(info)
+}
+
+/// Scans `processes`, given as their pid, their comm if it was read beforehand, and a function
+/// reading them in full, reading at most `max_examined` of them in full.
+///
+/// Processes are examined in the order given, which for `/proc` is by pid, so roughly oldest
+/// first.
+pub fn scan<F: IsKworkerFn, R: FnOnce() -> Result<ProcInfo>>(
+    processes: impl IntoIterator<Item = (i32, Option<String>, R)>,
+    prefilter: &CommPrefilter,
+    max_examined: Option<usize>,
+    is_kworker: F,
+    frozen: impl Fn(&ProcInfo) -> bool,
+) -> Scan {
+    let mut scan = Scan::default();
+    let mut examined = 0;
+    for (pid, comm, read) in processes {
+        let capped = max_examined.is_some_and(|max| examined >= max);
+        let read = || {
+            examined += 1;
+            read()
+        };
+        match examine(pid, comm, prefilter, capped, read, &is_kworker, &frozen) {
+            Ok(info) => scan.kworkers.push(info),
+            Err(skipped) => scan.skipped.push(skipped),
+        }
+    }
+    let capped = scan
+        .skipped
+        .iter()
+        .filter(|s| s.reason == SkipReason::NotExamined)
+        .count();
+    if capped > 0 {
+        warn!(
+            "Process scan examined its maximum of {examined} candidates, leaving {capped} out: \
+             results may be incomplete"
+        );
+    }
+    scan
+}
+
+/// Yields the items of `iter` until `elapsed()` reaches `budget`, if any.
+fn within_budget<I: Iterator>(
+    iter: I,
+    budget: Option<std::time::Duration>,
+    mut elapsed: impl FnMut() -> std::time::Duration,
+) -> impl Iterator<Item = I::Item> {
+    iter.take_while(move |_| match budget {
+        Some(budget) if elapsed() >= budget => {
+            warn!(
+                "Process scan exceeded its {} budget, truncating it",
+                format_duration(budget)
+            );
+            false
+        }
+        _ => true,
     })
 }
 
-impl System for LiveSystem {
-    fn find_oldest_kworker<F: IsKworkerFn>(&self, is_kworker: F) -> Result<Option<ProcInfo>> {
-        let processes = all_processes().context("failed to list all processes")?;
-        let oldest_kworker = processes
-            .filter_map(Result::ok)
-            .filter_map(|p| to_proc_info(p).ok())
-            .filter(is_kworker)
-            .min_by_key(|p| p.starttime);
-        Ok(oldest_kworker)
+/// A sync that was still blocked when its timeout expired.
+#[derive(Debug)]
+pub struct SyncTimedOut(pub std::time::Duration);
+
+impl std::fmt::Display for SyncTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sync still blocked after {}", format_duration(self.0))
     }
+}
+
+impl std::error::Error for SyncTimedOut {}
+
+/// A sync that wasn't started, as one that timed out earlier is still blocked.
+#[derive(Debug)]
+pub struct SyncStillBlocked;
 
-    fn now(&self) -> chrono::DateTime<chrono::Local> {
-        chrono::Local::now()
+impl std::fmt::Display for SyncStillBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "a previous sync is still blocked")
     }
+}
+
+impl std::error::Error for SyncStillBlocked {}
 
-    fn wait_for_kworker<F: IsKworkerFn>(
+/// Runs syncs on detached threads, one at a time.
+///
+/// Syncs can't be cancelled, so one that times out is left to finish on its own, if it ever does,
+/// while the caller carries on. Until it does, no other is started, so that retries don't pile up
+/// blocked threads.
+#[derive(Debug, Clone, Default)]
+pub struct SyncRunner {
+    /// Whether a sync thread is running.
+    in_flight: Arc<AtomicBool>,
+}
+
+impl SyncRunner {
+    /// Runs `f` on a detached thread, failing with `SyncTimedOut` if it doesn't return within
+    /// `timeout`, or with `SyncStillBlocked` right away if a previous one is still running.
+    /// `keepalive` pings meanwhile.
+    pub fn run<R: Send + 'static>(
         &self,
-        is_kworker: F,
         timeout: std::time::Duration,
-    ) -> Result<()> {
-        let mut monitor =
-            PidMonitor::new().context("failed to create process event monitor (cnproc)")?;
-        let start = std::time::Instant::now();
-        loop {
-            // On a busy system, the kernel may drop netlink events. To safeguard against this,
-            // we'll periodically re-scan the full process list.
-            if start.elapsed() >= timeout {
-                debug!("wait_for_kworker timed out after {timeout:?}, forcing a full process scan");
-                return Ok(());
+        keepalive: &KeepAlive,
+        f: impl FnOnce() -> R + Send + 'static,
+    ) -> Result<R> {
+        if self.in_flight.swap(true, Ordering::SeqCst) {
+            return Err(SyncStillBlocked.into());
+        }
+        let (sender, receiver) = mpsc::channel();
+        let in_flight = Arc::clone(&self.in_flight);
+        let spawned = std::thread::Builder::new()
+            .name("sync".to_string())
+            .spawn(move || {
+                let result = f();
+                in_flight.store(false, Ordering::SeqCst);
+                // The receiver is gone if the sync took too long, which was reported already.
+                let _ = sender.send(result);
+            });
+        if let Err(e) = spawned {
+            self.in_flight.store(false, Ordering::SeqCst);
+            return Err(e).context("failed to start the sync thread");
+        }
+        let received = keepalive.wait(timeout, |step| match receiver.recv_timeout(step) {
+            Ok(result) => Some(Ok(result)),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(Err(())),
+        });
+        match received {
+            Some(Ok(result)) => Ok(result),
+            None => Err(SyncTimedOut(timeout).into()),
+            Some(Err(())) => {
+                self.in_flight.store(false, Ordering::SeqCst);
+                Err(anyhow!("sync thread panicked"))
             }
+        }
+    }
+}
 
-            let event = monitor
-                .recv()
-                .context("failed to receive process event from kernel")?;
+impl LiveSystem {
+    /// Checks that process events can be listened to, as `wait_for_kworker` does. Past startup,
+    /// failing to do so only falls back to polling.
+    pub fn check_process_events(&self) -> Result<()> {
+        PidMonitor::new().map(drop).map_err(process_events_error)
+    }
 
-            let pid = match event {
-                PidEvent::Exec { process_pid, .. } => process_pid,
-                PidEvent::Fork { child_pid, .. } => child_pid,
-                _ => continue,
-            };
+    /// Checks that `emergency_sync` can work, as far as can be told without triggering it.
+    pub fn check_sysrq(&self) -> Result<()> {
+        let path = self.sysrq_trigger();
+        if !path.exists() {
+            bail!(
+                "{} doesn't exist, the kernel was built without CONFIG_MAGIC_SYSRQ",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn sysrq_trigger(&self) -> PathBuf {
+        self.procfs_root.join("sysrq-trigger")
+    }
+
+    /// Opens the process `pid` under `procfs_root`.
+    fn process(&self, pid: i32) -> procfs::ProcResult<Process> {
+        Process::new_with_root(self.procfs_root.join(pid.to_string()))
+    }
+
+    /// Returns the comm of `p`, for the prefilter, or `None` if it should not be applied.
+    ///
+    /// Reading the comm alone is much cheaper than `to_proc_info`, which matters as most scans
+    /// find no matching process at all.
+    fn read_comm(&self, p: &Process) -> Option<String> {
+        // Globs may match the command line instead, which the prefilter knows nothing about.
+        if self.read_cmdline {
+            return None;
+        }
+        // If it is gone already, `to_proc_info` reports it.
+        let path = self.procfs_root.join(p.pid().to_string()).join("comm");
+        let mut comm = std::fs::read_to_string(path).ok()?;
+        comm.truncate(comm.trim_end_matches('\n').len());
+        Some(comm)
+    }
 
-            if let Ok(proc) = Process::new(pid) {
-                if let Ok(info) = to_proc_info(proc) {
-                    if is_kworker(&info) {
-                        debug!(
-                            "Detected matching kworker (pid {}, comm: '{}'), returning",
-                            pid, info.comm
+    fn to_proc_info(&self, p: Process) -> Result<ProcInfo> {
+        let stat = p.stat().context("failed to read process stat")?;
+        let uid = p.uid().context("failed to read process uid")?;
+        let starttime = self
+            .clock
+            .start_time(stat.starttime, procfs::ticks_per_second());
+        let cpu_time = cpu_time(stat.utime + stat.stime, procfs::ticks_per_second());
+        let cmdline = if self.read_cmdline {
+            let args = p.cmdline().context("failed to read process cmdline")?;
+            Some(args.join(" ")).filter(|c| !c.is_empty())
+        } else {
+            None
+        };
+        let kernel_thread =
+            StatFlags::from_bits_truncate(stat.flags).contains(StatFlags::PF_KTHREAD);
+        // Running processes, and every process on kernels that hide it, have a wchan of "0".
+        let wchan = if self.read_wchan {
+            p.wchan().ok().filter(|w| !w.is_empty() && w != "0")
+        } else {
+            None
+        };
+        Ok(ProcInfo {
+            pid: stat.pid,
+            uid,
+            comm: stat.comm,
+            starttime,
+            cpu_time,
+            cmdline,
+            kernel_thread,
+            state: stat.state,
+            wchan,
+        })
+    }
+}
+
+impl System for LiveSystem {
+    fn find_all_kworkers<F: IsKworkerFn>(
+        &self,
+        prefilter: &CommPrefilter,
+        is_kworker: F,
+    ) -> Result<Scan> {
+        let processes =
+            all_processes_with_root(&self.procfs_root).context("failed to list all processes")?;
+        let start = std::time::Instant::now();
+        let processes = within_budget(processes, self.scan_budget, move || start.elapsed());
+        // Processes that could not even be opened are gone, with no pid to report them by.
+        let processes = processes.filter_map(Result::ok).map(|p| {
+            let comm = self.read_comm(&p);
+            (p.pid(), comm, move || self.to_proc_info(p))
+        });
+        Ok(scan(
+            processes,
+            prefilter,
+            self.max_examined,
+            is_kworker,
+            |p: &ProcInfo| in_frozen_cgroup(&self.procfs_root, p),
+        ))
+    }
+
+    fn refresh_kworkers<F: IsKworkerFn>(
+        &self,
+        pids: &[i32],
+        prefilter: &CommPrefilter,
+        is_kworker: F,
+    ) -> Result<Scan> {
+        // Exited processes can't be opened, and are gone with nothing to report.
+        let processes = pids
+            .iter()
+            .filter_map(|&pid| self.process(pid).ok())
+            .map(|p| {
+                let comm = self.read_comm(&p);
+                (p.pid(), comm, move || self.to_proc_info(p))
+            });
+        Ok(scan(
+            processes,
+            prefilter,
+            None,
+            is_kworker,
+            |p: &ProcInfo| in_frozen_cgroup(&self.procfs_root, p),
+        ))
+    }
+
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.clock.now()
+    }
+
+    fn uptime(&self) -> Result<chrono::Duration> {
+        let uptime = procfs::Uptime::current().context("failed to read uptime")?;
+        to_chrono(uptime.uptime_duration())
+    }
+
+    fn wait_for_kworker<F: IsKworkerFn, X: FnMut(i32)>(
+        &self,
+        is_kworker: F,
+        exited: X,
+        timeout: std::time::Duration,
+    ) -> Result<Option<ProcInfo>> {
+        if !self.poll_only.load(Ordering::Relaxed) {
+            let mut connection = self
+                .process_events
+                .lock()
+                .unwrap_or_else(|e| e.into_inner());
+            if connection.is_none() {
+                match ConnectorEvents::connect() {
+                    Ok(events) => *connection = Some(events),
+                    Err(e) => {
+                        warn!(
+                            "Failed to create process event monitor (cnproc), polling for new \
+                             kworkers every {} instead: {e}",
+                            format_duration(timeout)
                         );
-                        return Ok(());
+                        self.poll_only.store(true, Ordering::Relaxed);
                     }
                 }
             }
+            if let Some(events) = connection.as_mut() {
+                let discarded = events.discard_pending();
+                if discarded > 0 {
+                    debug!("Discarded {discarded} process events received since the last wait");
+                }
+                let lookup = |pid| {
+                    self.process(pid)
+                        .ok()
+                        .and_then(|p| self.to_proc_info(p).ok())
+                };
+                let reconnect = || Ok(ConnectorEvents::connect()?);
+                let result = events::wait_for_kworker(
+                    events,
+                    reconnect,
+                    lookup,
+                    is_kworker,
+                    exited,
+                    timeout,
+                    &self.keepalive,
+                );
+                // Connects again on the next wait, rather than going on with a broken connection.
+                if result.is_err() {
+                    *connection = None;
+                }
+                return result;
+            }
         }
+        // The caller scans again once the wait is over, which is all polling needs.
+        self.keepalive.sleep(timeout);
+        Ok(None)
+    }
+
+    /// Only fails with `SyncTimedOut` if `sync(2)` blocks for longer than `sync_timeout`, or with
+    /// `SyncStillBlocked` right away if an earlier sync is still blocked, as it otherwise can't.
+    fn sync(&self) -> Result<()> {
+        let sync_ioprio = self.sync_ioprio;
+        self.sync_runner
+            .run(self.sync_timeout, &self.keepalive, move || {
+                let Some(class) = sync_ioprio else {
+                    return rustix::fs::sync();
+                };
+                if let Err(e) = run_with_ioprio(class, rustix::fs::sync) {
+                    warn!(
+                        "Failed to sync with {class:?} I/O priority, using the default one: {e:?}"
+                    );
+                    rustix::fs::sync();
+                }
+            })
+    }
+
+    fn sync_fs(&self, mount: &Path) -> Result<()> {
+        let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC;
+        let fd = rustix::fs::open(mount, flags, Mode::empty())
+            .with_context(|| format!("failed to open {}", mount.display()))?;
+        let sync_ioprio = self.sync_ioprio;
+        let result = self
+            .sync_runner
+            .run(self.sync_timeout, &self.keepalive, move || {
+                let syncfs = || rustix::fs::syncfs(&fd);
+                match sync_ioprio {
+                    None => syncfs(),
+                    Some(class) => run_with_ioprio(class, syncfs).unwrap_or_else(|e| {
+                        warn!(
+                        "Failed to sync with {class:?} I/O priority, using the default one: {e:?}"
+                    );
+                        syncfs()
+                    }),
+                }
+            });
+        result
+            .and_then(|result| Ok(result?))
+            .with_context(|| format!("failed to sync {}", mount.display()))
+    }
+
+    fn mount_of(&self, device: (u32, u32)) -> Result<Option<PathBuf>> {
+        let mountinfo = std::fs::read_to_string(sync_mode::MOUNTINFO_PATH)
+            .with_context(|| format!("failed to read {}", sync_mode::MOUNTINFO_PATH))?;
+        Ok(sync_mode::mount_point(&mountinfo, device))
+    }
+
+    fn fs_status(&self, path: &Path) -> Result<FsStatus> {
+        FsStatus::of(path).with_context(|| format!("failed to statvfs {}", path.display()))
+    }
+
+    fn signal(&self, pid: i32, signal: Signal) -> Result<()> {
+        let target = Pid::from_raw(pid).with_context(|| format!("invalid pid {pid}"))?;
+        kill_process(target, signal).with_context(|| format!("failed to signal pid {pid}"))
+    }
+
+    fn cpu_time_over(
+        &self,
+        pid: i32,
+        interval: std::time::Duration,
+    ) -> Result<std::time::Duration> {
+        // Both samples go through the same handle, so if the pid gets reused in between, the
+        // second one fails rather than sampling another process.
+        let process = self.process(pid).context("failed to open process")?;
+        let ticks = || -> Result<u64> {
+            let stat = process.stat().context("failed to read process stat")?;
+            Ok(stat.utime + stat.stime)
+        };
+        let before = ticks()?;
+        self.keepalive.sleep(interval);
+        let ticks = ticks()?.saturating_sub(before);
+        Ok(std::time::Duration::from_secs_f64(
+            ticks as f64 / procfs::ticks_per_second() as f64,
+        ))
     }
 
-    fn sync(&self) {
-        rustix::fs::sync();
+    fn stack(&self, pid: i32) -> Result<String> {
+        // Only readable by root, with CAP_SYS_ADMIN on recent kernels.
+        let path = self.procfs_root.join(pid.to_string()).join("stack");
+        std::fs::read_to_string(path).context("failed to read stack")
+    }
+
+    fn run_command(&self, command: &str, timeout: std::time::Duration) -> Result<bool> {
+        let mut child = std::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(command)
+            .spawn()
+            .with_context(|| format!("failed to run '{command}'"))?;
+        let exited = self.keepalive.wait(timeout, |step| {
+            let exited = child.try_wait().context("failed to wait for command");
+            let exited = exited.transpose();
+            if exited.is_none() {
+                std::thread::sleep(step.min(std::time::Duration::from_millis(100)));
+            }
+            exited
+        });
+        match exited {
+            Some(status) => {
+                let status = status?;
+                debug!("'{command}' exited with {status}");
+                Ok(status.success())
+            }
+            None => {
+                warn!(
+                    "'{command}' still running after {}, killing it",
+                    format_duration(timeout)
+                );
+                // It may have exited in the meantime, in which case there is nothing to kill.
+                let _ = child.kill();
+                let _ = child.wait();
+                Ok(false)
+            }
+        }
+    }
+
+    fn emergency_sync(&self) -> Result<()> {
+        let path = self.sysrq_trigger();
+        // Unlike the keyboard, the trigger file isn't restricted by the kernel.sysrq sysctl.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(b"s"))
+            .with_context(|| format!("failed to write to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sync_runner_gives_up_on_blocked_syncs() {
+        let runner = SyncRunner::default();
+        assert_eq!(
+            runner
+                .run(Duration::from_secs(5), &KeepAlive::default(), || 42)
+                .unwrap(),
+            42
+        );
+
+        let started = std::time::Instant::now();
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let error = runner
+            .run(
+                Duration::from_millis(20),
+                &KeepAlive::default(),
+                move || {
+                    let _ = blocked.recv();
+                },
+            )
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(error.is::<SyncTimedOut>());
+        assert_eq!(error.to_string(), "sync still blocked after 20ms");
+
+        // No other thread is started while the first one hangs.
+        let started_syncs = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let sync = || {
+            let started_syncs = Arc::clone(&started_syncs);
+            move || started_syncs.fetch_add(1, Ordering::SeqCst)
+        };
+        let error = runner
+            .run(Duration::from_secs(5), &KeepAlive::default(), sync())
+            .unwrap_err();
+        assert!(error.is::<SyncStillBlocked>());
+        assert!(runner
+            .clone()
+            .run(Duration::from_secs(5), &KeepAlive::default(), sync())
+            .is_err());
+        assert_eq!(started_syncs.load(Ordering::SeqCst), 0);
+
+        // Until it returns.
+        drop(unblock);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while runner.in_flight.load(Ordering::SeqCst) && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(
+            runner
+                .run(Duration::from_secs(5), &KeepAlive::default(), sync())
+                .unwrap(),
+            0
+        );
+        assert_eq!(started_syncs.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_poll_only_waits_until_the_next_scan() {
+        let system = LiveSystem {
+            read_cmdline: false,
+            read_wchan: false,
+            sync_ioprio: None,
+            sync_timeout: DEFAULT_SYNC_TIMEOUT,
+            sync_runner: SyncRunner::default(),
+            scan_budget: None,
+            max_examined: None,
+            poll_only: AtomicBool::new(true),
+            process_events: Mutex::default(),
+            keepalive: Arc::default(),
+            clock: BootClock::anchored(),
+            procfs_root: PathBuf::from(DEFAULT_PROCFS_ROOT),
+        };
+        let started = std::time::Instant::now();
+        let found = system
+            .wait_for_kworker(|_: &ProcInfo| true, |_| {}, Duration::from_millis(20))
+            .unwrap();
+        assert!(found.is_none());
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_scans_a_staged_procfs() {
+        let root = std::env::temp_dir().join(format!("stuck_wbs_procfs_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let stage = |pid: i32, comm: &str, state: char, starttime: u64| {
+            let dir = root.join(pid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("comm"), format!("{comm}\n")).unwrap();
+            // Flagged as a kernel thread (PF_KTHREAD), so that no cgroup is looked up.
+            let mut fields = vec!["0".to_string(); 50];
+            fields[0] = state.to_string();
+            fields[1] = "2".to_string();
+            fields[6] = StatFlags::PF_KTHREAD.bits().to_string();
+            fields[11] = "150".to_string();
+            fields[12] = "250".to_string();
+            fields[19] = starttime.to_string();
+            let stat = format!("{pid} ({comm}) {}\n", fields.join(" "));
+            std::fs::write(dir.join("stat"), stat).unwrap();
+        };
+        stage(4242, "kworker/u8:2+inode_switch_wbs", 'D', 12_345);
+        stage(4243, "kworker/0:1-events", 'I', 100);
+        let stack =
+            "[<0>] inode_switch_wbs_work_fn+0x2a/0x4a0\n[<0>] process_one_work+0x1e5/0x3b0\n";
+        std::fs::write(root.join("4242/stack"), stack).unwrap();
+        let system = LiveSystem {
+            read_cmdline: false,
+            read_wchan: false,
+            sync_ioprio: None,
+            sync_timeout: DEFAULT_SYNC_TIMEOUT,
+            sync_runner: SyncRunner::default(),
+            scan_budget: None,
+            max_examined: None,
+            poll_only: AtomicBool::new(true),
+            process_events: Mutex::default(),
+            keepalive: Arc::default(),
+            clock: BootClock::anchored(),
+            procfs_root: root.clone(),
+        };
+
+        let prefilter = CommPrefilter::new(["kworker/*inode_switch_wbs*"]);
+        let scan = system
+            .find_all_kworkers(&prefilter, |p: &ProcInfo| {
+                p.comm.contains("inode_switch_wbs")
+            })
+            .unwrap();
+        assert_eq!(system.stack(4242).unwrap(), stack);
+        assert!(system.stack(4243).is_err());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(scan.kworkers.len(), 1, "{:?}", scan.kworkers);
+        let kworker = &scan.kworkers[0];
+        assert_eq!(kworker.pid, 4242);
+        assert_eq!(kworker.comm, "kworker/u8:2+inode_switch_wbs");
+        assert_eq!(kworker.state, 'D');
+        assert!(kworker.kernel_thread);
+        let tps = procfs::ticks_per_second();
+        assert_eq!(kworker.starttime, system.clock.start_time(12_345, tps));
+        assert_eq!(kworker.cpu_time, cpu_time(400, tps));
+    }
+
+    #[test]
+    fn test_process_events_error_hints_at_remedies() {
+        let denied = process_events_error(std::io::ErrorKind::PermissionDenied.into());
+        assert_eq!(
+            format!("{denied:#}"),
+            "failed to listen to process events (needs CAP_NET_ADMIN for process events; rerun \
+             as root or use --no-netlink): permission denied"
+        );
+        let unsupported = process_events_error(std::io::Error::from_raw_os_error(93));
+        assert!(
+            format!("{unsupported:#}").contains("CONFIG_PROC_EVENTS; use --no-netlink"),
+            "{unsupported:#}"
+        );
+    }
+
+    #[test]
+    fn test_cpu_time_from_ticks() {
+        assert_eq!(cpu_time(0, 100), Duration::ZERO);
+        assert_eq!(cpu_time(1234, 100), Duration::from_millis(12_340));
+        assert_eq!(cpu_time(3, 250), Duration::from_millis(12));
+    }
+
+    #[test]
+    fn test_within_budget_truncates_scan() {
+        let now = chrono::Utc::now();
+        // The true oldest process comes last, after the budget is exceeded.
+        let processes = [20, 50, 10, 90].map(|age| ProcInfo {
+            pid: 1000 + age,
+            uid: 0,
+            starttime: now - chrono::Duration::seconds(age.into()),
+            cpu_time: Duration::ZERO,
+            comm: "kworker/0:1".to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'R',
+            wchan: None,
+        });
+        // Each process takes 10ms to read.
+        let mut reads = 0;
+        let elapsed = || {
+            reads += 1;
+            Duration::from_millis((reads - 1) * 10)
+        };
+
+        let scanned: Vec<ProcInfo> = within_budget(
+            processes.clone().into_iter(),
+            Some(Duration::from_millis(25)),
+            elapsed,
+        )
+        .collect();
+        assert_eq!(scanned.len(), 3);
+        let oldest = scanned.iter().min_by_key(|p| p.starttime).unwrap();
+        assert_eq!(oldest.pid, 1050);
+
+        let unbounded = within_budget(processes.into_iter(), None, || Duration::MAX);
+        assert_eq!(unbounded.count(), 4);
+    }
+
+    #[test]
+    fn test_max_examined_caps_full_reads() {
+        let reads = std::cell::Cell::new(0);
+        let processes = || {
+            let reads = &reads;
+            (0..6).map(move |pid| {
+                // Only kworkers are candidates, bash is rejected by the prefilter.
+                let comm = if pid % 2 == 0 { "kworker/0:1" } else { "bash" };
+                let read = move || {
+                    reads.set(reads.get() + 1);
+                    Ok(ProcInfo {
+                        pid,
+                        uid: 0,
+                        starttime: chrono::Utc::now(),
+                        cpu_time: Duration::ZERO,
+                        comm: comm.to_string(),
+                        cmdline: None,
+                        kernel_thread: true,
+                        state: 'R',
+                        wchan: None,
+                    })
+                };
+                (pid, Some(comm.to_string()), read)
+            })
+        };
+        let prefilter = CommPrefilter::new(["kworker/*"]);
+        let reasons = |scan: &Scan| -> Vec<(i32, SkipReason)> {
+            scan.skipped.iter().map(|s| (s.pid, s.reason)).collect()
+        };
+
+        let capped = scan(
+            processes(),
+            &prefilter,
+            Some(2),
+            |_: &ProcInfo| true,
+            |_: &ProcInfo| false,
+        );
+        assert_eq!(reads.get(), 2);
+        assert_eq!(
+            capped.kworkers.iter().map(|p| p.pid).collect::<Vec<_>>(),
+            [0, 2]
+        );
+        assert_eq!(
+            reasons(&capped),
+            [
+                (1, SkipReason::NotMonitored),
+                (3, SkipReason::NotMonitored),
+                (4, SkipReason::NotExamined),
+                (5, SkipReason::NotMonitored),
+            ]
+        );
+
+        reads.set(0);
+        let full = scan(
+            processes(),
+            &prefilter,
+            None,
+            |_: &ProcInfo| true,
+            |_: &ProcInfo| false,
+        );
+        assert_eq!(reads.get(), 3);
+        assert_eq!(full.kworkers.len(), 3);
+    }
+
+    #[test]
+    fn test_examine_attributes_skip_reasons() {
+        let prefilter = CommPrefilter::new(["kworker/*"]);
+        let is_kworker = |p: &ProcInfo| p.uid == 0 && p.comm.starts_with("kworker/");
+        type Read = Box<dyn FnOnce() -> Result<ProcInfo>>;
+        let read = |comm: &str, uid| -> Read {
+            let info = ProcInfo {
+                pid: 42,
+                uid,
+                starttime: chrono::Utc::now(),
+                cpu_time: Duration::ZERO,
+                comm: comm.to_string(),
+                cmdline: None,
+                kernel_thread: false,
+                state: 'R',
+                wchan: None,
+            };
+            Box::new(move || Ok(info))
+        };
+        let reason = |comm: Option<&str>, read: Read, frozen| {
+            let comm = comm.map(str::to_string);
+            examine(
+                42,
+                comm,
+                &prefilter,
+                false,
+                read,
+                &is_kworker,
+                |_: &ProcInfo| frozen,
+            )
+            .map(|p| p.comm)
+            .map_err(|s| (s.comm, s.reason))
+        };
+
+        assert_eq!(
+            reason(Some("bash"), read("bash", 0), false),
+            Err((Some("bash".to_string()), SkipReason::NotMonitored))
+        );
+        // Without a prefilter, the full read is what rules it out.
+        assert_eq!(
+            reason(None, read("bash", 0), false),
+            Err((Some("bash".to_string()), SkipReason::NotMonitored))
+        );
+        assert_eq!(
+            reason(None, read("kworker/0:1", 1000), false),
+            Err((Some("kworker/0:1".to_string()), SkipReason::NotMonitored))
+        );
+        let gone: Read = Box::new(|| anyhow::bail!("no such process"));
+        assert_eq!(
+            reason(Some("kworker/0:1"), gone, false),
+            Err((Some("kworker/0:1".to_string()), SkipReason::Unreadable))
+        );
+        assert_eq!(
+            reason(Some("kworker/0:1"), read("kworker/0:1", 0), true),
+            Err((Some("kworker/0:1".to_string()), SkipReason::FrozenCgroup))
+        );
+        assert_eq!(
+            reason(Some("kworker/0:1"), read("kworker/0:1", 0), false),
+            Ok("kworker/0:1".to_string())
+        );
     }
 }
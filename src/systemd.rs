@@ -0,0 +1,165 @@
+//! `--systemd`, notifying systemd of readiness and feeding its watchdog, so that a service with
+//! `Type=notify` and `WatchdogSec=` is restarted if the daemon itself wedges.
+//!
+//! Implements the few messages of the `sd_notify(3)` protocol needed, which are single datagrams
+//! sent to the socket named by `NOTIFY_SOCKET`.
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+/// Names the socket to send notifications to, set by systemd for `Type=notify` services.
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+
+/// How long systemd waits for a watchdog ping, in microseconds, set when `WatchdogSec=` is.
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+
+/// The process whose pings systemd expects, which may not be this one.
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+
+/// Sends notifications to systemd.
+#[derive(Debug)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// How often to ping the watchdog, if it is enabled for this process.
+    watchdog_interval: Option<Duration>,
+}
+
+/// Parses `NOTIFY_SOCKET`, a path or, if starting with '@', an abstract socket name.
+fn parse_socket(value: &str) -> Result<SocketAddr> {
+    let addr = match value.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None if value.starts_with('/') => SocketAddr::from_pathname(value),
+        None => bail!("invalid {NOTIFY_SOCKET_ENV} '{value}', expected a path or @name"),
+    };
+    addr.with_context(|| format!("invalid {NOTIFY_SOCKET_ENV} '{value}'"))
+}
+
+/// Returns how often to ping a watchdog expecting pings every `usec` microseconds from `pid`, if
+/// it is enabled for `own_pid`.
+///
+/// Pings are sent twice as often as required, as `sd_watchdog_enabled(3)` recommends.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if pid.is_some_and(|pid| pid.parse() != Ok(own_pid)) {
+        return None;
+    }
+    let usec: u64 = usec?.parse().ok().filter(|&usec| usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+impl Notifier {
+    /// Returns a notifier if this process runs under systemd with `Type=notify`, which `required`
+    /// makes mandatory.
+    pub fn from_env(required: bool) -> Result<Option<Self>> {
+        let Some(socket) = std::env::var_os(NOTIFY_SOCKET_ENV) else {
+            if required {
+                bail!("--systemd requires {NOTIFY_SOCKET_ENV}, set by systemd for Type=notify services");
+            }
+            return Ok(None);
+        };
+        let usec = std::env::var(WATCHDOG_USEC_ENV).ok();
+        let pid = std::env::var(WATCHDOG_PID_ENV).ok();
+        // Commands the monitor runs have no business with them.
+        for var in [NOTIFY_SOCKET_ENV, WATCHDOG_USEC_ENV, WATCHDOG_PID_ENV] {
+            std::env::remove_var(var);
+        }
+        let addr = parse_socket(&socket.to_string_lossy())?;
+        let watchdog_interval =
+            watchdog_interval(usec.as_deref(), pid.as_deref(), std::process::id());
+        if usec.is_some() && watchdog_interval.is_none() {
+            debug!("The systemd watchdog is not enabled for this process");
+        }
+        let socket = UnixDatagram::unbound().context("failed to create the notification socket")?;
+        Ok(Some(Notifier {
+            socket,
+            addr,
+            watchdog_interval,
+        }))
+    }
+
+    /// How often `watchdog` must be called, if the watchdog is enabled.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    fn notify(&self, state: &str) {
+        if let Err(e) = self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            warn!("Failed to notify systemd of {state}: {e}");
+        }
+    }
+
+    /// Tells systemd the daemon finished starting up.
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    /// Pings the watchdog, if enabled.
+    pub fn watchdog(&self) {
+        if self.watchdog_interval.is_some() {
+            self.notify("WATCHDOG=1");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        // Meant for another process, such as the `--supervise` parent.
+        assert_eq!(watchdog_interval(Some("30000000"), Some("7"), 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(Some("soon"), None, 42), None);
+    }
+
+    #[test]
+    fn test_parse_socket() {
+        let path = parse_socket("/run/systemd/notify").unwrap();
+        assert_eq!(
+            path.as_pathname(),
+            Some(std::path::Path::new("/run/systemd/notify"))
+        );
+        let abstract_name = parse_socket("@/org/freedesktop/systemd1/notify").unwrap();
+        assert_eq!(
+            abstract_name.as_abstract_name(),
+            Some(&b"/org/freedesktop/systemd1/notify"[..])
+        );
+        assert!(parse_socket("notify").is_err());
+    }
+
+    #[test]
+    fn test_notifications_are_sent_to_the_socket() {
+        let path = std::env::temp_dir().join(format!("stuck_wbs_{}_notify", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let notifier = Notifier {
+            socket: UnixDatagram::unbound().unwrap(),
+            addr: SocketAddr::from_pathname(&path).unwrap(),
+            watchdog_interval: Some(Duration::from_millis(10)),
+        };
+
+        notifier.ready();
+        notifier.watchdog();
+        let mut buf = [0u8; 64];
+        let received: Vec<_> = std::iter::from_fn(|| {
+            let n = receiver.recv(&mut buf).ok()?;
+            Some(String::from_utf8_lossy(&buf[..n]).into_owned())
+        })
+        .collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(received, ["READY=1", "WATCHDOG=1"]);
+    }
+}
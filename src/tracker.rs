@@ -0,0 +1,182 @@
+//! Follows matching processes from one scan to the next, to tell how long each has been matching
+//! rather than how long it has been running.
+//!
+//! Kworkers are pooled and their comm names the work at hand, so one started hours ago may have
+//! only just picked up the work a glob matches. Pids are reused, too, which start times tell
+//! apart.
+//!
+//! Between full scans of every process, at most once per `--rescan-interval`, the busy poll only
+//! re-reads the processes matching in the last scan, which on a host with thousands of processes
+//! is much cheaper. Those that exit are dropped by the next scan, or as soon as process events report it.
+use crate::system::ProcInfo;
+use std::collections::HashMap;
+
+type Time = chrono::DateTime<chrono::Utc>;
+
+/// Identifies a process: its pid, and its start time to tell reused pids apart.
+pub type Key = (i32, Time);
+
+/// Returns the key of `p`.
+pub fn key(p: &ProcInfo) -> Key {
+    (p.pid, p.starttime)
+}
+
+/// The matching processes of the last scan.
+#[derive(Debug, Default)]
+pub struct Tracker {
+    /// When the last scan started, `None` before the first one.
+    last_scan: Option<Time>,
+    /// When the last scan of every process started, rather than of the candidates only.
+    last_full_scan: Option<Time>,
+    /// Since when each matching process has been matching.
+    matching: HashMap<Key, Time>,
+}
+
+impl Tracker {
+    /// Returns when the last scan started, if any did.
+    pub fn last_scan(&self) -> Option<Time> {
+        self.last_scan
+    }
+
+    /// Forgets the process `pid`, which exited, so that it is no longer a candidate.
+    pub fn forget(&mut self, pid: i32) {
+        self.matching.retain(|(matching, _), _| *matching != pid);
+    }
+
+    /// Returns the pids of the processes that matched in the last scan, for re-reading only them
+    /// as of `now`, or `None` if every process should be scanned: none matched, or the last full
+    /// scan started `interval` or longer ago.
+    pub fn candidates(&self, now: Time, interval: std::time::Duration) -> Option<Vec<i32>> {
+        // Negative if the clock went back, which a full scan recovers from.
+        let since_full_scan = now.signed_duration_since(self.last_full_scan?).to_std();
+        if self.matching.is_empty() || !since_full_scan.is_ok_and(|d| d < interval) {
+            return None;
+        }
+        let mut pids: Vec<i32> = self.matching.keys().map(|(pid, _)| *pid).collect();
+        pids.sort_unstable();
+        Some(pids)
+    }
+
+    /// Records that `kworkers` matched in the scan of every process started at `now`, returning
+    /// since when each of them has been matching.
+    ///
+    /// A process that matched in the last scan too has been matching since then or earlier, one
+    /// that didn't started matching after the last full scan. All that is known on the first scan
+    /// is when each process started.
+    pub fn observe(&mut self, kworkers: &[ProcInfo], now: Time) -> HashMap<Key, Time> {
+        let since = self.record(kworkers, now);
+        self.last_full_scan = Some(now);
+        since
+    }
+
+    /// Like `observe`, for a scan of the `candidates` only, which evicts those that exited or
+    /// stopped matching.
+    pub fn observe_candidates(&mut self, kworkers: &[ProcInfo], now: Time) -> HashMap<Key, Time> {
+        self.record(kworkers, now)
+    }
+
+    fn record(&mut self, kworkers: &[ProcInfo], now: Time) -> HashMap<Key, Time> {
+        self.last_scan = Some(now);
+        // Reused pids are the only processes a scan of the candidates finds that weren't matching.
+        let last_full_scan = self.last_full_scan;
+        self.matching = kworkers
+            .iter()
+            .map(|p| {
+                let since = self.matching.get(&key(p)).copied().unwrap_or_else(|| {
+                    last_full_scan.map_or(p.starttime, |last| last.max(p.starttime))
+                });
+                (key(p), since)
+            })
+            .collect();
+        self.matching.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proc_info(pid: i32, starttime: Time) -> ProcInfo {
+        ProcInfo {
+            pid,
+            uid: 0,
+            comm: "kworker/u8:2+inode_switch_wbs".to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'D',
+            wchan: None,
+            starttime,
+            cpu_time: std::time::Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_first_scan_goes_by_start_time() {
+        let now = chrono::Utc::now();
+        let started = now - chrono::Duration::hours(2);
+        let since = Tracker::default().observe(&[proc_info(1000, started)], now);
+        assert_eq!(since, HashMap::from([((1000, started), started)]));
+    }
+
+    #[test]
+    fn test_matching_is_followed_across_scans() {
+        let start = chrono::Utc::now();
+        let at = |s| start + chrono::Duration::seconds(s);
+        let mut tracker = Tracker::default();
+        let long_running = proc_info(1000, at(-3600));
+        tracker.observe(std::slice::from_ref(&long_running), at(0));
+        let since = tracker.observe(std::slice::from_ref(&long_running), at(1));
+        assert_eq!(since[&key(&long_running)], at(-3600));
+
+        // A kworker started long ago that only just picked up matching work.
+        let pooled = proc_info(2000, at(-7200));
+        let since = tracker.observe(&[long_running.clone(), pooled.clone()], at(2));
+        assert_eq!(since[&key(&long_running)], at(-3600));
+        assert_eq!(since[&key(&pooled)], at(1));
+
+        // Gone from the matching set, then back.
+        tracker.observe(std::slice::from_ref(&long_running), at(3));
+        let since = tracker.observe(&[long_running, pooled.clone()], at(40));
+        assert_eq!(since[&key(&pooled)], at(3));
+    }
+
+    #[test]
+    fn test_reused_pid_starts_over() {
+        let start = chrono::Utc::now();
+        let at = |s| start + chrono::Duration::seconds(s);
+        let mut tracker = Tracker::default();
+        tracker.observe(&[proc_info(1000, at(-60))], at(0));
+        tracker.observe(&[proc_info(1000, at(-60))], at(30));
+
+        // The pid now belongs to a process started since the last scan.
+        let reused = proc_info(1000, at(35));
+        let since = tracker.observe(std::slice::from_ref(&reused), at(40));
+        assert_eq!(since[&key(&reused)], at(35));
+    }
+
+    #[test]
+    fn test_candidates_until_the_next_full_scan() {
+        let start = chrono::Utc::now();
+        let at = |s| start + chrono::Duration::seconds(s);
+        let interval = std::time::Duration::from_secs(60);
+        let mut tracker = Tracker::default();
+        assert_eq!(tracker.candidates(at(0), interval), None);
+        tracker.observe(&[proc_info(2000, at(-60)), proc_info(1000, at(-60))], at(0));
+        assert_eq!(tracker.candidates(at(1), interval), Some(vec![1000, 2000]));
+
+        // 2000 exited, and is no longer a candidate.
+        let since = tracker.observe_candidates(&[proc_info(1000, at(-60))], at(1));
+        assert_eq!(since[&(1000, at(-60))], at(-60));
+        assert_eq!(tracker.candidates(at(2), interval), Some(vec![1000]));
+        // Found matching by a scan of the candidates, so since the last full scan at most.
+        let reused = proc_info(1000, at(-30));
+        let since = tracker.observe_candidates(std::slice::from_ref(&reused), at(2));
+        assert_eq!(since[&key(&reused)], at(0));
+
+        assert_eq!(tracker.candidates(at(60), interval), None);
+        // The clock went back.
+        assert_eq!(tracker.candidates(at(-1), interval), None);
+        tracker.observe_candidates(&[], at(3));
+        assert_eq!(tracker.candidates(at(4), interval), None);
+    }
+}
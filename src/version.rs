@@ -0,0 +1,66 @@
+//! `--version`, identifying the build in bug reports and fleet audits.
+
+/// The git revision the daemon was built from, "unknown" outside of a checkout.
+pub const GIT_HASH: &str = env!("STUCK_WBS_GIT_HASH");
+
+/// Returns the line printed by `--version`, e.g.
+/// "stuck_writeback_workaround 1.0.0 (git 1a2b3c4d5e6f, built 2026-10-14T12:00:00Z)".
+pub fn long() -> String {
+    describe(
+        env!("CARGO_PKG_VERSION"),
+        GIT_HASH,
+        env!("STUCK_WBS_BUILD_EPOCH"),
+    )
+}
+
+/// Formats the version line for `version`, `git_hash` and `build_epoch`, the build time in
+/// seconds since the epoch.
+fn describe(version: &str, git_hash: &str, build_epoch: &str) -> String {
+    let built = build_epoch
+        .parse()
+        .ok()
+        .and_then(|secs| chrono::DateTime::from_timestamp(secs, 0))
+        .map_or_else(
+            || String::from("at an unknown time"),
+            |t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        );
+    format!(
+        "{} {version} (git {git_hash}, built {built})",
+        env!("CARGO_PKG_NAME")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe("1.2.3", "1a2b3c4d5e6f", "1700000000"),
+            "stuck_writeback_workaround 1.2.3 (git 1a2b3c4d5e6f, built 2023-11-14T22:13:20Z)"
+        );
+        assert_eq!(
+            describe("1.2.3", "unknown", ""),
+            "stuck_writeback_workaround 1.2.3 (git unknown, built at an unknown time)"
+        );
+        // What the build embedded is well-formed.
+        let long = long();
+        assert!(
+            long.starts_with(&format!(
+                "stuck_writeback_workaround {} (git ",
+                env!("CARGO_PKG_VERSION")
+            )),
+            "{long}"
+        );
+        assert!(!long.contains("unknown time"), "{long}");
+        assert!(
+            GIT_HASH == "unknown"
+                || GIT_HASH
+                    .trim_end_matches("-dirty")
+                    .chars()
+                    .all(|c| c.is_ascii_hexdigit()),
+            "{GIT_HASH}"
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Structured reports POSTed to a `--webhook` URL, for ChatOps and incident tooling.
+use crate::duration::format_duration;
+use crate::labels::Labels;
+use anyhow::Result;
+use log::{debug, warn};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Whether this build can send webhooks, which requires the `webhook` feature.
+pub const SUPPORTED: bool = cfg!(feature = "webhook");
+
+/// How long a webhook delivery may take, connection included, before it is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times a report is POSTed before it is dropped.
+const ATTEMPTS: u32 = 3;
+
+/// How long to wait before the second attempt, doubling before each one after.
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// What a report is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// A stuck process triggered a remediation.
+    Trigger,
+    /// A synthetic trigger from `--emit-test-event`, no action was taken.
+    TestTrigger,
+}
+
+/// The JSON payload describing a trigger.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub event: Event,
+    /// The name of the host the daemon runs on.
+    pub host: String,
+    /// When the trigger happened, in RFC 3339 format.
+    pub timestamp: String,
+    /// The oldest matching process.
+    pub comm: String,
+    pub pid: i32,
+    /// Which threshold was crossed: "runtime", "cpu_time", "summed_age" or "count".
+    pub cause: &'static str,
+    /// The runtime compared to the threshold: the oldest's own, the CPU time of the process for
+    /// "cpu_time", or the sum of all ages. For "count", the oldest's own, which may be below its
+    /// threshold.
+    pub runtime_seconds: i64,
+    pub threshold_seconds: i64,
+    /// The remediation, e.g. "sync" or "signal:SIGKILL".
+    pub action: String,
+    /// How many matching processes were counted towards `runtime_seconds`.
+    pub kworkers: usize,
+    /// How many real triggers happened since the daemon started, this one included.
+    pub triggers_total: u64,
+    /// The `--label`s, as an object.
+    #[serde(skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+}
+
+impl Report {
+    /// Serializes the report as the JSON payload of the webhook.
+    pub fn to_json(&self) -> String {
+        // The report only holds strings and numbers, which always serialize.
+        serde_json::to_string(self).expect("failed to serialize report")
+    }
+}
+
+/// Returns the name of the host, as reported by `uname`.
+pub fn hostname() -> String {
+    rustix::system::uname()
+        .nodename()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// POSTs `report` to `url` in the background, so a slow webhook never stalls the monitor.
+///
+/// Failures are retried a few times, then only logged.
+pub fn send(url: &str, report: &Report) {
+    let url = url.to_string();
+    let body = report.to_json();
+    std::thread::spawn(move || match deliver(&url, &body, RETRY_DELAY) {
+        Ok(()) => debug!("Delivered report to webhook {url}"),
+        Err(e) => warn!("Failed to deliver report to webhook {url}, giving up: {e:?}"),
+    });
+}
+
+/// POSTs `body` to `url` up to `ATTEMPTS` times, waiting `delay` before the first retry.
+fn deliver(url: &str, body: &str, mut delay: Duration) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        match post(url, body, TIMEOUT) {
+            Err(e) if attempt < ATTEMPTS => {
+                warn!(
+                    "Failed to deliver report to webhook {url}, retrying in {}: {e:?}",
+                    format_duration(delay)
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn post(url: &str, body: &str, timeout: Duration) -> Result<()> {
+    use anyhow::Context;
+
+    ureq::AgentBuilder::new()
+        .timeout(timeout)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(body)
+        .context("request failed")?;
+    Ok(())
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post(_url: &str, _body: &str, _timeout: Duration) -> Result<()> {
+    anyhow::bail!("built without the webhook feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_serialization() {
+        let report = Report {
+            event: Event::Trigger,
+            host: "db-1".to_string(),
+            timestamp: "2025-01-02T03:04:05+00:00".to_string(),
+            comm: "kworker/u16:2+inode_switch_wbs".to_string(),
+            pid: 4242,
+            cause: "runtime",
+            runtime_seconds: 45,
+            threshold_seconds: 30,
+            action: "sync".to_string(),
+            kworkers: 1,
+            triggers_total: 3,
+            labels: Labels::default(),
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "event": "trigger",
+                "host": "db-1",
+                "timestamp": "2025-01-02T03:04:05+00:00",
+                "comm": "kworker/u16:2+inode_switch_wbs",
+                "pid": 4242,
+                "cause": "runtime",
+                "runtime_seconds": 45,
+                "threshold_seconds": 30,
+                "action": "sync",
+                "kworkers": 1,
+                "triggers_total": 3,
+            })
+        );
+
+        let cluster = "cluster=prod".parse().unwrap();
+        let report = Report {
+            labels: Labels::new(vec![cluster]).unwrap(),
+            ..report
+        };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["labels"], serde_json::json!({"cluster": "prod"}));
+        assert_eq!(
+            serde_json::to_value(Event::TestTrigger).unwrap(),
+            serde_json::json!("test_trigger")
+        );
+    }
+
+    #[cfg(feature = "webhook")]
+    #[test]
+    fn test_deliver_retries_until_the_webhook_accepts() {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        // Fails the first request, then accepts and returns the second.
+        let server = std::thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                )
+                .unwrap();
+            }
+            bodies
+        });
+
+        let report = Report {
+            event: Event::Trigger,
+            host: hostname(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            comm: "kworker/u16:2+inode_switch_wbs".to_string(),
+            pid: 4242,
+            cause: "runtime",
+            runtime_seconds: 45,
+            threshold_seconds: 30,
+            action: "sync".to_string(),
+            kworkers: 1,
+            triggers_total: 1,
+            labels: Labels::default(),
+        };
+        deliver(&url, &report.to_json(), Duration::from_millis(10)).unwrap();
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        let json: serde_json::Value = serde_json::from_str(&bodies[1]).unwrap();
+        assert_eq!(json["host"], hostname());
+        assert_eq!(json["comm"], "kworker/u16:2+inode_switch_wbs");
+        assert_eq!(json["runtime_seconds"], 45);
+        assert!(chrono::DateTime::parse_from_rfc3339(json["timestamp"].as_str().unwrap()).is_ok());
+    }
+
+    #[cfg(feature = "webhook")]
+    #[test]
+    fn test_post_times_out_on_unresponsive_webhook() {
+        // The kernel completes the handshake on its own, but nothing ever answers.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let start = std::time::Instant::now();
+        assert!(post(&url, "{}", Duration::from_millis(200)).is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
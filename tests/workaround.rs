@@ -0,0 +1,134 @@
+//! Drives the library as other tools would, against a simulated system of their own.
+use anyhow::Result;
+use rustix::process::Signal;
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use stuck_writeback_workaround::action::Action;
+use stuck_writeback_workaround::daemon_state::DaemonState;
+use stuck_writeback_workaround::fs_status::FsStatus;
+use stuck_writeback_workaround::metrics::Metrics;
+use stuck_writeback_workaround::prefilter::CommPrefilter;
+use stuck_writeback_workaround::system::{IsKworkerFn, ProcInfo, Scan, System};
+use stuck_writeback_workaround::{workaround, Config, Outcome, Timings};
+
+/// A system running a single kworker, started `age` ago.
+struct Simulated {
+    now: chrono::DateTime<chrono::Utc>,
+    age: chrono::Duration,
+    syncs: Cell<usize>,
+}
+
+impl System for Simulated {
+    fn find_all_kworkers<F: IsKworkerFn>(
+        &self,
+        _prefilter: &CommPrefilter,
+        is_kworker: F,
+    ) -> Result<Scan> {
+        let kworker = ProcInfo {
+            pid: 1000,
+            uid: 0,
+            comm: "kworker/u8:2+inode_switch_wbs".to_string(),
+            cmdline: None,
+            kernel_thread: true,
+            state: 'D',
+            wchan: None,
+            starttime: self.now - self.age,
+            cpu_time: Duration::ZERO,
+        };
+        Ok(Scan {
+            kworkers: [kworker].into_iter().filter(|p| is_kworker(p)).collect(),
+            skipped: Vec::new(),
+        })
+    }
+
+    fn now(&self) -> chrono::DateTime<chrono::Utc> {
+        self.now
+    }
+
+    fn uptime(&self) -> Result<chrono::Duration> {
+        Ok(chrono::Duration::days(1))
+    }
+
+    fn wait_for_kworker<F: IsKworkerFn, X: FnMut(i32)>(
+        &self,
+        _is_kworker: F,
+        _exited: X,
+        _timeout: Duration,
+    ) -> Result<Option<ProcInfo>> {
+        Ok(None)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.syncs.set(self.syncs.get() + 1);
+        Ok(())
+    }
+
+    fn sync_fs(&self, _mount: &Path) -> Result<()> {
+        anyhow::bail!("not simulated")
+    }
+
+    fn mount_of(&self, _device: (u32, u32)) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    fn fs_status(&self, _path: &Path) -> Result<FsStatus> {
+        anyhow::bail!("not simulated")
+    }
+
+    fn signal(&self, _pid: i32, _signal: Signal) -> Result<()> {
+        anyhow::bail!("not simulated")
+    }
+
+    fn cpu_time_over(&self, _pid: i32, _interval: Duration) -> Result<Duration> {
+        Ok(Duration::ZERO)
+    }
+
+    fn stack(&self, _pid: i32) -> Result<String> {
+        anyhow::bail!("not simulated")
+    }
+
+    fn run_command(&self, _command: &str, _timeout: Duration) -> Result<bool> {
+        Ok(true)
+    }
+
+    fn emergency_sync(&self) -> Result<()> {
+        anyhow::bail!("not simulated")
+    }
+}
+
+#[test]
+fn test_workaround_syncs_on_a_simulated_system() {
+    let config = Config::default();
+    let simulated = |age| Simulated {
+        now: chrono::Utc::now(),
+        age,
+        syncs: Cell::new(0),
+    };
+
+    let system = simulated(chrono::Duration::seconds(5));
+    let outcome = workaround(
+        &system,
+        &Metrics::default(),
+        &DaemonState::default(),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(outcome, Outcome::BelowThreshold);
+    assert_eq!(system.syncs.get(), 0);
+
+    let system = simulated(chrono::Duration::minutes(5));
+    let outcome = workaround(
+        &system,
+        &Metrics::default(),
+        &DaemonState::default(),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(outcome, Outcome::Remediated(Action::Sync));
+    assert_eq!(system.syncs.get(), 1);
+    assert_eq!(
+        outcome.sleep_duration(&config.timings),
+        Timings::default().recovery_time
+    );
+}